@@ -0,0 +1,84 @@
+use chip8::assembler::assemble;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+#[derive(argh::FromArgs)]
+/// Assemble a chip8 program written in a subset of Octo.
+struct Options {
+    /// the source file
+    #[argh(positional)]
+    source: PathBuf,
+
+    /// where to write the rom, defaulting to the source with a .ch8 extension
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// where to write a source map
+    #[argh(option)]
+    source_map: Option<PathBuf>,
+}
+
+/// Make `path` relative to `dir` if it is inside of it, so source maps can be moved along with the source
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    path.strip_prefix(&dir)
+        .map(Path::to_path_buf)
+        .unwrap_or(path)
+}
+
+fn main() {
+    let options: Options = argh::from_env();
+
+    let source = match std::fs::read_to_string(&options.source) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Failed to read '{}': {}", options.source.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let map_dir = options
+        .source_map
+        .as_ref()
+        .and_then(|path| path.parent())
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }
+        });
+    let file = match map_dir {
+        Some(dir) => relative_to(&options.source, dir),
+        None => options.source.clone(),
+    };
+
+    let assembly = match assemble(&source, file) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            eprintln!("{}:{}", options.source.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let output = match options.output {
+        Some(output) => output,
+        None => options.source.with_extension("ch8"),
+    };
+    if let Err(e) = std::fs::write(&output, &assembly.rom) {
+        eprintln!("Failed to write '{}': {}", output.display(), e);
+        std::process::exit(1);
+    }
+
+    if let Some(path) = options.source_map.as_ref() {
+        let mut json = assembly.source_map.to_json();
+        json.push('\n');
+        if let Err(e) = std::fs::write(path, json) {
+            eprintln!("Failed to write '{}': {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
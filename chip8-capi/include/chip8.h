@@ -0,0 +1,207 @@
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* Generated by cbindgen from src/lib.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The width of the display in pixels
+ */
+#define CHIP8_WIDTH 64
+
+/**
+ * The height of the display in pixels
+ */
+#define CHIP8_HEIGHT 32
+
+/**
+ * The number of keys on the keypad
+ */
+#define CHIP8_NUM_KEYS 16
+
+/**
+ * The result of a call
+ */
+typedef enum Chip8Status {
+  CHIP8_STATUS_OK = 0,
+  /**
+   * A required pointer was null
+   */
+  CHIP8_STATUS_NULL_POINTER,
+  /**
+   * The rom does not fit in memory
+   */
+  CHIP8_STATUS_INVALID_PROGRAM_SIZE,
+  /**
+   * The program ran into an instruction that doesn't exist
+   */
+  CHIP8_STATUS_UNKNOWN_INSTRUCTION,
+  CHIP8_STATUS_INVALID_REG,
+  CHIP8_STATUS_INVALID_ADDRESS,
+  /**
+   * The program returned from a subroutine with an empty stack
+   */
+  CHIP8_STATUS_STACK_UNDERFLOW,
+  /**
+   * The program nested subroutines too deeply
+   */
+  CHIP8_STATUS_STACK_OVERFLOW,
+  /**
+   * The program counter left memory
+   */
+  CHIP8_STATUS_PROGRAM_COUNTER_OUT_OF_BOUNDS,
+  /**
+   * The key is not on the keypad
+   */
+  CHIP8_STATUS_INVALID_KEY,
+  /**
+   * The save state is damaged or from an incompatible version
+   */
+  CHIP8_STATUS_INVALID_STATE,
+  /**
+   * The buffer is too small for the save state
+   */
+  CHIP8_STATUS_BUFFER_TOO_SMALL,
+  /**
+   * The deflicker mode isn't `off`, `or:<frames>` or `decay:<persistence>`
+   */
+  CHIP8_STATUS_INVALID_DEFLICKER_MODE,
+  /**
+   * The emulator hit a bug. It is in an unknown state and should be reset or destroyed.
+   */
+  CHIP8_STATUS_PANICKED,
+} Chip8Status;
+
+/**
+ * A chip8 with a clock
+ */
+typedef struct Chip8Emulator Chip8Emulator;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create an emulator with nothing loaded. Free it with `chip8_destroy`.
+ */
+struct Chip8Emulator *chip8_create(void);
+
+/**
+ * Free an emulator. Passing null does nothing.
+ *
+ * # Safety
+ * `emulator` must be null or come from `chip8_create`, and must not be used afterwards.
+ */
+void chip8_destroy(struct Chip8Emulator *emulator);
+
+/**
+ * Load a rom and start it, configuring quirks and speed if the rom is known.
+ *
+ * # Safety
+ * `emulator` must be valid and `data` must point to `len` readable bytes.
+ */
+enum Chip8Status chip8_load(struct Chip8Emulator *emulator, const uint8_t *data, size_t len);
+
+/**
+ * Restart the loaded rom
+ *
+ * # Safety
+ * `emulator` must be valid.
+ */
+enum Chip8Status chip8_reset(struct Chip8Emulator *emulator);
+
+/**
+ * Run for one 60hz frame
+ *
+ * # Safety
+ * `emulator` must be valid.
+ */
+enum Chip8Status chip8_run_frame(struct Chip8Emulator *emulator);
+
+/**
+ * Press or release a key
+ *
+ * # Safety
+ * `emulator` must be valid.
+ */
+enum Chip8Status chip8_set_key(struct Chip8Emulator *emulator, uint8_t key, bool pressed);
+
+/**
+ * Get the display, `CHIP8_WIDTH * CHIP8_HEIGHT` bytes row by row that are 1 for lit pixels and 0 otherwise.
+ *
+ * The pointer stays valid until the emulator is destroyed, and the contents change as it runs.
+ * Returns null if `emulator` is null.
+ *
+ * # Safety
+ * `emulator` must be null or valid.
+ */
+const uint8_t *chip8_framebuffer(const struct Chip8Emulator *emulator);
+
+/**
+ * Set how frames are blended to soften flicker, from a nul terminated string like `off`, `or:2` or `decay:0.5`
+ *
+ * # Safety
+ * `emulator` must be valid and `mode` must be a valid nul terminated string.
+ */
+enum Chip8Status chip8_set_deflicker(struct Chip8Emulator *emulator,
+                                     const char *mode);
+
+/**
+ * Get how bright each pixel should be shown after deflickering,
+ * `CHIP8_WIDTH * CHIP8_HEIGHT` bytes row by row from 0 to 255.
+ *
+ * The pointer stays valid until the emulator is destroyed, and the contents change as it runs.
+ * Returns null if `emulator` is null.
+ *
+ * # Safety
+ * `emulator` must be null or valid.
+ */
+const uint8_t *chip8_presentation(const struct Chip8Emulator *emulator);
+
+/**
+ * Check whether the buzzer is sounding
+ *
+ * # Safety
+ * `emulator` must be null or valid.
+ */
+bool chip8_sound_on(const struct Chip8Emulator *emulator);
+
+/**
+ * Get the number of bytes `chip8_save_state` needs
+ *
+ * # Safety
+ * `emulator` must be null or valid.
+ */
+size_t chip8_save_state_size(const struct Chip8Emulator *emulator);
+
+/**
+ * Save the emulator state into a buffer of at least `chip8_save_state_size` bytes
+ *
+ * # Safety
+ * `emulator` must be valid and `data` must point to `len` writable bytes.
+ */
+enum Chip8Status chip8_save_state(const struct Chip8Emulator *emulator, uint8_t *data, size_t len);
+
+/**
+ * Restore a state made by `chip8_save_state`. Nothing changes if the state is invalid.
+ *
+ * # Safety
+ * `emulator` must be valid and `data` must point to `len` readable bytes.
+ */
+enum Chip8Status chip8_load_state(struct Chip8Emulator *emulator, const uint8_t *data, size_t len);
+
+/**
+ * Describe a status as a static, nul terminated string
+ */
+const char *chip8_status_string(enum Chip8Status status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
@@ -0,0 +1,385 @@
+//! C bindings for chip8.
+//!
+//! The emulator is an opaque [`Chip8Emulator`] that is created with [`chip8_create`] and freed with [`chip8_destroy`].
+//! Functions that can fail return a [`Chip8Status`].
+//! A panic never unwinds into C: it is caught and reported as [`Chip8Status::Panicked`], or as the same value a null
+//! emulator gives for functions that don't return a status.
+//! The header is generated into `include/chip8.h` by cbindgen when the crate is built.
+
+use chip8::{
+    database::{
+        RomDatabase,
+        DEFAULT_TICKRATE,
+    },
+    deflicker::DeflickerMode,
+    Chip8Error,
+    Chip8ErrorKind,
+    Machine,
+    Quirks,
+};
+use std::{
+    ffi::CStr,
+    os::raw::c_char,
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    slice,
+};
+
+/// The width of the display in pixels
+pub const CHIP8_WIDTH: usize = 64;
+
+/// The height of the display in pixels
+pub const CHIP8_HEIGHT: usize = 32;
+
+/// The number of keys on the keypad
+pub const CHIP8_NUM_KEYS: usize = 16;
+
+const _: () = assert!(CHIP8_WIDTH == chip8::GFX_WIDTH && CHIP8_HEIGHT == chip8::GFX_HEIGHT);
+const _: () = assert!(CHIP8_NUM_KEYS == chip8::NUM_KEYS);
+
+/// The result of a call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Status {
+    Ok = 0,
+    /// A required pointer was null
+    NullPointer,
+    /// The rom does not fit in memory
+    InvalidProgramSize,
+    /// The program ran into an instruction that doesn't exist
+    UnknownInstruction,
+    InvalidReg,
+    InvalidAddress,
+    /// The program returned from a subroutine with an empty stack
+    StackUnderflow,
+    /// The program nested subroutines too deeply
+    StackOverflow,
+    /// The program counter left memory
+    ProgramCounterOutOfBounds,
+    /// The key is not on the keypad
+    InvalidKey,
+    /// The save state is damaged or from an incompatible version
+    InvalidState,
+    /// The buffer is too small for the save state
+    BufferTooSmall,
+    /// The deflicker mode isn't `off`, `or:<frames>` or `decay:<persistence>`
+    InvalidDeflickerMode,
+    /// The emulator hit a bug. It is in an unknown state and should be reset or destroyed.
+    Panicked,
+}
+
+impl From<Chip8Error> for Chip8Status {
+    fn from(e: Chip8Error) -> Self {
+        match e.kind() {
+            Chip8ErrorKind::InvalidProgramSize(_) => Chip8Status::InvalidProgramSize,
+            Chip8ErrorKind::UnknownInstruction(_) => Chip8Status::UnknownInstruction,
+            Chip8ErrorKind::InvalidReg(_) => Chip8Status::InvalidReg,
+            Chip8ErrorKind::InvalidAddress(_) => Chip8Status::InvalidAddress,
+            Chip8ErrorKind::InvalidKey(_) => Chip8Status::InvalidKey,
+            Chip8ErrorKind::StackUnderflow => Chip8Status::StackUnderflow,
+            Chip8ErrorKind::StackOverflow => Chip8Status::StackOverflow,
+            Chip8ErrorKind::ProgramCounterOutOfBounds(_) => Chip8Status::ProgramCounterOutOfBounds,
+        }
+    }
+}
+
+impl<T> From<Result<T, Chip8Error>> for Chip8Status {
+    fn from(result: Result<T, Chip8Error>) -> Self {
+        match result {
+            Ok(_) => Chip8Status::Ok,
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// A chip8 with a clock
+pub struct Chip8Emulator {
+    machine: Machine,
+    rom: Vec<u8>,
+}
+
+impl Chip8Emulator {
+    fn reset(&mut self) -> Result<(), Chip8Error> {
+        let chip8 = self.machine.chip8_mut();
+        chip8.init();
+        chip8.load(&self.rom)?;
+        self.machine.reset_clock();
+        Ok(())
+    }
+}
+
+/// Run the body of an entry point, returning `fallback` if it panics
+fn guard<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+/// Turn a pointer into a mutable reference, returning `NullPointer` from the calling function if it is null
+macro_rules! deref {
+    ($ptr:expr) => {
+        match $ptr.as_mut() {
+            Some(emulator) => emulator,
+            None => return Chip8Status::NullPointer,
+        }
+    };
+}
+
+/// Create an emulator with nothing loaded. Free it with `chip8_destroy`.
+#[no_mangle]
+pub extern "C" fn chip8_create() -> *mut Chip8Emulator {
+    guard(std::ptr::null_mut(), || {
+        let mut chip8 = chip8::Chip8::new();
+        chip8.init();
+        Box::into_raw(Box::new(Chip8Emulator {
+            machine: Machine::new(chip8),
+            rom: Vec::new(),
+        }))
+    })
+}
+
+/// Free an emulator. Passing null does nothing.
+///
+/// # Safety
+/// `emulator` must be null or come from `chip8_create`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_destroy(emulator: *mut Chip8Emulator) {
+    guard((), || {
+        if !emulator.is_null() {
+            drop(Box::from_raw(emulator));
+        }
+    })
+}
+
+/// Load a rom and start it, configuring quirks and speed if the rom is known.
+///
+/// # Safety
+/// `emulator` must be valid and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load(
+    emulator: *mut Chip8Emulator,
+    data: *const u8,
+    len: usize,
+) -> Chip8Status {
+    guard(Chip8Status::Panicked, || {
+        let emulator = deref!(emulator);
+        if data.is_null() {
+            return Chip8Status::NullPointer;
+        }
+        let rom = slice::from_raw_parts(data, len);
+        if rom.len() > chip8::MEMORY_SIZE - chip8::MEMORY_START {
+            return Chip8Status::InvalidProgramSize;
+        }
+
+        // Unknown roms get the defaults, not whatever the last rom used
+        let (quirks, tickrate) = match RomDatabase::bundled().lookup(rom) {
+            Some(info) => (info.quirks, info.tickrate),
+            None => (Quirks::default(), DEFAULT_TICKRATE),
+        };
+        emulator.machine.chip8_mut().set_quirks(quirks);
+        emulator.machine.set_tickrate(tickrate);
+        emulator.rom = rom.to_vec();
+        emulator.reset().into()
+    })
+}
+
+/// Restart the loaded rom
+///
+/// # Safety
+/// `emulator` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(emulator: *mut Chip8Emulator) -> Chip8Status {
+    guard(Chip8Status::Panicked, || deref!(emulator).reset().into())
+}
+
+/// Run for one 60hz frame
+///
+/// # Safety
+/// `emulator` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(emulator: *mut Chip8Emulator) -> Chip8Status {
+    guard(Chip8Status::Panicked, || {
+        deref!(emulator).machine.run_frame().into()
+    })
+}
+
+/// Press or release a key
+///
+/// # Safety
+/// `emulator` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(
+    emulator: *mut Chip8Emulator,
+    key: u8,
+    pressed: bool,
+) -> Chip8Status {
+    guard(Chip8Status::Panicked, || {
+        deref!(emulator)
+            .machine
+            .chip8_mut()
+            .set_key(usize::from(key), pressed)
+            .into()
+    })
+}
+
+/// Get the display, `CHIP8_WIDTH * CHIP8_HEIGHT` bytes row by row that are 1 for lit pixels and 0 otherwise.
+///
+/// The pointer stays valid until the emulator is destroyed, and the contents change as it runs.
+/// Returns null if `emulator` is null.
+///
+/// # Safety
+/// `emulator` must be null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(emulator: *const Chip8Emulator) -> *const u8 {
+    guard(std::ptr::null(), || {
+        match emulator.as_ref() {
+            // bool has the same layout as a u8 that is 0 or 1
+            Some(emulator) => emulator.machine.chip8().gfx.as_ptr().cast(),
+            None => std::ptr::null(),
+        }
+    })
+}
+
+/// Set how frames are blended to soften flicker, from a nul terminated string like `off`, `or:2` or `decay:0.5`
+///
+/// # Safety
+/// `emulator` must be valid and `mode` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_deflicker(
+    emulator: *mut Chip8Emulator,
+    mode: *const c_char,
+) -> Chip8Status {
+    guard(Chip8Status::Panicked, || {
+        let emulator = deref!(emulator);
+        if mode.is_null() {
+            return Chip8Status::NullPointer;
+        }
+        let mode = CStr::from_ptr(mode)
+            .to_str()
+            .ok()
+            .and_then(|mode| mode.parse::<DeflickerMode>().ok());
+        match mode {
+            Some(mode) => {
+                emulator.machine.set_deflicker_mode(mode);
+                Chip8Status::Ok
+            }
+            None => Chip8Status::InvalidDeflickerMode,
+        }
+    })
+}
+
+/// Get how bright each pixel should be shown after deflickering,
+/// `CHIP8_WIDTH * CHIP8_HEIGHT` bytes row by row from 0 to 255.
+///
+/// The pointer stays valid until the emulator is destroyed, and the contents change as it runs.
+/// Returns null if `emulator` is null.
+///
+/// # Safety
+/// `emulator` must be null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_presentation(emulator: *const Chip8Emulator) -> *const u8 {
+    guard(std::ptr::null(), || match emulator.as_ref() {
+        Some(emulator) => emulator.machine.presentation().as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+/// Check whether the buzzer is sounding
+///
+/// # Safety
+/// `emulator` must be null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_on(emulator: *const Chip8Emulator) -> bool {
+    guard(false, || {
+        emulator
+            .as_ref()
+            .is_some_and(|emulator| emulator.machine.chip8().is_sound_on())
+    })
+}
+
+/// Get the number of bytes `chip8_save_state` needs
+///
+/// # Safety
+/// `emulator` must be null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state_size(emulator: *const Chip8Emulator) -> usize {
+    guard(0, || {
+        emulator
+            .as_ref()
+            .map_or(0, |emulator| emulator.machine.save_state().len())
+    })
+}
+
+/// Save the emulator state into a buffer of at least `chip8_save_state_size` bytes
+///
+/// # Safety
+/// `emulator` must be valid and `data` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(
+    emulator: *const Chip8Emulator,
+    data: *mut u8,
+    len: usize,
+) -> Chip8Status {
+    guard(Chip8Status::Panicked, || {
+        let emulator = match emulator.as_ref() {
+            Some(emulator) => emulator,
+            None => return Chip8Status::NullPointer,
+        };
+        if data.is_null() {
+            return Chip8Status::NullPointer;
+        }
+        let state = emulator.machine.save_state();
+        if len < state.len() {
+            return Chip8Status::BufferTooSmall;
+        }
+        slice::from_raw_parts_mut(data, state.len()).copy_from_slice(&state);
+        Chip8Status::Ok
+    })
+}
+
+/// Restore a state made by `chip8_save_state`. Nothing changes if the state is invalid.
+///
+/// # Safety
+/// `emulator` must be valid and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(
+    emulator: *mut Chip8Emulator,
+    data: *const u8,
+    len: usize,
+) -> Chip8Status {
+    guard(Chip8Status::Panicked, || {
+        let emulator = deref!(emulator);
+        if data.is_null() {
+            return Chip8Status::NullPointer;
+        }
+        match emulator
+            .machine
+            .load_state(slice::from_raw_parts(data, len))
+        {
+            Ok(()) => Chip8Status::Ok,
+            Err(_) => Chip8Status::InvalidState,
+        }
+    })
+}
+
+/// Describe a status as a static, nul terminated string
+#[no_mangle]
+pub extern "C" fn chip8_status_string(status: Chip8Status) -> *const c_char {
+    let message: &'static str = match status {
+        Chip8Status::Ok => "ok\0",
+        Chip8Status::NullPointer => "null pointer\0",
+        Chip8Status::InvalidProgramSize => "rom does not fit in memory\0",
+        Chip8Status::UnknownInstruction => "unknown instruction\0",
+        Chip8Status::InvalidReg => "invalid register\0",
+        Chip8Status::InvalidAddress => "invalid address\0",
+        Chip8Status::StackUnderflow => "stack underflow\0",
+        Chip8Status::StackOverflow => "stack overflow\0",
+        Chip8Status::ProgramCounterOutOfBounds => "program counter out of bounds\0",
+        Chip8Status::InvalidKey => "invalid key\0",
+        Chip8Status::InvalidState => "invalid save state\0",
+        Chip8Status::BufferTooSmall => "buffer too small\0",
+        Chip8Status::InvalidDeflickerMode => "invalid deflicker mode\0",
+        Chip8Status::Panicked => "emulator panicked\0",
+    };
+    message.as_ptr().cast()
+}
@@ -0,0 +1,94 @@
+#include "chip8.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define CHECK(call)                                                                            \
+    do {                                                                                       \
+        Chip8Status status = (call);                                                           \
+        if (status != CHIP8_STATUS_OK) {                                                       \
+            fprintf(stderr, "%s:%d: %s: %s\n", __FILE__, __LINE__, #call,                      \
+                    chip8_status_string(status));                                              \
+            return 1;                                                                          \
+        }                                                                                      \
+    } while (0)
+
+#define EXPECT(cond)                                                                           \
+    do {                                                                                       \
+        if (!(cond)) {                                                                         \
+            fprintf(stderr, "%s:%d: expected %s\n", __FILE__, __LINE__, #cond);                \
+            return 1;                                                                          \
+        }                                                                                      \
+    } while (0)
+
+static int count_pixels(const uint8_t *framebuffer) {
+    int count = 0;
+    for (int i = 0; i < CHIP8_WIDTH * CHIP8_HEIGHT; i++) {
+        count += framebuffer[i];
+    }
+    return count;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <rom>\n", argv[0]);
+        return 2;
+    }
+
+    FILE *file = fopen(argv[1], "rb");
+    EXPECT(file != NULL);
+    uint8_t rom[4096];
+    size_t rom_len = fread(rom, 1, sizeof(rom), file);
+    fclose(file);
+
+    Chip8Emulator *emulator = chip8_create();
+    EXPECT(emulator != NULL);
+    CHECK(chip8_load(emulator, rom, rom_len));
+    CHECK(chip8_set_key(emulator, 1, true));
+    EXPECT(chip8_set_key(emulator, 16, true) == CHIP8_STATUS_INVALID_KEY);
+
+    for (int i = 0; i < 30; i++) {
+        CHECK(chip8_run_frame(emulator));
+    }
+    const uint8_t *framebuffer = chip8_framebuffer(emulator);
+    EXPECT(count_pixels(framebuffer) > 0);
+
+    EXPECT(chip8_set_deflicker(emulator, "blend") == CHIP8_STATUS_INVALID_DEFLICKER_MODE);
+    CHECK(chip8_set_deflicker(emulator, "or:2"));
+    CHECK(chip8_run_frame(emulator));
+    const uint8_t *presentation = chip8_presentation(emulator);
+    for (int i = 0; i < CHIP8_WIDTH * CHIP8_HEIGHT; i++) {
+        EXPECT(!framebuffer[i] || presentation[i] == 255);
+    }
+
+    size_t state_len = chip8_save_state_size(emulator);
+    uint8_t *state = malloc(state_len);
+    EXPECT(chip8_save_state(emulator, state, state_len - 1) == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    CHECK(chip8_save_state(emulator, state, state_len));
+
+    uint8_t expected[CHIP8_WIDTH * CHIP8_HEIGHT];
+    for (int i = 0; i < 60; i++) {
+        CHECK(chip8_run_frame(emulator));
+    }
+    memcpy(expected, framebuffer, sizeof(expected));
+
+    CHECK(chip8_load_state(emulator, state, state_len));
+    for (int i = 0; i < 60; i++) {
+        CHECK(chip8_run_frame(emulator));
+    }
+    EXPECT(memcmp(expected, framebuffer, sizeof(expected)) == 0);
+
+    state[0] ^= 0xFF;
+    EXPECT(chip8_load_state(emulator, state, state_len) == CHIP8_STATUS_INVALID_STATE);
+    free(state);
+
+    CHECK(chip8_reset(emulator));
+    EXPECT(count_pixels(framebuffer) == 0);
+    (void)chip8_sound_on(emulator);
+
+    EXPECT(strcmp(chip8_status_string(CHIP8_STATUS_PANICKED), "emulator panicked") == 0);
+    chip8_destroy(emulator);
+    printf("ok\n");
+    return 0;
+}
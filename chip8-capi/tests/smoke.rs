@@ -0,0 +1,45 @@
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+};
+
+/// The directory cargo put the static library in, which is the one the test runs from
+fn library_dir() -> PathBuf {
+    let mut dir = std::env::current_exe().unwrap();
+    dir.pop();
+    dir
+}
+
+#[test]
+fn c_smoke_test() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let exe = Path::new(env!("CARGO_TARGET_TMPDIR")).join("chip8-smoke");
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".into());
+
+    let status = Command::new(&cc)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg(crate_dir.join("tests/smoke.c"))
+        .arg(library_dir().join("libchip8_capi.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run '{}': {}", cc, e));
+    assert!(status.success(), "compiling the smoke test failed");
+
+    let output = Command::new(&exe)
+        .arg(crate_dir.join("../pong.c8"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"ok\n");
+}
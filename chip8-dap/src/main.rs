@@ -0,0 +1,37 @@
+mod protocol;
+mod session;
+
+use crate::session::Session;
+use std::{
+    io,
+    sync::mpsc,
+};
+
+fn main() {
+    // Requests are read on their own thread so a running program can be paused
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        loop {
+            match protocol::read_message(&mut stdin) {
+                Ok(Some(message)) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Failed to read a message: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let stdout = io::stdout();
+    if let Err(e) = Session::new(stdout.lock()).run(receiver) {
+        eprintln!("Failed to write a message: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,758 @@
+//! A debugging session for one rom.
+//!
+//! Requests are handled one at a time. While the program runs, it is executed in slices
+//! so pause requests and breakpoint changes are noticed quickly.
+
+use crate::protocol::{
+    base64,
+    Sender,
+};
+use chip8::{
+    analysis::{
+        Analysis,
+        ByteKind,
+    },
+    database::RomDatabase,
+    rom::Rom,
+    source_map::{
+        Line,
+        SourceMap,
+    },
+    CallFrame,
+    Chip8,
+    Instruction,
+    Machine,
+    MEMORY_SIZE,
+    NUM_REGISTERS,
+    OPCODE_SIZE,
+};
+use serde::Deserialize;
+use serde_json::{
+    json,
+    Value,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{
+        self,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::mpsc::{
+        Receiver,
+        TryRecvError,
+    },
+};
+
+/// A chip8 only has one thread
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REFERENCE: u64 = 1;
+const TIMERS_REFERENCE: u64 = 2;
+const MEMORY_REFERENCE: u64 = 3;
+
+/// The number of bytes in each row of the memory view
+const MEMORY_ROW: usize = 16;
+
+/// How many instructions run between checks for new requests
+const SLICE: u32 = 4096;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchArguments {
+    /// The rom
+    program: PathBuf,
+    source_map: Option<PathBuf>,
+    #[serde(default)]
+    stop_on_entry: bool,
+    /// Instructions per frame, defaulting to the rom database's tick rate
+    tickrate: Option<u32>,
+    #[serde(default)]
+    seed: u64,
+}
+
+struct Breakpoint {
+    id: u64,
+    addr: u16,
+}
+
+/// Why the program is running
+enum Run {
+    Continue,
+    /// Run until the program reaches a different line, or for one instruction if `line` is `None`.
+    /// When stepping over, calls deeper than `depth` run to completion.
+    Step {
+        over: bool,
+        depth: usize,
+        line: Option<Line>,
+    },
+    /// Run until the call stack is shallower than `depth`
+    StepOut {
+        depth: usize,
+    },
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsDisassembleRequest": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsSteppingGranularity": true,
+        "supportsReadMemoryRequest": true,
+        "supportsSetVariable": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+/// Format an address the way it is shown and referenced
+fn address(addr: u16) -> String {
+    format!("0x{:03X}", addr)
+}
+
+/// Parse a memory or instruction reference, in hex with a `0x` prefix or in decimal
+fn parse_address(reference: &str) -> Option<i64> {
+    match reference
+        .strip_prefix("0x")
+        .or_else(|| reference.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => reference.parse().ok(),
+    }
+}
+
+/// Parse the value of a register, in hex with a `0x` prefix or in decimal
+fn parse_value(value: &str) -> Option<u16> {
+    parse_address(value.trim()).and_then(|value| u16::try_from(value).ok())
+}
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args[name]
+        .as_str()
+        .ok_or_else(|| format!("missing argument '{}'", name))
+}
+
+pub struct Session<W> {
+    sender: Sender<W>,
+    /// Events to send after the response to the current request
+    events: Vec<(&'static str, Value)>,
+
+    machine: Option<Machine>,
+    source_map: Option<SourceMap>,
+    /// Tells the rom's sprites and data apart from its code when disassembling
+    analysis: Option<Analysis>,
+    stop_on_entry: bool,
+    running: Option<Run>,
+
+    /// Breakpoints set on lines, by file index in the source map
+    source_breakpoints: HashMap<usize, Vec<Breakpoint>>,
+    instruction_breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: u64,
+
+    done: bool,
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(writer: W) -> Self {
+        Session {
+            sender: Sender::new(writer),
+            events: Vec::new(),
+            machine: None,
+            source_map: None,
+            analysis: None,
+            stop_on_entry: false,
+            running: None,
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            done: false,
+        }
+    }
+
+    /// Handle requests until the client disconnects
+    pub fn run(mut self, requests: Receiver<Value>) -> io::Result<()> {
+        while !self.done {
+            let request = if self.running.is_some() {
+                match requests.try_recv() {
+                    Ok(request) => request,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                }
+            };
+            self.handle(&request)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        if request["type"] != "request" {
+            return Ok(());
+        }
+
+        let args = &request["arguments"];
+        let result = match request["command"].as_str().unwrap_or_default() {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "chip8" }] })),
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => Ok(Self::scopes()),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "continue" => self.resume(Run::Continue),
+            "next" => self.step(args, true),
+            "stepIn" => self.step(args, false),
+            "stepOut" => self.step_out(),
+            "pause" => self.pause(),
+            "disassemble" => self.disassemble(args),
+            "readMemory" => self.read_memory(args),
+            "terminate" => {
+                self.running = None;
+                self.events.push(("terminated", json!({})));
+                Ok(json!({}))
+            }
+            "disconnect" => {
+                self.done = true;
+                Ok(json!({}))
+            }
+            command => Err(format!("unsupported request '{}'", command)),
+        };
+
+        match result {
+            Ok(body) => self.sender.respond(request, body)?,
+            Err(message) => self.sender.fail(request, &message)?,
+        }
+        self.flush_events()
+    }
+
+    fn flush_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.sender.event(event, body)?;
+        }
+        Ok(())
+    }
+
+    fn machine(&self) -> Result<&Machine, String> {
+        self.machine
+            .as_ref()
+            .ok_or_else(|| String::from("no program is running"))
+    }
+
+    fn machine_mut(&mut self) -> Result<&mut Machine, String> {
+        self.machine
+            .as_mut()
+            .ok_or_else(|| String::from("no program is running"))
+    }
+
+    /// Stop the program, telling the client why
+    fn stop(&mut self, reason: &str, description: Option<String>, hits: Vec<u64>) {
+        self.running = None;
+
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        if !hits.is_empty() {
+            body["hitBreakpointIds"] = json!(hits);
+        }
+        self.events.push(("stopped", body));
+    }
+
+    /// Get the file and line an address was assembled from
+    fn source(&self, addr: u16) -> Option<(Value, u32)> {
+        let map = self.source_map.as_ref()?;
+        let line = map.line(addr)?;
+        let path = map.file_path(line.file);
+        let path = path.canonicalize().unwrap_or(path);
+        let source = json!({
+            "name": path.file_name().map(|name| name.to_string_lossy()),
+            "path": path,
+        });
+        Some((source, line.line))
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let args: LaunchArguments =
+            serde_json::from_value(args.clone()).map_err(|e| e.to_string())?;
+
+        let rom = Rom::open(&args.program, &RomDatabase::bundled())
+            .map_err(|e| format!("failed to read '{}': {}", args.program.display(), e))?;
+        let mut chip8 = Chip8::new();
+        chip8.init();
+        rom.load_into(&mut chip8)
+            .map_err(|e| format!("failed to load '{}': {}", args.program.display(), e))?;
+        chip8.seed_rng(args.seed);
+
+        let mut machine = Machine::new(chip8);
+        machine.set_tickrate(args.tickrate.unwrap_or(rom.tickrate));
+
+        self.source_map = args
+            .source_map
+            .map(|path| {
+                SourceMap::load(&path)
+                    .map_err(|e| format!("failed to load source map '{}': {}", path.display(), e))
+            })
+            .transpose()?
+            .or(rom.source_map);
+        self.analysis = Some(Analysis::with_load_address(&rom.data, rom.load_address));
+        self.machine = Some(machine);
+        self.stop_on_entry = args.stop_on_entry;
+        self.source_breakpoints.clear();
+        self.instruction_breakpoints.clear();
+
+        // The client sends breakpoints once it knows the program is loaded
+        self.events.push(("initialized", json!({})));
+        Ok(json!({}))
+    }
+
+    fn add_breakpoint(&mut self, addr: u16) -> Breakpoint {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        Breakpoint { id, addr }
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = string_arg(&args["source"], "path")?;
+        let file = self
+            .source_map
+            .as_ref()
+            .and_then(|map| map.find_file(Path::new(path)));
+
+        let mut breakpoints = Vec::new();
+        let mut reply = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as u32;
+            let resolved = file.and_then(|file| self.source_map.as_ref()?.resolve_line(file, line));
+
+            match resolved {
+                Some((resolved, addr)) => {
+                    let breakpoint = self.add_breakpoint(addr);
+                    reply.push(json!({
+                        "id": breakpoint.id,
+                        "verified": true,
+                        "line": resolved.line,
+                        "source": args["source"],
+                        "instructionReference": address(addr),
+                    }));
+                    breakpoints.push(breakpoint);
+                }
+                None => reply.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": if file.is_some() {
+                        "no code at or after this line"
+                    } else {
+                        "this file is not in the source map"
+                    },
+                })),
+            }
+        }
+
+        if let Some(file) = file {
+            self.source_breakpoints.insert(file, breakpoints);
+        }
+        Ok(json!({ "breakpoints": reply }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut breakpoints = Vec::new();
+        let mut reply = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let addr = requested["instructionReference"]
+                .as_str()
+                .and_then(parse_address)
+                .map(|addr| addr + requested["offset"].as_i64().unwrap_or(0))
+                .filter(|&addr| 0 <= addr && addr < MEMORY_SIZE as i64);
+
+            match addr {
+                Some(addr) => {
+                    let breakpoint = self.add_breakpoint(addr as u16);
+                    let mut bp = json!({
+                        "id": breakpoint.id,
+                        "verified": true,
+                        "instructionReference": address(breakpoint.addr),
+                    });
+                    if let Some((source, line)) = self.source(breakpoint.addr) {
+                        bp["source"] = source;
+                        bp["line"] = json!(line);
+                    }
+                    reply.push(bp);
+                    breakpoints.push(breakpoint);
+                }
+                None => reply.push(json!({
+                    "verified": false,
+                    "message": "the address is outside of memory",
+                })),
+            }
+        }
+
+        self.instruction_breakpoints = breakpoints;
+        Ok(json!({ "breakpoints": reply }))
+    }
+
+    /// The ids of the breakpoints at an address
+    fn breakpoints_at(&self, addr: u16) -> Vec<u64> {
+        self.source_breakpoints
+            .values()
+            .flatten()
+            .chain(self.instruction_breakpoints.iter())
+            .filter(|breakpoint| breakpoint.addr == addr)
+            .map(|breakpoint| breakpoint.id)
+            .collect()
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        self.machine()?;
+        if self.stop_on_entry {
+            self.stop("entry", None, Vec::new());
+        } else {
+            self.running = Some(Run::Continue);
+        }
+        Ok(json!({}))
+    }
+
+    fn stack_trace(&self, args: &Value) -> Result<Value, String> {
+        let chip8 = self.machine()?.chip8();
+
+        // The innermost frame is at PC, and every caller is at its call
+        let calls: Vec<CallFrame> = chip8.call_stack().iter().rev().copied().collect();
+        let addrs: Vec<u16> = std::iter::once(chip8.pc())
+            .chain(calls.iter().map(|call| call.call_site))
+            .collect();
+
+        let mut frames = Vec::new();
+        for (id, &addr) in addrs.iter().enumerate() {
+            let symbol = self.source_map.as_ref().and_then(|map| map.symbol(addr));
+            let name = match (symbol, calls.get(id)) {
+                (Some((label, 0)), _) => label.to_string(),
+                (Some((label, offset)), _) => format!("{}+{}", label, offset),
+                (None, Some(call)) => format!("sub_{:03X}", call.target),
+                (None, None) => String::from("main"),
+            };
+
+            let mut frame = json!({
+                "id": id,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": address(addr),
+            });
+            if let Some((source, line)) = self.source(addr) {
+                frame["source"] = source;
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+            }
+            frames.push(frame);
+        }
+
+        let total = frames.len();
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64().unwrap_or(0) as usize {
+            0 => total,
+            levels => levels,
+        };
+        let frames: Vec<Value> = frames.into_iter().skip(start).take(levels).collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": total }))
+    }
+
+    /// Registers are shared by every frame
+    fn scopes() -> Value {
+        json!({
+            "scopes": [
+                {
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": REGISTERS_REFERENCE,
+                    "expensive": false,
+                },
+                {
+                    "name": "Timers",
+                    "variablesReference": TIMERS_REFERENCE,
+                    "expensive": false,
+                },
+                {
+                    "name": "Memory",
+                    "variablesReference": MEMORY_REFERENCE,
+                    "indexedVariables": MEMORY_SIZE / MEMORY_ROW,
+                    "expensive": true,
+                },
+            ],
+        })
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let chip8 = self.machine()?.chip8();
+        let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let variables = match args["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => {
+                let mut variables: Vec<Value> = chip8
+                    .registers()
+                    .iter()
+                    .enumerate()
+                    .map(|(reg, value)| {
+                        variable(&format!("V{:X}", reg), format!("0x{:02X}", value))
+                    })
+                    .collect();
+                for (name, value) in [("I", chip8.i()), ("PC", chip8.pc())] {
+                    let mut register = variable(name, address(value));
+                    register["memoryReference"] = json!(address(value));
+                    variables.push(register);
+                }
+                variables.push(variable("SP", chip8.sp().to_string()));
+                variables
+            }
+            Some(TIMERS_REFERENCE) => vec![
+                variable("DT", chip8.delay_timer().to_string()),
+                variable("ST", chip8.sound_timer().to_string()),
+            ],
+            Some(MEMORY_REFERENCE) => {
+                let rows = MEMORY_SIZE / MEMORY_ROW;
+                let start = (args["start"].as_u64().unwrap_or(0) as usize).min(rows);
+                let count = match args["count"].as_u64().unwrap_or(0) as usize {
+                    0 => rows,
+                    count => count,
+                };
+                chip8
+                    .memory()
+                    .chunks(MEMORY_ROW)
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(row, bytes)| {
+                        let addr = address((row * MEMORY_ROW) as u16);
+                        let value = bytes
+                            .iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        let mut row = variable(&addr, value);
+                        row["memoryReference"] = json!(addr);
+                        row
+                    })
+                    .collect()
+            }
+            _ => return Err(String::from("unknown variables reference")),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = string_arg(args, "name")?;
+        let value = string_arg(args, "value")?;
+        let value = parse_value(value).ok_or_else(|| format!("'{}' is not a number", value))?;
+        let byte = u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", name));
+
+        let chip8 = self.machine_mut()?.chip8_mut();
+        let shown = match (args["variablesReference"].as_u64(), name) {
+            (Some(REGISTERS_REFERENCE), "I") => {
+                chip8.set_i(value);
+                address(value)
+            }
+            (Some(REGISTERS_REFERENCE), "PC") => {
+                chip8.set_pc(value).map_err(|e| e.to_string())?;
+                address(value)
+            }
+            (Some(REGISTERS_REFERENCE), "SP") => {
+                return Err(String::from("SP can't be changed"));
+            }
+            (Some(REGISTERS_REFERENCE), name) => {
+                let reg = name
+                    .strip_prefix('V')
+                    .and_then(|reg| u8::from_str_radix(reg, 16).ok())
+                    .filter(|&reg| usize::from(reg) < NUM_REGISTERS)
+                    .ok_or_else(|| format!("unknown register '{}'", name))?;
+                let byte = byte?;
+                chip8.set_reg(reg, byte).map_err(|e| e.to_string())?;
+                format!("0x{:02X}", byte)
+            }
+            (Some(TIMERS_REFERENCE), "DT") => {
+                let byte = byte?;
+                chip8.set_delay_timer(byte);
+                byte.to_string()
+            }
+            (Some(TIMERS_REFERENCE), "ST") => {
+                let byte = byte?;
+                chip8.set_sound_timer(byte);
+                byte.to_string()
+            }
+            _ => return Err(format!("'{}' can't be changed", name)),
+        };
+        Ok(json!({ "value": shown }))
+    }
+
+    fn resume(&mut self, run: Run) -> Result<Value, String> {
+        self.machine()?;
+        self.running = Some(run);
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn step(&mut self, args: &Value, over: bool) -> Result<Value, String> {
+        let chip8 = self.machine()?.chip8();
+        let line = if args["granularity"] == "instruction" {
+            None
+        } else {
+            self.source_map
+                .as_ref()
+                .and_then(|map| map.line(chip8.pc()))
+        };
+        let depth = chip8.call_stack().len();
+        self.resume(Run::Step { over, depth, line })
+    }
+
+    fn step_out(&mut self) -> Result<Value, String> {
+        let depth = self.machine()?.chip8().call_stack().len();
+        self.resume(Run::StepOut { depth })
+    }
+
+    fn pause(&mut self) -> Result<Value, String> {
+        self.machine()?;
+        if self.running.is_some() {
+            self.stop("pause", None, Vec::new());
+        }
+        Ok(json!({}))
+    }
+
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let chip8 = self.machine()?.chip8();
+        let base = parse_address(string_arg(args, "memoryReference")?)
+            .ok_or_else(|| String::from("invalid memory reference"))?
+            + args["offset"].as_i64().unwrap_or(0)
+            + args["instructionOffset"].as_i64().unwrap_or(0) * i64::from(OPCODE_SIZE);
+        let count = args["instructionCount"].as_u64().unwrap_or(0) as i64;
+
+        let instructions: Vec<Value> = (0..count)
+            .map(|i| base + i * i64::from(OPCODE_SIZE))
+            .map(|addr| {
+                if addr < 0 || addr + i64::from(OPCODE_SIZE) > MEMORY_SIZE as i64 {
+                    return json!({
+                        "address": address(addr.clamp(0, MEMORY_SIZE as i64) as u16),
+                        "instruction": "??",
+                        "presentationHint": "invalid",
+                    });
+                }
+
+                let addr = addr as u16;
+                let memory = chip8.memory();
+                let bytes = &memory[usize::from(addr)..usize::from(addr + OPCODE_SIZE)];
+                let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+                let text = match self.analysis.as_ref().map(|a| a.byte_kind(addr)) {
+                    Some(ByteKind::Sprite) => String::from("sprite"),
+                    Some(ByteKind::Data) => String::from("data"),
+                    _ => Instruction::from(opcode).to_string(),
+                };
+                let mut instruction = json!({
+                    "address": address(addr),
+                    "instructionBytes": format!("{:02X} {:02X}", bytes[0], bytes[1]),
+                    "instruction": text,
+                });
+                if let Some((source, line)) = self.source(addr) {
+                    instruction["location"] = source;
+                    instruction["line"] = json!(line);
+                }
+                instruction
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let memory = self.machine()?.chip8().memory();
+        let start = parse_address(string_arg(args, "memoryReference")?)
+            .ok_or_else(|| String::from("invalid memory reference"))?
+            + args["offset"].as_i64().unwrap_or(0);
+        let count = args["count"].as_u64().unwrap_or(0) as i64;
+
+        let readable_start = start.clamp(0, MEMORY_SIZE as i64);
+        let readable_end = (start + count).clamp(readable_start, MEMORY_SIZE as i64);
+        let data = &memory[readable_start as usize..readable_end as usize];
+        Ok(json!({
+            "address": address(readable_start as u16),
+            "data": base64(data),
+            "unreadableBytes": count - data.len() as i64,
+        }))
+    }
+
+    /// Run the program for a while, stopping on breakpoints, errors and finished steps
+    fn run_slice(&mut self) -> io::Result<()> {
+        for _ in 0..SLICE {
+            let machine = match self.machine.as_mut() {
+                Some(machine) => machine,
+                None => break,
+            };
+            if let Err(e) = machine.step() {
+                let description = match self.source_map.as_ref() {
+                    Some(map) => format!("{} at {}", e.kind(), map.describe(machine.chip8().pc())),
+                    None => e.to_string(),
+                };
+                self.stop("exception", Some(description), Vec::new());
+                break;
+            }
+
+            let pc = machine.chip8().pc();
+            let hits = self.breakpoints_at(pc);
+            if !hits.is_empty() {
+                self.stop("breakpoint", None, hits);
+                break;
+            }
+            if self.step_done() {
+                self.stop("step", None, Vec::new());
+                break;
+            }
+        }
+        self.flush_events()
+    }
+
+    /// Check whether a step has finished
+    fn step_done(&self) -> bool {
+        let chip8 = match self.machine.as_ref() {
+            Some(machine) => machine.chip8(),
+            None => return true,
+        };
+        let depth = chip8.call_stack().len();
+
+        match self.running {
+            Some(Run::Continue) | None => false,
+            Some(Run::Step {
+                over,
+                depth: start_depth,
+                line,
+            }) => {
+                if over && depth > start_depth {
+                    return false;
+                }
+                match line {
+                    Some(line) => {
+                        depth != start_depth
+                            || self
+                                .source_map
+                                .as_ref()
+                                .and_then(|map| map.line(chip8.pc()))
+                                != Some(line)
+                    }
+                    None => true,
+                }
+            }
+            Some(Run::StepOut { depth: start_depth }) => depth < start_depth,
+        }
+    }
+}
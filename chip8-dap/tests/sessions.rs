@@ -0,0 +1,147 @@
+//! Replays recorded sessions from `tests/sessions`.
+//!
+//! A session is a list of steps. `send` steps are requests, which get their `seq` and `type` filled in.
+//! `expect` steps match the next message from the adapter, ignoring fields that aren't in the recording.
+//! `${roms}` in strings is replaced with the path of the roms directory.
+
+use serde_json::{
+    json,
+    Value,
+};
+use std::{
+    io::{
+        BufRead,
+        BufReader,
+        Read,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        ChildStdout,
+        Command,
+        Stdio,
+    },
+};
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn substitute(value: &mut Value) {
+    match value {
+        Value::String(string) => {
+            let roms = manifest_dir().join("../roms");
+            *string = string.replace("${roms}", &roms.display().to_string());
+        }
+        Value::Array(values) => values.iter_mut().for_each(substitute),
+        Value::Object(values) => values.values_mut().for_each(substitute),
+        _ => {}
+    }
+}
+
+/// Check that every field of `expected` is in `actual`
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|actual| matches(value, actual))),
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual.iter())
+                    .all(|(expected, actual)| matches(expected, actual))
+        }
+        (Value::String(expected), Value::String(actual)) => {
+            // Paths are compared after resolving symlinks and `..`
+            expected == actual
+                || Path::new(expected).canonicalize().ok() == Some(PathBuf::from(actual))
+        }
+        _ => expected == actual,
+    }
+}
+
+fn read_message(reader: &mut BufReader<ChildStdout>) -> Value {
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        assert_ne!(
+            reader.read_line(&mut line).unwrap(),
+            0,
+            "the adapter exited"
+        );
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn replay(path: &Path) {
+    let mut steps: Vec<Value> = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+    steps.iter_mut().for_each(substitute);
+
+    let mut adapter = Command::new(env!("CARGO_BIN_EXE_chip8-dap"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = adapter.stdin.take().unwrap();
+    let mut stdout = BufReader::new(adapter.stdout.take().unwrap());
+
+    let mut seq = 0;
+    for (i, step) in steps.into_iter().enumerate() {
+        if let Some(request) = step.get("send") {
+            seq += 1;
+            let mut request = request.clone();
+            request["seq"] = json!(seq);
+            request["type"] = json!("request");
+            let body = request.to_string();
+            write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+            stdin.flush().unwrap();
+        } else if let Some(expected) = step.get("expect") {
+            let actual = read_message(&mut stdout);
+            assert!(
+                matches(expected, &actual),
+                "{}: step {} expected\n{:#}\nbut got\n{:#}",
+                path.display(),
+                i,
+                expected,
+                actual
+            );
+        } else {
+            panic!(
+                "{}: step {} is neither sent nor expected",
+                path.display(),
+                i
+            );
+        }
+    }
+
+    drop(stdin);
+    assert!(adapter.wait().unwrap().success());
+}
+
+#[test]
+fn recorded_sessions() {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(manifest_dir().join("tests/sessions"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        replay(&path);
+    }
+}
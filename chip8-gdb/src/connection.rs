@@ -0,0 +1,171 @@
+//! Packet framing for the GDB remote serial protocol.
+//!
+//! Packets look like `$data#cs`, where `cs` is the sum of the data bytes as two hex digits.
+//! Until the client turns them off, every packet is acknowledged with `+`, or `-` to ask for it again.
+//! A lone `0x03` byte outside of a packet asks a running program to stop.
+
+use std::{
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Write,
+    },
+    net::TcpStream,
+};
+
+const INTERRUPT: u8 = 0x03;
+const ESCAPE: u8 = b'}';
+
+/// Something a client sent
+#[derive(Debug, PartialEq, Eq)]
+pub enum Incoming {
+    Packet(Vec<u8>),
+    Interrupt,
+}
+
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Whether packets are acknowledged
+    ack: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            ack: true,
+        })
+    }
+
+    /// Stop acknowledging packets, after the client asked for it with `QStartNoAckMode`
+    pub fn disable_ack(&mut self) {
+        self.ack = false;
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = match self.reader.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        self.reader.consume(1);
+        Ok(Some(byte))
+    }
+
+    fn expect_byte(&mut self) -> io::Result<u8> {
+        self.read_byte()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "client disconnected"))
+    }
+
+    /// Wait for the next packet or interrupt. Returns `None` once the client disconnects.
+    pub fn receive(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Incoming::Interrupt)),
+                Some(b'$') => {}
+                // Acknowledgements and noise between packets
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                let byte = self.expect_byte()?;
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                data.push(byte);
+            }
+            let checksum = [self.expect_byte()?, self.expect_byte()?];
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(sum);
+
+            if self.ack {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Incoming::Packet(unescape(&data))));
+            }
+        }
+    }
+
+    /// Send a packet, waiting for the client to acknowledge it
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data {
+            if matches!(byte, b'$' | b'#' | b'*' | ESCAPE) {
+                packet.push(ESCAPE);
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let sum = packet[1..]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        write!(packet, "#{:02x}", sum)?;
+
+        loop {
+            self.writer.write_all(&packet)?;
+            if !self.ack {
+                return Ok(());
+            }
+            loop {
+                match self.expect_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// Check for an interrupt without waiting
+    pub fn poll_interrupt(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = self.reader.fill_buf().map(|buffer| buffer.is_empty());
+            self.reader.get_ref().set_nonblocking(false)?;
+            match result {
+                Ok(true) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "client disconnected",
+                    ))
+                }
+                Ok(false) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Clients only send interrupts while the program runs
+        let interrupted = self.reader.buffer().contains(&INTERRUPT);
+        let len = self.reader.buffer().len();
+        self.reader.consume(len);
+        Ok(interrupted)
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == ESCAPE {
+            if let Some(&escaped) = bytes.next() {
+                unescaped.push(escaped ^ 0x20);
+            }
+        } else {
+            unescaped.push(byte);
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,112 @@
+mod connection;
+mod server;
+
+use crate::{
+    connection::Connection,
+    server::Server,
+};
+use chip8::{
+    database::RomDatabase,
+    rom::Rom,
+    source_map::SourceMap,
+    Chip8,
+    Machine,
+};
+use std::{
+    io::Write,
+    net::TcpListener,
+    path::PathBuf,
+};
+
+#[derive(argh::FromArgs)]
+/// Debug a chip8 rom with GDB or any other client of the GDB remote serial protocol.
+///
+/// The server listens on localhost and serves a single client.
+struct Options {
+    /// the rom to debug
+    #[argh(positional)]
+    rom: PathBuf,
+
+    /// the port to listen on, or 0 to pick a free one
+    #[argh(option, default = "1234")]
+    port: u16,
+
+    /// instructions per frame, defaulting to the rom database's tick rate
+    #[argh(option)]
+    tickrate: Option<u32>,
+
+    /// the seed for random numbers
+    #[argh(option, default = "0")]
+    seed: u64,
+
+    /// a source map, to show where the program stops in its source
+    #[argh(option)]
+    source_map: Option<PathBuf>,
+}
+
+fn main() {
+    let options: Options = argh::from_env();
+
+    let rom = match Rom::open(&options.rom, &RomDatabase::bundled()) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Failed to read '{}': {}", options.rom.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut chip8 = Chip8::new();
+    chip8.init();
+    if let Err(e) = rom.load_into(&mut chip8) {
+        eprintln!("Failed to load '{}': {}", options.rom.display(), e);
+        std::process::exit(1);
+    }
+    chip8.seed_rng(options.seed);
+
+    let mut machine = Machine::new(chip8);
+    machine.set_tickrate(options.tickrate.unwrap_or(rom.tickrate));
+
+    let source_map = options
+        .source_map
+        .as_ref()
+        .map(|path| match SourceMap::load(path) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("Failed to load source map '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        })
+        .or(rom.source_map);
+
+    let listener = match TcpListener::bind(("127.0.0.1", options.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on port {}: {}", options.port, e);
+            std::process::exit(1);
+        }
+    };
+    match listener.local_addr() {
+        Ok(addr) => println!("listening on {}", addr),
+        Err(e) => {
+            eprintln!("Failed to get the listening address: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let _ = std::io::stdout().flush();
+
+    let result = listener
+        .accept()
+        .and_then(|(stream, _)| Connection::new(stream))
+        .and_then(|connection| {
+            let server = Server::new(machine, connection);
+            match source_map {
+                Some(map) => server.with_source_map(map),
+                None => server,
+            }
+            .run()
+        });
+    if let Err(e) = result {
+        eprintln!("Connection failed: {}", e);
+        std::process::exit(1);
+    }
+}
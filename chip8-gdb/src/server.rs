@@ -0,0 +1,442 @@
+//! The debugger side of the GDB remote serial protocol.
+//!
+//! Registers are numbered V0 to VF, then I, PC, SP, DT and ST, as described by [`target_xml`].
+//! Memory is the 4K address space of the chip8.
+
+use crate::connection::{
+    Connection,
+    Incoming,
+};
+use chip8::{
+    source_map::SourceMap,
+    Chip8Error,
+    Chip8ErrorKind,
+    Machine,
+    MEMORY_SIZE,
+    NUM_REGISTERS,
+};
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    fmt::Write as _,
+    io,
+};
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// The register number of PC
+const PC_REGISTER: usize = NUM_REGISTERS + 1;
+
+/// Names and sizes in bytes of the registers after V0 to VF
+const SPECIAL_REGISTERS: &[(&str, usize, &str)] = &[
+    ("i", 2, "data_ptr"),
+    ("pc", 2, "code_ptr"),
+    ("sp", 1, "uint8"),
+    ("dt", 1, "uint8"),
+    ("st", 1, "uint8"),
+];
+
+/// How many instructions run between checks for an interrupt
+const INTERRUPT_POLL_INTERVAL: u32 = 4096;
+
+/// The target description that tells the client what the registers are called
+pub fn target_xml() -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\"?>\n",
+        "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n",
+        "<target version=\"1.0\">\n",
+        "  <feature name=\"org.chip8.core\">\n",
+    ));
+    for reg in 0..NUM_REGISTERS {
+        writeln!(
+            xml,
+            "    <reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>",
+            reg, reg
+        )
+        .unwrap();
+    }
+    for (i, &(name, size, kind)) in SPECIAL_REGISTERS.iter().enumerate() {
+        writeln!(
+            xml,
+            "    <reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+            name,
+            size * 8,
+            kind,
+            NUM_REGISTERS + i
+        )
+        .unwrap();
+    }
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &[u8]) -> Option<usize> {
+    usize::from_str_radix(std::str::from_utf8(text).ok()?, 16).ok()
+}
+
+/// Parse `addr,len`
+fn parse_range(text: &[u8]) -> Option<(usize, usize)> {
+    let comma = text.iter().position(|&byte| byte == b',')?;
+    Some((parse_hex(&text[..comma])?, parse_hex(&text[comma + 1..])?))
+}
+
+/// The signal a client sees when the program stops on an error
+fn signal(error: &Chip8Error) -> u8 {
+    match error.kind() {
+        Chip8ErrorKind::UnknownInstruction(_) | Chip8ErrorKind::InvalidReg(_) => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+/// What to do after handling a packet
+enum Response {
+    Reply(Vec<u8>),
+    /// Reply, then close the connection
+    Close(Vec<u8>),
+}
+
+pub struct Server {
+    machine: Machine,
+    connection: Connection,
+    breakpoints: BTreeSet<u16>,
+    xml: String,
+    source_map: Option<SourceMap>,
+}
+
+impl Server {
+    pub fn new(machine: Machine, connection: Connection) -> Self {
+        Server {
+            machine,
+            connection,
+            breakpoints: BTreeSet::new(),
+            xml: target_xml(),
+            source_map: None,
+        }
+    }
+
+    /// Tell the client where the program stops in its source
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Self {
+        self.source_map = Some(source_map);
+        self
+    }
+
+    /// Serve the client until it detaches or disconnects
+    pub fn run(mut self) -> io::Result<()> {
+        while let Some(incoming) = self.connection.receive()? {
+            let response = match incoming {
+                Incoming::Interrupt => Response::Reply(self.stop_reply(SIGINT, "")),
+                Incoming::Packet(packet) => self.handle(&packet)?,
+            };
+            match response {
+                Response::Reply(reply) => self.connection.send(&reply)?,
+                Response::Close(reply) => {
+                    self.connection.send(&reply)?;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> io::Result<Response> {
+        let (&command, args) = match packet.split_first() {
+            Some(split) => split,
+            None => return Ok(Response::Reply(Vec::new())),
+        };
+
+        let reply = match command {
+            b'?' => self.stop_reply(SIGTRAP, ""),
+            b'g' => self.read_registers(),
+            b'G' => self.write_registers(args),
+            b'p' => self.read_register(args),
+            b'P' => self.write_register(args),
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'c' => self.resume(args, false)?,
+            b's' => self.resume(args, true)?,
+            b'Z' | b'z' => self.breakpoint(command == b'Z', args),
+            b'H' | b'T' => b"OK".to_vec(),
+            b'D' => return Ok(Response::Close(b"OK".to_vec())),
+            b'k' => return Ok(Response::Close(Vec::new())),
+            b'q' | b'Q' | b'v' => return self.query(packet),
+            _ => Vec::new(),
+        };
+        Ok(Response::Reply(reply))
+    }
+
+    fn query(&mut self, packet: &[u8]) -> io::Result<Response> {
+        let reply = if packet.starts_with(b"qSupported") {
+            b"PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+".to_vec()
+        } else if let Some(args) = packet.strip_prefix(b"qXfer:features:read:target.xml:") {
+            match parse_range(args) {
+                Some((offset, len)) => {
+                    let xml = self.xml.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = offset.saturating_add(len).min(xml.len());
+                    let mut reply = vec![if end == xml.len() { b'l' } else { b'm' }];
+                    reply.extend_from_slice(&xml[start..end]);
+                    reply
+                }
+                None => b"E01".to_vec(),
+            }
+        } else if packet == b"QStartNoAckMode" {
+            self.connection.send(b"OK")?;
+            self.connection.disable_ack();
+            // Already replied, with acknowledgements still on
+            return self.receive_next();
+        } else if packet == b"qAttached" {
+            b"1".to_vec()
+        } else if packet == b"qC" {
+            b"QC1".to_vec()
+        } else if packet == b"qfThreadInfo" {
+            b"m1".to_vec()
+        } else if packet == b"qsThreadInfo" {
+            b"l".to_vec()
+        } else if packet.starts_with(b"vKill") {
+            return Ok(Response::Close(b"OK".to_vec()));
+        } else {
+            Vec::new()
+        };
+        Ok(Response::Reply(reply))
+    }
+
+    /// Handle the packet after one that was already replied to
+    fn receive_next(&mut self) -> io::Result<Response> {
+        match self.connection.receive()? {
+            Some(Incoming::Packet(packet)) => self.handle(&packet),
+            Some(Incoming::Interrupt) => Ok(Response::Reply(self.stop_reply(SIGINT, ""))),
+            None => Ok(Response::Close(Vec::new())),
+        }
+    }
+
+    /// A `T` stop reply with PC, so the client doesn't have to ask for it
+    fn stop_reply(&self, signal: u8, reason: &str) -> Vec<u8> {
+        let pc = self.machine.chip8().pc().to_le_bytes();
+        format!("T{:02x}{:02x}:{};{}", signal, PC_REGISTER, hex(&pc), reason).into_bytes()
+    }
+
+    fn register(&self, reg: usize) -> Option<Vec<u8>> {
+        let chip8 = self.machine.chip8();
+        let value = match reg.checked_sub(NUM_REGISTERS) {
+            None => return Some(vec![chip8.registers()[reg]]),
+            Some(0) => chip8.i().to_le_bytes().to_vec(),
+            Some(1) => chip8.pc().to_le_bytes().to_vec(),
+            Some(2) => vec![chip8.sp()],
+            Some(3) => vec![chip8.delay_timer()],
+            Some(4) => vec![chip8.sound_timer()],
+            Some(_) => return None,
+        };
+        Some(value)
+    }
+
+    fn set_register(&mut self, reg: usize, value: &[u8]) -> Result<(), ()> {
+        let expected = match reg.checked_sub(NUM_REGISTERS) {
+            None => 1,
+            Some(special) => SPECIAL_REGISTERS.get(special).ok_or(())?.1,
+        };
+        if value.len() != expected {
+            return Err(());
+        }
+
+        let chip8 = self.machine.chip8_mut();
+        let word = || u16::from_le_bytes([value[0], value[1]]);
+        match reg.checked_sub(NUM_REGISTERS) {
+            None => chip8.set_reg(reg as u8, value[0]).map_err(|_| ())?,
+            Some(0) => chip8.set_i(word()),
+            Some(1) => chip8.set_pc(word()).map_err(|_| ())?,
+            // The stack pointer can't be moved without breaking the call stack
+            Some(2) if value[0] == chip8.sp() => {}
+            Some(2) => return Err(()),
+            Some(3) => chip8.set_delay_timer(value[0]),
+            Some(4) => chip8.set_sound_timer(value[0]),
+            Some(_) => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn register_count() -> usize {
+        NUM_REGISTERS + SPECIAL_REGISTERS.len()
+    }
+
+    fn read_registers(&self) -> Vec<u8> {
+        let bytes: Vec<u8> = (0..Self::register_count())
+            .flat_map(|reg| self.register(reg).unwrap())
+            .collect();
+        hex(&bytes).into_bytes()
+    }
+
+    fn write_registers(&mut self, args: &[u8]) -> Vec<u8> {
+        let bytes = match unhex(args) {
+            Some(bytes) => bytes,
+            None => return b"E01".to_vec(),
+        };
+
+        let mut offset = 0;
+        for reg in 0..Self::register_count() {
+            let len = self.register(reg).unwrap().len();
+            let value = match bytes.get(offset..offset + len) {
+                Some(value) => value,
+                None => return b"E01".to_vec(),
+            };
+            if self.set_register(reg, value).is_err() {
+                return b"E01".to_vec();
+            }
+            offset += len;
+        }
+        b"OK".to_vec()
+    }
+
+    fn read_register(&self, args: &[u8]) -> Vec<u8> {
+        match parse_hex(args).and_then(|reg| self.register(reg)) {
+            Some(value) => hex(&value).into_bytes(),
+            None => b"E01".to_vec(),
+        }
+    }
+
+    fn write_register(&mut self, args: &[u8]) -> Vec<u8> {
+        let written = args
+            .iter()
+            .position(|&byte| byte == b'=')
+            .and_then(|equals| Some((parse_hex(&args[..equals])?, unhex(&args[equals + 1..])?)))
+            .map(|(reg, value)| self.set_register(reg, &value));
+        match written {
+            Some(Ok(())) => b"OK".to_vec(),
+            _ => b"E01".to_vec(),
+        }
+    }
+
+    fn read_memory(&self, args: &[u8]) -> Vec<u8> {
+        match parse_range(args) {
+            Some((addr, len)) if addr < MEMORY_SIZE => {
+                let end = addr.saturating_add(len).min(MEMORY_SIZE);
+                hex(&self.machine.chip8().memory()[addr..end]).into_bytes()
+            }
+            _ => b"E01".to_vec(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &[u8]) -> Vec<u8> {
+        let colon = match args.iter().position(|&byte| byte == b':') {
+            Some(colon) => colon,
+            None => return b"E01".to_vec(),
+        };
+        let (addr, data) = match (parse_range(&args[..colon]), unhex(&args[colon + 1..])) {
+            (Some((addr, len)), Some(data)) if data.len() == len => (addr, data),
+            _ => return b"E01".to_vec(),
+        };
+        if addr.saturating_add(data.len()) > MEMORY_SIZE {
+            return b"E01".to_vec();
+        }
+
+        for (i, &byte) in data.iter().enumerate() {
+            if self
+                .machine
+                .chip8_mut()
+                .poke((addr + i) as u16, byte)
+                .is_err()
+            {
+                return b"E01".to_vec();
+            }
+        }
+        b"OK".to_vec()
+    }
+
+    /// `Z0,addr,kind` and `z0,addr,kind`. Hardware breakpoints are treated like software ones.
+    fn breakpoint(&mut self, insert: bool, args: &[u8]) -> Vec<u8> {
+        let mut fields = args.split(|&byte| byte == b',');
+        let kind = fields.next();
+        if !matches!(kind, Some(b"0") | Some(b"1")) {
+            return Vec::new();
+        }
+        let addr = match fields.next().and_then(parse_hex) {
+            Some(addr) if addr < MEMORY_SIZE => addr as u16,
+            _ => return b"E01".to_vec(),
+        };
+
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+        b"OK".to_vec()
+    }
+
+    /// Continue or step, optionally from a new address
+    fn resume(&mut self, args: &[u8], step: bool) -> io::Result<Vec<u8>> {
+        if !args.is_empty() {
+            let pc = parse_hex(args).and_then(|pc| u16::try_from(pc).ok());
+            match pc.map(|pc| self.machine.chip8_mut().set_pc(pc)) {
+                Some(Ok(())) => {}
+                _ => return Ok(b"E01".to_vec()),
+            }
+        }
+
+        let mut polls = 0;
+        loop {
+            if let Err(e) = self.machine.step() {
+                let message = match self.location() {
+                    Some(location) => {
+                        format!("chip8: the program stopped at {}: {}\n", location, e)
+                    }
+                    None => format!("chip8: the program stopped: {}\n", e),
+                };
+                self.output(&message)?;
+                return Ok(self.stop_reply(signal(&e), ""));
+            }
+
+            if step {
+                return Ok(self.stop_reply(SIGTRAP, ""));
+            }
+            if self.breakpoints.contains(&self.machine.chip8().pc()) {
+                self.report_location()?;
+                return Ok(self.stop_reply(SIGTRAP, "swbreak:;"));
+            }
+
+            polls += 1;
+            if polls == INTERRUPT_POLL_INTERVAL {
+                polls = 0;
+                if self.connection.poll_interrupt()? {
+                    self.report_location()?;
+                    return Ok(self.stop_reply(SIGINT, ""));
+                }
+            }
+        }
+    }
+
+    /// Describe the PC using the source map
+    fn location(&self) -> Option<String> {
+        let map = self.source_map.as_ref()?;
+        Some(map.describe(self.machine.chip8().pc()))
+    }
+
+    /// Print where the program stopped on the client's console, if the source map knows
+    fn report_location(&mut self) -> io::Result<()> {
+        match self.location() {
+            Some(location) => self.output(&format!("chip8: stopped at {}\n", location)),
+            None => Ok(()),
+        }
+    }
+
+    /// Print a message on the client's console
+    fn output(&mut self, message: &str) -> io::Result<()> {
+        let mut output = b"O".to_vec();
+        output.extend_from_slice(hex(message.as_bytes()).as_bytes());
+        self.connection.send(&output)
+    }
+}
@@ -0,0 +1,178 @@
+//! A scripted client that talks to the server the way GDB would
+
+use std::{
+    io::{
+        BufRead,
+        BufReader,
+        Read,
+        Write,
+    },
+    net::TcpStream,
+    path::Path,
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+};
+
+struct Client {
+    server: Child,
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl Client {
+    fn start(rom: &str, args: &[&str]) -> Self {
+        let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+        let mut server = Command::new(env!("CARGO_BIN_EXE_chip8-gdb"))
+            .current_dir(&roms)
+            .arg(rom)
+            .args(["--port", "0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut line = String::new();
+        BufReader::new(server.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line.trim().strip_prefix("listening on ").unwrap();
+
+        Client {
+            server,
+            stream: TcpStream::connect(addr).unwrap(),
+            ack: true,
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+        if self.ack {
+            assert_eq!(self.read_byte(), b'+');
+        }
+    }
+
+    fn receive(&mut self) -> String {
+        while self.read_byte() != b'$' {}
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                b'}' => {
+                    let byte = self.read_byte();
+                    data.push(byte ^ 0x20);
+                }
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(
+            u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(),
+            sum
+        );
+        if self.ack {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.receive()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+    }
+}
+
+#[test]
+fn registers_memory_and_breakpoints() {
+    let mut client = Client::start("ibm.c8", &[]);
+
+    let supported = client.request("qSupported:multiprocess+;swbreak+");
+    assert!(supported.contains("qXfer:features:read+"));
+    let xml = client.request("qXfer:features:read:target.xml:0,fff");
+    assert!(xml.starts_with('l'));
+    assert!(xml.contains("<reg name=\"vf\" bitsize=\"8\""));
+    assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"17\"/>"));
+
+    assert_eq!(client.request("?"), "T0511:0002;");
+    // V0 to VF, then I, PC, SP, DT and ST
+    let registers = client.request("g");
+    assert_eq!(registers, format!("{}00000002000000", "00".repeat(16)));
+    assert_eq!(client.request("m200,4"), "00e0a22a");
+
+    assert_eq!(client.request("Z0,208,2"), "OK");
+    assert_eq!(client.request("c"), "T0511:0802;swbreak:;");
+    assert_eq!(client.request("p0"), "0c");
+    assert_eq!(client.request("p1"), "08");
+    assert_eq!(client.request("p10"), "2a02");
+    assert_eq!(client.request("s"), "T0511:0a02;");
+    assert_eq!(client.request("z0,208,2"), "OK");
+
+    assert_eq!(client.request("M300,2:abcd"), "OK");
+    assert_eq!(client.request("m300,2"), "abcd");
+    assert_eq!(client.request("P0=ff"), "OK");
+    assert_eq!(client.request("p0"), "ff");
+    assert_eq!(client.request("P12=01"), "E01");
+    assert_eq!(client.request("m1000,1"), "E01");
+
+    assert_eq!(client.request("qUnknownPacket"), "");
+    assert_eq!(client.request("D"), "OK");
+    assert!(client.server.wait().unwrap().success());
+}
+
+#[test]
+fn interrupting_without_acks() {
+    let mut client = Client::start("ibm.c8", &[]);
+
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.ack = false;
+
+    // The rom ends in an infinite loop
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    let stop = client.receive();
+    assert!(stop.starts_with("T02"), "{}", stop);
+
+    assert_eq!(client.request("p11"), "2802");
+    assert_eq!(client.request("D"), "OK");
+    assert!(client.server.wait().unwrap().success());
+}
+
+#[test]
+fn source_locations() {
+    let mut client = Client::start("ibm.c8", &["--source-map", "ibm.map.json"]);
+
+    assert_eq!(client.request("Z0,208,2"), "OK");
+    let output = client.request("c");
+    let message = output.strip_prefix('O').unwrap();
+    let message: Vec<u8> = (0..message.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&message[i..i + 2], 16).unwrap())
+        .collect();
+    assert_eq!(
+        String::from_utf8(message).unwrap(),
+        "chip8: stopped at ibm.8o:8 (main+8)\n"
+    );
+    assert_eq!(client.receive(), "T0511:0802;swbreak:;");
+
+    // Steps don't print anything
+    assert_eq!(client.request("s"), "T0511:0a02;");
+    assert_eq!(client.request("D"), "OK");
+    assert!(client.server.wait().unwrap().success());
+}
@@ -0,0 +1,680 @@
+//! A libretro core.
+//!
+//! Every `retro_run` runs one 60hz frame. The display goes out as XRGB8888 through a two color palette,
+//! blended by the deflicker presentation of the machine,
+//! and the buzzer as a square wave. The RetroPad is mapped to the keys the rom database suggests for the rom,
+//! and the keyboard covers the whole keypad with the usual `1234`/`QWER`/`ASDF`/`ZXCV` layout.
+
+pub mod libretro;
+
+use crate::libretro::*;
+use chip8::{
+    audio::SquareWave,
+    database::{
+        RomDatabase,
+        RomInfo,
+    },
+    deflicker::DeflickerMode,
+    rom::Rom,
+    Machine,
+    Quirks,
+    GFX_HEIGHT,
+    GFX_WIDTH,
+};
+use std::{
+    ffi::{
+        CStr,
+        CString,
+    },
+    os::raw::{
+        c_char,
+        c_uint,
+        c_void,
+    },
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    ptr,
+    slice,
+    sync::{
+        Mutex,
+        MutexGuard,
+    },
+};
+
+/// The audio sample rate
+pub const SAMPLE_RATE: u32 = 44100;
+
+const FPS: u32 = 60;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FPS) as usize;
+
+const SPEED_OPTION: &[u8] = b"chip8_speed\0";
+const QUIRKS_OPTION: &[u8] = b"chip8_quirks\0";
+const PALETTE_OPTION: &[u8] = b"chip8_palette\0";
+const DEFLICKER_OPTION: &[u8] = b"chip8_deflicker\0";
+
+/// Background and foreground colors
+const PALETTES: &[(&str, [u32; 2])] = &[
+    ("white", [0x000000, 0xFFFFFF]),
+    ("green", [0x0A140A, 0x33FF66]),
+    ("amber", [0x140C00, 0xFFB000]),
+    ("lcd", [0x9BBC0F, 0x0F380F]),
+];
+
+/// RetroPad buttons by the action they are named after in the rom database, and their key for unknown roms
+const BUTTONS: &[(c_uint, &str, Option<u8>)] = &[
+    (RETRO_DEVICE_ID_JOYPAD_UP, "up", Some(0x2)),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, "down", Some(0x8)),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, "left", Some(0x4)),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, "right", Some(0x6)),
+    (RETRO_DEVICE_ID_JOYPAD_A, "a", Some(0x5)),
+    (RETRO_DEVICE_ID_JOYPAD_B, "b", None),
+    (RETRO_DEVICE_ID_JOYPAD_X, "x", None),
+    (RETRO_DEVICE_ID_JOYPAD_Y, "y", None),
+    (RETRO_DEVICE_ID_JOYPAD_START, "start", None),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, "select", None),
+];
+
+/// The keyboard key of each chip8 key
+const KEYBOARD: [u8; 16] = [
+    b'x', b'1', b'2', b'3', b'q', b'w', b'e', b'a', b's', b'd', b'z', b'c', b'4', b'r', b'f', b'v',
+];
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<retro_environment_t>,
+    video_refresh: Option<retro_video_refresh_t>,
+    audio_sample_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+    log: Option<retro_log_printf_t>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    log: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn callbacks() -> Callbacks {
+    *lock(&CALLBACKS)
+}
+
+unsafe fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+/// Get the value of a core option
+unsafe fn option(key: &[u8]) -> Option<String> {
+    let mut variable = retro_variable {
+        key: key.as_ptr().cast(),
+        value: ptr::null(),
+    };
+    if !environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE,
+        (&mut variable as *mut retro_variable).cast(),
+    ) || variable.value.is_null()
+    {
+        return None;
+    }
+    Some(
+        CStr::from_ptr(variable.value)
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+unsafe fn log_error(message: &str) {
+    if let (Some(log), Ok(message)) = (callbacks().log, CString::new(message)) {
+        log(
+            RETRO_LOG_ERROR,
+            b"[chip8] %s\n\0".as_ptr().cast(),
+            message.as_ptr(),
+        );
+    }
+}
+
+/// Parse a css hex color like `#RRGGBB`
+fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// Mix the background and foreground of a palette by a brightness from 0 to 255
+fn blend([background, foreground]: [u32; 2], level: u8) -> u32 {
+    let level = u32::from(level);
+    [16, 8, 0].iter().fold(0, |color, &shift| {
+        let from = background >> shift & 0xFF;
+        let to = foreground >> shift & 0xFF;
+        color | ((from * (255 - level) + to * level + 127) / 255) << shift
+    })
+}
+
+struct Core {
+    machine: Machine,
+    rom: Rom,
+    /// Whether the program stopped on an error, until it is reset
+    halted: bool,
+
+    palette: [u32; 2],
+    video: Vec<u32>,
+    wave: SquareWave,
+    mono: Vec<i16>,
+    stereo: Vec<i16>,
+
+    /// The chip8 key of each mapped RetroPad button as port, button and key
+    buttons: Vec<(c_uint, c_uint, u8)>,
+    /// Input descriptor text, which has to outlive the descriptors
+    descriptions: Vec<CString>,
+}
+
+impl Core {
+    fn new(data: &[u8]) -> Result<Self, String> {
+        let rom = Rom::parse(data, &RomDatabase::bundled()).map_err(|e| e.to_string())?;
+        let mut chip8 = chip8::Chip8::new();
+        chip8.init();
+        rom.load_into(&mut chip8).map_err(|e| e.to_string())?;
+
+        let buttons = Self::map_buttons(rom.info.as_ref());
+
+        Ok(Core {
+            machine: Machine::new(chip8),
+            rom,
+            halted: false,
+            palette: PALETTES[0].1,
+            video: vec![0; GFX_WIDTH * GFX_HEIGHT],
+            wave: SquareWave::new(SAMPLE_RATE),
+            mono: vec![0; SAMPLES_PER_FRAME],
+            stereo: vec![0; SAMPLES_PER_FRAME * 2],
+            buttons,
+            descriptions: Vec::new(),
+        })
+    }
+
+    /// Use the keys from the rom database for known roms, with a second player on port 1
+    fn map_buttons(info: Option<&RomInfo>) -> Vec<(c_uint, c_uint, u8)> {
+        let keys = match info {
+            Some(info) if !info.keys.is_empty() => &info.keys,
+            _ => {
+                return BUTTONS
+                    .iter()
+                    .filter_map(|&(id, _, key)| Some((0, id, key?)))
+                    .collect()
+            }
+        };
+
+        let mut buttons = Vec::new();
+        for &(id, action, _) in BUTTONS {
+            if let Some(&key) = keys.get(action) {
+                buttons.push((0, id, key));
+            }
+            let player2 = format!("player2{}{}", action[..1].to_uppercase(), &action[1..]);
+            if let Some(&key) = keys.get(&player2) {
+                buttons.push((1, id, key));
+            }
+        }
+        buttons
+    }
+
+    unsafe fn set_input_descriptors(&mut self) {
+        self.descriptions = self
+            .buttons
+            .iter()
+            .map(|&(port, id, key)| {
+                let (_, action, _) = BUTTONS.iter().find(|(button, _, _)| *button == id).unwrap();
+                CString::new(format!("Player {} {} (key {:X})", port + 1, action, key)).unwrap()
+            })
+            .collect();
+
+        let mut descriptors: Vec<retro_input_descriptor> = self
+            .buttons
+            .iter()
+            .zip(self.descriptions.iter())
+            .map(|(&(port, id, _), description)| retro_input_descriptor {
+                port,
+                device: RETRO_DEVICE_JOYPAD,
+                index: 0,
+                id,
+                description: description.as_ptr(),
+            })
+            .collect();
+        descriptors.push(retro_input_descriptor {
+            port: 0,
+            device: 0,
+            index: 0,
+            id: 0,
+            description: ptr::null(),
+        });
+        environment(
+            RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+            descriptors.as_mut_ptr().cast(),
+        );
+    }
+
+    unsafe fn apply_options(&mut self) {
+        let tickrate = match option(SPEED_OPTION).and_then(|speed| speed.parse().ok()) {
+            Some(tickrate) => tickrate,
+            None => self.rom.tickrate,
+        };
+        match tickrate
+            .checked_mul(chip8::machine::TIMER_HZ)
+            .filter(|&speed| speed > 0)
+        {
+            Some(speed) if speed != self.machine.instructions_per_second() => {
+                self.machine.set_instructions_per_second(speed);
+            }
+            Some(_) => {}
+            None => log_error(&format!(
+                "{} instructions per frame is out of range",
+                tickrate
+            )),
+        }
+
+        let quirks = match option(QUIRKS_OPTION).as_deref() {
+            Some("chip8") => Quirks::CHIP8,
+            Some("modern") => Quirks::MODERN_CHIP8,
+            Some("schip") => Quirks::SCHIP,
+            Some("xochip") => Quirks::XOCHIP,
+            _ => self.rom.quirks,
+        };
+        self.machine.chip8_mut().set_quirks(quirks);
+
+        let palette = option(PALETTE_OPTION);
+        self.palette = PALETTES
+            .iter()
+            .find(|(name, _)| Some(*name) == palette.as_deref())
+            .map(|&(_, palette)| palette)
+            .or_else(|| self.rom_palette())
+            .unwrap_or(PALETTES[0].1);
+
+        let deflicker = option(DEFLICKER_OPTION)
+            .and_then(|mode| mode.parse().ok())
+            .unwrap_or(DeflickerMode::Off);
+        if deflicker != self.machine.deflicker_mode() {
+            self.machine.set_deflicker_mode(deflicker);
+        }
+    }
+
+    /// The colors the rom database suggests
+    fn rom_palette(&self) -> Option<[u32; 2]> {
+        let pixels = &self.rom.info.as_ref()?.colors.as_ref()?.pixels;
+        match pixels.as_slice() {
+            [background, foreground, ..] => {
+                Some([parse_color(background)?, parse_color(foreground)?])
+            }
+            _ => None,
+        }
+    }
+
+    fn reset(&mut self) {
+        let chip8 = self.machine.chip8_mut();
+        chip8.init();
+        // The rom was loaded before, so it fits
+        let _ = chip8.load_at(&self.rom.data, self.rom.load_address);
+        self.machine.reset_clock();
+        self.halted = false;
+    }
+
+    unsafe fn poll_input(&mut self, input_state: retro_input_state_t) {
+        let mut keys = [false; 16];
+        for (key, &keycode) in KEYBOARD.iter().enumerate() {
+            keys[key] = input_state(0, RETRO_DEVICE_KEYBOARD, 0, c_uint::from(keycode)) != 0;
+        }
+        for &(port, id, key) in self.buttons.iter() {
+            if input_state(port, RETRO_DEVICE_JOYPAD, 0, id) != 0 {
+                keys[usize::from(key)] = true;
+            }
+        }
+
+        let chip8 = self.machine.chip8_mut();
+        for (key, &pressed) in keys.iter().enumerate() {
+            // There are exactly as many keys as the keypad has
+            let _ = chip8.set_key(key, pressed);
+        }
+    }
+
+    unsafe fn run(&mut self, callbacks: Callbacks) {
+        if let Some(input_state) = callbacks.input_state {
+            self.poll_input(input_state);
+        }
+
+        let mut sound_on = false;
+        if !self.halted {
+            let machine = &mut self.machine;
+            match panic::catch_unwind(AssertUnwindSafe(|| machine.run_frame())) {
+                Ok(Ok(events)) => sound_on = events.sound_on || events.sound_started,
+                Ok(Err(e)) => {
+                    log_error(&format!("the program stopped: {}", e));
+                    self.halted = true;
+                }
+                Err(_) => {
+                    log_error("the emulator panicked");
+                    self.halted = true;
+                }
+            }
+        }
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            for (pixel, &level) in self
+                .video
+                .iter_mut()
+                .zip(self.machine.presentation().iter())
+            {
+                *pixel = blend(self.palette, level);
+            }
+            video_refresh(
+                self.video.as_ptr().cast(),
+                GFX_WIDTH as c_uint,
+                GFX_HEIGHT as c_uint,
+                GFX_WIDTH * 4,
+            );
+        }
+
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            self.wave.fill(sound_on, &mut self.mono);
+            for (frame, &sample) in self.stereo.chunks_exact_mut(2).zip(self.mono.iter()) {
+                frame[0] = sample;
+                frame[1] = sample;
+            }
+            audio_sample_batch(self.stereo.as_ptr(), SAMPLES_PER_FRAME);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+/// # Safety
+/// `environment` must be a valid libretro environment callback.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(environment: retro_environment_t) {
+    lock(&CALLBACKS).environment = Some(environment);
+
+    let speed = b"Instructions per frame; auto|7|10|15|20|30|50|100|200|500|1000\0";
+    let quirks = b"Quirks; auto|chip8|modern|schip|xochip\0";
+    let palette = b"Palette; auto|white|green|amber|lcd\0";
+    let deflicker = b"Deflicker; off|or:2|or:3|decay:0.5|decay:0.75\0";
+    let mut variables = [
+        retro_variable {
+            key: SPEED_OPTION.as_ptr().cast(),
+            value: speed.as_ptr().cast(),
+        },
+        retro_variable {
+            key: QUIRKS_OPTION.as_ptr().cast(),
+            value: quirks.as_ptr().cast(),
+        },
+        retro_variable {
+            key: PALETTE_OPTION.as_ptr().cast(),
+            value: palette.as_ptr().cast(),
+        },
+        retro_variable {
+            key: DEFLICKER_OPTION.as_ptr().cast(),
+            value: deflicker.as_ptr().cast(),
+        },
+        retro_variable {
+            key: ptr::null(),
+            value: ptr::null(),
+        },
+    ];
+    environment(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr().cast(),
+    );
+
+    let mut log = retro_log_callback { log: None };
+    if environment(
+        RETRO_ENVIRONMENT_GET_LOG_INTERFACE,
+        (&mut log as *mut retro_log_callback).cast(),
+    ) {
+        lock(&CALLBACKS).log = log.log;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: retro_video_refresh_t) {
+    lock(&CALLBACKS).video_refresh = Some(video_refresh);
+}
+
+/// Single samples aren't used, everything goes through `retro_set_audio_sample_batch`
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: retro_audio_sample_batch_t) {
+    lock(&CALLBACKS).audio_sample_batch = Some(audio_sample_batch);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: retro_input_poll_t) {
+    lock(&CALLBACKS).input_poll = Some(input_poll);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: retro_input_state_t) {
+    lock(&CALLBACKS).input_state = Some(input_state);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *lock(&CORE) = None;
+}
+
+/// # Safety
+/// `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    *info = retro_system_info {
+        library_name: b"chip8-rs\0".as_ptr().cast(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+        valid_extensions: b"ch8|c8|gif|c8b\0".as_ptr().cast(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: GFX_WIDTH as c_uint,
+            base_height: GFX_HEIGHT as c_uint,
+            max_width: GFX_WIDTH as c_uint,
+            max_height: GFX_HEIGHT as c_uint,
+            aspect_ratio: GFX_WIDTH as f32 / GFX_HEIGHT as f32,
+        },
+        timing: retro_system_timing {
+            fps: f64::from(FPS),
+            sample_rate: f64::from(SAMPLE_RATE),
+        },
+    };
+}
+
+/// Only the RetroPad and keyboard are supported, so this does nothing
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = lock(&CORE).as_mut() {
+        core.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    unsafe {
+        if let Some(input_poll) = callbacks.input_poll {
+            input_poll();
+        }
+
+        if let Some(core) = lock(&CORE).as_mut() {
+            // A panic must not unwind into the frontend, so the core stops until it is reset
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut updated = false;
+                if environment(
+                    RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+                    (&mut updated as *mut bool).cast(),
+                ) && updated
+                {
+                    core.apply_options();
+                }
+
+                core.run(callbacks);
+            }));
+            if result.is_err() {
+                log_error("the emulator panicked");
+                core.halted = true;
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    lock(&CORE)
+        .as_ref()
+        .map_or(0, |core| core.machine.save_state().len())
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = lock(&CORE);
+    let core = match core.as_ref() {
+        Some(core) => core,
+        None => return false,
+    };
+    let state = core.machine.save_state();
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+    slice::from_raw_parts_mut(data.cast::<u8>(), state.len()).copy_from_slice(&state);
+    true
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = lock(&CORE);
+    let core = match core.as_mut() {
+        Some(core) => core,
+        None => return false,
+    };
+    if data.is_null() {
+        return false;
+    }
+    match core
+        .machine
+        .load_state(slice::from_raw_parts(data.cast::<u8>(), size))
+    {
+        Ok(()) => {
+            core.halted = false;
+            true
+        }
+        Err(e) => {
+            log_error(&format!("failed to load state: {}", e));
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a valid game info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const retro_game_info) -> bool {
+    panic::catch_unwind(|| load_game(game)).unwrap_or_else(|_| {
+        log_error("the emulator panicked loading the game");
+        false
+    })
+}
+
+unsafe fn load_game(game: *const retro_game_info) -> bool {
+    let game = match game.as_ref() {
+        Some(game) if !game.data.is_null() => game,
+        _ => return false,
+    };
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        (&mut format as *mut c_uint).cast(),
+    ) {
+        log_error("XRGB8888 is not supported");
+        return false;
+    }
+
+    let rom = slice::from_raw_parts(game.data.cast::<u8>(), game.size);
+    let mut core = match Core::new(rom) {
+        Ok(core) => core,
+        Err(e) => {
+            log_error(&e);
+            return false;
+        }
+    };
+    core.apply_options();
+    core.set_input_descriptors();
+
+    *lock(&CORE) = Some(core);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const retro_game_info,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *lock(&CORE) = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -0,0 +1,279 @@
+//! A minimal libretro frontend that loads the core the way RetroArch would
+
+use chip8_libretro::libretro::*;
+use libloading::{
+    Library,
+    Symbol,
+};
+use std::{
+    collections::HashMap,
+    ffi::{
+        CStr,
+        CString,
+    },
+    os::raw::{
+        c_uint,
+        c_void,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Mutex,
+};
+
+#[derive(Default)]
+struct Frontend {
+    pixel_format: Option<c_uint>,
+    /// Core options and their descriptions
+    variables: HashMap<String, String>,
+    /// Values of core options set by the "user"
+    settings: HashMap<String, CString>,
+    settings_updated: bool,
+    input_descriptors: usize,
+
+    frame: Vec<u32>,
+    audio_frames: usize,
+    loud_samples: usize,
+    /// Held RetroPad buttons of port 0
+    buttons: Vec<c_uint>,
+    polls: usize,
+}
+
+static FRONTEND: Mutex<Option<Frontend>> = Mutex::new(None);
+
+fn with_frontend<T>(f: impl FnOnce(&mut Frontend) -> T) -> T {
+    f(FRONTEND
+        .lock()
+        .unwrap()
+        .get_or_insert_with(Frontend::default))
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    with_frontend(|frontend| match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            frontend.pixel_format = Some(*data.cast::<c_uint>());
+            true
+        }
+        RETRO_ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data.cast::<retro_variable>();
+            while !(*variable).key.is_null() {
+                let key = CStr::from_ptr((*variable).key).to_str().unwrap();
+                let value = CStr::from_ptr((*variable).value).to_str().unwrap();
+                frontend.variables.insert(key.into(), value.into());
+                variable = variable.add(1);
+            }
+            true
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE => {
+            let variable = &mut *data.cast::<retro_variable>();
+            let key = CStr::from_ptr(variable.key).to_str().unwrap();
+            match frontend.settings.get(key) {
+                Some(value) => {
+                    variable.value = value.as_ptr();
+                    true
+                }
+                None => false,
+            }
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *data.cast::<bool>() = std::mem::replace(&mut frontend.settings_updated, false);
+            true
+        }
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS => {
+            let mut descriptor = data.cast::<retro_input_descriptor>();
+            while !(*descriptor).description.is_null() {
+                frontend.input_descriptors += 1;
+                descriptor = descriptor.add(1);
+            }
+            true
+        }
+        _ => false,
+    })
+}
+
+unsafe extern "C" fn video_refresh(
+    data: *const c_void,
+    width: c_uint,
+    height: c_uint,
+    pitch: usize,
+) {
+    assert_eq!((width, height, pitch), (64, 32, 64 * 4));
+    let pixels = std::slice::from_raw_parts(data.cast::<u32>(), 64 * 32);
+    with_frontend(|frontend| frontend.frame = pixels.to_vec());
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {
+    panic!("the core should only use batches");
+}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = std::slice::from_raw_parts(data, frames * 2);
+    with_frontend(|frontend| {
+        frontend.audio_frames += frames;
+        frontend.loud_samples += samples.iter().filter(|&&sample| sample != 0).count();
+    });
+    frames
+}
+
+unsafe extern "C" fn input_poll() {
+    with_frontend(|frontend| frontend.polls += 1);
+}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    with_frontend(|frontend| {
+        i16::from(port == 0 && device == RETRO_DEVICE_JOYPAD && frontend.buttons.contains(&id))
+    })
+}
+
+/// The cdylib cargo built next to this test
+fn core_path() -> PathBuf {
+    let mut path = std::env::current_exe().unwrap();
+    path.pop();
+    path.join(libloading::library_filename("chip8_libretro"))
+}
+
+struct Core {
+    library: Library,
+}
+
+impl Core {
+    fn get<T>(&self, name: &str) -> Symbol<'_, T> {
+        unsafe { self.library.get(name.as_bytes()).unwrap() }
+    }
+
+    fn call(&self, name: &str) {
+        unsafe { self.get::<unsafe extern "C" fn()>(name)() }
+    }
+
+    fn run(&self, frames: usize) {
+        for _ in 0..frames {
+            self.call("retro_run");
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        unsafe {
+            let size = self.get::<unsafe extern "C" fn() -> usize>("retro_serialize_size")();
+            let mut data = vec![0; size];
+            assert!(self
+                .get::<unsafe extern "C" fn(*mut c_void, usize) -> bool>(
+                    "retro_serialize"
+                )(data.as_mut_ptr().cast(), size));
+            data
+        }
+    }
+
+    fn unserialize(&self, data: &[u8]) -> bool {
+        unsafe {
+            self.get::<unsafe extern "C" fn(*const c_void, usize) -> bool>("retro_unserialize")(
+                data.as_ptr().cast(),
+                data.len(),
+            )
+        }
+    }
+}
+
+#[test]
+fn runs_pong_in_a_headless_frontend() {
+    let core = Core {
+        library: unsafe { Library::new(core_path()).unwrap() },
+    };
+    unsafe {
+        assert_eq!(
+            core.get::<unsafe extern "C" fn() -> c_uint>("retro_api_version")(),
+            1
+        );
+
+        core.get::<unsafe extern "C" fn(retro_environment_t)>("retro_set_environment")(environment);
+        core.get::<unsafe extern "C" fn(retro_video_refresh_t)>("retro_set_video_refresh")(
+            video_refresh,
+        );
+        core.get::<unsafe extern "C" fn(retro_audio_sample_t)>("retro_set_audio_sample")(
+            audio_sample,
+        );
+        core.get::<unsafe extern "C" fn(retro_audio_sample_batch_t)>(
+            "retro_set_audio_sample_batch",
+        )(audio_sample_batch);
+        core.get::<unsafe extern "C" fn(retro_input_poll_t)>("retro_set_input_poll")(input_poll);
+        core.get::<unsafe extern "C" fn(retro_input_state_t)>("retro_set_input_state")(input_state);
+        core.call("retro_init");
+
+        let mut system_info = std::mem::zeroed::<retro_system_info>();
+        core.get::<unsafe extern "C" fn(*mut retro_system_info)>("retro_get_system_info")(
+            &mut system_info,
+        );
+        assert_eq!(
+            CStr::from_ptr(system_info.library_name).to_str().unwrap(),
+            "chip8-rs"
+        );
+        assert!(!system_info.need_fullpath);
+
+        let rom = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("../pong.c8")).unwrap();
+        let game = retro_game_info {
+            path: std::ptr::null(),
+            data: rom.as_ptr().cast(),
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(core
+            .get::<unsafe extern "C" fn(*const retro_game_info) -> bool>(
+                "retro_load_game"
+            )(&game));
+
+        let mut av_info = std::mem::zeroed::<retro_system_av_info>();
+        core.get::<unsafe extern "C" fn(*mut retro_system_av_info)>("retro_get_system_av_info")(
+            &mut av_info,
+        );
+        assert_eq!(
+            (av_info.geometry.base_width, av_info.geometry.base_height),
+            (64, 32)
+        );
+        assert_eq!(av_info.timing.fps, 60.0);
+    }
+
+    with_frontend(|frontend| {
+        assert_eq!(frontend.pixel_format, Some(RETRO_PIXEL_FORMAT_XRGB8888));
+        assert!(frontend.variables.contains_key("chip8_speed"));
+        assert!(frontend.variables.contains_key("chip8_quirks"));
+        assert!(frontend.variables.contains_key("chip8_palette"));
+        // Up and down for both players
+        assert_eq!(frontend.input_descriptors, 4);
+        frontend.buttons.push(RETRO_DEVICE_ID_JOYPAD_UP);
+    });
+
+    core.run(600);
+    with_frontend(|frontend| {
+        assert_eq!(frontend.polls, 600);
+        assert_eq!(frontend.audio_frames, 600 * 735);
+        assert!(frontend.loud_samples > 0, "pong should beep");
+        assert!(frontend
+            .frame
+            .iter()
+            .all(|&pixel| pixel == 0 || pixel == 0xFFFFFF));
+        assert!(frontend.frame.contains(&0xFFFFFF));
+    });
+
+    // A state replays the same frames
+    let state = core.serialize();
+    core.run(60);
+    let expected = with_frontend(|frontend| frontend.frame.clone());
+    assert!(core.unserialize(&state));
+    core.run(60);
+    assert!(with_frontend(|frontend| frontend.frame == expected));
+    assert!(!core.unserialize(&state[1..]));
+
+    // Options apply while running
+    with_frontend(|frontend| {
+        frontend
+            .settings
+            .insert("chip8_palette".into(), CString::new("green").unwrap());
+        frontend.settings_updated = true;
+    });
+    core.run(1);
+    with_frontend(|frontend| assert!(frontend.frame.contains(&0x33FF66)));
+
+    core.call("retro_reset");
+    core.call("retro_unload_game");
+    core.call("retro_deinit");
+}
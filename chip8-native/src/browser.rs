@@ -0,0 +1,399 @@
+//! A launcher for the roms in a directory.
+//!
+//! Roms are `.ch8`, `.c8` and `.c8b` files, Octo cartridge gifs and the roms in zip archives.
+//! Each gets a thumbnail of its screen after running headless for [`THUMBNAIL_FRAMES`] frames the first time it's shown,
+//! and the selected rom runs live next to the list.
+//! A rom that panics is skipped when it fails to load, gets a blank thumbnail or stops its preview.
+
+use crate::{
+    display::{
+        Display,
+        Effects,
+        PALETTES,
+    },
+    font::{
+        draw_text,
+        ADVANCE,
+        GLYPH_HEIGHT,
+    },
+};
+use chip8::{
+    archive::{
+        RomArchive,
+        ROM_EXTENSIONS,
+    },
+    database::RomDatabase,
+    rom::Rom,
+    Chip8,
+    Machine,
+    GFX_HEIGHT,
+    GFX_SIZE,
+    GFX_WIDTH,
+};
+use sdl2::{
+    event::Event,
+    keyboard::Keycode,
+    pixels::Color,
+    rect::Rect,
+    render::{
+        Canvas,
+        TextureCreator,
+    },
+    video::{
+        Window,
+        WindowContext,
+    },
+};
+use std::{
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    path::Path,
+    time::Duration,
+};
+
+/// How long roms run before their thumbnail is taken
+pub const THUMBNAIL_FRAMES: usize = 120;
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 320;
+const ROW_HEIGHT: u32 = 40;
+const LIST_WIDTH: u32 = 360;
+const VISIBLE_ROWS: usize = (HEIGHT / ROW_HEIGHT) as usize;
+/// Where the live preview and the details of the selected rom go
+const PANE_X: i32 = LIST_WIDTH as i32 + 16;
+
+const BACKGROUND: Color = Color::RGB(0, 0, 0);
+const SELECTED: Color = Color::RGB(48, 48, 64);
+const BORDER: Color = Color::RGB(96, 96, 96);
+const TEXT: Color = Color::RGB(255, 255, 255);
+const DIM_TEXT: Color = Color::RGB(160, 160, 160);
+/// The menu draws screens without effects
+const PLAIN: Effects = Effects {
+    grid: false,
+    scanlines: false,
+    integer_scaling: true,
+};
+
+/// A rom in the list
+pub struct Entry {
+    /// Where the rom came from, like `pong.c8` or `GAMES.zip: GAMES/PONG.ch8`
+    pub source: String,
+    /// The file name of the rom
+    pub name: String,
+    pub rom: Rom,
+    /// The screen after [`THUMBNAIL_FRAMES`] frames, once the entry was shown
+    thumbnail: Option<Vec<u8>>,
+}
+
+impl Entry {
+    fn new(source: String, name: String, rom: Rom) -> Self {
+        Entry {
+            source,
+            name,
+            rom,
+            thumbnail: None,
+        }
+    }
+
+    /// Get the thumbnail, running the rom for it the first time
+    fn thumbnail(&mut self) -> &[u8] {
+        let rom = &self.rom;
+        let source = &self.source;
+        self.thumbnail.get_or_insert_with(|| {
+            let screen = guard(source, || {
+                let mut machine = start(rom);
+                for _ in 0..THUMBNAIL_FRAMES {
+                    if machine.run_frame().is_err() {
+                        break;
+                    }
+                }
+                Ok(machine.presentation().to_vec())
+            });
+            screen.unwrap_or_else(|_| vec![0; GFX_SIZE])
+        })
+    }
+
+    /// The title from the rom or the database, or the file name
+    pub fn title(&self) -> &str {
+        match self.rom.title.as_ref() {
+            Some(title) => title,
+            None => &self.name,
+        }
+    }
+}
+
+/// What the user asked for
+pub enum Action {
+    None,
+    Launch(Box<Rom>),
+    Quit,
+}
+
+/// The selected rom, running
+struct Preview {
+    entry: usize,
+    machine: Machine,
+    /// Whether the rom stopped on an error
+    halted: bool,
+}
+
+pub struct Browser<'a> {
+    dir: String,
+    entries: Vec<Entry>,
+    selected: usize,
+    /// The first entry shown in the list
+    scroll: usize,
+    preview: Option<Preview>,
+    /// Draws the thumbnails, one after another
+    thumbnail_display: Display<'a>,
+    preview_display: Display<'a>,
+}
+
+impl<'a> Browser<'a> {
+    /// List the roms in a directory, skipping files that fail to load
+    pub fn scan(dir: &Path, creator: &'a TextureCreator<WindowContext>) -> Result<Self, String> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+
+        let database = RomDatabase::bundled();
+        let mut entries = Vec::new();
+        for path in paths {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let extension = path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default();
+
+            if extension == "zip" {
+                let mut archive = match RomArchive::open(&path) {
+                    Ok(archive) => archive,
+                    Err(e) => {
+                        eprintln!("Skipping '{}': {}", path.display(), e);
+                        continue;
+                    }
+                };
+                for i in 0..archive.roms().len() {
+                    let entry = &archive.roms()[i];
+                    let source = format!("{}: {}", name, entry.path);
+                    let file_name = String::from(entry.file_name());
+                    let rom = archive.read(i).map_err(|e| e.to_string()).and_then(|data| {
+                        guard(&source, || {
+                            Rom::parse(&data, &database).map_err(|e| e.to_string())
+                        })
+                    });
+                    match rom {
+                        Ok(rom) => entries.push(Entry::new(source, file_name, rom)),
+                        Err(e) => eprintln!("Skipping '{}': {}", source, e),
+                    }
+                }
+            } else if ROM_EXTENSIONS.contains(&extension.as_str()) || extension == "gif" {
+                let rom = guard(&name, || {
+                    Rom::open(&path, &database).map_err(|e| e.to_string())
+                });
+                match rom {
+                    Ok(rom) => entries.push(Entry::new(name.clone(), name, rom)),
+                    Err(e) => eprintln!("Skipping '{}': {}", path.display(), e),
+                }
+            }
+        }
+
+        Ok(Browser {
+            dir: dir.display().to_string(),
+            entries,
+            selected: 0,
+            scroll: 0,
+            preview: None,
+            thumbnail_display: Display::new(creator, PALETTES[0], PLAIN),
+            preview_display: Display::new(creator, PALETTES[0], PLAIN),
+        })
+    }
+
+    pub fn handle_event(&mut self, event: &Event) -> Action {
+        let code = match event {
+            Event::Quit { .. } => return Action::Quit,
+            Event::KeyDown {
+                keycode: Some(code),
+                ..
+            } => *code,
+            _ => return Action::None,
+        };
+        if self.entries.is_empty() {
+            return Action::None;
+        }
+
+        let last = self.entries.len() - 1;
+        self.selected = match code {
+            Keycode::Up => self.selected.saturating_sub(1),
+            Keycode::Down => (self.selected + 1).min(last),
+            Keycode::PageUp => self.selected.saturating_sub(VISIBLE_ROWS),
+            Keycode::PageDown => (self.selected + VISIBLE_ROWS).min(last),
+            Keycode::Home => 0,
+            Keycode::End => last,
+            Keycode::Return | Keycode::KpEnter => {
+                return Action::Launch(Box::new(self.entries[self.selected].rom.clone()))
+            }
+            _ => return Action::None,
+        };
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + VISIBLE_ROWS {
+            self.scroll = self.selected + 1 - VISIBLE_ROWS;
+        }
+        Action::None
+    }
+
+    /// Run the selected rom for the time since the last call
+    pub fn run_for(&mut self, elapsed: Duration) {
+        if self.preview().is_none_or(|preview| preview.halted) {
+            return;
+        }
+        let preview = self.preview.as_mut().expect("the preview was just started");
+        let machine = &mut preview.machine;
+        let result = guard(&self.entries[preview.entry].source, || {
+            machine.run_for(elapsed).map_err(|e| e.to_string())
+        });
+        preview.halted = result.is_err();
+    }
+
+    /// Get the preview of the selected rom, starting it if the selection changed
+    fn preview(&mut self) -> Option<&mut Preview> {
+        let selected = self.selected;
+        let entry = self.entries.get(selected)?;
+        if self.preview.as_ref().map(|preview| preview.entry) != Some(selected) {
+            self.preview = None;
+        }
+        Some(self.preview.get_or_insert_with(|| Preview {
+            entry: selected,
+            machine: start(&entry.rom),
+            halted: false,
+        }))
+    }
+
+    /// Draw the menu
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        canvas.set_draw_color(BACKGROUND);
+        canvas.clear();
+
+        if self.entries.is_empty() {
+            let message = format!("NO ROMS IN {}", self.dir);
+            draw_text(canvas, &message, 8, 8, 2, 50, TEXT)?;
+            return draw_help(canvas);
+        }
+
+        for (row, entry) in self
+            .entries
+            .iter_mut()
+            .enumerate()
+            .skip(self.scroll)
+            .take(VISIBLE_ROWS)
+        {
+            let y = ((row - self.scroll) as u32 * ROW_HEIGHT) as i32;
+            if row == self.selected {
+                canvas.set_draw_color(SELECTED);
+                canvas.fill_rect(Rect::new(0, y, LIST_WIDTH, ROW_HEIGHT))?;
+            }
+            let thumbnail = entry.thumbnail();
+            draw_screen(canvas, &mut self.thumbnail_display, thumbnail, 4, y + 4, 1)?;
+
+            let x = 4 + GFX_WIDTH as i32 + 8;
+            let max_chars = ((LIST_WIDTH as i32 - x) / (ADVANCE * 2) as i32) as usize;
+            let text_y = y + (ROW_HEIGHT - GLYPH_HEIGHT * 2) as i32 / 2;
+            draw_text(canvas, entry.title(), x, text_y, 2, max_chars, TEXT)?;
+        }
+
+        self.draw_selected(canvas)?;
+        draw_help(canvas)
+    }
+
+    fn draw_selected(&mut self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        if let Some(preview) = self.preview.as_ref() {
+            let screen = preview.machine.presentation();
+            draw_screen(canvas, &mut self.preview_display, screen, PANE_X, 8, 4)?;
+        }
+
+        let entry = &self.entries[self.selected];
+        let max_chars = ((WIDTH as i32 - PANE_X) / ADVANCE as i32) as usize;
+        let mut lines = Vec::new();
+        if let Some(info) = entry.rom.info.as_ref() {
+            if !info.authors.is_empty() {
+                lines.push(format!("BY {}", info.authors.join(", ")));
+            }
+            if let Some(release) = info.release.as_ref() {
+                lines.push(format!("RELEASED {}", release));
+            }
+        }
+        lines.push(entry.source.clone());
+        if let Some(platform) = entry.rom.platform.as_ref() {
+            lines.push(format!("PLATFORM {}", platform));
+        }
+        lines.push(format!("{} INSTRUCTIONS PER FRAME", entry.rom.tickrate));
+
+        let mut y = 8 + 32 * 4 + 8;
+        draw_text(canvas, entry.title(), PANE_X, y, 2, max_chars / 2, TEXT)?;
+        y += 20;
+        for line in lines {
+            draw_text(canvas, &line, PANE_X, y, 1, max_chars, DIM_TEXT)?;
+            y += 10;
+        }
+        Ok(())
+    }
+}
+
+/// Start a rom with its settings
+fn start(rom: &Rom) -> Machine {
+    let mut chip8 = Chip8::new();
+    chip8.init();
+    // Parsing the rom checked that it fits
+    let _ = rom.load_into(&mut chip8);
+    let mut machine = Machine::new(chip8);
+    machine.set_tickrate(rom.tickrate);
+    machine
+}
+
+/// Draw a screen of presentation brightness with a border around it
+fn draw_screen(
+    canvas: &mut Canvas<Window>,
+    display: &mut Display,
+    screen: &[u8],
+    x: i32,
+    y: i32,
+    scale: u32,
+) -> Result<(), String> {
+    let width = GFX_WIDTH as u32 * scale;
+    let height = GFX_HEIGHT as u32 * scale;
+    canvas.set_draw_color(BORDER);
+    canvas.draw_rect(Rect::new(x - 1, y - 1, width + 2, height + 2))?;
+    display.update(screen);
+    display.draw_at(canvas, Rect::new(x, y, width, height))
+}
+
+/// Run something for a rom, turning a panic into an error
+fn guard<T>(source: &str, f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        eprintln!("'{}' panicked", source);
+        Err(String::from("the emulator panicked"))
+    })
+}
+
+fn draw_help(canvas: &mut Canvas<Window>) -> Result<(), String> {
+    let y = (HEIGHT - GLYPH_HEIGHT - 6) as i32;
+    draw_text(
+        canvas,
+        "ENTER: PLAY  F1: MENU  ESC: QUIT",
+        PANE_X,
+        y,
+        1,
+        50,
+        DIM_TEXT,
+    )
+}
@@ -0,0 +1,268 @@
+//! Drawing the chip8 screen.
+//!
+//! The screen is drawn on the CPU into a framebuffer a whole multiple of 64x32 in size, overlays included,
+//! and uploaded as a single streaming texture. The texture is centered in the window and keeps its aspect ratio.
+//!
+//! The core only has one display plane, so every palette is a background and a lit color. XO-CHIP's
+//! multi-color palettes need a second plane and aren't offered.
+
+use chip8::{
+    capture::Colors,
+    database::RomInfo,
+    GFX_HEIGHT,
+    GFX_SIZE,
+    GFX_WIDTH,
+};
+use sdl2::{
+    pixels::{
+        Color,
+        PixelFormatEnum,
+    },
+    rect::Rect,
+    render::{
+        Canvas,
+        Texture,
+        TextureCreator,
+    },
+    video::{
+        Window,
+        WindowContext,
+    },
+};
+
+/// Colors for the screen, one for each state of a pixel on the core's single plane
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    /// The background and lit pixels, as `0xRRGGBB`
+    pub colors: [u32; 2],
+}
+
+pub const PALETTES: &[Palette] = &[
+    Palette {
+        name: "white",
+        colors: [0x000000, 0xFFFFFF],
+    },
+    Palette {
+        name: "green",
+        colors: [0x0A140A, 0x33FF66],
+    },
+    Palette {
+        name: "amber",
+        colors: [0x140C00, 0xFFB000],
+    },
+    Palette {
+        name: "lcd",
+        colors: [0x9BBC0F, 0x0F380F],
+    },
+    // The defaults of Octo
+    Palette {
+        name: "octo",
+        colors: [0x996600, 0xFFCC00],
+    },
+];
+
+impl Palette {
+    /// Find a palette by name
+    pub fn find(name: &str) -> Option<Self> {
+        PALETTES
+            .iter()
+            .find(|palette| palette.name.eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    /// The colors the database suggests for a rom, with the ones it leaves out taken from `fallback`
+    pub fn for_rom(info: Option<&RomInfo>, fallback: Self) -> Self {
+        let pixels = match info.and_then(|info| info.colors.as_ref()) {
+            Some(colors) if colors.pixels.len() >= 2 => &colors.pixels,
+            _ => return fallback,
+        };
+
+        let mut palette = Palette {
+            name: "rom",
+            colors: fallback.colors,
+        };
+        for (color, pixel) in palette.colors.iter_mut().zip(pixels) {
+            match parse_color(pixel) {
+                Some(parsed) => *color = parsed,
+                None => return fallback,
+            }
+        }
+        palette
+    }
+
+    /// The colors for screenshots and recordings
+    pub fn capture_colors(self) -> Colors {
+        Colors {
+            background: self.colors[0],
+            foreground: self.colors[1],
+        }
+    }
+
+    /// The next palette in [`PALETTES`], to cycle through them
+    pub fn next(self) -> Self {
+        let index = PALETTES
+            .iter()
+            .position(|palette| palette.name == self.name)
+            .map_or(0, |index| (index + 1) % PALETTES.len());
+        PALETTES[index]
+    }
+}
+
+/// Parse a color like `#FF8800`
+fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// How the screen is drawn besides its colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Effects {
+    /// Darken the edges of every pixel
+    pub grid: bool,
+    /// Darken every other row of the texture, so pixels get lines across them at scales of 2 and up
+    pub scanlines: bool,
+    /// Scale by whole numbers only, so every pixel is the same size
+    pub integer_scaling: bool,
+}
+
+pub struct Display<'a> {
+    creator: &'a TextureCreator<WindowContext>,
+    texture: Option<Texture<'a>>,
+    /// The size of a chip8 pixel in the texture
+    scale: u32,
+    framebuffer: Vec<u8>,
+    /// How lit each pixel is, from 0 to 1
+    intensity: Vec<f32>,
+    pub palette: Palette,
+    pub effects: Effects,
+}
+
+impl<'a> Display<'a> {
+    pub fn new(
+        creator: &'a TextureCreator<WindowContext>,
+        palette: Palette,
+        effects: Effects,
+    ) -> Self {
+        Display {
+            creator,
+            texture: None,
+            scale: 0,
+            framebuffer: Vec::new(),
+            intensity: vec![0.0; GFX_SIZE],
+            palette,
+            effects,
+        }
+    }
+
+    /// Take a frame of the screen, as presentation brightness from 0 to 255
+    pub fn update(&mut self, screen: &[u8]) {
+        for (intensity, &pixel) in self.intensity.iter_mut().zip(screen) {
+            *intensity = pixel as f32 / 255.0;
+        }
+    }
+
+    /// Draw the last frame to the window
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        let scale = (width / GFX_WIDTH as u32)
+            .min(height / GFX_HEIGHT as u32)
+            .max(1);
+        let (dest_width, dest_height) = if self.effects.integer_scaling {
+            (GFX_WIDTH as u32 * scale, GFX_HEIGHT as u32 * scale)
+        } else {
+            let dest_width = width.min(height * 2);
+            (dest_width, dest_width / 2)
+        };
+        let dest = Rect::new(
+            (width.saturating_sub(dest_width) / 2) as i32,
+            (height.saturating_sub(dest_height) / 2) as i32,
+            dest_width,
+            dest_height,
+        );
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        self.draw_at(canvas, dest)
+    }
+
+    /// Draw the last frame stretched over part of the window
+    pub fn draw_at(&mut self, canvas: &mut Canvas<Window>, dest: Rect) -> Result<(), String> {
+        let scale = (dest.width() / GFX_WIDTH as u32)
+            .min(dest.height() / GFX_HEIGHT as u32)
+            .max(1);
+        if scale != self.scale || self.texture.is_none() {
+            self.resize(scale)?;
+        }
+        self.render();
+
+        let texture = self.texture.as_mut().expect("the texture was just made");
+        texture
+            .update(None, &self.framebuffer, GFX_WIDTH * scale as usize * 3)
+            .map_err(|e| e.to_string())?;
+        canvas.copy(texture, None, dest)
+    }
+
+    fn resize(&mut self, scale: u32) -> Result<(), String> {
+        let width = GFX_WIDTH as u32 * scale;
+        let height = GFX_HEIGHT as u32 * scale;
+        self.texture = Some(
+            self.creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+                .map_err(|e| e.to_string())?,
+        );
+        self.framebuffer = vec![0; (width * height * 3) as usize];
+        self.scale = scale;
+        Ok(())
+    }
+
+    /// Fill the framebuffer from the pixel intensities
+    fn render(&mut self) {
+        let scale = self.scale as usize;
+        let [background, foreground] = self.palette.colors.map(rgb);
+        let width = GFX_WIDTH * scale;
+        // Overlays need a few texels per pixel to look like anything
+        let grid = self.effects.grid && scale >= 3;
+        let scanlines = self.effects.scanlines && scale >= 2;
+
+        for (i, &lit) in self.intensity.iter().enumerate() {
+            // Blend between the colors by how lit the pixel is
+            let color = [0, 1, 2]
+                .map(|channel| background[channel] * (1.0 - lit) + foreground[channel] * lit);
+
+            let cell_x = (i % GFX_WIDTH) * scale;
+            let cell_y = (i / GFX_WIDTH) * scale;
+            for y in 0..scale {
+                let row = (cell_y + y) * width;
+                for x in 0..scale {
+                    let mut shade = 1.0;
+                    if grid && (x == scale - 1 || y == scale - 1) {
+                        shade *= 0.6;
+                    }
+                    if scanlines && (cell_y + y) % 2 == 1 {
+                        shade *= 0.5;
+                    }
+
+                    let offset = (row + cell_x + x) * 3;
+                    for (texel, channel) in
+                        self.framebuffer[offset..offset + 3].iter_mut().zip(&color)
+                    {
+                        *texel = (channel * shade) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Split a color into its channels
+fn rgb(color: u32) -> [f32; 3] {
+    [
+        (color >> 16 & 0xFF) as f32,
+        (color >> 8 & 0xFF) as f32,
+        (color & 0xFF) as f32,
+    ]
+}
@@ -1,17 +1,574 @@
-use chip8::Chip8;
+mod browser;
+mod display;
+mod font;
+
+use crate::{
+    browser::{
+        Action,
+        Browser,
+    },
+    display::{
+        Display,
+        Effects,
+        Palette,
+        PALETTES,
+    },
+};
+use chip8::{
+    archive::RomArchive,
+    capture::{
+        save_png,
+        Colors,
+        Recorder,
+        RecordingFormat,
+        WavWriter,
+    },
+    database::RomDatabase,
+    deflicker::DeflickerMode,
+    rom::Rom,
+    source_map::SourceMap,
+    trace::Tracer,
+    Chip8,
+    Chip8Result,
+    FrameEvents,
+    Machine,
+    StackOverflowMode,
+};
 use sdl2::{
     event::Event,
     keyboard::Keycode,
-    pixels::Color,
-    rect::Rect,
 };
-use std::time::Duration;
+use std::{
+    fs::File,
+    io::{
+        BufWriter,
+        Read,
+        Seek,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+/// The longest stretch of time emulated at once, so a stalled host doesn't cause a burst of catch-up
+const MAX_FRAME_TIME: Duration = Duration::from_millis(100);
+
+/// The sample rate of recorded sound
+const SAMPLE_RATE: u32 = 44100;
+
+#[derive(argh::FromArgs)]
+/// A chip8 emulator
+struct Options {
+    /// the rom to run, or a zip archive of roms. Without it, a menu lists the roms in the rom directory
+    #[argh(positional)]
+    rom: Option<PathBuf>,
+
+    /// the directory the menu lists roms from. F1 goes back to the menu from a game
+    #[argh(option, default = "PathBuf::from(\"roms\")")]
+    rom_dir: PathBuf,
+
+    /// the rom to run from a zip archive, by name or index. Without it, a menu asks which one
+    #[argh(option)]
+    pick: Option<String>,
+
+    /// list the roms in a zip archive and exit
+    #[argh(switch)]
+    list: bool,
+
+    /// write an execution trace to this file
+    #[argh(option)]
+    trace: Option<PathBuf>,
+
+    /// the trace format, either 'text' or 'json'
+    #[argh(option, default = "TraceFormat::Text")]
+    trace_format: TraceFormat,
+
+    /// only trace instructions at or after this hex address
+    #[argh(option, from_str_fn(parse_hex_u16), default = "0")]
+    trace_from: u16,
+
+    /// only trace instructions before this hex address
+    #[argh(option, from_str_fn(parse_hex_u16), default = "0xFFFF")]
+    trace_to: u16,
+
+    /// a source map, to show source locations in traces and errors
+    #[argh(option)]
+    source_map: Option<PathBuf>,
+
+    /// how deep calls can nest, overriding the rom database
+    #[argh(option)]
+    stack_depth: Option<u8>,
+
+    /// warn about calls nested deeper than the stack depth instead of stopping
+    #[argh(switch)]
+    warn_stack_overflow: bool,
+
+    /// the colors, 'auto' for the ones the rom database suggests, or one of 'white', 'green', 'amber', 'lcd' and 'octo'.
+    /// F2 cycles through them
+    #[argh(option, default = "String::from(\"auto\")")]
+    palette: String,
+
+    /// darken the edges of pixels. F3 toggles it
+    #[argh(switch)]
+    grid: bool,
+
+    /// darken every other row, like a CRT. F4 toggles it
+    #[argh(switch)]
+    scanlines: bool,
+
+    /// how frames are blended to soften flicker: 'off', 'or:<frames>' to show pixels lit in any of the last frames,
+    /// or 'decay:<persistence>' for pixels to fade out, keeping that much brightness each frame
+    #[argh(option, default = "DeflickerMode::Off")]
+    deflicker: DeflickerMode,
+
+    /// fill the window instead of scaling by whole numbers
+    #[argh(switch)]
+    stretch: bool,
+
+    /// run the rom for this many frames without a window, then exit
+    #[argh(option)]
+    headless: Option<u64>,
+
+    /// with --headless, save a png of the screen once the frames ran
+    #[argh(option)]
+    screenshot: Option<PathBuf>,
+
+    /// record the rom from the start to a .gif, to an APNG with a .png or .apng extension,
+    /// or to a .y4m video for encoding offline
+    #[argh(option)]
+    record: Option<PathBuf>,
+
+    /// record the buzzer from the start to a .wav file
+    #[argh(option)]
+    record_audio: Option<PathBuf>,
+
+    /// how many times larger than the chip8 screen screenshots and recordings are
+    #[argh(option, default = "8")]
+    capture_scale: u32,
+
+    /// the directory F5 saves screenshots to and F6 saves recordings to
+    #[argh(option, default = "PathBuf::from(\".\")")]
+    capture_dir: PathBuf,
+
+    /// the format of recordings started with F6, 'gif', 'apng' or 'y4m'. Y4M recordings come with a .wav of the buzzer
+    #[argh(option, default = "RecordingFormat::Gif")]
+    record_format: RecordingFormat,
+}
+
+enum TraceFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("unknown trace format '{}'", s)),
+        }
+    }
+}
+
+fn parse_hex_u16(value: &str) -> Result<u16, String> {
+    let value = value.trim_start_matches("0x");
+    u16::from_str_radix(value, 16).map_err(|e| e.to_string())
+}
+
+/// Read the rom to run, picking it from an archive if it's in one
+fn read_rom(options: &Options) -> Result<Rom, String> {
+    let path = match options.rom.as_ref() {
+        Some(path) => path,
+        None => return Err(String::from("No rom was given")),
+    };
+    let is_archive = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+    if !is_archive {
+        return Rom::open(path, &RomDatabase::bundled())
+            .map_err(|e| format!("Failed to load '{}': {}", path.display(), e));
+    }
+
+    let mut archive = RomArchive::open(path)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    if options.list {
+        for (i, rom) in archive.roms().iter().enumerate() {
+            println!("{}: {}", i, rom.path);
+        }
+        std::process::exit(0);
+    }
+    if archive.roms().is_empty() {
+        return Err(format!("There are no roms in '{}'", path.display()));
+    }
+
+    let index = match options.pick.as_ref() {
+        Some(name) => archive.find(name).map_err(|e| e.to_string())?,
+        None => pick_from_menu(&archive)?,
+    };
+    if let Some(notes) = archive.notes(index).map_err(|e| e.to_string())? {
+        println!("{}", notes.trim_end());
+    }
+    let data = archive.read(index).map_err(|e| e.to_string())?;
+    Rom::parse(&data, &RomDatabase::bundled())
+        .map_err(|e| format!("Failed to load '{}': {}", archive.roms()[index].path, e))
+}
+
+/// Ask which rom of an archive to run on the terminal
+fn pick_from_menu<R: Read + Seek>(archive: &RomArchive<R>) -> Result<usize, String> {
+    for (i, rom) in archive.roms().iter().enumerate() {
+        println!("{:>3}: {}", i, rom.path);
+    }
+    loop {
+        print!("Pick a rom by number or name: ");
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => return Err(String::from("No rom was picked")),
+            Ok(_) => {}
+            Err(e) => return Err(format!("Failed to read the choice: {}", e)),
+        }
+        match archive.find(line.trim()) {
+            Ok(index) => return Ok(index),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// A rom being played
+struct Game {
+    machine: Machine,
+    source_map: Option<Arc<SourceMap>>,
+    /// The files being recorded to
+    recording: Vec<PathBuf>,
+}
+
+impl Game {
+    /// Start a rom with the settings from the command line
+    fn new(rom: Rom, options: &Options) -> Result<Self, String> {
+        let mut chip8 = Chip8::new();
+        chip8.init();
+        rom.load_into(&mut chip8)
+            .map_err(|e| format!("Invalid ROM: {}", e))?;
+
+        if let Some(title) = rom.title.as_ref() {
+            println!("{}", title);
+        }
+        if let Some(info) = rom.info.as_ref() {
+            if !info.authors.is_empty() {
+                println!("By {}", info.authors.join(", "));
+            }
+            let mut keys: Vec<_> = info.keys.iter().collect();
+            keys.sort_by_key(|(_, &key)| key);
+            for (action, key) in keys {
+                println!("{}: {:X}", action, key);
+            }
+        }
+
+        if let Some(stack_depth) = options.stack_depth {
+            let mut quirks = chip8.quirks();
+            quirks.stack_depth = stack_depth;
+            chip8.set_quirks(quirks);
+        }
+        if options.warn_stack_overflow {
+            chip8.set_stack_overflow_mode(StackOverflowMode::Warn);
+        }
+
+        let source_map = options
+            .source_map
+            .as_ref()
+            .map(SourceMap::load)
+            .transpose()
+            .map_err(|e| format!("Failed to load source map: {}", e))?
+            .or(rom.source_map)
+            .map(Arc::new);
+
+        if let Some(path) = options.trace.as_ref() {
+            let file = File::create(path)
+                .map(BufWriter::new)
+                .map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+            let mut tracer = match options.trace_format {
+                TraceFormat::Text => Tracer::text(file),
+                TraceFormat::Json => Tracer::json_lines(file),
+            };
+            if let Some(map) = source_map.as_ref() {
+                tracer = tracer.with_source_map(map.clone());
+            }
+            chip8.set_tracer(Some(
+                tracer.with_filter(options.trace_from..options.trace_to),
+            ));
+        }
+
+        let mut machine = Machine::new(chip8);
+        machine.set_tickrate(rom.tickrate);
+        machine.set_deflicker_mode(options.deflicker);
+        Ok(Game {
+            machine,
+            source_map,
+            recording: Vec::new(),
+        })
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        let chip8 = self.machine.chip8_mut();
+        match event {
+            Event::KeyDown {
+                keycode: Some(code),
+                ..
+            } => {
+                if let Some(key) = keypad_key(*code) {
+                    let _ = chip8.set_key(key, true);
+                }
+            }
+            Event::KeyUp {
+                keycode: Some(code),
+                ..
+            } => {
+                if let Some(key) = keypad_key(*code) {
+                    let _ = chip8.set_key(key, false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Run the program, returning whether it can go on
+    fn run_for(&mut self, elapsed: Duration) -> bool {
+        let result = self.machine.run_for(elapsed);
+        self.check(result)
+    }
+
+    /// Run the program for exactly one frame, returning whether it can go on
+    fn run_frame(&mut self) -> bool {
+        let result = self.machine.run_frame();
+        self.check(result)
+    }
+
+    /// Report what happened while running, returning whether the program can go on
+    fn check(&mut self, result: Chip8Result<FrameEvents>) -> bool {
+        match result {
+            Ok(events) => {
+                if events.stack_overflowed {
+                    let chip8 = self.machine.chip8();
+                    eprintln!(
+                        "Warning: calls are nested {} deep, past the stack depth of {}",
+                        chip8.sp(),
+                        chip8.quirks().stack_depth
+                    );
+                }
+                true
+            }
+            Err(e) => {
+                match self.source_map.as_ref() {
+                    Some(map) => eprintln!(
+                        "Chip8 error at {}: {:#}",
+                        map.describe(self.machine.chip8().pc()),
+                        e
+                    ),
+                    None => eprintln!("Chip8 error: {:#}", e),
+                }
+                false
+            }
+        }
+    }
+
+    /// Save a png of the screen
+    fn screenshot(&self, path: &Path, options: &Options, colors: Colors) {
+        match save_png(
+            path,
+            self.machine.presentation(),
+            options.capture_scale,
+            colors,
+        ) {
+            Ok(()) => println!("Saved a screenshot to '{}'", path.display()),
+            Err(e) => eprintln!("Failed to save '{}': {}", path.display(), e),
+        }
+    }
+
+    /// Record every frame from now on, the screen to `video` and the buzzer to `audio`
+    fn start_recording(
+        &mut self,
+        video: Option<PathBuf>,
+        audio: Option<PathBuf>,
+        options: &Options,
+        colors: Colors,
+    ) {
+        let failed = |path: &Path, e| eprintln!("Failed to record to '{}': {}", path.display(), e);
+        let wav = match audio
+            .as_ref()
+            .map(|path| (path, WavWriter::create(path, SAMPLE_RATE)))
+        {
+            Some((path, Err(e))) => return failed(path, e),
+            Some((_, Ok(wav))) => Some(wav),
+            None => None,
+        };
+        let recorder = match (video.as_ref(), wav) {
+            (Some(path), wav) => match Recorder::create(path, options.capture_scale, colors) {
+                Ok(recorder) => match wav {
+                    Some(wav) => recorder.with_audio(wav),
+                    None => recorder,
+                },
+                Err(e) => return failed(path, e),
+            },
+            (None, Some(wav)) => Recorder::audio_only(wav),
+            (None, None) => return,
+        };
+
+        self.recording = video.into_iter().chain(audio).collect();
+        println!("Recording to {}", describe_paths(&self.recording));
+        self.machine.set_recorder(Some(recorder));
+    }
+
+    /// Stop recording and finish the files, if recording
+    fn stop_recording(&mut self) {
+        let recorder = match self.machine.set_recorder(None) {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        let paths = describe_paths(&std::mem::take(&mut self.recording));
+        let frames = recorder.frames();
+        match recorder.finish() {
+            Ok(()) => println!("Saved {} frames to {}", frames, paths),
+            Err(e) => eprintln!("Failed to write {}: {}", paths, e),
+        }
+    }
+
+    /// Finish the recording and flush the trace, if there are any
+    fn finish(&mut self) {
+        self.stop_recording();
+        if let Some(tracer) = self.machine.chip8_mut().tracer_mut() {
+            if let Err(e) = tracer.take_error().map_or_else(|| tracer.flush(), Err) {
+                eprintln!("Failed to write trace: {}", e);
+            }
+        }
+    }
+}
+
+/// Get the palette to use for a rom
+fn palette(options: &Options, rom: &Rom) -> Palette {
+    let default = Palette::find("white").expect("white is a palette");
+    match options.palette.as_str() {
+        "auto" => Palette::for_rom(rom.info.as_ref(), default),
+        name => Palette::find(name).unwrap_or(default),
+    }
+}
+
+/// List paths for messages, like `'a.y4m' and 'a.wav'`
+fn describe_paths(paths: &[PathBuf]) -> String {
+    let quoted: Vec<_> = paths
+        .iter()
+        .map(|path| format!("'{}'", path.display()))
+        .collect();
+    quoted.join(" and ")
+}
+
+/// A new file in the capture directory, named after the time
+fn capture_path(options: &Options, extension: &str) -> PathBuf {
+    let time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    options.capture_dir.join(format!(
+        "chip8-{}-{:03}.{}",
+        time.as_secs(),
+        time.subsec_millis(),
+        extension
+    ))
+}
+
+/// Run a rom for a number of frames without a window
+fn run_headless(rom: Rom, frames: u64, options: &Options) {
+    let colors = palette(options, &rom).capture_colors();
+    let mut game = match Game::new(rom, options) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    game.start_recording(
+        options.record.clone(),
+        options.record_audio.clone(),
+        options,
+        colors,
+    );
+    for _ in 0..frames {
+        if !game.run_frame() {
+            break;
+        }
+    }
+    if let Some(path) = options.screenshot.as_ref() {
+        game.screenshot(path, options, colors);
+    }
+    game.finish();
+}
+
+/// Get the chip8 key a keyboard key is mapped to
+fn keypad_key(code: Keycode) -> Option<usize> {
+    let key = match code {
+        Keycode::X => 0,
+        Keycode::Num1 => 1,
+        Keycode::Num2 => 2,
+        Keycode::Num3 => 3,
+        Keycode::Q => 4,
+        Keycode::W => 5,
+        Keycode::E => 6,
+        Keycode::A => 7,
+        Keycode::S => 8,
+        Keycode::D => 9,
+        Keycode::Z => 10,
+        Keycode::C => 11,
+        Keycode::Num4 => 12,
+        Keycode::R => 13,
+        Keycode::F => 14,
+        Keycode::V => 15,
+        _ => return None,
+    };
+    Some(key)
+}
 
 fn main() {
-    // let filename = "../BLINKY.c8";
-    let filename = "../BC_test.ch8";
-    // let file_data = include_bytes!("../../roms/tetris.c8").to_vec();
+    let options: Options = argh::from_env();
+    if options.palette != "auto" && Palette::find(&options.palette).is_none() {
+        eprintln!("Unknown palette '{}'", options.palette);
+        return;
+    }
+
+    if options.capture_scale == 0 {
+        eprintln!("The capture scale must be at least 1");
+        return;
+    }
+
+    let rom = match options.rom.as_ref().map(|_| read_rom(&options)).transpose() {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
+    match (options.headless, rom) {
+        (Some(frames), Some(rom)) => run_headless(rom, frames, &options),
+        (Some(_), None) => eprintln!("--headless needs a rom"),
+        (None, _) if options.screenshot.is_some() => {
+            eprintln!("--screenshot needs --headless, F5 takes screenshots in the window")
+        }
+        (None, rom) => run_window(rom, &options),
+    }
+}
+
+/// Run roms in a window, starting with a rom or the menu
+fn run_window(rom: Option<Rom>, options: &Options) {
     let sdl_context = match sdl2::init() {
         Ok(c) => c,
         Err(e) => {
@@ -31,6 +588,7 @@ fn main() {
     let window = match video_subsystem
         .window("Chip8", 64 * 10, 32 * 10)
         .position_centered()
+        .resizable()
         .build()
     {
         Ok(w) => w,
@@ -40,7 +598,7 @@ fn main() {
         }
     };
 
-    let mut canvas = match window.into_canvas().target_texture().build() {
+    let mut canvas = match window.into_canvas().build() {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Failed to init canvas: {}", e);
@@ -56,114 +614,202 @@ fn main() {
         }
     };
 
-    let file_data = match std::fs::read(filename) {
-        Ok(f) => f,
+    let texture_creator = canvas.texture_creator();
+    let effects = Effects {
+        grid: options.grid,
+        scanlines: options.scanlines,
+        integer_scaling: !options.stretch,
+    };
+    let first_palette = match rom.as_ref() {
+        Some(rom) => palette(options, rom),
+        None => PALETTES[0],
+    };
+    let mut display = Display::new(&texture_creator, first_palette, effects);
+
+    // The menu is only scanned once it's needed
+    let mut browser = None;
+    let mut game = match rom.map(|rom| Game::new(rom, options)).transpose() {
+        Ok(game) => game,
         Err(e) => {
-            eprintln!("Failed to read '{}': {}", filename, e);
+            eprintln!("{}", e);
             return;
         }
     };
-
-    let cycles_per_tick = 7;
-
-    let mut chip8 = Chip8::new();
-    chip8.init();
-    match chip8.load(&file_data) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Invalid ROM: {:#?}", e);
+    if let Some(game) = game.as_mut() {
+        game.start_recording(
+            options.record.clone(),
+            options.record_audio.clone(),
+            options,
+            display.palette.capture_colors(),
+        );
+    }
+    if game.is_none() {
+        match Browser::scan(&options.rom_dir, &texture_creator) {
+            Ok(menu) => browser = Some(menu),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
         }
     }
+    let mut last_frame = Instant::now();
 
     'running: loop {
+        let mut launch = None;
+        let mut to_menu = false;
         for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                Event::KeyDown {
-                    keycode: Some(code),
-                    ..
-                } => match code {
-                    Keycode::X => chip8.set_key(0, true),
-                    Keycode::Num1 => chip8.set_key(1, true),
-                    Keycode::Num2 => chip8.set_key(2, true),
-                    Keycode::Num3 => chip8.set_key(3, true),
-                    Keycode::Q => chip8.set_key(4, true),
-                    Keycode::W => chip8.set_key(5, true),
-                    Keycode::E => chip8.set_key(6, true),
-                    Keycode::A => chip8.set_key(7, true),
-                    Keycode::S => chip8.set_key(8, true),
-                    Keycode::D => chip8.set_key(9, true),
-                    Keycode::Z => chip8.set_key(10, true),
-                    Keycode::C => chip8.set_key(11, true),
-                    Keycode::Num4 => chip8.set_key(12, true),
-                    Keycode::R => chip8.set_key(13, true),
-                    Keycode::F => chip8.set_key(14, true),
-                    Keycode::V => chip8.set_key(15, true),
-                    _ => {}
-                },
-                Event::KeyUp {
-                    keycode: Some(code),
-                    ..
-                } => match code {
-                    Keycode::X => chip8.set_key(0, false),
-                    Keycode::Num1 => chip8.set_key(1, false),
-                    Keycode::Num2 => chip8.set_key(2, false),
-                    Keycode::Num3 => chip8.set_key(3, false),
-                    Keycode::Q => chip8.set_key(4, false),
-                    Keycode::W => chip8.set_key(5, false),
-                    Keycode::E => chip8.set_key(6, false),
-                    Keycode::A => chip8.set_key(7, false),
-                    Keycode::S => chip8.set_key(8, false),
-                    Keycode::D => chip8.set_key(9, false),
-                    Keycode::Z => chip8.set_key(10, false),
-                    Keycode::C => chip8.set_key(11, false),
-                    Keycode::Num4 => chip8.set_key(12, false),
-                    Keycode::R => chip8.set_key(13, false),
-                    Keycode::F => chip8.set_key(14, false),
-                    Keycode::V => chip8.set_key(15, false),
-                    _ => {}
-                },
-                _ => {}
+            match (&event, game.as_mut()) {
+                (Event::Quit { .. }, _)
+                | (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    },
+                    _,
+                ) => break 'running,
+                (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F1),
+                        ..
+                    },
+                    Some(_),
+                ) => to_menu = true,
+                (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F2),
+                        ..
+                    },
+                    Some(_),
+                ) => display.palette = display.palette.next(),
+                (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F3),
+                        ..
+                    },
+                    Some(_),
+                ) => display.effects.grid = !display.effects.grid,
+                (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F4),
+                        ..
+                    },
+                    Some(_),
+                ) => display.effects.scanlines = !display.effects.scanlines,
+                (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        ..
+                    },
+                    Some(game),
+                ) => game.screenshot(
+                    &capture_path(options, "png"),
+                    options,
+                    display.palette.capture_colors(),
+                ),
+                (
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F6),
+                        ..
+                    },
+                    Some(game),
+                ) => {
+                    if game.recording.is_empty() {
+                        let path = capture_path(options, options.record_format.extension());
+                        // Y4M has no sound, so it goes next to the video
+                        let audio = Some(path.with_extension("wav"))
+                            .filter(|_| options.record_format == RecordingFormat::Y4m);
+                        game.start_recording(
+                            Some(path),
+                            audio,
+                            options,
+                            display.palette.capture_colors(),
+                        );
+                    } else {
+                        game.stop_recording();
+                    }
+                }
+                (_, Some(game)) => game.handle_event(&event),
+                (_, None) => {
+                    if let Some(menu) = browser.as_mut() {
+                        match menu.handle_event(&event) {
+                            Action::Launch(rom) => launch = Some(rom),
+                            Action::Quit => break 'running,
+                            Action::None => {}
+                        }
+                    }
+                }
             }
         }
 
-        chip8.update_timers();
+        if let Some(rom) = launch {
+            let title = rom.title.clone().unwrap_or_else(|| String::from("Chip8"));
+            let rom_palette = palette(options, &rom);
+            match Game::new(*rom, options) {
+                Ok(new_game) => {
+                    let _ = canvas.window_mut().set_title(&title);
+                    display.palette = rom_palette;
+                    game = Some(new_game);
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+            last_frame = Instant::now();
+        }
 
-        for _ in 0..cycles_per_tick {
-            let cycle = match chip8.cycle() {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Chip8 error: {:#?}", e);
+        let now = Instant::now();
+        let elapsed = (now - last_frame).min(MAX_FRAME_TIME);
+        last_frame = now;
+        if let Some(current) = game.as_mut() {
+            if !current.run_for(elapsed) {
+                // Without a menu to go back to, an error ends the program
+                if browser.is_none() {
                     break 'running;
                 }
-            };
-            println!("{}", cycle);
+                to_menu = true;
+            }
+        } else if let Some(menu) = browser.as_mut() {
+            menu.run_for(elapsed);
         }
 
-        canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
-        canvas.clear();
-
-        for (i, &el) in chip8.gfx.iter().enumerate() {
-            let x = (i as i32 % 64) * 10;
-            let y = (i / 64) as i32 * 10;
-            if el {
-                canvas.set_draw_color(Color::RGBA(255, 255, 255, 255));
-                canvas
-                    .fill_rect(Rect::new(x, y, 10, 10))
-                    .expect("could not fill rect");
+        if to_menu {
+            if browser.is_none() {
+                match Browser::scan(&options.rom_dir, &texture_creator) {
+                    Ok(menu) => browser = Some(menu),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+            if browser.is_some() {
+                if let Some(mut game) = game.take() {
+                    game.finish();
+                }
+                let _ = canvas.window_mut().set_title("Chip8");
             }
+        }
 
-            if i % 2 == 0 {
-                canvas.set_draw_color(Color::RGBA(255, 0, 255, 255));
-                // canvas.fill_rect(Rect::new(x, y, 10, 10));
+        let drawn = match (game.as_ref(), browser.as_mut()) {
+            (Some(game), _) => {
+                display.update(game.machine.presentation());
+                canvas
+                    .set_logical_size(0, 0)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| display.draw(&mut canvas))
             }
+            // The menu is laid out for the starting size of the window
+            (None, Some(menu)) => canvas
+                .set_logical_size(64 * 10, 32 * 10)
+                .map_err(|e| e.to_string())
+                .and_then(|_| menu.draw(&mut canvas)),
+            (None, None) => break 'running,
+        };
+        if let Err(e) = drawn {
+            eprintln!("Failed to draw: {}", e);
+            break 'running;
         }
 
         canvas.present();
         std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
+
+    if let Some(game) = game.as_mut() {
+        game.finish();
+    }
 }
@@ -0,0 +1,264 @@
+use chip8::{
+    database::{
+        RomDatabase,
+        DEFAULT_TICKRATE,
+    },
+    deflicker::DeflickerMode,
+    Machine,
+    Quirks,
+    GFX_HEIGHT,
+    GFX_WIDTH,
+    MEMORY_SIZE,
+    NUM_KEYS,
+};
+use pyo3::{
+    create_exception,
+    exceptions::{
+        PyException,
+        PyValueError,
+    },
+    prelude::*,
+    types::PyBytes,
+};
+use std::sync::{
+    Mutex,
+    MutexGuard,
+};
+
+create_exception!(chip8, Chip8Error, PyException);
+
+fn to_py_err(e: chip8::Chip8Error) -> PyErr {
+    Chip8Error::new_err(e.to_string())
+}
+
+struct Inner {
+    machine: Machine,
+    rom: Vec<u8>,
+    title: Option<String>,
+}
+
+/// A chip8 that runs at the speed the rom database suggests for the loaded rom
+#[pyclass]
+struct Chip8 {
+    // Machines aren't `Sync`, and the lock lets frames run without holding the GIL
+    inner: Mutex<Inner>,
+}
+
+impl Chip8 {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[pymethods]
+impl Chip8 {
+    #[new]
+    #[pyo3(signature = (rom = None))]
+    fn new(rom: Option<&[u8]>) -> PyResult<Self> {
+        let mut chip8 = chip8::Chip8::new();
+        chip8.init();
+        let chip8 = Chip8 {
+            inner: Mutex::new(Inner {
+                machine: Machine::new(chip8),
+                rom: Vec::new(),
+                title: None,
+            }),
+        };
+        if let Some(rom) = rom {
+            chip8.load(rom)?;
+        }
+        Ok(chip8)
+    }
+
+    /// Load a rom and start it from the beginning, configuring quirks and speed if the rom is known
+    fn load(&self, rom: &[u8]) -> PyResult<()> {
+        let mut inner = self.lock();
+        inner.rom = rom.to_vec();
+
+        // Unknown roms get the defaults, not whatever the last rom used
+        let info = RomDatabase::bundled().lookup(rom);
+        let (quirks, tickrate) = match info.as_ref() {
+            Some(info) => (info.quirks, info.tickrate),
+            None => (Quirks::default(), DEFAULT_TICKRATE),
+        };
+        inner.machine.chip8_mut().set_quirks(quirks);
+        inner.machine.set_tickrate(tickrate);
+        inner.title = info.map(|info| info.title);
+
+        inner.reset()
+    }
+
+    /// Restart the loaded rom
+    fn reset(&self) -> PyResult<()> {
+        self.lock().reset()
+    }
+
+    /// Run some number of frames, releasing the GIL while they run.
+    ///
+    /// Returns whether the display changed.
+    #[pyo3(signature = (frames = 1))]
+    fn step(&self, py: Python<'_>, frames: u32) -> PyResult<bool> {
+        let mut inner = self.lock();
+        let machine = &mut inner.machine;
+        py.detach(|| {
+            let mut drew = false;
+            for _ in 0..frames {
+                drew |= machine.run_frame()?.drew;
+            }
+            Ok(drew)
+        })
+        .map_err(to_py_err)
+    }
+
+    /// Press or release a key
+    fn set_key(&self, key: usize, pressed: bool) -> PyResult<()> {
+        if key >= NUM_KEYS {
+            return Err(PyValueError::new_err(format!("invalid key {}", key)));
+        }
+        self.lock()
+            .machine
+            .chip8_mut()
+            .set_key(key, pressed)
+            .map_err(to_py_err)
+    }
+
+    /// The display as a `(32, 64)` `uint8` numpy array of 0s and 1s
+    fn framebuffer<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let bytes = self.framebuffer_bytes(py);
+        py.import("numpy")?
+            .call_method1("frombuffer", (bytes, "uint8"))?
+            .call_method1("reshape", ((GFX_HEIGHT, GFX_WIDTH),))
+    }
+
+    /// The display as bytes of 0s and 1s, row by row
+    fn framebuffer_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let inner = self.lock();
+        let gfx: Vec<u8> = inner
+            .machine
+            .chip8()
+            .gfx
+            .iter()
+            .map(|&el| el as u8)
+            .collect();
+        PyBytes::new(py, &gfx)
+    }
+
+    /// Set how frames are blended to soften flicker: `off`, `or:<frames>` or `decay:<persistence>`
+    fn set_deflicker(&self, mode: &str) -> PyResult<()> {
+        let mode: DeflickerMode = mode.parse().map_err(PyValueError::new_err)?;
+        self.lock().machine.set_deflicker_mode(mode);
+        Ok(())
+    }
+
+    /// How bright each pixel should be shown after deflickering, as a `(32, 64)` `uint8` numpy array from 0 to 255
+    fn presentation<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let bytes = self.presentation_bytes(py);
+        py.import("numpy")?
+            .call_method1("frombuffer", (bytes, "uint8"))?
+            .call_method1("reshape", ((GFX_HEIGHT, GFX_WIDTH),))
+    }
+
+    /// How bright each pixel should be shown after deflickering, as bytes from 0 to 255, row by row
+    fn presentation_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.lock().machine.presentation()[..])
+    }
+
+    /// Seed the random number generator
+    fn seed(&self, seed: u64) {
+        self.lock().machine.chip8_mut().seed_rng(seed);
+    }
+
+    /// Copy the whole emulator state
+    fn save_state(&self) -> State {
+        State {
+            machine: Mutex::new(self.lock().machine.clone()),
+        }
+    }
+
+    /// Go back to a state made by `save_state`
+    fn load_state(&self, state: &State) {
+        let machine = state
+            .machine
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        self.lock().machine = machine;
+    }
+
+    /// Read a byte of memory
+    fn peek(&self, addr: u16) -> PyResult<u8> {
+        self.lock().machine.chip8().peek(addr).map_err(to_py_err)
+    }
+
+    /// Write a byte of memory
+    fn poke(&self, addr: u16, value: u8) -> PyResult<()> {
+        self.lock()
+            .machine
+            .chip8_mut()
+            .poke(addr, value)
+            .map_err(to_py_err)
+    }
+
+    /// All of memory
+    fn memory<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.lock().machine.chip8().memory()[..])
+    }
+
+    /// The V registers
+    #[getter]
+    fn registers<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.lock().machine.chip8().registers()[..])
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.lock().machine.chip8().pc()
+    }
+
+    #[getter]
+    fn i(&self) -> u16 {
+        self.lock().machine.chip8().i()
+    }
+
+    #[getter]
+    fn instructions_per_second(&self) -> u32 {
+        self.lock().machine.instructions_per_second()
+    }
+
+    #[getter]
+    fn sound_on(&self) -> bool {
+        self.lock().machine.chip8().is_sound_on()
+    }
+
+    /// The title of the loaded rom, if known
+    #[getter]
+    fn title(&self) -> Option<String> {
+        self.lock().title.clone()
+    }
+}
+
+impl Inner {
+    fn reset(&mut self) -> PyResult<()> {
+        let chip8 = self.machine.chip8_mut();
+        chip8.init();
+        chip8.load(&self.rom).map_err(to_py_err)?;
+        self.machine.reset_clock();
+        Ok(())
+    }
+}
+
+/// A copy of an emulator made by `Chip8.save_state`
+#[pyclass(frozen)]
+struct State {
+    machine: Mutex<Machine>,
+}
+
+#[pymodule]
+#[pyo3(name = "chip8")]
+fn chip8_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Chip8>()?;
+    m.add_class::<State>()?;
+    m.add("Chip8Error", m.py().get_type::<Chip8Error>())?;
+    m.add("MEMORY_SIZE", MEMORY_SIZE)?;
+    Ok(())
+}
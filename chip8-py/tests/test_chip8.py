@@ -0,0 +1,99 @@
+import os
+import threading
+import unittest
+
+import chip8
+
+ROOT = os.path.join(os.path.dirname(__file__), "..", "..")
+
+
+def read_rom(name):
+    with open(os.path.join(ROOT, name), "rb") as f:
+        return f.read()
+
+
+class Chip8Test(unittest.TestCase):
+    def setUp(self):
+        self.rom = read_rom("pong.c8")
+        self.chip8 = chip8.Chip8(self.rom)
+
+    def test_load(self):
+        self.assertEqual(self.chip8.pc, 0x200)
+        self.assertEqual(self.chip8.memory()[0x200 : 0x200 + len(self.rom)], self.rom)
+        self.assertIsNotNone(self.chip8.title)
+
+    def test_unknown_roms_get_the_defaults(self):
+        # Pong runs as the VIP, 15 instructions a frame with the vblank quirk
+        self.assertEqual(self.chip8.instructions_per_second, 15 * 60)
+
+        # Draw twice and loop, which takes two frames with the vblank quirk
+        self.chip8.load(bytes([0xD0, 0x01, 0xD0, 0x01, 0x12, 0x04]))
+        self.assertIsNone(self.chip8.title)
+        self.assertEqual(self.chip8.instructions_per_second, 7 * 60)
+        self.chip8.step(1)
+        self.assertNotIn(1, self.chip8.framebuffer_bytes())
+
+    def test_step_draws(self):
+        self.assertTrue(self.chip8.step(10))
+        self.assertIn(1, self.chip8.framebuffer_bytes())
+
+    def test_reset(self):
+        self.chip8.step(10)
+        self.chip8.reset()
+        self.assertEqual(self.chip8.pc, 0x200)
+        self.assertNotIn(1, self.chip8.framebuffer_bytes())
+
+    def test_state_round_trip(self):
+        self.chip8.step(30)
+        state = self.chip8.save_state()
+        self.chip8.set_key(1, True)
+        self.chip8.step(60)
+        after = self.chip8.framebuffer_bytes()
+
+        self.chip8.load_state(state)
+        self.chip8.step(60)
+        self.assertEqual(self.chip8.framebuffer_bytes(), after)
+
+    def test_peek_poke(self):
+        self.chip8.poke(0x300, 0xAB)
+        self.assertEqual(self.chip8.peek(0x300), 0xAB)
+        with self.assertRaises(chip8.Chip8Error):
+            self.chip8.peek(chip8.MEMORY_SIZE)
+        with self.assertRaises(ValueError):
+            self.chip8.set_key(16, True)
+
+    def test_step_from_threads(self):
+        machines = [chip8.Chip8(self.rom) for _ in range(4)]
+        for m in machines:
+            m.seed(7)
+        threads = [threading.Thread(target=m.step, args=(120,)) for m in machines]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+        frames = {m.framebuffer_bytes() for m in machines}
+        self.assertEqual(len(frames), 1)
+
+    def test_deflicker(self):
+        self.chip8.set_deflicker("or:2")
+        self.chip8.step(10)
+        lit = [i for i, value in enumerate(self.chip8.framebuffer_bytes()) if value]
+        presentation = self.chip8.presentation_bytes()
+        self.assertTrue(all(presentation[i] == 255 for i in lit))
+        with self.assertRaises(ValueError):
+            self.chip8.set_deflicker("blend")
+
+    def test_framebuffer(self):
+        try:
+            import numpy
+        except ImportError:
+            self.skipTest("numpy is not installed")
+        self.chip8.step(10)
+        fb = self.chip8.framebuffer()
+        self.assertEqual(fb.shape, (32, 64))
+        self.assertEqual(fb.dtype, numpy.uint8)
+        self.assertEqual(fb.tobytes(), self.chip8.framebuffer_bytes())
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,210 @@
+//! Finding where two chip8 execution traces diverge.
+//!
+//! Traces are lists of [`TextTraceLine`]s, from text traces or from running roms.
+//! [`diff`] lines them up and finds the first instruction where they differ.
+
+use chip8::{
+    trace::TextTraceLine,
+    Quirks,
+};
+
+/// What to leave out when comparing lines
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiffOptions {
+    /// Don't compare the delay and sound timers
+    pub ignore_timers: bool,
+    /// Don't compare memory writes
+    pub ignore_writes: bool,
+}
+
+/// The first pair of lines that differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the line in the left trace
+    pub left: usize,
+    /// The index of the line in the right trace
+    pub right: usize,
+    /// How the lines differ, like `V3: 01 vs 02`
+    pub differences: Vec<String>,
+}
+
+/// The result of comparing two traces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// The line of the left trace the comparison started at
+    pub left_start: usize,
+    /// The line of the right trace the comparison started at
+    pub right_start: usize,
+    /// The number of lines that matched, from the starts to the divergence or to the end of the shorter trace
+    pub matched: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// Line up two traces and find the first line where they differ.
+///
+/// If one trace ends before anything differs, there is no divergence.
+pub fn diff(left: &[TextTraceLine], right: &[TextTraceLine], options: DiffOptions) -> Diff {
+    let (left_start, right_start) = align(left, right);
+    let pairs = left[left_start..].iter().zip(right[right_start..].iter());
+
+    let mut matched = 0;
+    let mut divergence = None;
+    for (l, r) in pairs {
+        let differences = compare(l, r, options);
+        if !differences.is_empty() {
+            divergence = Some(Divergence {
+                left: left_start + matched,
+                right: right_start + matched,
+                differences,
+            });
+            break;
+        }
+        matched += 1;
+    }
+
+    Diff {
+        left_start,
+        right_start,
+        matched,
+        divergence,
+    }
+}
+
+/// Parse a text trace, skipping blank lines and `#` comments. Lines are returned with their trimmed text.
+pub fn parse_trace(text: &str) -> Result<Vec<(String, TextTraceLine)>, String> {
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parsed = line.parse().map_err(|e| format!("line {}: {}", i + 1, e))?;
+        lines.push((line.to_string(), parsed));
+    }
+    Ok(lines)
+}
+
+/// Parse a quirk spec like `chip8,-vblank,+shift`
+pub fn parse_quirks(spec: &str, db: Option<Quirks>) -> Result<Quirks, String> {
+    let mut parts = spec.split(',').map(str::trim);
+    let mut quirks = match parts.next().unwrap_or("db") {
+        "db" => db.unwrap_or_default(),
+        "default" => Quirks::default(),
+        "chip8" => Quirks::CHIP8,
+        "modern" => Quirks::MODERN_CHIP8,
+        "schip" => Quirks::SCHIP,
+        "xochip" => Quirks::XOCHIP,
+        preset => return Err(format!("unknown quirk preset '{}'", preset)),
+    };
+
+    for part in parts {
+        let (value, name) = if let Some(name) = part.strip_prefix('+') {
+            (true, name)
+        } else if let Some(name) = part.strip_prefix('-') {
+            (false, name)
+        } else {
+            return Err(format!("quirk change '{}' must start with + or -", part));
+        };
+        let quirk = match name {
+            "shift" => &mut quirks.shift,
+            "memory-increment-by-x" => &mut quirks.memory_increment_by_x,
+            "memory-leave-i-unchanged" => &mut quirks.memory_leave_i_unchanged,
+            "wrap" => &mut quirks.wrap,
+            "jump" => &mut quirks.jump,
+            "vblank" => &mut quirks.vblank,
+            "logic" => &mut quirks.logic,
+            _ => return Err(format!("unknown quirk '{}'", name)),
+        };
+        *quirk = value;
+    }
+
+    Ok(quirks)
+}
+
+/// Find where two traces line up, in case one starts later than the other
+pub fn align(left: &[TextTraceLine], right: &[TextTraceLine]) -> (usize, usize) {
+    let (left_first, right_first) = match (left.first(), right.first()) {
+        (Some(l), Some(r)) => (l, r),
+        _ => return (0, 0),
+    };
+
+    let same_instruction =
+        |a: &TextTraceLine, b: &TextTraceLine| a.pc == b.pc && a.opcode == b.opcode;
+    if same_instruction(left_first, right_first) {
+        return (0, 0);
+    }
+
+    let left_start = left
+        .iter()
+        .position(|line| same_instruction(line, right_first));
+    let right_start = right
+        .iter()
+        .position(|line| same_instruction(line, left_first));
+    match (left_start, right_start) {
+        (Some(l), Some(r)) if l <= r => (l, 0),
+        (_, Some(r)) => (0, r),
+        (Some(l), None) => (l, 0),
+        (None, None) => (0, 0),
+    }
+}
+
+/// Describe how two lines differ, comparing only the fields both have
+pub fn compare(left: &TextTraceLine, right: &TextTraceLine, options: DiffOptions) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    if left.pc != right.pc {
+        differences.push(format!("PC: {:04X} vs {:04X}", left.pc, right.pc));
+    }
+    if left.opcode != right.opcode {
+        differences.push(format!(
+            "opcode: {:04X} vs {:04X}",
+            left.opcode, right.opcode
+        ));
+    }
+    for (reg, (l, r)) in left.v.iter().zip(right.v.iter()).enumerate() {
+        if let (Some(l), Some(r)) = (l, r) {
+            if l != r {
+                differences.push(format!("V{:X}: {:02X} vs {:02X}", reg, l, r));
+            }
+        }
+    }
+    if let (Some(l), Some(r)) = (left.i, right.i) {
+        if l != r {
+            differences.push(format!("I: {:04X} vs {:04X}", l, r));
+        }
+    }
+    if let (Some(l), Some(r)) = (left.sp, right.sp) {
+        if l != r {
+            differences.push(format!("SP: {:02X} vs {:02X}", l, r));
+        }
+    }
+    if !options.ignore_timers {
+        if let (Some(l), Some(r)) = (left.delay_timer, right.delay_timer) {
+            if l != r {
+                differences.push(format!("DT: {:02X} vs {:02X}", l, r));
+            }
+        }
+        if let (Some(l), Some(r)) = (left.sound_timer, right.sound_timer) {
+            if l != r {
+                differences.push(format!("ST: {:02X} vs {:02X}", l, r));
+            }
+        }
+    }
+    if !options.ignore_writes && left.writes != right.writes {
+        let format_writes = |line: &TextTraceLine| {
+            line.writes
+                .iter()
+                .map(|write| format!("{:04X}={:02X}", write.addr, write.value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        differences.push(format!(
+            "memory writes: [{}] vs [{}]",
+            format_writes(left),
+            format_writes(right)
+        ));
+    }
+
+    differences
+}
@@ -0,0 +1,231 @@
+use chip8::{
+    database::RomDatabase,
+    rom::Rom,
+    trace::{
+        TextTraceLine,
+        Tracer,
+    },
+    Chip8,
+    Machine,
+};
+use chip8_tracediff::{
+    diff,
+    parse_quirks,
+    parse_trace,
+    DiffOptions,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+#[derive(argh::FromArgs)]
+/// Find the first instruction where two chip8 execution traces diverge.
+///
+/// Each input is either a text trace, as written by `chip8-native --trace`, or a rom.
+/// Files ending in .txt, .trace or .log are read as traces. Anything else is run headless and traced.
+struct Options {
+    /// the first trace or rom
+    #[argh(positional)]
+    left: PathBuf,
+
+    /// the second trace or rom
+    #[argh(positional)]
+    right: PathBuf,
+
+    /// quirks for the first rom: a preset (db, default, chip8, modern, schip, xochip),
+    /// optionally followed by changes like ',+shift,-vblank'
+    #[argh(option, default = "String::from(\"db\")")]
+    left_quirks: String,
+
+    /// quirks for the second rom, in the same format as --left-quirks
+    #[argh(option, default = "String::from(\"db\")")]
+    right_quirks: String,
+
+    /// the number of instructions to run roms for
+    #[argh(option, default = "100_000")]
+    steps: usize,
+
+    /// instructions per frame for roms, defaulting to the rom database's tick rate
+    #[argh(option)]
+    tickrate: Option<u32>,
+
+    /// the seed for random numbers in roms
+    #[argh(option, default = "0")]
+    seed: u64,
+
+    /// the number of lines to show before and after the divergence
+    #[argh(option, default = "5")]
+    context: usize,
+
+    /// don't compare the delay and sound timers
+    #[argh(switch)]
+    ignore_timers: bool,
+
+    /// don't compare memory writes
+    #[argh(switch)]
+    ignore_writes: bool,
+}
+
+/// A parsed trace
+struct Trace {
+    name: String,
+    /// The text of each line, for showing context
+    text: Vec<String>,
+    lines: Vec<TextTraceLine>,
+    /// Why the trace ended early, if it did
+    stopped: Option<String>,
+}
+
+fn main() {
+    let options: Options = argh::from_env();
+
+    let left = match load(&options.left, &options.left_quirks, &options) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to load '{}': {}", options.left.display(), e);
+            std::process::exit(2);
+        }
+    };
+    let right = match load(&options.right, &options.right_quirks, &options) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to load '{}': {}", options.right.display(), e);
+            std::process::exit(2);
+        }
+    };
+
+    for trace in [&left, &right] {
+        println!("{}: {} instructions", trace.name, trace.lines.len());
+        if let Some(reason) = trace.stopped.as_ref() {
+            println!("  stopped early: {}", reason);
+        }
+    }
+
+    let diff_options = DiffOptions {
+        ignore_timers: options.ignore_timers,
+        ignore_writes: options.ignore_writes,
+    };
+    let result = diff(&left.lines, &right.lines, diff_options);
+    if result.left_start != 0 || result.right_start != 0 {
+        println!(
+            "Aligned {} line {} with {} line {}",
+            left.name,
+            result.left_start + 1,
+            right.name,
+            result.right_start + 1
+        );
+    }
+
+    let divergence = match result.divergence {
+        Some(divergence) => divergence,
+        None => {
+            println!("No divergence in {} instructions", result.matched);
+            for (trace, start) in [(&left, result.left_start), (&right, result.right_start)] {
+                let extra = trace.lines.len() - start - result.matched;
+                if extra > 0 {
+                    println!("{} has {} more instructions", trace.name, extra);
+                }
+            }
+            return;
+        }
+    };
+
+    println!();
+    println!(
+        "First divergence after {} matching instructions ({} line {}, {} line {}):",
+        result.matched,
+        left.name,
+        divergence.left + 1,
+        right.name,
+        divergence.right + 1
+    );
+    for difference in divergence.differences.iter() {
+        println!("  {}", difference);
+    }
+
+    for (trace, index) in [(&left, divergence.left), (&right, divergence.right)] {
+        println!();
+        println!("{}:", trace.name);
+        let start = index.saturating_sub(options.context);
+        let end = (index + options.context + 1).min(trace.text.len());
+        for (i, text) in trace.text.iter().enumerate().take(end).skip(start) {
+            let marker = if i == index { '>' } else { ' ' };
+            println!("{} {:>7} {}", marker, i + 1, text);
+        }
+    }
+
+    std::process::exit(1);
+}
+
+/// Load a trace, running the rom first if `path` isn't a trace
+fn load(path: &Path, quirks: &str, options: &Options) -> Result<Trace, String> {
+    let name = path.display().to_string();
+    let is_trace = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext, "txt" | "trace" | "log"));
+
+    if is_trace {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let (text, lines) = parse_trace(&text)?.into_iter().unzip();
+        Ok(Trace {
+            name,
+            text,
+            lines,
+            stopped: None,
+        })
+    } else {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        run_rom(name, &data, quirks, options)
+    }
+}
+
+/// Run a rom headless, tracing every instruction
+fn run_rom(name: String, data: &[u8], quirks: &str, options: &Options) -> Result<Trace, String> {
+    let rom = Rom::parse(data, &RomDatabase::bundled()).map_err(|e| e.to_string())?;
+    let quirks = parse_quirks(quirks, Some(rom.quirks))?;
+    let tickrate = options.tickrate.unwrap_or(rom.tickrate);
+
+    let mut chip8 = Chip8::new();
+    chip8.init();
+    rom.load_into(&mut chip8).map_err(|e| e.to_string())?;
+    chip8.set_quirks(quirks);
+    chip8.seed_rng(options.seed);
+    chip8.set_tracer(Some(Tracer::buffer(usize::MAX)));
+
+    let mut machine = Machine::new(chip8);
+    machine.set_tickrate(tickrate);
+
+    let mut stopped = None;
+    while steps(&machine) < options.steps as u64 {
+        if let Err(e) = machine.run_frame() {
+            stopped = Some(e.to_string());
+            break;
+        }
+    }
+
+    let (text, lines) = machine
+        .chip8()
+        .tracer()
+        .and_then(|tracer| tracer.trace_buffer())
+        .map(|buffer| {
+            buffer
+                .records()
+                .take(options.steps)
+                .map(|record| (record.to_string(), TextTraceLine::from(&record)))
+                .unzip()
+        })
+        .unwrap_or_default();
+
+    Ok(Trace {
+        name,
+        text,
+        lines,
+        stopped,
+    })
+}
+
+fn steps(machine: &Machine) -> u64 {
+    machine.chip8().tracer().map_or(0, |tracer| tracer.steps())
+}
@@ -0,0 +1,151 @@
+use chip8::{
+    trace::{
+        TextTraceLine,
+        Tracer,
+    },
+    Chip8,
+    Quirks,
+};
+use chip8_tracediff::{
+    diff,
+    parse_quirks,
+    parse_trace,
+    DiffOptions,
+    Divergence,
+};
+
+/// v0 := 1, then shift v0 left into itself and store it forever, which depends on the shift quirk
+const SHIFTER: &[u8] = &[
+    0x60, 0x01, 0x61, 0x02, 0x80, 0x1E, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x04,
+];
+
+/// Trace the first instructions of a program
+fn trace(rom: &[u8], quirks: Quirks, steps: usize) -> Vec<TextTraceLine> {
+    let mut chip8 = Chip8::new();
+    chip8.init();
+    chip8.set_quirks(quirks);
+    chip8.load(rom).unwrap();
+    chip8.set_tracer(Some(Tracer::buffer(steps)));
+    for _ in 0..steps {
+        chip8.cycle().unwrap();
+    }
+    let tracer = chip8.tracer().unwrap();
+    tracer
+        .trace_buffer()
+        .unwrap()
+        .records()
+        .map(|record| TextTraceLine::from(&record))
+        .collect()
+}
+
+#[test]
+fn matching_traces_dont_diverge() {
+    let left = trace(SHIFTER, Quirks::SCHIP, 50);
+    let right = trace(SHIFTER, Quirks::SCHIP, 50);
+    let result = diff(&left, &right, DiffOptions::default());
+    assert_eq!((result.left_start, result.right_start), (0, 0));
+    assert_eq!(result.matched, 50);
+    assert_eq!(result.divergence, None);
+}
+
+#[test]
+fn the_first_divergence_is_found() {
+    let left = trace(SHIFTER, Quirks::SCHIP, 50);
+    let right = trace(SHIFTER, Quirks::CHIP8, 50);
+    let result = diff(&left, &right, DiffOptions::default());
+    // The setup matches, then 8XYE shifts v0 or v1
+    assert_eq!(result.matched, 2);
+    assert_eq!(
+        result.divergence,
+        Some(Divergence {
+            left: 2,
+            right: 2,
+            differences: vec!["V0: 02 vs 04".to_string()],
+        })
+    );
+}
+
+#[test]
+fn ignored_fields_dont_count() {
+    let text = "0200: 6001 V0:01 DT:05 W:0300=01\n";
+    let other = "0200: 6001 V0:01 DT:04 W:0300=02\n";
+    let left: Vec<_> = parse_trace(text)
+        .unwrap()
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect();
+    let right: Vec<_> = parse_trace(other)
+        .unwrap()
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect();
+
+    let result = diff(&left, &right, DiffOptions::default());
+    assert_eq!(
+        result.divergence.unwrap().differences,
+        ["DT: 05 vs 04", "memory writes: [0300=01] vs [0300=02]"]
+    );
+
+    let options = DiffOptions {
+        ignore_timers: true,
+        ignore_writes: true,
+    };
+    assert_eq!(diff(&left, &right, options).divergence, None);
+}
+
+#[test]
+fn traces_of_different_lengths_compare_the_overlap() {
+    let long = trace(SHIFTER, Quirks::SCHIP, 50);
+    let short = trace(SHIFTER, Quirks::SCHIP, 20);
+    let result = diff(&long, &short, DiffOptions::default());
+    assert_eq!(result.matched, 20);
+    assert_eq!(result.divergence, None);
+
+    assert_eq!(diff(&[], &short, DiffOptions::default()).matched, 0);
+}
+
+#[test]
+fn traces_that_start_later_are_aligned() {
+    let full = trace(SHIFTER, Quirks::SCHIP, 50);
+    // A trace that started at the third instruction
+    let late = &full[2..40];
+    let result = diff(&full, late, DiffOptions::default());
+    assert_eq!((result.left_start, result.right_start), (2, 0));
+    assert_eq!(result.matched, 38);
+    assert_eq!(result.divergence, None);
+
+    let result = diff(late, &full, DiffOptions::default());
+    assert_eq!((result.left_start, result.right_start), (0, 2));
+}
+
+#[test]
+fn text_traces_parse() {
+    let text = "# from another emulator\n\n  0200: 6001 V0:01 I:0000\n0202: 1202\n";
+    let lines = parse_trace(text).unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].0, "0200: 6001 V0:01 I:0000");
+    assert_eq!(lines[0].1.v[0], Some(1));
+    assert_eq!(lines[1].1.i, None);
+
+    assert_eq!(
+        parse_trace("0200: 6001\nnope").unwrap_err(),
+        "line 2: invalid hex value 'nope'"
+    );
+}
+
+#[test]
+fn quirk_specs_parse() {
+    assert_eq!(parse_quirks("db", Some(Quirks::SCHIP)), Ok(Quirks::SCHIP));
+    assert_eq!(parse_quirks("db", None), Ok(Quirks::default()));
+    assert_eq!(
+        parse_quirks("chip8,+shift,-vblank", None),
+        Ok(Quirks {
+            shift: true,
+            vblank: false,
+            ..Quirks::CHIP8
+        })
+    );
+    assert!(parse_quirks("chip9", None).is_err());
+    assert!(parse_quirks("chip8,shift", None).is_err());
+    assert!(parse_quirks("chip8,+wobble", None).is_err());
+}
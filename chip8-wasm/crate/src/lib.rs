@@ -1,10 +1,100 @@
+use chip8::{
+    database::{
+        RomDatabase,
+        RomInfo,
+    },
+    deflicker::DeflickerMode,
+    machine::MAX_RUN_FOR,
+    rom::Rom,
+    CallFrame,
+    Chip8ErrorKind,
+};
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
 
+/// An error thrown to JS
+#[wasm_bindgen]
+pub struct Chip8Error(chip8::Chip8Error);
+
+#[wasm_bindgen]
+impl Chip8Error {
+    /// The kind of error, like `StackOverflow`
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        let kind = match self.0.kind() {
+            Chip8ErrorKind::InvalidProgramSize(_) => "InvalidProgramSize",
+            Chip8ErrorKind::UnknownInstruction(_) => "UnknownInstruction",
+            Chip8ErrorKind::InvalidReg(_) => "InvalidReg",
+            Chip8ErrorKind::InvalidAddress(_) => "InvalidAddress",
+            Chip8ErrorKind::InvalidKey(_) => "InvalidKey",
+            Chip8ErrorKind::StackUnderflow => "StackUnderflow",
+            Chip8ErrorKind::StackOverflow => "StackOverflow",
+            Chip8ErrorKind::ProgramCounterOutOfBounds(_) => "ProgramCounterOutOfBounds",
+        };
+        kind.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// Whether the program raised the error and can't go on
+    #[wasm_bindgen(getter)]
+    pub fn fatal(&self) -> bool {
+        self.0.is_fatal()
+    }
+
+    /// The address of the instruction that failed
+    #[wasm_bindgen(getter)]
+    pub fn pc(&self) -> Option<u16> {
+        self.0.pc()
+    }
+
+    /// The instruction that failed, if it could be fetched
+    #[wasm_bindgen(getter)]
+    pub fn opcode(&self) -> Option<u16> {
+        self.0.context()?.opcode
+    }
+
+    /// V0 to VF when the error happened
+    #[wasm_bindgen(getter)]
+    pub fn registers(&self) -> Option<Vec<u8>> {
+        Some(self.0.context()?.registers.to_vec())
+    }
+
+    /// Return addresses when the error happened, oldest first
+    #[wasm_bindgen(getter, js_name = callStack)]
+    pub fn call_stack(&self) -> Option<Vec<u16>> {
+        let frames = &self.0.context()?.call_stack;
+        Some(frames.iter().map(CallFrame::return_address).collect())
+    }
+
+    /// The addresses of the calls in `callStack`
+    #[wasm_bindgen(getter, js_name = callSites)]
+    pub fn call_sites(&self) -> Option<Vec<u16>> {
+        let frames = &self.0.context()?.call_stack;
+        Some(frames.iter().map(|frame| frame.call_site).collect())
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_string_js(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl From<chip8::Chip8Error> for Chip8Error {
+    fn from(e: chip8::Chip8Error) -> Self {
+        Chip8Error(e)
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Default)]
 pub struct Chip8 {
-    chip8: chip8::Chip8,
-    speed: usize,
+    machine: chip8::Machine,
+    info: Option<RomInfo>,
+    title: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -13,37 +103,92 @@ impl Chip8 {
     pub fn new() -> Self {
         let mut chip8 = chip8::Chip8::new();
         chip8.init();
-        Chip8 { chip8, speed: 1 }
+        Chip8 {
+            machine: chip8::Machine::new(chip8),
+            info: None,
+            title: None,
+        }
     }
 
     pub fn reset(&mut self) {
-        self.chip8.init();
+        self.machine.chip8_mut().init();
+        self.machine.reset_clock();
     }
 
+    /// Load a rom, an Octo cartridge or a `.c8b` bundle and start it from the beginning.
+    /// Quirks and speed come from the rom, or are the defaults if it isn't known.
+    /// Throws if the rom can't be read or doesn't fit in memory.
     pub fn load(&mut self, data: &[u8]) -> Result<(), JsValue> {
-        self.chip8
-            .load(data)
-            .map_err(|e| format!("{:#?}", e).into())
+        let rom = Rom::parse(data, &RomDatabase::bundled())
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let chip8 = self.machine.chip8_mut();
+        chip8.init();
+        rom.load_into(chip8).map_err(Chip8Error::from)?;
+        self.machine.set_tickrate(rom.tickrate);
+        self.machine.reset_clock();
+
+        self.info = rom.info;
+        self.title = rom.title;
+        Ok(())
     }
 
-    /// Should be called at 60hz
-    pub fn cycle(&mut self) {
-        for _ in 0..self.speed {
-            self.chip8.cycle().unwrap();
-        }
+    /// The title of the loaded rom, if known
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    /// The chip8 key mapped to an action like `up` or `a` by the loaded rom, if known
+    pub fn key_hint(&self, action: &str) -> Option<u8> {
+        self.info.as_ref()?.keys.get(action).copied()
+    }
+
+    /// Should be called at 60hz. Throws a `Chip8Error` if the program fails.
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        self.machine.run_frame()?;
+        Ok(())
+    }
 
-        self.chip8.update_timers();
+    /// Run for some number of milliseconds, for hosts that don't call in at 60hz. Negative and NaN
+    /// times run nothing and anything over `MAX_RUN_FOR` (250 ms) runs that long.
+    ///
+    /// Returns whether the display changed. Throws a `Chip8Error` if the program fails.
+    pub fn run_for(&mut self, millis: f64) -> Result<bool, Chip8Error> {
+        let duration = Duration::try_from_secs_f64(millis.max(0.0) / 1000.0).unwrap_or(MAX_RUN_FOR);
+        let events = self.machine.run_for(duration)?;
+        Ok(events.drew)
     }
 
-    pub fn set_speed(&mut self, speed: usize) {
-        self.speed = speed;
+    /// Set the number of instructions executed per frame
+    pub fn set_speed(&mut self, speed: u32) {
+        self.machine.set_tickrate(speed);
     }
 
-    pub fn set_key(&mut self, key: usize, val: bool) {
-        self.chip8.set_key(key, val);
+    /// Press or release a key from 0 to 15. Throws a `Chip8Error` for any other key.
+    pub fn set_key(&mut self, key: usize, val: bool) -> Result<(), Chip8Error> {
+        self.machine.chip8_mut().set_key(key, val)?;
+        Ok(())
     }
 
     pub fn get_gfx_data(&self) -> Vec<u8> {
-        self.chip8.gfx.iter().map(|&el| el as u8).collect()
+        self.machine
+            .chip8()
+            .gfx
+            .iter()
+            .map(|&el| el as u8)
+            .collect()
+    }
+
+    /// Set how frames are blended to soften flicker: `off`, `or:<frames>` or `decay:<persistence>`.
+    /// Throws if the mode is invalid.
+    pub fn set_deflicker(&mut self, mode: &str) -> Result<(), JsValue> {
+        let mode: DeflickerMode = mode.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        self.machine.set_deflicker_mode(mode);
+        Ok(())
+    }
+
+    /// How bright each pixel should be shown after deflickering, from 0 to 255
+    pub fn get_presentation(&self) -> Vec<u8> {
+        self.machine.presentation().to_vec()
     }
 }
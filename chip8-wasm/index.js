@@ -12,7 +12,7 @@ import('./crate/pkg').then(module => {
     }).then((arrayBuffer) => {
         let data = new Uint8Array(arrayBuffer);
         chip8.load(data);
-        chip8.set_speed(4);
+        chip8.set_deflicker("or:2");
 
         let keyMap = new Map();
         keyMap.set(88, 0);
@@ -39,18 +39,26 @@ import('./crate/pkg').then(module => {
             }
         });
 
-        setInterval(function () {
-            let data = chip8.get_gfx_data();
+        let interval = setInterval(function () {
+            let data = chip8.get_presentation();
             let ctx = document.getElementById('canvas').getContext('2d');
             ctx.fillStyle = "black";
             ctx.fillRect(0, 0, ctx.canvas.width, ctx.canvas.height);
+            ctx.fillStyle = "red";
             for (var i = 0; i != data.length; i++) {
-                ctx.fillStyle = "red";
                 if (data[i]) {
+                    ctx.globalAlpha = data[i] / 255;
                     ctx.fillRect((i % 64) * 10, ((i / 64) | 0) * 10, 10, 10);
                 }
             }
-            chip8.cycle();
+            ctx.globalAlpha = 1;
+            try {
+                chip8.cycle();
+            } catch (e) {
+                // e is a Chip8Error with the kind, pc, opcode, registers and call stack
+                console.error(e.toString(), e.kind, e.registers, e.callStack);
+                clearInterval(interval);
+            }
         }, 1000 / 60);
     });
 
@@ -0,0 +1,624 @@
+//! Static analysis of roms.
+//!
+//! [`Analysis::new`] follows every path from the entry point to build a control flow graph.
+//! Jumps, calls and returns are followed directly, skip instructions branch two ways,
+//! and `BNNN` jump tables are resolved by looking at the table and at recent writes to V0.
+//!
+//! Bytes that are never reached as code are classified by how they're used:
+//! bytes that `DXYN` draws from are sprites and bytes that `FX33`, `FX55` or `FX65` touch are data.
+//! Only values of I that are known statically are used for this.
+
+use crate::{
+    Instruction,
+    MEMORY_SIZE,
+    MEMORY_START,
+    OPCODE_SIZE,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    fmt::Write,
+};
+
+/// The most entries a `BNNN` table can have, since V0 is a byte and entries are 2 bytes
+const MAX_TABLE_ENTRIES: u16 = 128;
+
+/// What a byte of the rom is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    /// Never reached or referenced
+    Unknown,
+    /// Part of a reachable instruction
+    Code,
+    /// Drawn by `DXYN`
+    Sprite,
+    /// Read or written by `FX33`, `FX55` or `FX65`
+    Data,
+}
+
+/// How control gets from one block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction
+    Fallthrough,
+    /// `1NNN`
+    Jump,
+    /// A skip instruction skipped the next instruction
+    Skip,
+    /// `2NNN`
+    Call,
+    /// A possible `BNNN` target
+    Table,
+}
+
+/// An edge of the control flow graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that is only entered at the top and only left at the bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u16,
+    /// The address after the last instruction
+    pub end: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    pub successors: Vec<Edge>,
+}
+
+/// A store through I into code, which makes the code self-modifying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModifyingWrite {
+    /// The address of the store instruction
+    pub pc: u16,
+    /// The first address written
+    pub start: u16,
+    /// The address after the last one written
+    pub end: u16,
+}
+
+/// What is statically known while walking a path
+#[derive(Debug, Clone, Copy, Default)]
+struct KnownState {
+    i: Option<u16>,
+    v0: Option<u8>,
+}
+
+/// The control flow graph and byte classification of a rom
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    load_address: u16,
+    rom: Vec<u8>,
+    kinds: Vec<ByteKind>,
+    blocks: BTreeMap<u16, BasicBlock>,
+    subroutines: BTreeSet<u16>,
+    self_modifying_writes: Vec<SelfModifyingWrite>,
+    /// Targets outside of the rom
+    external_targets: BTreeSet<u16>,
+    /// Reachable addresses that don't hold a valid instruction
+    invalid: BTreeSet<u16>,
+}
+
+impl Analysis {
+    /// Analyze a rom loaded at `MEMORY_START`
+    pub fn new(rom: &[u8]) -> Self {
+        Self::with_load_address(rom, MEMORY_START as u16)
+    }
+
+    /// Analyze a rom loaded at `load_address`, which is also the entry point
+    pub fn with_load_address(rom: &[u8], load_address: u16) -> Self {
+        let available = MEMORY_SIZE.saturating_sub(usize::from(load_address));
+        let rom = &rom[..rom.len().min(available)];
+
+        let mut analysis = Analysis {
+            load_address,
+            rom: rom.to_vec(),
+            kinds: vec![ByteKind::Unknown; rom.len()],
+            blocks: BTreeMap::new(),
+            subroutines: BTreeSet::new(),
+            self_modifying_writes: Vec::new(),
+            external_targets: BTreeSet::new(),
+            invalid: BTreeSet::new(),
+        };
+        analysis.explore();
+        analysis
+    }
+
+    /// The address the rom was loaded at
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /// Iterate over the basic blocks in address order
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+
+    /// Get the block starting at `addr`
+    pub fn block(&self, addr: u16) -> Option<&BasicBlock> {
+        self.blocks.get(&addr)
+    }
+
+    /// Get the block containing `addr`
+    pub fn block_containing(&self, addr: u16) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=addr)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| addr < block.end)
+    }
+
+    /// The entry points of subroutines
+    pub fn subroutines(&self) -> &BTreeSet<u16> {
+        &self.subroutines
+    }
+
+    /// Stores that may overwrite code
+    pub fn self_modifying_writes(&self) -> &[SelfModifyingWrite] {
+        &self.self_modifying_writes
+    }
+
+    /// Jump and call targets outside of the rom
+    pub fn external_targets(&self) -> &BTreeSet<u16> {
+        &self.external_targets
+    }
+
+    /// Reachable addresses that don't hold a valid instruction
+    pub fn invalid_instructions(&self) -> &BTreeSet<u16> {
+        &self.invalid
+    }
+
+    /// Get what the byte at `addr` is used for
+    pub fn byte_kind(&self, addr: u16) -> ByteKind {
+        self.offset(addr)
+            .map_or(ByteKind::Unknown, |offset| self.kinds[offset])
+    }
+
+    /// Check whether `addr` is the start of a reachable instruction
+    pub fn is_instruction(&self, addr: u16) -> bool {
+        self.block_containing(addr).is_some_and(|block| {
+            block
+                .instructions
+                .binary_search_by_key(&addr, |&(addr, _)| addr)
+                .is_ok()
+        })
+    }
+
+    /// Get the blocks that belong to the subroutine at `entry`, without following calls
+    pub fn subroutine_blocks(&self, entry: u16) -> BTreeSet<u16> {
+        let mut seen = BTreeSet::new();
+        let mut queue = vec![entry];
+        while let Some(addr) = queue.pop() {
+            let block = match self.blocks.get(&addr) {
+                Some(block) => block,
+                None => continue,
+            };
+            if !seen.insert(addr) {
+                continue;
+            }
+
+            queue.extend(
+                block
+                    .successors
+                    .iter()
+                    .filter(|edge| edge.kind != EdgeKind::Call)
+                    .map(|edge| edge.target),
+            );
+        }
+        seen
+    }
+
+    /// Get a label for an address, if it starts a block
+    pub fn label(&self, addr: u16) -> Option<String> {
+        if self.subroutines.contains(&addr) {
+            Some(format!("sub_{:03X}", addr))
+        } else if addr == self.load_address {
+            Some(String::from("main"))
+        } else if self.blocks.contains_key(&addr) {
+            Some(format!("loc_{:03X}", addr))
+        } else {
+            None
+        }
+    }
+
+    /// Render a listing with labels, where unreached bytes are shown as data
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        let end = self.load_address + self.rom.len() as u16;
+        let mut addr = self.load_address;
+        while addr < end {
+            if let Some(block) = self.blocks.get(&addr) {
+                if let Some(label) = self.label(addr) {
+                    let _ = writeln!(out, "{}:", label);
+                }
+                for &(addr, instruction) in block.instructions.iter() {
+                    let opcode = self.opcode(addr).unwrap_or(0);
+                    let _ = writeln!(out, "    {:03X}: {:04X}  {}", addr, opcode, instruction);
+                }
+                addr = block.end;
+                continue;
+            }
+
+            let kind = self.byte_kind(addr);
+            let mut row = Vec::new();
+            while addr < end
+                && row.len() < 8
+                && !self.blocks.contains_key(&addr)
+                && self.byte_kind(addr) == kind
+            {
+                row.push(self.rom[usize::from(addr - self.load_address)]);
+                addr += 1;
+
+                // Sprites are easier to read one row at a time
+                if kind == ByteKind::Sprite {
+                    break;
+                }
+            }
+
+            let start = addr - row.len() as u16;
+            let bytes: Vec<_> = row.iter().map(|b| format!("{:02X}", b)).collect();
+            let _ = write!(out, "    {:03X}: {:<24}", start, bytes.join(" "));
+            match kind {
+                ByteKind::Sprite => {
+                    let pixels: String = (0..8)
+                        .map(|bit| {
+                            if row[0] & (0x80 >> bit) != 0 {
+                                '#'
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    let _ = writeln!(out, "; sprite {}", pixels);
+                }
+                ByteKind::Data => {
+                    let _ = writeln!(out, "; data");
+                }
+                _ => {
+                    let _ = writeln!(out, "; unknown");
+                }
+            }
+        }
+        out
+    }
+
+    /// Render the control flow graph in Graphviz DOT format.
+    ///
+    /// Each subroutine gets its own cluster. Calls are dashed, skips are red and table jumps are blue.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph rom {\n");
+        out.push_str("    node [shape=box fontname=monospace];\n");
+
+        let mut clustered = BTreeSet::new();
+        let entries = std::iter::once(self.load_address).chain(self.subroutines.iter().copied());
+        for (i, entry) in entries.enumerate() {
+            let blocks: Vec<_> = self
+                .subroutine_blocks(entry)
+                .into_iter()
+                .filter(|addr| !clustered.contains(addr))
+                .collect();
+            if blocks.is_empty() {
+                continue;
+            }
+
+            let _ = writeln!(out, "    subgraph cluster_{} {{", i);
+            let label = self
+                .label(entry)
+                .unwrap_or_else(|| format!("{:03X}", entry));
+            let _ = writeln!(out, "        label=\"{}\";", label);
+            for addr in blocks {
+                clustered.insert(addr);
+                self.write_dot_node(&mut out, &self.blocks[&addr], "        ");
+            }
+            out.push_str("    }\n");
+        }
+        for block in self.blocks.values() {
+            if !clustered.contains(&block.start) {
+                self.write_dot_node(&mut out, block, "    ");
+            }
+        }
+
+        for block in self.blocks.values() {
+            for edge in block.successors.iter() {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough | EdgeKind::Jump => "",
+                    EdgeKind::Skip => " [color=red]",
+                    EdgeKind::Call => " [style=dashed]",
+                    EdgeKind::Table => " [color=blue]",
+                };
+                let _ = writeln!(
+                    out,
+                    "    n{:03X} -> n{:03X}{};",
+                    block.start, edge.target, style
+                );
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    fn write_dot_node(&self, out: &mut String, block: &BasicBlock, indent: &str) {
+        let mut label = self
+            .label(block.start)
+            .unwrap_or_else(|| format!("{:03X}", block.start));
+        label.push_str("\\l");
+        for (addr, instruction) in block.instructions.iter() {
+            let _ = write!(label, "{:03X}: {}\\l", addr, instruction);
+        }
+        let _ = writeln!(out, "{}n{:03X} [label=\"{}\"];", indent, block.start, label);
+    }
+
+    fn offset(&self, addr: u16) -> Option<usize> {
+        let offset = usize::from(addr.checked_sub(self.load_address)?);
+        if offset < self.rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn opcode(&self, addr: u16) -> Option<u16> {
+        let offset = self.offset(addr)?;
+        let hi = self.rom[offset];
+        let lo = *self.rom.get(offset + 1)?;
+        Some(u16::from_be_bytes([hi, lo]))
+    }
+
+    fn mark(&mut self, start: u16, len: u16, kind: ByteKind) {
+        for addr in start..start.saturating_add(len) {
+            if let Some(offset) = self.offset(addr) {
+                // Code wins over everything, and sprites are more specific than data
+                let current = &mut self.kinds[offset];
+                match (*current, kind) {
+                    (ByteKind::Code, _) | (ByteKind::Sprite, ByteKind::Data) => {}
+                    _ => *current = kind,
+                }
+            }
+        }
+    }
+
+    /// Walk all reachable code, then split it into blocks
+    fn explore(&mut self) {
+        let mut instructions: BTreeMap<u16, Instruction> = BTreeMap::new();
+        let mut leaders: BTreeSet<u16> = BTreeSet::new();
+        let mut successors: BTreeMap<u16, Vec<Edge>> = BTreeMap::new();
+        let mut stores: Vec<(u16, u16, u16)> = Vec::new();
+
+        let mut queue = vec![(self.load_address, KnownState::default())];
+        leaders.insert(self.load_address);
+
+        while let Some((mut pc, mut known)) = queue.pop() {
+            loop {
+                if instructions.contains_key(&pc) {
+                    break;
+                }
+                let opcode = match self.opcode(pc) {
+                    Some(opcode) => opcode,
+                    None => {
+                        self.external_targets.insert(pc);
+                        break;
+                    }
+                };
+                let instruction = Instruction::from(opcode);
+                instructions.insert(pc, instruction);
+                self.mark(pc, OPCODE_SIZE, ByteKind::Code);
+
+                let next = pc + OPCODE_SIZE;
+                let mut edges = Vec::new();
+                match instruction {
+                    Instruction::Jump(target) => {
+                        edges.push(Edge {
+                            target,
+                            kind: EdgeKind::Jump,
+                        });
+                    }
+                    Instruction::Call(target) => {
+                        self.subroutines.insert(target);
+                        edges.push(Edge {
+                            target,
+                            kind: EdgeKind::Call,
+                        });
+                        edges.push(Edge {
+                            target: next,
+                            kind: EdgeKind::Fallthrough,
+                        });
+                        // The subroutine may change anything
+                        known = KnownState::default();
+                    }
+                    Instruction::JumpOffset(base) => {
+                        for target in self.table_targets(base, known.v0) {
+                            edges.push(Edge {
+                                target,
+                                kind: EdgeKind::Table,
+                            });
+                        }
+                    }
+                    Instruction::SkipEqualConst(..)
+                    | Instruction::SkipNotEqualConst(..)
+                    | Instruction::SkipEqual(..)
+                    | Instruction::SkipNotEqual(..)
+                    | Instruction::SkipPressed(..)
+                    | Instruction::SkipNotPressed(..) => {
+                        edges.push(Edge {
+                            target: next,
+                            kind: EdgeKind::Fallthrough,
+                        });
+                        edges.push(Edge {
+                            target: next + OPCODE_SIZE,
+                            kind: EdgeKind::Skip,
+                        });
+                    }
+                    Instruction::Return => {}
+                    Instruction::Unknown(_) => {
+                        self.invalid.insert(pc);
+                    }
+                    _ => {
+                        self.track(pc, instruction, &mut known, &mut stores);
+                        pc = next;
+                        continue;
+                    }
+                }
+
+                for edge in edges.iter() {
+                    leaders.insert(edge.target);
+                    queue.push((edge.target, known));
+                }
+                successors.insert(pc, edges);
+                break;
+            }
+        }
+
+        for (pc, start, end) in stores {
+            let overlaps_code = (start..end).any(|addr| {
+                instructions.contains_key(&addr)
+                    || (addr > 0 && instructions.contains_key(&(addr - 1)))
+            });
+            if overlaps_code {
+                self.self_modifying_writes
+                    .push(SelfModifyingWrite { pc, start, end });
+            }
+        }
+
+        // Split the reachable instructions into blocks
+        let mut current: Option<BasicBlock> = None;
+        for (&addr, &instruction) in instructions.iter() {
+            let continues = current
+                .as_ref()
+                .is_some_and(|block| block.end == addr && !leaders.contains(&addr));
+            if !continues {
+                if let Some(mut block) = current.take() {
+                    block.successors.push(Edge {
+                        target: block.end,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                    self.blocks.insert(block.start, block);
+                }
+                current = Some(BasicBlock {
+                    start: addr,
+                    end: addr,
+                    instructions: Vec::new(),
+                    successors: Vec::new(),
+                });
+            }
+
+            let block = current.as_mut().expect("block was just started");
+            block.instructions.push((addr, instruction));
+            block.end = addr + OPCODE_SIZE;
+
+            if let Some(edges) = successors.remove(&addr) {
+                let mut block = current.take().expect("block was just started");
+                block.successors = edges;
+                block.successors.sort();
+                block.successors.dedup();
+                self.blocks.insert(block.start, block);
+            }
+        }
+        if let Some(mut block) = current.take() {
+            // The rom ended in the middle of a block
+            if self.opcode(block.end).is_some() {
+                block.successors.push(Edge {
+                    target: block.end,
+                    kind: EdgeKind::Fallthrough,
+                });
+            }
+            self.blocks.insert(block.start, block);
+        }
+
+        for block in self.blocks.values() {
+            for edge in block.successors.iter() {
+                if self.offset(edge.target).is_none() {
+                    self.external_targets.insert(edge.target);
+                }
+            }
+        }
+    }
+
+    /// Update what is known about I and V0, and classify bytes accessed through I
+    fn track(
+        &mut self,
+        pc: u16,
+        instruction: Instruction,
+        known: &mut KnownState,
+        stores: &mut Vec<(u16, u16, u16)>,
+    ) {
+        match instruction {
+            Instruction::SetI(addr) => known.i = Some(addr),
+            Instruction::AddI(_) | Instruction::LoadFont(_) => known.i = None,
+            Instruction::Draw(_, _, n) => {
+                if let Some(i) = known.i {
+                    self.mark(i, u16::from(n), ByteKind::Sprite);
+                }
+            }
+            Instruction::StoreBcd(_) => {
+                if let Some(i) = known.i {
+                    self.mark(i, 3, ByteKind::Data);
+                    stores.push((pc, i, i + 3));
+                }
+            }
+            Instruction::StoreV(x) => {
+                if let Some(i) = known.i {
+                    self.mark(i, u16::from(x) + 1, ByteKind::Data);
+                    stores.push((pc, i, i + u16::from(x) + 1));
+                }
+                // Depending on quirks, I may have moved
+                known.i = None;
+            }
+            Instruction::LoadV(x) => {
+                if let Some(i) = known.i {
+                    self.mark(i, u16::from(x) + 1, ByteKind::Data);
+                }
+                known.i = None;
+            }
+            _ => {}
+        }
+
+        match instruction {
+            Instruction::SetVConst(0, value) => known.v0 = Some(value),
+            Instruction::AddVConst(0, value) => {
+                known.v0 = known.v0.map(|v0| v0.wrapping_add(value))
+            }
+            Instruction::SetV(0, _)
+            | Instruction::Or(0, _)
+            | Instruction::And(0, _)
+            | Instruction::Xor(0, _)
+            | Instruction::Add(0, _)
+            | Instruction::Sub(0, _)
+            | Instruction::ShiftRight(0, _)
+            | Instruction::SubN(0, _)
+            | Instruction::ShiftLeft(0, _)
+            | Instruction::Rand(0, _)
+            | Instruction::LoadDelay(0)
+            | Instruction::HaltUntilPressed(0)
+            | Instruction::LoadV(_) => known.v0 = None,
+            _ => {}
+        }
+    }
+
+    /// Guess the targets of `BNNN`
+    fn table_targets(&self, base: u16, v0: Option<u8>) -> Vec<u16> {
+        if let Some(v0) = v0 {
+            return vec![base + u16::from(v0)];
+        }
+
+        // Most tables are a list of jumps indexed by an even V0
+        let mut targets = Vec::new();
+        for entry in 0..MAX_TABLE_ENTRIES {
+            let addr = base + entry * OPCODE_SIZE;
+            match self.opcode(addr).map(Instruction::from) {
+                Some(Instruction::Jump(_)) => targets.push(addr),
+                _ => break,
+            }
+        }
+
+        if targets.is_empty() {
+            targets.push(base);
+        }
+        targets
+    }
+}
@@ -0,0 +1,200 @@
+//! Roms packed in ZIP archives.
+//!
+//! Entries ending in `.ch8`, `.c8` or `.c8b` are roms, in the order they're stored.
+//! A `.txt` file next to a rom with the same name, ignoring case, holds its notes, so `GAMES/HIDDEN.txt` goes with `GAMES/HIDDEN.ch8`.
+
+use crate::MEMORY_SIZE;
+use std::{
+    fmt,
+    fs::File,
+    io::{
+        Read,
+        Seek,
+    },
+    path::Path,
+};
+use zip::{
+    result::ZipError,
+    ZipArchive,
+};
+
+/// The extensions of entries that are roms
+pub const ROM_EXTENSIONS: &[&str] = &["ch8", "c8", "c8b"];
+
+/// The largest entry that is read. Bundles address their programs with 16 bit offsets and lengths, so no rom is bigger.
+pub const MAX_ENTRY_SIZE: u64 = 2 * 0x10000;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(std::io::Error),
+    Zip(ZipError),
+    /// No rom matches a name or index
+    NotFound(String),
+    /// An entry is bigger than [`MAX_ENTRY_SIZE`], by its path
+    TooLarge(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Io(e) => e.fmt(f),
+            ArchiveError::Zip(e) => write!(f, "invalid zip: {}", e),
+            ArchiveError::NotFound(name) => write!(f, "there is no rom '{}' in the archive", name),
+            ArchiveError::TooLarge(path) => write!(
+                f,
+                "'{}' is bigger than the {} bytes a rom can be",
+                path, MAX_ENTRY_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(e: std::io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<ZipError> for ArchiveError {
+    fn from(e: ZipError) -> Self {
+        ArchiveError::Zip(e)
+    }
+}
+
+/// A rom in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveRom {
+    /// The path of the entry, like `GAMES/PONG.ch8`
+    pub path: String,
+    index: usize,
+    notes: Option<usize>,
+}
+
+impl ArchiveRom {
+    /// The name of the entry without its directory, like `PONG.ch8`
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Whether the rom has a `.txt` file with notes
+    pub fn has_notes(&self) -> bool {
+        self.notes.is_some()
+    }
+}
+
+/// A ZIP archive of roms
+pub struct RomArchive<R> {
+    archive: ZipArchive<R>,
+    roms: Vec<ArchiveRom>,
+}
+
+impl RomArchive<File> {
+    /// Open an archive file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> RomArchive<R> {
+    /// Read the list of entries of an archive
+    pub fn new(reader: R) -> Result<Self, ArchiveError> {
+        let mut archive = ZipArchive::new(reader)?;
+        let mut names = Vec::with_capacity(archive.len());
+        for index in 0..archive.len() {
+            names.push(String::from(archive.by_index_raw(index)?.name()));
+        }
+        let roms = names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| has_extension(name, ROM_EXTENSIONS))
+            .map(|(index, name)| ArchiveRom {
+                path: name.clone(),
+                index,
+                notes: names.iter().position(|other| {
+                    has_extension(other, &["txt"]) && stem(other).eq_ignore_ascii_case(stem(name))
+                }),
+            })
+            .collect();
+
+        Ok(RomArchive { archive, roms })
+    }
+
+    /// Get the roms, in the order they're stored
+    pub fn roms(&self) -> &[ArchiveRom] {
+        &self.roms
+    }
+
+    /// Find a rom by its index in [`roms`](Self::roms), or by its path or file name, with or without the extension.
+    /// Names are compared ignoring case.
+    pub fn find(&self, name: &str) -> Result<usize, ArchiveError> {
+        let found = match name.parse::<usize>() {
+            Ok(index) if index < self.roms.len() => Some(index),
+            _ => self.roms.iter().position(|rom| {
+                [
+                    rom.path.as_str(),
+                    rom.file_name(),
+                    stem(rom.file_name()),
+                    stem(&rom.path),
+                ]
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(name))
+            }),
+        };
+        found.ok_or_else(|| ArchiveError::NotFound(String::from(name)))
+    }
+
+    /// Read a rom
+    pub fn read(&mut self, rom: usize) -> Result<Vec<u8>, ArchiveError> {
+        let index = self.rom(rom)?.index;
+        self.read_entry(index)
+    }
+
+    /// Read the notes of a rom, if it has any
+    pub fn notes(&mut self, rom: usize) -> Result<Option<String>, ArchiveError> {
+        let index = match self.rom(rom)?.notes {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let data = self.read_entry(index)?;
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn rom(&self, rom: usize) -> Result<&ArchiveRom, ArchiveError> {
+        self.roms
+            .get(rom)
+            .ok_or_else(|| ArchiveError::NotFound(rom.to_string()))
+    }
+
+    fn read_entry(&mut self, index: usize) -> Result<Vec<u8>, ArchiveError> {
+        let entry = self.archive.by_index(index)?;
+        let path = String::from(entry.name());
+        // The size in the archive can lie, so it's only a hint and reads stop past the limit
+        let capacity = entry.size().min(MEMORY_SIZE as u64);
+        let mut data = Vec::with_capacity(capacity as usize);
+        entry.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_ENTRY_SIZE {
+            return Err(ArchiveError::TooLarge(path));
+        }
+        Ok(data)
+    }
+}
+
+/// Check whether a path ends in one of some extensions, ignoring case
+fn has_extension(path: &str, extensions: &[&str]) -> bool {
+    match path.rsplit_once('.') {
+        Some((_, extension)) => extensions
+            .iter()
+            .any(|wanted| extension.eq_ignore_ascii_case(wanted)),
+        None => false,
+    }
+}
+
+/// Get a path without its extension
+fn stem(path: &str) -> &str {
+    match path.rsplit_once('.') {
+        Some((stem, _)) if !stem.ends_with('/') => stem,
+        _ => path,
+    }
+}
@@ -0,0 +1,594 @@
+//! An assembler for a subset of Octo.
+//!
+//! Supported are labels (`: name`), constants (`:const name value`), calls with `:call name` or just `name`,
+//! every instruction the interpreter implements in Octo syntax, `if ... then`, `if ... begin ... else ... end`,
+//! `loop ... again` with `while`, and numbers on their own, which are bytes of data.
+//! Conditions compare a register with `==` or `!=` against a number or another register, or test a key with `key` or `-key`.
+//!
+//! Comments start with `#`. Numbers can be decimal, hex with `0x` or binary with `0b`.
+//! [`assemble`] produces the rom and a [`SourceMap`] for it.
+
+use crate::{
+    source_map::SourceMap,
+    MEMORY_SIZE,
+    MEMORY_START,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+};
+
+/// An error in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: u32,
+    pub kind: AssembleErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleErrorKind {
+    /// The source ended in the middle of a statement
+    UnexpectedEnd,
+    /// A token that doesn't fit, and what was expected instead
+    Unexpected(String, &'static str),
+    InvalidNumber(String),
+    /// A number that doesn't fit where it's used
+    OutOfRange(String),
+    UnknownName(String),
+    DuplicateName(String),
+    /// A block was closed without being opened, like `again` without `loop`
+    Unmatched(&'static str),
+    /// A block that was opened is never closed
+    Unclosed(&'static str),
+    /// The program doesn't fit in memory
+    TooLarge,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AssembleErrorKind::UnexpectedEnd => write!(f, "unexpected end of file"),
+            AssembleErrorKind::Unexpected(token, expected) => {
+                write!(f, "expected {}, found '{}'", expected, token)
+            }
+            AssembleErrorKind::InvalidNumber(token) => write!(f, "invalid number '{}'", token),
+            AssembleErrorKind::OutOfRange(token) => write!(f, "'{}' is out of range", token),
+            AssembleErrorKind::UnknownName(name) => write!(f, "unknown name '{}'", name),
+            AssembleErrorKind::DuplicateName(name) => write!(f, "'{}' is already defined", name),
+            AssembleErrorKind::Unmatched(token) => {
+                write!(f, "'{}' without a matching block", token)
+            }
+            AssembleErrorKind::Unclosed(block) => write!(f, "'{}' is never closed", block),
+            AssembleErrorKind::TooLarge => write!(f, "the program doesn't fit in memory"),
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// An assembled program
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub source_map: SourceMap,
+}
+
+/// Assemble a program. `file` is the name of the source file in the source map.
+pub fn assemble<P: AsRef<Path>>(source: &str, file: P) -> Result<Assembly, AssembleError> {
+    let mut source_map = SourceMap::new();
+    let file = source_map.add_file(file.as_ref());
+
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |text| Token {
+                text,
+                line: i as u32 + 1,
+            })
+        })
+        .collect();
+
+    let mut assembler = Assembler {
+        tokens,
+        pos: 0,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        source_map,
+        file,
+        mapped_line: None,
+    };
+    assembler.run()?;
+
+    let end = assembler.address();
+    assembler.source_map.set_end(Some(end));
+    Ok(Assembly {
+        rom: assembler.rom,
+        source_map: assembler.source_map,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: u32,
+}
+
+/// An address that is filled in once every label is known
+struct Fixup<'a> {
+    /// The offset of the instruction in the rom
+    offset: usize,
+    name: Token<'a>,
+}
+
+/// An open block
+enum Block {
+    /// `if ... begin`, with the offset of the jump past the block
+    If { jump: usize, line: u32 },
+    /// `else`, with the offset of the jump past the block
+    Else { jump: usize, line: u32 },
+    /// `loop`, with the offsets of the jumps out of it made by `while`
+    Loop {
+        start: u16,
+        exits: Vec<usize>,
+        line: u32,
+    },
+}
+
+/// A condition, as the instruction that skips when it is true
+#[derive(Clone, Copy)]
+struct Condition(u16);
+
+impl Condition {
+    /// The instruction that skips when the condition is false
+    fn inverse(self) -> u16 {
+        match self.0 & 0xF000 {
+            0x3000 => self.0 ^ 0x7000,
+            0x4000 => self.0 ^ 0x7000,
+            0x5000 => self.0 ^ 0xC000,
+            0x9000 => self.0 ^ 0xC000,
+            // EX9E and EXA1
+            _ => (self.0 & 0xFF00) | if self.0 & 0xFF == 0x9E { 0xA1 } else { 0x9E },
+        }
+    }
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, u16>,
+    constants: HashMap<&'a str, u16>,
+    fixups: Vec<Fixup<'a>>,
+    blocks: Vec<Block>,
+    source_map: SourceMap,
+    file: usize,
+    /// The last line added to the source map
+    mapped_line: Option<u32>,
+}
+
+fn error(line: u32, kind: AssembleErrorKind) -> AssembleError {
+    AssembleError { line, kind }
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let reg = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if reg.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(reg, 16).ok()
+}
+
+impl<'a> Assembler<'a> {
+    fn address(&self) -> u16 {
+        (MEMORY_START + self.rom.len()) as u16
+    }
+
+    /// The line of the previous token, for errors at the end of the source
+    fn last_line(&self) -> u32 {
+        self.tokens.last().map_or(1, |token| token.line)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, AssembleError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| error(self.last_line(), AssembleErrorKind::UnexpectedEnd))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    fn expect(&mut self, text: &'static str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(error(
+                token.line,
+                AssembleErrorKind::Unexpected(token.text.to_string(), text),
+            ));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        parse_register(token.text).ok_or_else(|| {
+            error(
+                token.line,
+                AssembleErrorKind::Unexpected(token.text.to_string(), "a register"),
+            )
+        })
+    }
+
+    /// A number or constant that fits in `bits` bits. Negative numbers are two's complement.
+    fn value(&mut self, bits: u32) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        self.value_of(token, bits)
+    }
+
+    fn value_of(&self, token: Token<'a>, bits: u32) -> Result<u16, AssembleError> {
+        let value = match self.constants.get(token.text) {
+            Some(&value) => i32::from(value),
+            None => parse_number(token.text).ok_or_else(|| {
+                let kind = if token
+                    .text
+                    .starts_with(|c: char| c.is_ascii_digit() || c == '-')
+                {
+                    AssembleErrorKind::InvalidNumber(token.text.to_string())
+                } else {
+                    AssembleErrorKind::UnknownName(token.text.to_string())
+                };
+                error(token.line, kind)
+            })?,
+        };
+
+        let max = 1 << bits;
+        if value >= max || value < -(max / 2) {
+            return Err(error(
+                token.line,
+                AssembleErrorKind::OutOfRange(token.text.to_string()),
+            ));
+        }
+        Ok((value & (max - 1)) as u16)
+    }
+
+    fn map_line(&mut self, line: u32) {
+        if self.mapped_line != Some(line) {
+            let address = self.address();
+            self.source_map.add_line(address, self.file, line);
+            self.mapped_line = Some(line);
+        }
+    }
+
+    fn emit(&mut self, opcode: u16, line: u32) {
+        self.map_line(line);
+        self.rom.extend_from_slice(&opcode.to_be_bytes());
+    }
+
+    /// Emit an instruction whose low 12 bits are an address, which may be a label defined later
+    fn emit_address(&mut self, opcode: u16, token: Token<'a>) -> Result<(), AssembleError> {
+        let offset = self.rom.len();
+        if let Some(&address) = self.labels.get(token.text) {
+            self.emit(opcode | address, token.line);
+        } else if self.constants.contains_key(token.text) || parse_number(token.text).is_some() {
+            let address = self.value_of(token, 12)?;
+            self.emit(opcode | address, token.line);
+        } else {
+            self.emit(opcode, token.line);
+            self.fixups.push(Fixup {
+                offset,
+                name: token,
+            });
+        }
+        Ok(())
+    }
+
+    /// Emit a jump to be patched later, returning its offset
+    fn emit_placeholder_jump(&mut self, line: u32) -> usize {
+        let offset = self.rom.len();
+        self.emit(0x1000, line);
+        offset
+    }
+
+    /// Point the jump at `offset` at the current address
+    fn patch_jump(&mut self, offset: usize) {
+        let opcode = 0x1000 | self.address();
+        self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn define(&mut self, name: Token<'a>) -> Result<(), AssembleError> {
+        if self.labels.contains_key(name.text) || self.constants.contains_key(name.text) {
+            return Err(error(
+                name.line,
+                AssembleErrorKind::DuplicateName(name.text.to_string()),
+            ));
+        }
+        if parse_register(name.text).is_some() || parse_number(name.text).is_some() {
+            return Err(error(
+                name.line,
+                AssembleErrorKind::Unexpected(name.text.to_string(), "a name"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = u16::from(self.register()?) << 8;
+        let op = self.next()?;
+        let compare = |assembler: &mut Self, with_const: u16, with_reg: u16| {
+            let token = assembler.next()?;
+            match parse_register(token.text) {
+                Some(y) => Ok(Condition(with_reg | x | u16::from(y) << 4)),
+                None => Ok(Condition(with_const | x | assembler.value_of(token, 8)?)),
+            }
+        };
+
+        match op.text {
+            "==" => compare(self, 0x3000, 0x5000),
+            "!=" => compare(self, 0x4000, 0x9000),
+            "key" => Ok(Condition(0xE09E | x)),
+            "-key" => Ok(Condition(0xE0A1 | x)),
+            _ => Err(error(
+                op.line,
+                AssembleErrorKind::Unexpected(op.text.to_string(), "'==', '!=', 'key' or '-key'"),
+            )),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (line, name) = match block {
+                Block::If { line, .. } => (*line, "begin"),
+                Block::Else { line, .. } => (*line, "else"),
+                Block::Loop { line, .. } => (*line, "loop"),
+            };
+            return Err(error(line, AssembleErrorKind::Unclosed(name)));
+        }
+
+        // Addresses past the end of memory would spill into the opcodes
+        if self.rom.len() > MEMORY_SIZE - MEMORY_START {
+            return Err(error(self.last_line(), AssembleErrorKind::TooLarge));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self.labels.get(fixup.name.text).ok_or_else(|| {
+                error(
+                    fixup.name.line,
+                    AssembleErrorKind::UnknownName(fixup.name.text.to_string()),
+                )
+            })?;
+            self.rom[fixup.offset] |= (address >> 8) as u8;
+            self.rom[fixup.offset + 1] = address as u8;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        let line = token.line;
+
+        match token.text {
+            ":" => {
+                let name = self.next()?;
+                self.define(name)?;
+                let address = self.address();
+                self.labels.insert(name.text, address);
+                self.source_map.add_label(name.text, address);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.define(name)?;
+                let value = self.value(12)?;
+                self.constants.insert(name.text, value);
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(0x2000, target)?;
+            }
+            "clear" => self.emit(0x00E0, line),
+            "return" | ";" => self.emit(0x00EE, line),
+            "jump" => {
+                let target = self.next()?;
+                self.emit_address(0x1000, target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_address(0xB000, target)?;
+            }
+            "sprite" => {
+                let x = u16::from(self.register()?);
+                let y = u16::from(self.register()?);
+                let n = self.value(4)?;
+                self.emit(0xD000 | x << 8 | y << 4 | n, line);
+            }
+            "bcd" => {
+                let x = u16::from(self.register()?);
+                self.emit(0xF033 | x << 8, line);
+            }
+            "save" => {
+                let x = u16::from(self.register()?);
+                self.emit(0xF055 | x << 8, line);
+            }
+            "load" => {
+                let x = u16::from(self.register()?);
+                self.emit(0xF065 | x << 8, line);
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = u16::from(self.register()?);
+                let low = if token.text == "delay" { 0x15 } else { 0x18 };
+                self.emit(0xF000 | x << 8 | low, line);
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.text {
+                    ":=" if self.peek() == Some("hex") => {
+                        self.next()?;
+                        let x = u16::from(self.register()?);
+                        self.emit(0xF029 | x << 8, line);
+                    }
+                    ":=" => {
+                        let target = self.next()?;
+                        self.emit_address(0xA000, target)?;
+                    }
+                    "+=" => {
+                        let x = u16::from(self.register()?);
+                        self.emit(0xF01E | x << 8, line);
+                    }
+                    _ => {
+                        return Err(error(
+                            op.line,
+                            AssembleErrorKind::Unexpected(op.text.to_string(), "':=' or '+='"),
+                        ))
+                    }
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text {
+                    "then" => self.emit(condition.inverse(), line),
+                    "begin" => {
+                        self.emit(condition.0, line);
+                        let jump = self.emit_placeholder_jump(line);
+                        self.blocks.push(Block::If { jump, line });
+                    }
+                    _ => {
+                        return Err(error(
+                            keyword.line,
+                            AssembleErrorKind::Unexpected(
+                                keyword.text.to_string(),
+                                "'then' or 'begin'",
+                            ),
+                        ))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let skip = self.emit_placeholder_jump(line);
+                    self.patch_jump(jump);
+                    self.blocks.push(Block::Else { jump: skip, line });
+                }
+                _ => return Err(error(line, AssembleErrorKind::Unmatched("else"))),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_jump(jump)
+                }
+                _ => return Err(error(line, AssembleErrorKind::Unmatched("end"))),
+            },
+            "loop" => {
+                let start = self.address();
+                self.blocks.push(Block::Loop {
+                    start,
+                    exits: Vec::new(),
+                    line,
+                });
+            }
+            "while" => {
+                let condition = self.condition()?;
+                if !matches!(self.blocks.last(), Some(Block::Loop { .. })) {
+                    return Err(error(line, AssembleErrorKind::Unmatched("while")));
+                }
+                self.emit(condition.0, line);
+                let exit = self.emit_placeholder_jump(line);
+                if let Some(Block::Loop { exits, .. }) = self.blocks.last_mut() {
+                    exits.push(exit);
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.emit(0x1000 | start, line);
+                    for exit in exits {
+                        self.patch_jump(exit);
+                    }
+                }
+                _ => return Err(error(line, AssembleErrorKind::Unmatched("again"))),
+            },
+            text => {
+                if let Some(x) = parse_register(text) {
+                    self.assignment(u16::from(x) << 8, line)?;
+                } else if parse_number(text).is_some() {
+                    let byte = self.value_of(token, 8)?;
+                    self.map_line(line);
+                    self.rom.push(byte as u8);
+                } else {
+                    // Anything else is a call to a label
+                    self.emit_address(0x2000, token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A statement that starts with register X, which is already shifted into place
+    fn assignment(&mut self, x: u16, line: u32) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        let source = self.next()?;
+        let y = parse_register(source.text).map(|y| u16::from(y) << 4);
+
+        let opcode = match (op.text, y) {
+            (":=", Some(y)) => 0x8000 | x | y,
+            ("|=", Some(y)) => 0x8001 | x | y,
+            ("&=", Some(y)) => 0x8002 | x | y,
+            ("^=", Some(y)) => 0x8003 | x | y,
+            ("+=", Some(y)) => 0x8004 | x | y,
+            ("-=", Some(y)) => 0x8005 | x | y,
+            (">>=", Some(y)) => 0x8006 | x | y,
+            ("=-", Some(y)) => 0x8007 | x | y,
+            ("<<=", Some(y)) => 0x800E | x | y,
+            (":=", None) => match source.text {
+                "random" => 0xC000 | x | self.value(8)?,
+                "delay" => 0xF007 | x,
+                "key" => 0xF00A | x,
+                _ => 0x6000 | x | self.value_of(source, 8)?,
+            },
+            ("+=", None) => 0x7000 | x | self.value_of(source, 8)?,
+            ("-=", None) => 0x7000 | x | (self.value_of(source, 8)?.wrapping_neg() & 0xFF),
+            (_, None) if matches!(op.text, "|=" | "&=" | "^=" | ">>=" | "=-" | "<<=") => {
+                return Err(error(
+                    source.line,
+                    AssembleErrorKind::Unexpected(source.text.to_string(), "a register"),
+                ))
+            }
+            _ => {
+                return Err(error(
+                    op.line,
+                    AssembleErrorKind::Unexpected(op.text.to_string(), "an operator"),
+                ))
+            }
+        };
+        self.emit(opcode, line);
+        Ok(())
+    }
+}
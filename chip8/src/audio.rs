@@ -0,0 +1,82 @@
+//! Buzzer sound.
+//!
+//! The chip8 only has a buzzer that is either on or off, so what it sounds like is up to the host.
+//! XO-CHIP pattern audio isn't supported.
+//! [`SquareWave`] makes the classic beep for hosts that mix their own audio.
+
+/// The pitch of the buzzer unless configured otherwise
+pub const DEFAULT_FREQUENCY: f64 = 440.0;
+
+/// The amplitude of the buzzer unless configured otherwise
+pub const DEFAULT_VOLUME: i16 = i16::MAX / 4;
+
+/// A square wave generator that keeps its phase between buffers
+#[derive(Debug, Clone)]
+pub struct SquareWave {
+    sample_rate: u32,
+    frequency: f64,
+    volume: i16,
+    /// How far into the current period the wave is, from 0 to 1
+    phase: f64,
+}
+
+impl SquareWave {
+    /// Make a generator with the default pitch and volume
+    ///
+    /// # Panics
+    /// Panics if `sample_rate` is 0.
+    pub fn new(sample_rate: u32) -> Self {
+        assert!(sample_rate > 0, "the sample rate must not be 0");
+        SquareWave {
+            sample_rate,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            phase: 0.0,
+        }
+    }
+
+    /// Get the number of samples per second
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Get the pitch in hz
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Set the pitch in hz
+    pub fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+    }
+
+    /// Get the amplitude
+    pub fn volume(&self) -> i16 {
+        self.volume
+    }
+
+    /// Set the amplitude
+    pub fn set_volume(&mut self, volume: i16) {
+        self.volume = volume;
+    }
+
+    /// Fill a buffer with mono samples, which are silent while the buzzer is off
+    pub fn fill(&mut self, sound_on: bool, samples: &mut [i16]) {
+        if !sound_on {
+            samples.iter_mut().for_each(|sample| *sample = 0);
+            // Every beep starts at the beginning of a period
+            self.phase = 0.0;
+            return;
+        }
+
+        let step = self.frequency / f64::from(self.sample_rate);
+        for sample in samples.iter_mut() {
+            *sample = if self.phase < 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
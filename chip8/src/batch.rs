@@ -0,0 +1,233 @@
+//! Many emulators stepped together.
+//!
+//! [`VecChip8`] is meant for training agents: every instance runs one frame per [`VecChip8::step`],
+//! taking a bitmask of held keys as its action.
+//! Observations of all instances live in one buffer that is rewritten in place after every step.
+//!
+//! This is not a structure-of-arrays layout: each instance is a whole [`Machine`] in a `Vec`, and
+//! every step repacks each display into the observation buffer, a copy of 256 bytes per instance.
+//!
+//! With the `rayon` feature, instances are stepped in parallel.
+
+use crate::{
+    database::RomDatabase,
+    Chip8,
+    Chip8Error,
+    Chip8Result,
+    FrameEvents,
+    Machine,
+    GFX_HEIGHT,
+    GFX_WIDTH,
+    NUM_KEYS,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// The number of `u64`s in one observation, one per display row
+pub const OBSERVATION_LEN: usize = GFX_HEIGHT;
+
+/// A batch of emulators running the same program
+pub struct VecChip8 {
+    /// The state instances are reset to
+    template: Machine,
+    machines: Vec<Machine>,
+    /// Packed displays of every instance, `OBSERVATION_LEN` rows each.
+    /// Bit 63 of a row is the leftmost pixel.
+    observations: Vec<u64>,
+    events: Vec<FrameEvents>,
+    /// Instances stop stepping after an error until they are reset
+    errors: Vec<Option<Chip8Error>>,
+}
+
+impl VecChip8 {
+    /// Create `len` instances of a rom, configured from the bundled rom database if it knows the rom
+    pub fn new(rom: &[u8], len: usize) -> Chip8Result<Self> {
+        let mut chip8 = Chip8::new();
+        chip8.init();
+        chip8.load(rom)?;
+
+        let info = RomDatabase::bundled().lookup(rom);
+        if let Some(info) = info.as_ref() {
+            chip8.set_quirks(info.quirks);
+        }
+
+        let mut machine = Machine::new(chip8);
+        if let Some(info) = info {
+            machine.set_tickrate(info.tickrate);
+        }
+
+        Ok(Self::from_machine(machine, len))
+    }
+
+    /// Create `len` copies of a machine. Resetting an instance goes back to this machine.
+    pub fn from_machine(template: Machine, len: usize) -> Self {
+        let mut batch = VecChip8 {
+            machines: vec![template.clone(); len],
+            template,
+            observations: vec![0; len * OBSERVATION_LEN],
+            events: vec![FrameEvents::default(); len],
+            errors: std::iter::repeat_with(|| None).take(len).collect(),
+        };
+        for index in 0..len {
+            batch.observe(index);
+        }
+        batch
+    }
+
+    /// The number of instances
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// Run every instance for one frame.
+    ///
+    /// Bit `k` of an instance's action holds key `k` down for the frame.
+    ///
+    /// # Panics
+    /// Panics if the number of actions doesn't match the number of instances.
+    pub fn step(&mut self, actions: &[u16]) {
+        assert_eq!(
+            actions.len(),
+            self.len(),
+            "expected one action per instance"
+        );
+
+        #[cfg(not(feature = "rayon"))]
+        let instances = self
+            .machines
+            .iter_mut()
+            .zip(self.observations.chunks_mut(OBSERVATION_LEN))
+            .zip(self.events.iter_mut())
+            .zip(self.errors.iter_mut())
+            .zip(actions.iter().copied());
+
+        #[cfg(feature = "rayon")]
+        let instances = self
+            .machines
+            .par_iter_mut()
+            .zip(self.observations.par_chunks_mut(OBSERVATION_LEN))
+            .zip(self.events.par_iter_mut())
+            .zip(self.errors.par_iter_mut())
+            .zip(actions.par_iter().copied());
+
+        instances.for_each(|((((machine, observation), events), error), action)| {
+            step_instance(machine, observation, events, error, action)
+        });
+    }
+
+    /// The displays of all instances, [`OBSERVATION_LEN`] rows per instance
+    pub fn observations(&self) -> &[u64] {
+        &self.observations
+    }
+
+    /// The display of one instance
+    pub fn observation(&self, index: usize) -> &[u64] {
+        &self.observations[index * OBSERVATION_LEN..][..OBSERVATION_LEN]
+    }
+
+    /// What happened to each instance in the last step
+    pub fn events(&self) -> &[FrameEvents] {
+        &self.events
+    }
+
+    /// Get the error that stopped an instance, if any
+    pub fn error(&self, index: usize) -> Option<&Chip8Error> {
+        self.errors[index].as_ref()
+    }
+
+    /// Get an instance
+    pub fn machine(&self, index: usize) -> &Machine {
+        &self.machines[index]
+    }
+
+    /// Get an instance mutably. The observation is only updated on the next step.
+    pub fn machine_mut(&mut self, index: usize) -> &mut Machine {
+        &mut self.machines[index]
+    }
+
+    /// Put an instance back into its starting state
+    pub fn reset(&mut self, index: usize) {
+        let template = self.template.clone();
+        self.restore(index, &template);
+    }
+
+    /// Seed the random number generator of an instance
+    pub fn seed(&mut self, index: usize, seed: u64) {
+        self.machines[index].chip8_mut().seed_rng(seed);
+    }
+
+    /// Seed every instance, giving instance `i` the seed `seed + i`
+    pub fn seed_all(&mut self, seed: u64) {
+        for (i, machine) in self.machines.iter_mut().enumerate() {
+            machine.chip8_mut().seed_rng(seed.wrapping_add(i as u64));
+        }
+    }
+
+    /// Copy the state of an instance
+    pub fn snapshot(&self, index: usize) -> Machine {
+        self.machines[index].clone()
+    }
+
+    /// Replace an instance with a copy of a snapshot
+    pub fn restore(&mut self, index: usize, snapshot: &Machine) {
+        self.machines[index] = snapshot.clone();
+        self.events[index] = FrameEvents::default();
+        self.errors[index] = None;
+        self.observe(index);
+    }
+
+    fn observe(&mut self, index: usize) {
+        pack_display(
+            self.machines[index].chip8(),
+            &mut self.observations[index * OBSERVATION_LEN..][..OBSERVATION_LEN],
+        );
+    }
+}
+
+fn step_instance(
+    machine: &mut Machine,
+    observation: &mut [u64],
+    events: &mut FrameEvents,
+    error: &mut Option<Chip8Error>,
+    action: u16,
+) {
+    if error.is_some() {
+        *events = FrameEvents::default();
+        return;
+    }
+
+    hold_keys(machine.chip8_mut(), action);
+    match machine.run_frame() {
+        Ok(frame) => *events = frame,
+        Err(e) => {
+            *events = FrameEvents::default();
+            *error = Some(e);
+        }
+    }
+
+    pack_display(machine.chip8(), observation);
+}
+
+/// Hold down exactly the keys set in `mask`
+pub(crate) fn hold_keys(chip8: &mut Chip8, mask: u16) {
+    for key in 0..NUM_KEYS {
+        let pressed = mask & (1 << key) != 0;
+        if chip8.keys()[key] != pressed {
+            // Every key below NUM_KEYS exists
+            let _ = chip8.set_key(key, pressed);
+        }
+    }
+}
+
+/// Pack a display into one `u64` per row, with the leftmost pixel in bit 63
+pub(crate) fn pack_display(chip8: &Chip8, rows: &mut [u64]) {
+    for (row, pixels) in rows.iter_mut().zip(chip8.gfx.chunks(GFX_WIDTH)) {
+        *row = pixels
+            .iter()
+            .fold(0, |row, &pixel| (row << 1) | u64::from(pixel));
+    }
+}
@@ -0,0 +1,628 @@
+//! Screenshots and recordings.
+//!
+//! Screens are taken from a presentation buffer like [`Machine::presentation`](crate::Machine::presentation),
+//! which holds how bright each pixel is from 0 to 255, and drawn at a whole number scale with two [`Colors`].
+//! Everything is indexed color with a palette of 256 shades between the two colors, so deflickered screens keep their blending.
+//!
+//! A [`Recorder`] takes one screen per 60hz frame and writes an animated GIF or APNG, or a Y4M video.
+//! Runs of identical frames are merged into one longer frame in GIFs and APNGs.
+//! APNG frames are timed exactly in 60ths of a second.
+//! GIF delays are in hundredths of a second, so each frame ends at the hundredth nearest to when it really does.
+//! GIFs are written as they go while APNGs keep every distinct frame in memory until [`Recorder::finish`],
+//! since the frame count comes first in the file.
+//! Y4M is raw 4:4:4 video at 60 fps with every frame written out, for encoding offline with something like
+//! `ffmpeg -i run.y4m -i run.wav run.mp4`.
+//!
+//! A recorder can also write the buzzer to a WAV file through a [`SquareWave`], a frame's worth of samples at a time.
+//! Only the sound timer is recorded. The interpreter doesn't run XO-CHIP, so there is no pattern audio to record.
+//! Driven by [`Machine`](crate::Machine), recordings follow the emulated clock, so they have every frame
+//! and the sound lines up with them however slow the host is.
+
+use crate::{
+    audio::SquareWave,
+    machine::TIMER_HZ,
+    GFX_HEIGHT,
+    GFX_SIZE,
+    GFX_WIDTH,
+};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    fmt,
+    fs::File,
+    io::{
+        BufWriter,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::Path,
+    str::FromStr,
+};
+
+/// The colors of a capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colors {
+    /// The color of unlit pixels, as `0xRRGGBB`
+    pub background: u32,
+    /// The color of fully lit pixels, as `0xRRGGBB`
+    pub foreground: u32,
+}
+
+impl Colors {
+    /// The RGB palette of 256 shades from the background to the foreground
+    pub fn shades(&self) -> Vec<u8> {
+        let mut palette = Vec::with_capacity(256 * 3);
+        for level in 0..=255u32 {
+            for shift in [16, 8, 0] {
+                let from = self.background >> shift & 0xFF;
+                let to = self.foreground >> shift & 0xFF;
+                palette.push(((from * (255 - level) + to * level + 127) / 255) as u8);
+            }
+        }
+        palette
+    }
+}
+
+/// Convert RGB to limited range BT.601 YCbCr, the colors Y4M readers assume
+fn ycbcr(rgb: &[u8]) -> [u8; 3] {
+    let [r, g, b] = [rgb[0], rgb[1], rgb[2]].map(f32::from);
+    [
+        16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0,
+        128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0,
+        128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0,
+    ]
+    .map(|channel| channel.round() as u8)
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            background: 0x000000,
+            foreground: 0xFFFFFF,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(std::io::Error),
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+    /// The recording format couldn't be told from the file extension
+    UnknownFormat(String),
+    /// GIFs can't be more than 65535 pixels wide
+    ScaleTooLarge(u32),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::Io(e) => e.fmt(f),
+            CaptureError::Png(e) => write!(f, "failed to encode png: {}", e),
+            CaptureError::Gif(e) => write!(f, "failed to encode gif: {}", e),
+            CaptureError::UnknownFormat(path) => write!(
+                f,
+                "can't tell the recording format of '{}', expected a .gif, .png, .apng or .y4m file",
+                path
+            ),
+            CaptureError::ScaleTooLarge(scale) => {
+                write!(f, "a scale of {} is too large for a gif", scale)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(e: std::io::Error) -> Self {
+        CaptureError::Io(e)
+    }
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(e: png::EncodingError) -> Self {
+        CaptureError::Png(e)
+    }
+}
+
+impl From<gif::EncodingError> for CaptureError {
+    fn from(e: gif::EncodingError) -> Self {
+        CaptureError::Gif(e)
+    }
+}
+
+/// Scale a screen up into palette indices, row by row
+///
+/// # Panics
+/// Panics if `scale` is 0.
+pub fn scale_screen(screen: &[u8; GFX_SIZE], scale: u32) -> Vec<u8> {
+    assert!(scale > 0, "the scale must not be 0");
+    let scale = scale as usize;
+    let width = GFX_WIDTH * scale;
+    let mut pixels = Vec::with_capacity(GFX_SIZE * scale * scale);
+    for row in screen.chunks_exact(GFX_WIDTH) {
+        let start = pixels.len();
+        for &level in row {
+            pixels.extend(std::iter::repeat_n(level, scale));
+        }
+        for _ in 1..scale {
+            pixels.extend_from_within(start..start + width);
+        }
+    }
+    pixels
+}
+
+/// Make a png encoder for a scaled screen
+fn png_encoder<W: Write>(writer: W, scale: u32, colors: Colors) -> png::Encoder<'static, W> {
+    let mut encoder =
+        png::Encoder::new(writer, GFX_WIDTH as u32 * scale, GFX_HEIGHT as u32 * scale);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(colors.shades());
+    encoder
+}
+
+/// Write a screen as a png
+///
+/// # Panics
+/// Panics if `scale` is 0.
+pub fn write_png<W: Write>(
+    writer: W,
+    screen: &[u8; GFX_SIZE],
+    scale: u32,
+    colors: Colors,
+) -> Result<(), CaptureError> {
+    let pixels = scale_screen(screen, scale);
+    let mut writer = png_encoder(writer, scale, colors).write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Save a screen to a png file
+///
+/// # Panics
+/// Panics if `scale` is 0.
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    screen: &[u8; GFX_SIZE],
+    scale: u32,
+    colors: Colors,
+) -> Result<(), CaptureError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_png(&mut file, screen, scale, colors)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// The kind of file a [`Recorder`] writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    Gif,
+    Apng,
+    Y4m,
+}
+
+impl RecordingFormat {
+    /// Tell the format from a file extension, `.gif` for GIF, `.png` or `.apng` for APNG and `.y4m` for Y4M
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(RecordingFormat::Gif),
+            "png" | "apng" => Some(RecordingFormat::Apng),
+            "y4m" => Some(RecordingFormat::Y4m),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Gif => "gif",
+            RecordingFormat::Apng => "png",
+            RecordingFormat::Y4m => "y4m",
+        }
+    }
+}
+
+impl FromStr for RecordingFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(RecordingFormat::Gif),
+            "apng" => Ok(RecordingFormat::Apng),
+            "y4m" => Ok(RecordingFormat::Y4m),
+            _ => Err(format!(
+                "unknown recording format '{}', expected 'gif', 'apng' or 'y4m'",
+                s
+            )),
+        }
+    }
+}
+
+enum RecordingOutput {
+    Gif(gif::Encoder<Box<dyn Write + Send>>),
+    Apng {
+        writer: Box<dyn Write + Send>,
+        /// Distinct frames and how many 60hz frames each lasts
+        frames: Vec<(Box<[u8; GFX_SIZE]>, u64)>,
+    },
+    Y4m {
+        writer: Box<dyn Write + Send>,
+        /// The YCbCr color of each shade
+        shades: Vec<[u8; 3]>,
+    },
+}
+
+trait SeekWrite: Write + Seek {}
+
+impl<T: Write + Seek> SeekWrite for T {}
+
+/// Writes mono 16 bit PCM to a WAV file
+pub struct WavWriter {
+    writer: Box<dyn SeekWrite + Send>,
+    sample_rate: u32,
+    /// The number of samples written
+    samples: u64,
+}
+
+impl WavWriter {
+    /// Write to a writer. The sizes in the header are filled in by [`finish`](Self::finish).
+    pub fn new<W: Write + Seek + Send + 'static>(
+        writer: W,
+        sample_rate: u32,
+    ) -> Result<Self, CaptureError> {
+        let mut wav = WavWriter {
+            writer: Box::new(writer),
+            sample_rate,
+            samples: 0,
+        };
+        wav.write_header(0)?;
+        Ok(wav)
+    }
+
+    /// Write to a file
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Self, CaptureError> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+
+    /// Get the number of samples per second
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Get the number of samples written
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), CaptureError> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        self.writer.write_all(&bytes)?;
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Fill in the sizes in the header and flush.
+    /// WAV sizes are 32 bit, so players stop after about 6 hours at 44100hz.
+    pub fn finish(mut self) -> Result<(), CaptureError> {
+        let data_size = (self.samples * 2).min(u64::from(u32::MAX - 36)) as u32;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header(data_size)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self, data_size: u32) -> Result<(), CaptureError> {
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        // Bytes per sample and bits per sample
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+        self.writer.write_all(&header)?;
+        Ok(())
+    }
+}
+
+/// The buzzer of a recording
+struct AudioTrack {
+    wav: WavWriter,
+    wave: SquareWave,
+    buffer: Vec<i16>,
+}
+
+/// Records screens to an animated GIF, APNG or Y4M video, and the buzzer to a WAV file
+pub struct Recorder {
+    /// Where screens go, if anywhere
+    output: Option<RecordingOutput>,
+    audio: Option<AudioTrack>,
+    scale: u32,
+    colors: Colors,
+    /// The last screen and how many frames it lasted, held until a different one comes
+    pending: Option<(Box<[u8; GFX_SIZE]>, u64)>,
+    /// The number of frames recorded
+    frames: u64,
+    /// The number of frames written before the pending one, for GIF timing
+    written: u64,
+    error: Option<CaptureError>,
+}
+
+impl Recorder {
+    /// Record to a writer
+    ///
+    /// # Panics
+    /// Panics if `scale` is 0.
+    pub fn new<W: Write + Send + 'static>(
+        writer: W,
+        format: RecordingFormat,
+        scale: u32,
+        colors: Colors,
+    ) -> Result<Self, CaptureError> {
+        assert!(scale > 0, "the scale must not be 0");
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        let output = match format {
+            RecordingFormat::Gif => {
+                let width = u16::try_from(GFX_WIDTH as u32 * scale)
+                    .map_err(|_| CaptureError::ScaleTooLarge(scale))?;
+                let height = (GFX_HEIGHT as u32 * scale) as u16;
+                let mut encoder = gif::Encoder::new(writer, width, height, &colors.shades())?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                RecordingOutput::Gif(encoder)
+            }
+            RecordingFormat::Apng => RecordingOutput::Apng {
+                writer,
+                frames: Vec::new(),
+            },
+            RecordingFormat::Y4m => {
+                let mut writer = writer;
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    GFX_WIDTH as u32 * scale,
+                    GFX_HEIGHT as u32 * scale,
+                    TIMER_HZ
+                )?;
+                RecordingOutput::Y4m {
+                    writer,
+                    shades: colors.shades().chunks_exact(3).map(ycbcr).collect(),
+                }
+            }
+        };
+
+        Ok(Recorder {
+            output: Some(output),
+            audio: None,
+            scale,
+            colors,
+            pending: None,
+            frames: 0,
+            written: 0,
+            error: None,
+        })
+    }
+
+    /// Record to a file, in the format its extension names
+    ///
+    /// # Panics
+    /// Panics if `scale` is 0.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        scale: u32,
+        colors: Colors,
+    ) -> Result<Self, CaptureError> {
+        let path = path.as_ref();
+        let format = RecordingFormat::from_path(path)
+            .ok_or_else(|| CaptureError::UnknownFormat(path.display().to_string()))?;
+        let file = BufWriter::new(File::create(path)?);
+        Self::new(file, format, scale, colors)
+    }
+
+    /// Record only the buzzer
+    pub fn audio_only(wav: WavWriter) -> Self {
+        Recorder {
+            output: None,
+            audio: None,
+            scale: 1,
+            colors: Colors::default(),
+            pending: None,
+            frames: 0,
+            written: 0,
+            error: None,
+        }
+        .with_audio(wav)
+    }
+
+    /// Also record the buzzer, as the default [`SquareWave`] at the sample rate of the WAV file
+    ///
+    /// # Panics
+    /// Panics if the sample rate is 0.
+    pub fn with_audio(mut self, wav: WavWriter) -> Self {
+        self.audio = Some(AudioTrack {
+            wave: SquareWave::new(wav.sample_rate()),
+            wav,
+            buffer: Vec::new(),
+        });
+        self
+    }
+
+    /// Get the number of frames recorded
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Add the screen of a 60hz frame and whether the buzzer sounded during it.
+    ///
+    /// Errors are kept for [`take_error`](Self::take_error) and [`finish`](Self::finish), and nothing more is written after one.
+    pub fn push(&mut self, screen: &[u8; GFX_SIZE], sound_on: bool) {
+        if let (Some(audio), None) = (self.audio.as_mut(), self.error.as_ref()) {
+            // Frames get a whole number of samples that add up to the exact sample rate over a second
+            let rate = u64::from(audio.wav.sample_rate());
+            let hz = u64::from(TIMER_HZ);
+            let count = (self.frames + 1) * rate / hz - self.frames * rate / hz;
+            audio.buffer.resize(count as usize, 0);
+            audio.wave.fill(sound_on, &mut audio.buffer);
+            if let Err(e) = audio.wav.write_samples(&audio.buffer) {
+                self.error = Some(e);
+            }
+        }
+
+        self.frames += 1;
+        if self.output.is_none() {
+            return;
+        }
+        match self.pending.as_mut() {
+            Some((pending, count)) if **pending == *screen => *count += 1,
+            _ => {
+                if let Some((pending, count)) = self.pending.replace((Box::new(*screen), 1)) {
+                    self.write(pending, count);
+                }
+            }
+        }
+    }
+
+    /// Take the first error that happened while writing frames
+    pub fn take_error(&mut self) -> Option<CaptureError> {
+        self.error.take()
+    }
+
+    /// Write the last frame and anything held back, and flush the output.
+    ///
+    /// Both the video and the WAV file are finished even after an error, so whatever was recorded stays playable.
+    /// The first error is returned.
+    pub fn finish(mut self) -> Result<(), CaptureError> {
+        if let Some((pending, count)) = self.pending.take() {
+            self.write(pending, count);
+        }
+
+        let video = self.finish_video();
+        let audio = match self.audio.take() {
+            Some(audio) => audio.wav.finish(),
+            None => Ok(()),
+        };
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => video.and(audio),
+        }
+    }
+
+    fn finish_video(&mut self) -> Result<(), CaptureError> {
+        match self.output.take() {
+            Some(RecordingOutput::Gif(encoder)) => encoder.into_inner()?.flush()?,
+            Some(RecordingOutput::Y4m { mut writer, .. }) => writer.flush()?,
+            Some(RecordingOutput::Apng { writer, mut frames }) => {
+                if frames.is_empty() {
+                    frames.push((Box::new([0; GFX_SIZE]), 1));
+                }
+                write_apng(writer, &frames, self.scale, self.colors)?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Write a frame that lasts `count` 60hz frames
+    fn write(&mut self, screen: Box<[u8; GFX_SIZE]>, count: u64) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = match self.output.as_mut() {
+            Some(RecordingOutput::Gif(encoder)) => {
+                let hz = u64::from(TIMER_HZ);
+                let start = (self.written * 100 + hz / 2) / hz;
+                let end = ((self.written + count) * 100 + hz / 2) / hz;
+                self.written += count;
+
+                let buffer = scale_screen(&screen, self.scale);
+                let mut remaining = end - start;
+                let mut result = Ok(());
+                // The longest delay a frame can have is u16::MAX hundredths, so long stills are split up
+                while remaining > 0 && result.is_ok() {
+                    let delay = remaining.min(u64::from(u16::MAX));
+                    remaining -= delay;
+                    let frame = gif::Frame {
+                        width: (GFX_WIDTH as u32 * self.scale) as u16,
+                        height: (GFX_HEIGHT as u32 * self.scale) as u16,
+                        delay: delay as u16,
+                        buffer: Cow::Borrowed(&buffer),
+                        ..gif::Frame::default()
+                    };
+                    result = encoder.write_frame(&frame).map_err(CaptureError::from);
+                }
+                result
+            }
+            Some(RecordingOutput::Apng { frames, .. }) => {
+                frames.push((screen, count));
+                Ok(())
+            }
+            Some(RecordingOutput::Y4m { writer, shades }) => {
+                let pixels = scale_screen(&screen, self.scale);
+                let mut frame = Vec::with_capacity(6 + pixels.len() * 3);
+                frame.extend_from_slice(b"FRAME\n");
+                for plane in [0, 1, 2] {
+                    frame.extend(
+                        pixels
+                            .iter()
+                            .map(|&level| shades[usize::from(level)][plane]),
+                    );
+                }
+                (0..count)
+                    .try_for_each(|_| writer.write_all(&frame))
+                    .map_err(CaptureError::from)
+            }
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Write every frame of an APNG
+fn write_apng(
+    mut writer: Box<dyn Write + Send>,
+    frames: &[(Box<[u8; GFX_SIZE]>, u64)],
+    scale: u32,
+    colors: Colors,
+) -> Result<(), CaptureError> {
+    // Frames longer than the longest delay are split up
+    let max = u64::from(u16::MAX);
+    let chunks: Vec<(&[u8; GFX_SIZE], u16)> = frames
+        .iter()
+        .flat_map(|(screen, count)| {
+            let full = std::iter::repeat_n((&**screen, u16::MAX), (count / max) as usize);
+            let rest = Some(count % max)
+                .filter(|&rest| rest > 0)
+                .map(|rest| (&**screen, rest as u16));
+            full.chain(rest)
+        })
+        .collect();
+
+    let mut encoder = png_encoder(&mut writer, scale, colors);
+    encoder.set_animated(chunks.len() as u32, 0)?;
+    let mut png = encoder.write_header()?;
+    for (screen, delay) in chunks {
+        png.set_frame_delay(delay, TIMER_HZ as u16)?;
+        png.write_image_data(&scale_screen(screen, scale))?;
+    }
+    png.finish()?;
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,272 @@
+//! ROM metadata lookup.
+//!
+//! The data format is the one used by the community chip-8-database
+//! (https://github.com/chip-8/chip-8-database): `programs.json` holds the programs and their roms,
+//! `sha1-hashes.json` maps the SHA-1 of a rom to the index of its program
+//! and `platforms.json` holds the quirks and default tick rate of each platform.
+//!
+//! A small database covering the roms shipped with this repo is bundled.
+
+use crate::Quirks;
+use serde::Deserialize;
+use sha1::{
+    Digest,
+    Sha1,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+};
+
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../data/sha1-hashes.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../data/platforms.json");
+
+/// The tick rate used when neither the rom nor its platform specify one
+pub const DEFAULT_TICKRATE: u32 = 7;
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Json(serde_json::Error),
+    InvalidProgramIndex(String, usize),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Json(e) => write!(f, "invalid database json: {}", e),
+            DatabaseError::InvalidProgramIndex(hash, index) => {
+                write!(f, "hash '{}' points to missing program {}", hash, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(e: serde_json::Error) -> Self {
+        DatabaseError::Json(e)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Program {
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    file: Option<String>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+    #[serde(default)]
+    tickrate: Option<u32>,
+    #[serde(default)]
+    start_address: Option<u16>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    colors: Option<Colors>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    #[serde(default)]
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: QuirkOverrides,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+    stack_depth: Option<u8>,
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (self.shift, &mut quirks.shift),
+            (
+                self.memory_increment_by_x,
+                &mut quirks.memory_increment_by_x,
+            ),
+            (
+                self.memory_leave_i_unchanged,
+                &mut quirks.memory_leave_i_unchanged,
+            ),
+            (self.wrap, &mut quirks.wrap),
+            (self.jump, &mut quirks.jump),
+            (self.vblank, &mut quirks.vblank),
+            (self.logic, &mut quirks.logic),
+        ];
+
+        for (value, field) in fields {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        if let Some(stack_depth) = self.stack_depth {
+            quirks.stack_depth = stack_depth;
+        }
+    }
+}
+
+/// Recommended colors as css hex strings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Colors {
+    #[serde(default)]
+    pub pixels: Vec<String>,
+    #[serde(default)]
+    pub buzzer: Option<String>,
+    #[serde(default)]
+    pub silence: Option<String>,
+}
+
+/// Everything known about a rom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    /// Lowercase hex SHA-1 of the rom
+    pub sha1: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    /// Original file name
+    pub file: Option<String>,
+
+    /// The platform the rom should be run as
+    pub platform: Option<String>,
+    pub quirks: Quirks,
+    /// Instructions per frame
+    pub tickrate: u32,
+    pub start_address: Option<u16>,
+
+    /// Maps an action like `up` or `a` to a chip8 key
+    pub keys: HashMap<String, u8>,
+    pub colors: Option<Colors>,
+}
+
+/// A rom database
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    platforms: HashMap<String, Platform>,
+}
+
+impl RomDatabase {
+    /// Parse a database from the contents of `programs.json`, `sha1-hashes.json` and `platforms.json`
+    pub fn from_json(programs: &str, hashes: &str, platforms: &str) -> Result<Self, DatabaseError> {
+        let programs: Vec<Program> = serde_json::from_str(programs)?;
+        let hashes: HashMap<String, usize> = serde_json::from_str(hashes)?;
+        let platforms: Vec<Platform> = serde_json::from_str(platforms)?;
+
+        if let Some((hash, &index)) = hashes.iter().find(|(_, &index)| index >= programs.len()) {
+            return Err(DatabaseError::InvalidProgramIndex(hash.clone(), index));
+        }
+
+        Ok(RomDatabase {
+            programs,
+            hashes: hashes
+                .into_iter()
+                .map(|(hash, index)| (hash.to_ascii_lowercase(), index))
+                .collect(),
+            platforms: platforms.into_iter().map(|p| (p.id.clone(), p)).collect(),
+        })
+    }
+
+    /// The database bundled with this crate
+    pub fn bundled() -> Self {
+        Self::from_json(BUNDLED_PROGRAMS, BUNDLED_HASHES, BUNDLED_PLATFORMS)
+            .expect("bundled database is valid")
+    }
+
+    /// The number of known roms
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Look up a rom by its contents
+    pub fn lookup(&self, data: &[u8]) -> Option<RomInfo> {
+        self.lookup_hash(&sha1_hex(data))
+    }
+
+    /// Look up a rom by its hex SHA-1
+    pub fn lookup_hash(&self, sha1: &str) -> Option<RomInfo> {
+        let sha1 = sha1.to_ascii_lowercase();
+        let program = &self.programs[*self.hashes.get(&sha1)?];
+        let rom = program.roms.get(&sha1)?;
+
+        let platform = rom.platforms.first();
+        let mut quirks = platform
+            .and_then(|platform| Quirks::for_platform(platform))
+            .unwrap_or_default();
+        let mut tickrate = None;
+        if let Some(platform) = platform {
+            if let Some(platform) = self.platforms.get(platform) {
+                platform.quirks.apply(&mut quirks);
+                tickrate = platform.default_tickrate;
+            }
+
+            if let Some(overrides) = rom.quirky_platforms.get(platform) {
+                overrides.apply(&mut quirks);
+            }
+        }
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            authors: program.authors.clone(),
+            file: rom.file.clone(),
+            platform: platform.cloned(),
+            quirks,
+            tickrate: rom.tickrate.or(tickrate).unwrap_or(DEFAULT_TICKRATE),
+            start_address: rom.start_address,
+            keys: rom.keys.clone(),
+            colors: rom.colors.clone(),
+            sha1,
+        })
+    }
+}
+
+impl Default for RomDatabase {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+/// Get the lowercase hex SHA-1 of some data
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
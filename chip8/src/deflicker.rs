@@ -0,0 +1,146 @@
+//! Blending frames to hide flicker.
+//!
+//! Games that move a sprite by erasing it with XOR and drawing it again leave it off the screen for part of a frame,
+//! which shows up as flicker. A [`Deflicker`] is shown each finished frame and keeps a presentation buffer
+//! with how bright every pixel should look, from 0 to 255.
+//! [`Machine`](crate::Machine) keeps one next to the raw [`gfx`](crate::Chip8::gfx), so every frontend blends the same way.
+
+use crate::GFX_SIZE;
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+/// How frames are blended
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DeflickerMode {
+    /// Show the last frame as it is
+    #[default]
+    Off,
+    /// Show pixels that were lit in any of the last `n` frames
+    Or(u8),
+    /// Pixels fade out after turning off, keeping this much of their brightness each frame, from 0 to 1
+    Decay(f32),
+}
+
+impl FromStr for DeflickerMode {
+    type Err = String;
+
+    /// Parse `off`, `or:<frames>` or `decay:<persistence>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, value) = match s.split_once(':') {
+            Some((mode, value)) => (mode, Some(value)),
+            None => (s, None),
+        };
+        match (mode, value) {
+            ("off", None) => Ok(DeflickerMode::Off),
+            ("or", Some(frames)) => match frames.parse() {
+                Ok(frames) if frames > 0 => Ok(DeflickerMode::Or(frames)),
+                _ => Err(format!("'{}' is not a number of frames from 1 to 255", frames)),
+            },
+            ("decay", Some(persistence)) => match persistence.parse() {
+                Ok(persistence) if (0.0..=1.0).contains(&persistence) => {
+                    Ok(DeflickerMode::Decay(persistence))
+                }
+                _ => Err(format!("'{}' is not a persistence from 0 to 1", persistence)),
+            },
+            _ => Err(format!(
+                "unknown deflicker mode '{}', expected 'off', 'or:<frames>' or 'decay:<persistence>'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DeflickerMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeflickerMode::Off => write!(f, "off"),
+            DeflickerMode::Or(frames) => write!(f, "or:{}", frames),
+            DeflickerMode::Decay(persistence) => write!(f, "decay:{}", persistence),
+        }
+    }
+}
+
+/// Blends frames into a presentation buffer
+#[derive(Debug, Clone)]
+pub struct Deflicker {
+    mode: DeflickerMode,
+    /// Frames since each pixel was last lit, for [`DeflickerMode::Or`]
+    age: Box<[u8; GFX_SIZE]>,
+    /// The brightness of each pixel, for [`DeflickerMode::Decay`]
+    level: Box<[f32; GFX_SIZE]>,
+    presentation: Box<[u8; GFX_SIZE]>,
+}
+
+impl Deflicker {
+    pub fn new(mode: DeflickerMode) -> Self {
+        Deflicker {
+            mode,
+            age: Box::new([u8::MAX; GFX_SIZE]),
+            level: Box::new([0.0; GFX_SIZE]),
+            presentation: Box::new([0; GFX_SIZE]),
+        }
+    }
+
+    pub fn mode(&self) -> DeflickerMode {
+        self.mode
+    }
+
+    /// Switch modes, forgetting the frames seen so far
+    pub fn set_mode(&mut self, mode: DeflickerMode) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    /// Forget the frames seen so far. The buffers are cleared in place, so the presentation stays at the same address.
+    pub fn reset(&mut self) {
+        self.age.fill(u8::MAX);
+        self.level.fill(0.0);
+        self.presentation.fill(0);
+    }
+
+    /// Add a finished frame
+    pub fn push(&mut self, gfx: &[bool; GFX_SIZE]) {
+        match self.mode {
+            DeflickerMode::Off => {
+                for (shown, &lit) in self.presentation.iter_mut().zip(gfx.iter()) {
+                    *shown = if lit { u8::MAX } else { 0 };
+                }
+            }
+            DeflickerMode::Or(frames) => {
+                for ((shown, age), &lit) in self
+                    .presentation
+                    .iter_mut()
+                    .zip(self.age.iter_mut())
+                    .zip(gfx.iter())
+                {
+                    *age = if lit { 0 } else { age.saturating_add(1) };
+                    *shown = if *age < frames { u8::MAX } else { 0 };
+                }
+            }
+            DeflickerMode::Decay(persistence) => {
+                for ((shown, level), &lit) in self
+                    .presentation
+                    .iter_mut()
+                    .zip(self.level.iter_mut())
+                    .zip(gfx.iter())
+                {
+                    *level = if lit { 1.0 } else { *level * persistence };
+                    *shown = (*level * 255.0).round() as u8;
+                }
+            }
+        }
+    }
+
+    /// Get how bright each pixel should be shown, from 0 to 255, row by row
+    pub fn presentation(&self) -> &[u8; GFX_SIZE] {
+        &self.presentation
+    }
+}
+
+impl Default for Deflicker {
+    fn default() -> Self {
+        Self::new(DeflickerMode::default())
+    }
+}
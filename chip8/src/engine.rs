@@ -0,0 +1,299 @@
+//! Execution engines.
+//!
+//! An [`Engine`] runs instructions on a [`Chip8`]. The [`Interpreter`] is the reference:
+//! it fetches and decodes every instruction through [`Chip8::cycle`].
+//! The other engines translate straight-line runs of instructions once and replay them,
+//! which is faster for long headless runs. All of them produce exactly the same results.
+//!
+//! [`BlockCache`] caches decoded instructions.
+//! [`ThreadedCode`] goes further and turns each instruction into a closure with its operands baked in.
+//! Neither generates native code, so both work anywhere the crate does, including wasm.
+
+mod threaded;
+
+pub use self::threaded::ThreadedCode;
+use crate::{
+    Chip8,
+    Chip8Error,
+    Chip8Result,
+    Instruction,
+    MEMORY_SIZE,
+    OPCODE_SIZE,
+};
+use std::sync::Arc;
+
+/// The most instructions translated into one block
+const MAX_BLOCK_LEN: usize = 32;
+
+/// An error from [`Engine::run`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunError {
+    /// How many instructions ran before the one that failed
+    pub executed: u64,
+    pub error: Chip8Error,
+}
+
+/// Something that can execute instructions
+pub trait Engine {
+    /// Execute `count` instructions, returning how many were executed.
+    ///
+    /// Errors stop execution at the faulting instruction, just like [`Chip8::cycle`],
+    /// and report how many instructions ran before it.
+    fn run(&mut self, chip8: &mut Chip8, count: u64) -> Result<u64, RunError>;
+
+    /// Copy the engine, along with anything it has cached
+    fn boxed_clone(&self) -> Box<dyn Engine + Send>;
+}
+
+/// The available engines
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    /// Decode every instruction as it is executed
+    #[default]
+    Interpreter,
+    /// Cache decoded basic blocks
+    BlockCache,
+    /// Cache basic blocks compiled to closures
+    Threaded,
+}
+
+impl EngineKind {
+    /// Create a new engine of this kind
+    pub fn create(self) -> Box<dyn Engine + Send> {
+        match self {
+            EngineKind::Interpreter => Box::new(Interpreter),
+            EngineKind::BlockCache => Box::new(BlockCache::new()),
+            EngineKind::Threaded => Box::new(ThreadedCode::new()),
+        }
+    }
+}
+
+/// The reference engine
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Interpreter;
+
+impl Engine for Interpreter {
+    fn run(&mut self, chip8: &mut Chip8, count: u64) -> Result<u64, RunError> {
+        for executed in 0..count {
+            chip8
+                .cycle()
+                .map_err(|error| RunError { executed, error })?;
+        }
+        Ok(count)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Engine + Send> {
+        Box::new(*self)
+    }
+}
+
+/// How a caching engine prepares instructions and runs them
+trait Translate {
+    type Op;
+
+    fn translate(instruction: Instruction) -> Self::Op;
+
+    /// Run an instruction that is at the current pc
+    fn run(
+        op: &Self::Op,
+        chip8: &mut Chip8,
+        opcode: u16,
+        instruction: Instruction,
+    ) -> Chip8Result<()>;
+}
+
+/// Translated instructions starting at some address
+struct Block<Op> {
+    /// The address after the last instruction
+    end: u16,
+    ops: Vec<(u16, Instruction, Op)>,
+}
+
+/// Translated blocks and the bookkeeping needed to invalidate them.
+///
+/// Blocks are invalidated when the program stores into them with `FX33` or `FX55`.
+/// Any other change to memory, like [`Chip8::load`] or [`Chip8::poke`], flushes the whole cache.
+struct Cache<T: Translate> {
+    /// Blocks by their start address
+    blocks: Vec<Option<Arc<Block<T::Op>>>>,
+    /// Bytes that may be part of a cached block
+    code: Vec<bool>,
+    /// The memory generation the cache was built for
+    generation: u64,
+}
+
+impl<T: Translate> Cache<T> {
+    fn new() -> Self {
+        Cache {
+            blocks: vec![None; MEMORY_SIZE],
+            code: vec![false; MEMORY_SIZE],
+            generation: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.blocks.iter_mut().for_each(|block| *block = None);
+        self.code.iter_mut().for_each(|code| *code = false);
+    }
+
+    /// Get the block starting at `start`, translating it if needed
+    fn block(&mut self, chip8: &Chip8, start: u16) -> Chip8Result<Arc<Block<T::Op>>> {
+        if let Some(block) = self.blocks.get(usize::from(start)).and_then(Option::as_ref) {
+            return Ok(block.clone());
+        }
+
+        // Fail exactly like the interpreter would
+        chip8.fetch(start)?;
+
+        let mut ops = Vec::new();
+        let mut pc = start;
+        while ops.len() < MAX_BLOCK_LEN {
+            let opcode = match chip8.fetch(pc) {
+                Ok(opcode) => opcode,
+                Err(_) => break,
+            };
+            let instruction = Instruction::from(opcode);
+            ops.push((opcode, instruction, T::translate(instruction)));
+            pc += OPCODE_SIZE;
+
+            // Anything after these is only reached through another block
+            if matches!(
+                instruction,
+                Instruction::Jump(_)
+                    | Instruction::JumpOffset(_)
+                    | Instruction::Call(_)
+                    | Instruction::Return
+                    | Instruction::Unknown(_)
+            ) {
+                break;
+            }
+        }
+
+        for code in &mut self.code[usize::from(start)..usize::from(pc)] {
+            *code = true;
+        }
+        let block = Arc::new(Block { end: pc, ops });
+        self.blocks[usize::from(start)] = Some(block.clone());
+
+        Ok(block)
+    }
+
+    /// Drop blocks that overlap `start..end`, returning whether there may have been any
+    fn invalidate(&mut self, start: usize, end: usize) -> bool {
+        let end = end.min(MEMORY_SIZE);
+        if start >= end || !self.code[start..end].iter().any(|&code| code) {
+            return false;
+        }
+
+        let first = start.saturating_sub(MAX_BLOCK_LEN * usize::from(OPCODE_SIZE) - 1);
+        for block in &mut self.blocks[first..end] {
+            if block
+                .as_ref()
+                .is_some_and(|block| usize::from(block.end) > start)
+            {
+                *block = None;
+            }
+        }
+        true
+    }
+
+    fn run(&mut self, chip8: &mut Chip8, count: u64) -> Result<u64, RunError> {
+        if self.generation != chip8.memory_generation() {
+            self.clear();
+            self.generation = chip8.memory_generation();
+        }
+
+        let mut executed = 0;
+        'blocks: while executed < count {
+            let block = self.block(chip8, chip8.pc()).map_err(|e| RunError {
+                executed,
+                error: chip8.fail(e, None),
+            })?;
+            for (opcode, instruction, op) in block.ops.iter() {
+                // Stores are the only way the program can write memory
+                let i = usize::from(chip8.i());
+                let written = match instruction {
+                    Instruction::StoreBcd(_) => Some((i, i + 3)),
+                    Instruction::StoreV(x) => Some((i, i + usize::from(*x) + 1)),
+                    _ => None,
+                };
+
+                let pc = chip8.pc();
+                T::run(op, chip8, *opcode, *instruction).map_err(|e| RunError {
+                    executed,
+                    error: chip8.fail(e, Some(*opcode)),
+                })?;
+                executed += 1;
+
+                // The rest of this block may be stale now
+                if let Some((start, end)) = written {
+                    if self.invalidate(start, end) {
+                        continue 'blocks;
+                    }
+                }
+                if executed == count || chip8.pc() != pc + OPCODE_SIZE {
+                    continue 'blocks;
+                }
+            }
+        }
+
+        Ok(executed)
+    }
+}
+
+impl<T: Translate> Clone for Cache<T> {
+    fn clone(&self) -> Self {
+        Cache {
+            blocks: self.blocks.clone(),
+            code: self.code.clone(),
+            generation: self.generation,
+        }
+    }
+}
+
+/// Decoding is the whole translation
+struct Decoded;
+
+impl Translate for Decoded {
+    type Op = ();
+
+    fn translate(_instruction: Instruction) -> Self::Op {}
+
+    fn run(_op: &(), chip8: &mut Chip8, opcode: u16, instruction: Instruction) -> Chip8Result<()> {
+        chip8.step(opcode, instruction).map(drop)
+    }
+}
+
+/// An engine that caches decoded blocks.
+///
+/// Cloning is cheap, since blocks are shared until one of the clones invalidates them.
+#[derive(Clone)]
+pub struct BlockCache(Cache<Decoded>);
+
+impl BlockCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        BlockCache(Cache::new())
+    }
+
+    /// Drop every cached block
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for BlockCache {
+    fn run(&mut self, chip8: &mut Chip8, count: u64) -> Result<u64, RunError> {
+        self.0.run(chip8, count)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Engine + Send> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,229 @@
+use super::{
+    Cache,
+    Engine,
+    RunError,
+    Translate,
+};
+use crate::{
+    Chip8,
+    Chip8Result,
+    Instruction,
+    FLAG_REG,
+    OPCODE_SIZE,
+};
+
+/// An instruction with its operands baked in
+type Op = Box<dyn Fn(&mut Chip8) -> Chip8Result<()> + Send + Sync>;
+
+const FLAG: usize = FLAG_REG as usize;
+
+/// Common instructions get their own closures, everything else goes through [`Chip8::execute`]
+struct Closures;
+
+impl Translate for Closures {
+    type Op = Op;
+
+    fn translate(instruction: Instruction) -> Op {
+        // Decoding only produces register numbers below 16, so registers are indexed directly.
+        // Every closure has to end like `Chip8::execute` does.
+        match instruction {
+            Instruction::Jump(addr) => Box::new(move |c| {
+                c.pc = addr;
+                c.key_pressed = None;
+                Ok(())
+            }),
+            Instruction::Call(addr) => Box::new(move |c| {
+                c.push_stack(addr)?;
+                c.pc = addr;
+                c.key_pressed = None;
+                Ok(())
+            }),
+            Instruction::SkipEqualConst(x, val) => {
+                let x = usize::from(x);
+                Box::new(move |c| {
+                    c.pc += if c.v[x] == val {
+                        OPCODE_SIZE * 2
+                    } else {
+                        OPCODE_SIZE
+                    };
+                    c.key_pressed = None;
+                    Ok(())
+                })
+            }
+            Instruction::SkipNotEqualConst(x, val) => {
+                let x = usize::from(x);
+                Box::new(move |c| {
+                    c.pc += if c.v[x] != val {
+                        OPCODE_SIZE * 2
+                    } else {
+                        OPCODE_SIZE
+                    };
+                    c.key_pressed = None;
+                    Ok(())
+                })
+            }
+            Instruction::SkipEqual(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                Box::new(move |c| {
+                    c.pc += if c.v[x] == c.v[y] {
+                        OPCODE_SIZE * 2
+                    } else {
+                        OPCODE_SIZE
+                    };
+                    c.key_pressed = None;
+                    Ok(())
+                })
+            }
+            Instruction::SkipNotEqual(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                Box::new(move |c| {
+                    c.pc += if c.v[x] != c.v[y] {
+                        OPCODE_SIZE * 2
+                    } else {
+                        OPCODE_SIZE
+                    };
+                    c.key_pressed = None;
+                    Ok(())
+                })
+            }
+            Instruction::SetVConst(x, val) => {
+                let x = usize::from(x);
+                simple(move |c| c.v[x] = val)
+            }
+            Instruction::AddVConst(x, val) => {
+                let x = usize::from(x);
+                simple(move |c| c.v[x] = c.v[x].wrapping_add(val))
+            }
+            Instruction::SetV(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                simple(move |c| c.v[x] = c.v[y])
+            }
+            Instruction::Or(x, y) => logic(x, y, |a, b| a | b),
+            Instruction::And(x, y) => logic(x, y, |a, b| a & b),
+            Instruction::Xor(x, y) => logic(x, y, |a, b| a ^ b),
+            Instruction::Add(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                simple(move |c| {
+                    let (res, carry) = c.v[x].overflowing_add(c.v[y]);
+                    c.v[FLAG] = u8::from(carry);
+                    c.v[x] = res;
+                })
+            }
+            Instruction::Sub(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                simple(move |c| {
+                    let (reg_x, reg_y) = (c.v[x], c.v[y]);
+                    c.v[FLAG] = u8::from(reg_x > reg_y);
+                    c.v[x] = reg_x.wrapping_sub(reg_y);
+                })
+            }
+            Instruction::SubN(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                simple(move |c| {
+                    let (reg_x, reg_y) = (c.v[x], c.v[y]);
+                    c.v[FLAG] = u8::from(reg_y > reg_x);
+                    c.v[x] = reg_y.wrapping_sub(reg_x);
+                })
+            }
+            Instruction::ShiftRight(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                simple(move |c| {
+                    let reg = c.v[if c.quirks.shift { x } else { y }];
+                    c.v[FLAG] = reg & 0x1;
+                    c.v[x] = reg >> 1;
+                })
+            }
+            Instruction::ShiftLeft(x, y) => {
+                let (x, y) = (usize::from(x), usize::from(y));
+                simple(move |c| {
+                    let reg = c.v[if c.quirks.shift { x } else { y }];
+                    c.v[FLAG] = reg >> 7;
+                    c.v[x] = reg << 1;
+                })
+            }
+            Instruction::SetI(val) => simple(move |c| c.i = val),
+            Instruction::AddI(x) => {
+                let x = usize::from(x);
+                simple(move |c| c.i = c.i.wrapping_add(u16::from(c.v[x])))
+            }
+            Instruction::LoadFont(x) => {
+                let x = usize::from(x);
+                simple(move |c| c.i = u16::from(c.v[x]) * 5)
+            }
+            Instruction::LoadDelay(x) => {
+                let x = usize::from(x);
+                simple(move |c| c.v[x] = c.delay_timer)
+            }
+            Instruction::SetDelay(x) => {
+                let x = usize::from(x);
+                simple(move |c| c.delay_timer = c.v[x])
+            }
+            instruction => Box::new(move |c| c.execute(instruction)),
+        }
+    }
+
+    fn run(op: &Op, chip8: &mut Chip8, opcode: u16, instruction: Instruction) -> Chip8Result<()> {
+        // The closures skip tracing, so traced instructions take the slow path
+        if chip8.tracer.is_some() {
+            chip8.step(opcode, instruction).map(drop)
+        } else {
+            op(chip8)
+        }
+    }
+}
+
+/// An instruction that can't fail and always moves on to the next one
+fn simple(f: impl Fn(&mut Chip8) + Send + Sync + 'static) -> Op {
+    Box::new(move |c| {
+        f(c);
+        c.pc += OPCODE_SIZE;
+        c.key_pressed = None;
+        Ok(())
+    })
+}
+
+/// `8XY1`, `8XY2` and `8XY3`, which reset VF with the logic quirk
+fn logic(x: u8, y: u8, f: fn(u8, u8) -> u8) -> Op {
+    let (x, y) = (usize::from(x), usize::from(y));
+    simple(move |c| {
+        c.v[x] = f(c.v[x], c.v[y]);
+        if c.quirks.logic {
+            c.v[FLAG] = 0;
+        }
+    })
+}
+
+/// An engine that compiles blocks into chains of closures.
+///
+/// Closures are `Send` and `Sync`, and cloning the engine shares the compiled blocks,
+/// so copies of a [`Machine`](crate::Machine) only compile a rom once.
+#[derive(Clone)]
+pub struct ThreadedCode(Cache<Closures>);
+
+impl ThreadedCode {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        ThreadedCode(Cache::new())
+    }
+
+    /// Drop every compiled block
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl Default for ThreadedCode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for ThreadedCode {
+    fn run(&mut self, chip8: &mut Chip8, count: u64) -> Result<u64, RunError> {
+        self.0.run(chip8, count)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Engine + Send> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,513 @@
+//! Gym-style environments.
+//!
+//! An [`Env`] wraps a rom in the usual `reset() -> observation` and
+//! `step(action) -> (observation, reward, done)` interface.
+//! Rewards and termination come from a [`GameSpec`], which says where a game keeps its score
+//! and how to tell that it ended. Specs are json, see `data/games.json` for the bundled ones.
+//!
+//! Values are read from a location and optionally transformed:
+//! - `"V0"` to `"VF"` read a register
+//! - `"PC"` and `"I"` read those registers
+//! - `"[0x300]"` reads a byte of memory
+//! - `"bcd[0x300]"` reads a 3 byte binary coded decimal number, as written by `FX33`
+//!
+//! `divide`, `modulo` and `popcount` are applied to the value in that order.
+//! A reward term adds `scale` times the change of its value every frame.
+//! With `"change": "increase"` or `"change": "decrease"` only changes in that direction count.
+
+use crate::{
+    batch::{
+        hold_keys,
+        pack_display,
+        OBSERVATION_LEN,
+    },
+    database::{
+        sha1_hex,
+        RomDatabase,
+    },
+    Chip8,
+    Chip8Error,
+    Chip8Result,
+    Machine,
+};
+use rand::{
+    rngs::StdRng,
+    Rng,
+    SeedableRng,
+};
+use serde::Deserialize;
+use std::{
+    convert::TryFrom,
+    fmt,
+    str::FromStr,
+};
+
+const BUNDLED_GAMES: &str = include_str!("../data/games.json");
+
+/// Where a value is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Location {
+    Register(u8),
+    Pc,
+    I,
+    Memory(u16),
+    Bcd(u16),
+}
+
+impl Location {
+    /// Read the raw value
+    pub fn read(self, chip8: &Chip8) -> u32 {
+        let mem = |addr: u16| u32::from(chip8.peek(addr).unwrap_or(0));
+        match self {
+            Location::Register(reg) => u32::from(chip8.registers()[usize::from(reg)]),
+            Location::Pc => u32::from(chip8.pc()),
+            Location::I => u32::from(chip8.i()),
+            Location::Memory(addr) => mem(addr),
+            Location::Bcd(addr) => {
+                mem(addr) * 100 + mem(addr.wrapping_add(1)) * 10 + mem(addr.wrapping_add(2))
+            }
+        }
+    }
+}
+
+/// An error parsing a [`Location`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLocationError(String);
+
+impl fmt::Display for ParseLocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid location '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseLocationError {}
+
+impl FromStr for Location {
+    type Err = ParseLocationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseLocationError(s.to_string());
+        let address = |s: &str| parse_number(s).and_then(|n| u16::try_from(n).ok());
+
+        match s {
+            "PC" => return Ok(Location::Pc),
+            "I" => return Ok(Location::I),
+            _ => {}
+        }
+        if let Some(reg) = s.strip_prefix('V').filter(|reg| reg.len() == 1) {
+            return u8::from_str_radix(reg, 16)
+                .map(Location::Register)
+                .map_err(|_| error());
+        }
+        if let Some(addr) = s.strip_prefix("bcd[").and_then(|s| s.strip_suffix(']')) {
+            return address(addr).map(Location::Bcd).ok_or_else(error);
+        }
+        if let Some(addr) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            return address(addr).map(Location::Memory).ok_or_else(error);
+        }
+
+        Err(error())
+    }
+}
+
+impl TryFrom<String> for Location {
+    type Error = ParseLocationError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A number written either as a json number or a hex string like `"0x2C8"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "NumberRepr")]
+struct Number(u32);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberRepr {
+    Int(u32),
+    Str(String),
+}
+
+impl TryFrom<NumberRepr> for Number {
+    type Error = String;
+
+    fn try_from(repr: NumberRepr) -> Result<Self, Self::Error> {
+        match repr {
+            NumberRepr::Int(n) => Ok(Number(n)),
+            NumberRepr::Str(s) => parse_number(&s)
+                .map(Number)
+                .ok_or_else(|| format!("invalid number '{}'", s)),
+        }
+    }
+}
+
+/// A value derived from the emulator state
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Value {
+    pub value: Location,
+    #[serde(default)]
+    pub divide: Option<u32>,
+    #[serde(default)]
+    pub modulo: Option<u32>,
+    /// Count the set bits
+    #[serde(default)]
+    pub popcount: bool,
+}
+
+impl Value {
+    pub fn read(&self, chip8: &Chip8) -> u32 {
+        let mut value = self.value.read(chip8);
+        if let Some(divide) = self.divide.filter(|&d| d != 0) {
+            value /= divide;
+        }
+        if let Some(modulo) = self.modulo.filter(|&m| m != 0) {
+            value %= modulo;
+        }
+        if self.popcount {
+            value = value.count_ones();
+        }
+        value
+    }
+}
+
+/// Which changes of a value are rewarded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    #[default]
+    Any,
+    Increase,
+    Decrease,
+}
+
+/// Part of the reward
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RewardTerm {
+    #[serde(flatten)]
+    pub value: Value,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub change: Change,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl RewardTerm {
+    fn reward(&self, old: u32, new: u32) -> f64 {
+        let delta = i64::from(new) - i64::from(old);
+        let delta = match self.change {
+            Change::Any => delta,
+            Change::Increase => delta.max(0),
+            Change::Decrease => delta.min(0),
+        };
+        self.scale * delta as f64
+    }
+}
+
+/// Ends the episode when its value matches
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DoneCondition {
+    #[serde(flatten)]
+    pub value: Value,
+    #[serde(default, deserialize_with = "deserialize_number")]
+    pub equals: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_number")]
+    pub at_least: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_number")]
+    pub at_most: Option<u32>,
+}
+
+fn deserialize_number<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<Number>::deserialize(deserializer)?.map(|n| n.0))
+}
+
+impl DoneCondition {
+    pub fn is_met(&self, chip8: &Chip8) -> bool {
+        let value = self.value.read(chip8);
+        self.equals.is_none_or(|n| value == n)
+            && self.at_least.is_none_or(|n| value >= n)
+            && self.at_most.is_none_or(|n| value <= n)
+    }
+}
+
+/// Keys held for some frames right after a reset
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StartInput {
+    pub keys: Vec<u8>,
+    pub frames: u32,
+}
+
+/// How to play a game
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GameSpec {
+    pub name: String,
+    /// The rom this spec was written for
+    #[serde(default)]
+    pub sha1: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The keys held by each action
+    pub actions: Vec<Vec<u8>>,
+    /// Inputs that get the game from the title screen to the first frame of play
+    #[serde(default)]
+    pub start: Vec<StartInput>,
+    #[serde(default)]
+    pub reward: Vec<RewardTerm>,
+    /// The episode ends when any of these are met
+    #[serde(default)]
+    pub done: Vec<DoneCondition>,
+}
+
+impl GameSpec {
+    /// Parse a json list of specs
+    pub fn from_json(json: &str) -> Result<Vec<Self>, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// The specs bundled with this crate
+    pub fn bundled() -> Vec<Self> {
+        Self::from_json(BUNDLED_GAMES).expect("bundled game specs are valid")
+    }
+
+    /// Find the bundled spec for a rom
+    pub fn find_bundled(rom: &[u8]) -> Option<Self> {
+        let sha1 = sha1_hex(rom);
+        Self::bundled()
+            .into_iter()
+            .find(|spec| spec.sha1.as_deref() == Some(sha1.as_str()))
+    }
+
+    /// Get the keys held by an action as a bitmask
+    fn action_mask(&self, action: usize) -> u16 {
+        key_mask(&self.actions[action])
+    }
+}
+
+/// How an [`Env`] steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvConfig {
+    /// The number of frames each step runs for
+    pub frame_skip: u32,
+    /// The chance that a frame repeats the previous action instead of using the new one
+    pub sticky_action_probability: f64,
+    /// Episodes are cut off after this many frames
+    pub max_episode_frames: Option<u64>,
+    /// The seed for sticky actions and for the rom's random numbers
+    pub seed: u64,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            frame_skip: 4,
+            sticky_action_probability: 0.25,
+            max_episode_frames: Some(108_000),
+            seed: 0,
+        }
+    }
+}
+
+/// A rom played as a reinforcement learning environment
+pub struct Env {
+    spec: GameSpec,
+    config: EnvConfig,
+    /// The state every episode starts from
+    template: Machine,
+    machine: Machine,
+    rng: StdRng,
+
+    observation: [u64; OBSERVATION_LEN],
+    /// The last value of each reward term
+    values: Vec<u32>,
+    last_action: usize,
+    frame: u64,
+    done: bool,
+    truncated: bool,
+    error: Option<Chip8Error>,
+}
+
+impl Env {
+    /// Create an environment for a rom, configured from the bundled rom database if it knows the rom
+    pub fn new(rom: &[u8], spec: GameSpec, config: EnvConfig) -> Chip8Result<Self> {
+        let mut chip8 = Chip8::new();
+        chip8.init();
+        chip8.load(rom)?;
+
+        let info = RomDatabase::bundled().lookup(rom);
+        if let Some(info) = info.as_ref() {
+            chip8.set_quirks(info.quirks);
+        }
+
+        let mut machine = Machine::new(chip8);
+        if let Some(info) = info {
+            machine.set_tickrate(info.tickrate);
+        }
+
+        Ok(Self::from_machine(machine, spec, config))
+    }
+
+    /// Create an environment that starts every episode from a copy of `machine`
+    ///
+    /// # Panics
+    /// Panics if the spec has no actions.
+    pub fn from_machine(machine: Machine, spec: GameSpec, config: EnvConfig) -> Self {
+        assert!(!spec.actions.is_empty(), "a game needs at least one action");
+
+        let mut env = Env {
+            values: vec![0; spec.reward.len()],
+            spec,
+            config,
+            machine: machine.clone(),
+            template: machine,
+            rng: StdRng::seed_from_u64(config.seed),
+            observation: [0; OBSERVATION_LEN],
+            last_action: 0,
+            frame: 0,
+            done: false,
+            truncated: false,
+            error: None,
+        };
+        env.reset();
+        env
+    }
+
+    /// Get the spec
+    pub fn spec(&self) -> &GameSpec {
+        &self.spec
+    }
+
+    /// The number of actions
+    pub fn num_actions(&self) -> usize {
+        self.spec.actions.len()
+    }
+
+    /// Get the machine
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Start a new episode
+    pub fn reset(&mut self) -> &[u64] {
+        self.machine = self.template.clone();
+        let seed = self.rng.gen();
+        self.machine.chip8_mut().seed_rng(seed);
+
+        self.last_action = 0;
+        self.frame = 0;
+        self.done = false;
+        self.truncated = false;
+        self.error = None;
+
+        for input in self.spec.start.clone() {
+            for _ in 0..input.frames {
+                self.run_frame(key_mask(&input.keys));
+            }
+        }
+
+        let chip8 = self.machine.chip8();
+        self.values = self
+            .spec
+            .reward
+            .iter()
+            .map(|t| t.value.read(chip8))
+            .collect();
+        pack_display(chip8, &mut self.observation);
+
+        &self.observation
+    }
+
+    /// Play an action for [`EnvConfig::frame_skip`] frames, returning the observation,
+    /// the reward collected and whether the episode is over
+    ///
+    /// # Panics
+    /// Panics if `action` is out of range.
+    pub fn step(&mut self, action: usize) -> (&[u64], f64, bool) {
+        assert!(action < self.num_actions(), "invalid action {}", action);
+
+        let mut reward = 0.0;
+        for _ in 0..self.config.frame_skip.max(1) {
+            if self.done {
+                break;
+            }
+
+            if self.rng.gen::<f64>() >= self.config.sticky_action_probability {
+                self.last_action = action;
+            }
+            self.run_frame(self.spec.action_mask(self.last_action));
+            self.frame += 1;
+
+            let chip8 = self.machine.chip8();
+            for (term, old) in self.spec.reward.iter().zip(self.values.iter_mut()) {
+                let new = term.value.read(chip8);
+                reward += term.reward(*old, new);
+                *old = new;
+            }
+
+            if self.spec.done.iter().any(|done| done.is_met(chip8)) {
+                self.done = true;
+            } else if self
+                .config
+                .max_episode_frames
+                .is_some_and(|max| self.frame >= max)
+            {
+                self.done = true;
+                self.truncated = true;
+            }
+        }
+
+        pack_display(self.machine.chip8(), &mut self.observation);
+        (&self.observation, reward, self.done)
+    }
+
+    /// The current observation, one `u64` per display row with the leftmost pixel in bit 63
+    pub fn observation(&self) -> &[u64] {
+        &self.observation
+    }
+
+    /// The number of frames played this episode
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Whether the episode ended because it ran out of time rather than because the game ended
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The error that ended the episode, if any
+    pub fn error(&self) -> Option<&Chip8Error> {
+        self.error.as_ref()
+    }
+
+    fn run_frame(&mut self, keys: u16) {
+        if self.error.is_some() {
+            return;
+        }
+
+        hold_keys(self.machine.chip8_mut(), keys);
+        if let Err(e) = self.machine.run_frame() {
+            self.error = Some(e);
+            self.done = true;
+        }
+    }
+}
+
+fn key_mask(keys: &[u8]) -> u16 {
+    keys.iter().fold(0, |mask, &key| mask | 1 << (key & 0xF))
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
@@ -0,0 +1,147 @@
+//! Errors from loading and running programs.
+//!
+//! Errors raised while running a program carry an [`ErrorContext`] with the state of the emulator when the
+//! instruction failed. They are fatal: the program can't go on without changing its state, since running it
+//! again hits the same error. Errors from calls like [`Chip8::poke`](crate::Chip8::poke) have no context and leave
+//! the emulator as it was, so they can be recovered from.
+
+use crate::{
+    CallFrame,
+    Instruction,
+    NUM_REGISTERS,
+};
+use std::fmt;
+
+pub type Chip8Result<T> = Result<T, Chip8Error>;
+
+/// What went wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8ErrorKind {
+    InvalidProgramSize(usize),
+    UnknownInstruction(Instruction),
+    InvalidReg(u8),
+    InvalidAddress(u16),
+    InvalidKey(usize),
+    StackUnderflow,
+    StackOverflow,
+    ProgramCounterOutOfBounds(u16),
+}
+
+impl fmt::Display for Chip8ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8ErrorKind::InvalidProgramSize(size) => {
+                write!(f, "a {} byte program doesn't fit in memory", size)
+            }
+            Chip8ErrorKind::UnknownInstruction(Instruction::Unknown(opcode)) => {
+                write!(f, "unknown instruction {:04X}", opcode)
+            }
+            Chip8ErrorKind::UnknownInstruction(instruction) => {
+                write!(f, "unknown instruction {}", instruction)
+            }
+            Chip8ErrorKind::InvalidReg(reg) => write!(f, "there is no register {}", reg),
+            Chip8ErrorKind::InvalidAddress(addr) => {
+                write!(f, "address {:#05X} is outside of memory", addr)
+            }
+            Chip8ErrorKind::InvalidKey(key) => write!(f, "there is no key {:#X}", key),
+            Chip8ErrorKind::StackUnderflow => write!(f, "returned with an empty call stack"),
+            Chip8ErrorKind::StackOverflow => write!(f, "the call stack is full"),
+            Chip8ErrorKind::ProgramCounterOutOfBounds(pc) => {
+                write!(f, "the program counter {:#05X} is outside of memory", pc)
+            }
+        }
+    }
+}
+
+/// The state of the emulator when an instruction failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The address of the instruction
+    pub pc: u16,
+    /// The instruction, or `None` if it couldn't be fetched
+    pub opcode: Option<u16>,
+    pub registers: [u8; NUM_REGISTERS],
+    pub i: u16,
+    /// Calls that haven't returned, oldest first
+    pub call_stack: Vec<CallFrame>,
+}
+
+/// An error, with the context it happened in if it was raised by the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chip8Error {
+    kind: Chip8ErrorKind,
+    context: Option<Box<ErrorContext>>,
+}
+
+impl Chip8Error {
+    /// Make an error raised while running a program
+    pub fn with_context(kind: Chip8ErrorKind, context: ErrorContext) -> Self {
+        Chip8Error {
+            kind,
+            context: Some(Box::new(context)),
+        }
+    }
+
+    /// Get what went wrong
+    pub fn kind(&self) -> &Chip8ErrorKind {
+        &self.kind
+    }
+
+    /// Get the state of the emulator when the error happened, if the program raised it
+    pub fn context(&self) -> Option<&ErrorContext> {
+        self.context.as_deref()
+    }
+
+    /// Get the address of the instruction that failed
+    pub fn pc(&self) -> Option<u16> {
+        self.context().map(|context| context.pc)
+    }
+
+    /// Check whether the program raised the error and can't go on
+    pub fn is_fatal(&self) -> bool {
+        self.context.is_some()
+    }
+
+    /// Check whether the error left the emulator as it was
+    pub fn is_recoverable(&self) -> bool {
+        !self.is_fatal()
+    }
+}
+
+impl From<Chip8ErrorKind> for Chip8Error {
+    fn from(kind: Chip8ErrorKind) -> Self {
+        Chip8Error {
+            kind,
+            context: None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    /// Like `the call stack is full at 0x20A (220A)`. The alternate form adds the registers and the call stack.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)?;
+        let context = match self.context() {
+            Some(context) => context,
+            None => return Ok(()),
+        };
+
+        write!(f, " at {:#05X}", context.pc)?;
+        if let Some(opcode) = context.opcode {
+            write!(f, " ({:04X})", opcode)?;
+        }
+        if f.alternate() {
+            write!(f, "\nV: {:02X?}", context.registers)?;
+            write!(f, "\nI: {:#05X}", context.i)?;
+            let returns: Vec<u16> = context
+                .call_stack
+                .iter()
+                .map(CallFrame::return_address)
+                .collect();
+            write!(f, "\nStack: {:03X?}", returns)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Chip8Error {}
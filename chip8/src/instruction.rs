@@ -1,8 +1,9 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     ClearDisplay,
     Return,
     Jump(u16),
+    JumpOffset(u16),
     Call(u16),
     SkipEqualConst(u8, u8),
     SkipNotEqualConst(u8, u8),
@@ -17,9 +18,9 @@ pub enum Instruction {
     Xor(u8, u8),
     Add(u8, u8),
     Sub(u8, u8),
-    ShiftRight(u8),
+    ShiftRight(u8, u8),
     SubN(u8, u8),
-    ShiftLeft(u8),
+    ShiftLeft(u8, u8),
 
     SkipNotEqual(u8, u8),
     SetI(u16),
@@ -69,13 +70,14 @@ impl From<u16> for Instruction {
                 0x3 => Instruction::Xor(((n & 0xF00) >> 8) as u8, ((n & 0x0F0) >> 4) as u8),
                 0x4 => Instruction::Add(((n & 0xF00) >> 8) as u8, ((n & 0x0F0) >> 4) as u8),
                 0x5 => Instruction::Sub(((n & 0xF00) >> 8) as u8, ((n & 0x0F0) >> 4) as u8),
-                0x6 => Instruction::ShiftRight(((n & 0xF00) >> 8) as u8),
+                0x6 => Instruction::ShiftRight(x, y),
                 0x7 => Instruction::SubN(x, y),
-                0xE => Instruction::ShiftLeft(((n & 0xF00) >> 8) as u8),
+                0xE => Instruction::ShiftLeft(x, y),
                 _ => Instruction::Unknown(n),
             },
             0x9000 => Instruction::SkipNotEqual(((n & 0xF00) >> 8) as u8, ((n & 0x0F0) >> 4) as u8),
             0xA000 => Instruction::SetI(n & 0x0FFF),
+            0xB000 => Instruction::JumpOffset(nnn),
             0xC000 => Instruction::Rand(((n & 0x0F00) >> 8) as u8, (n & 0xFF) as u8),
             0xD000 => Instruction::Draw(
                 ((n & 0x0F00) >> 8) as u8,
@@ -1,11 +1,59 @@
+pub mod analysis;
+#[cfg(feature = "zip")]
+pub mod archive;
+pub mod assembler;
+pub mod audio;
+pub mod batch;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub mod database;
+pub mod deflicker;
+pub mod engine;
+pub mod env;
+pub mod error;
 pub mod instruction;
-
-pub use crate::instruction::Instruction;
-use rand::{
-    rngs::OsRng,
-    Rng,
+pub mod machine;
+pub mod quirks;
+pub mod rom;
+pub mod source_map;
+pub mod stack;
+pub mod state;
+pub mod trace;
+
+pub use crate::{
+    error::{
+        Chip8Error,
+        Chip8ErrorKind,
+        Chip8Result,
+        ErrorContext,
+    },
+    instruction::Instruction,
+    machine::{
+        FrameEvents,
+        Machine,
+    },
+    quirks::Quirks,
+    stack::{
+        CallFrame,
+        StackOverflowMode,
+    },
+    trace::Tracer,
+};
+use crate::{
+    state::SavableRng,
+    trace::{
+        MemoryWrite,
+        TraceRecord,
+        TraceState,
+    },
+};
+use std::{
+    fmt,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
 };
-use std::fmt;
 
 const FONT: &[u8] = &[
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -28,27 +76,25 @@ const FONT: &[u8] = &[
 
 pub const MEMORY_SIZE: usize = 4096;
 pub const NUM_REGISTERS: usize = 16;
+/// The default call stack depth
 pub const STACK_SIZE: usize = 16;
+/// The deepest calls can nest, even with [`StackOverflowMode::Warn`], so SP fits in a byte
+pub const MAX_STACK_DEPTH: usize = 255;
 pub const NUM_KEYS: usize = 16;
 pub const GFX_WIDTH: usize = 64;
-pub const GFX_HEIGHT: usize = 64;
+pub const GFX_HEIGHT: usize = 32;
 pub const GFX_SIZE: usize = GFX_WIDTH * GFX_HEIGHT;
 
 pub const MEMORY_START: usize = 0x200;
 pub const OPCODE_SIZE: u16 = 2;
 pub const FLAG_REG: u8 = 0xF;
 
-#[derive(Debug)]
-pub enum Chip8Error {
-    InvalidProgramSize(usize),
-    UnknownInstruction(Instruction),
-    InvalidReg(u8),
-    StackUnderflow,
-    StackOverflow,
-    ProgramCounterOutOfBounds(u16),
-}
+/// Memory generations are unique across all emulators, so caches can't mix them up
+static NEXT_MEMORY_GENERATION: AtomicU64 = AtomicU64::new(0);
 
-pub type Chip8Result<T> = Result<T, Chip8Error>;
+fn next_memory_generation() -> u64 {
+    NEXT_MEMORY_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
 
 pub struct Chip8 {
     /// Memory
@@ -62,11 +108,11 @@ pub struct Chip8 {
     /// Program counter
     pc: u16,
 
-    /// Return Address Stack
-    stack: [u16; STACK_SIZE],
-
-    /// Stack Pointer
-    sp: u8,
+    /// Calls that haven't returned yet, oldest first
+    stack: Vec<CallFrame>,
+    stack_overflow_mode: StackOverflowMode,
+    /// Set when a call went past the stack depth in [`StackOverflowMode::Warn`]
+    stack_overflowed: bool,
 
     /// GFX memory
     pub gfx: [bool; GFX_SIZE],
@@ -74,8 +120,25 @@ pub struct Chip8 {
     delay_timer: u8,
     sound_timer: u8,
     draw_flag: bool,
+    /// Whether the buzzer started since the last check
+    sound_started: bool,
+    /// Whether the buzzer stopped since the last check
+    sound_stopped: bool,
     keys: [bool; NUM_KEYS],
     key_pressed: Option<u8>,
+
+    quirks: Quirks,
+    /// Whether a timer tick happened since the last draw
+    vblank: bool,
+
+    rng: SavableRng,
+
+    tracer: Option<Tracer>,
+    /// Memory writes made by the current instruction, while tracing
+    trace_writes: Vec<MemoryWrite>,
+
+    /// Bumped whenever memory is changed from outside of the program
+    memory_generation: u64,
 }
 
 impl Chip8 {
@@ -86,14 +149,23 @@ impl Chip8 {
             v: [0; NUM_REGISTERS],
             i: 0,
             pc: MEMORY_START as u16,
-            stack: [0; STACK_SIZE],
-            sp: 0,
+            stack: Vec::new(),
+            stack_overflow_mode: StackOverflowMode::Error,
+            stack_overflowed: false,
             gfx: [false; GFX_SIZE],
             delay_timer: 0,
             sound_timer: 0,
             draw_flag: false,
+            sound_started: false,
+            sound_stopped: false,
             keys: [false; NUM_KEYS],
             key_pressed: None,
+            quirks: Quirks::default(),
+            vblank: false,
+            rng: SavableRng::from_entropy(),
+            tracer: None,
+            trace_writes: Vec::new(),
+            memory_generation: next_memory_generation(),
         }
     }
 
@@ -103,43 +175,112 @@ impl Chip8 {
         self.memory = [0; MEMORY_SIZE];
         self.v = [0; NUM_REGISTERS];
         self.pc = MEMORY_START as u16;
-        self.stack = [0; STACK_SIZE];
-        self.sp = 0;
+        self.stack.clear();
+        self.stack_overflowed = false;
         self.gfx = [false; GFX_SIZE];
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.draw_flag = false;
+        self.sound_started = false;
+        self.sound_stopped = false;
         self.keys = [false; NUM_KEYS];
         self.key_pressed = None;
+        self.vblank = false;
+        self.memory_generation = next_memory_generation();
 
         for (i, &el) in FONT.iter().enumerate() {
             self.memory[i] = el;
         }
     }
 
-    /// Load a rom
+    /// Load a rom at [`MEMORY_START`] and start executing there
     pub fn load(&mut self, data: &[u8]) -> Chip8Result<()> {
-        let data_len = data.len();
-        if data_len > self.memory.len() {
-            return Err(Chip8Error::InvalidProgramSize(data_len));
+        self.load_at(data, MEMORY_START as u16)
+    }
+
+    /// Load a rom at an address, like 0x600 for the ETI-660, and start executing there.
+    /// Nothing changes if the rom doesn't fit.
+    pub fn load_at(&mut self, data: &[u8], address: u16) -> Chip8Result<()> {
+        let start = usize::from(address);
+        if start >= MEMORY_SIZE {
+            return Err(Chip8ErrorKind::InvalidAddress(address).into());
+        }
+        if data.len() > MEMORY_SIZE - start {
+            return Err(Chip8ErrorKind::InvalidProgramSize(data.len()).into());
         }
 
-        self.memory[MEMORY_START..(data.len() + MEMORY_START)].clone_from_slice(&data[..]);
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        self.pc = address;
+        self.memory_generation = next_memory_generation();
 
         Ok(())
     }
 
     /// Execute 1 cycle
     pub fn cycle(&mut self) -> Chip8Result<Instruction> {
-        if self.pc >= MEMORY_SIZE as u16 {
-            return Err(Chip8Error::ProgramCounterOutOfBounds(self.pc));
+        let opcode = self.fetch(self.pc).map_err(|e| self.fail(e, None))?;
+        self.step(opcode, Instruction::from(opcode))
+            .map_err(|e| self.fail(e, Some(opcode)))
+    }
+
+    /// Add the current state to an error raised by the instruction at the pc
+    pub(crate) fn fail(&self, error: Chip8Error, opcode: Option<u16>) -> Chip8Error {
+        if error.is_fatal() {
+            return error;
+        }
+
+        let context = ErrorContext {
+            pc: self.pc,
+            opcode,
+            registers: self.v,
+            i: self.i,
+            call_stack: self.stack.clone(),
+        };
+        Chip8Error::with_context(error.kind().clone(), context)
+    }
+
+    /// Read the opcode at `pc`
+    pub(crate) fn fetch(&self, pc: u16) -> Chip8Result<u16> {
+        let pc = usize::from(pc);
+        if pc + 1 >= MEMORY_SIZE {
+            return Err(Chip8ErrorKind::ProgramCounterOutOfBounds(pc as u16).into());
         }
 
-        let op1 = self.memory[self.pc as usize] as u16;
-        let op2 = self.memory[self.pc as usize + 1] as u16;
-        let op = (op1 << 8) + op2;
-        let op = Instruction::from(op);
+        Ok(u16::from_be_bytes([self.memory[pc], self.memory[pc + 1]]))
+    }
 
+    /// Execute an instruction that was fetched from the current pc, tracing it if needed
+    pub(crate) fn step(&mut self, opcode: u16, op: Instruction) -> Chip8Result<Instruction> {
+        let pc = self.pc;
+        let trace_step = self
+            .tracer
+            .as_mut()
+            .and_then(|tracer| tracer.begin_step(pc));
+        let before = trace_step.map(|_| self.trace_state());
+        self.trace_writes.clear();
+
+        self.execute(op)?;
+
+        if let (Some(step), Some(before)) = (trace_step, before) {
+            let record = TraceRecord {
+                step,
+                pc,
+                opcode,
+                instruction: op,
+                before,
+                after: self.trace_state(),
+                writes: std::mem::take(&mut self.trace_writes),
+            };
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.record(record);
+            }
+        }
+
+        Ok(op)
+    }
+
+    /// Execute a decoded instruction
+    fn execute(&mut self, op: Instruction) -> Chip8Result<()> {
         match op {
             Instruction::ClearDisplay => {
                 self.gfx.iter_mut().for_each(|el| *el = false);
@@ -176,8 +317,16 @@ impl Chip8 {
             Instruction::Jump(addr) => {
                 self.pc = addr;
             }
+            Instruction::JumpOffset(addr) => {
+                let reg = if self.quirks.jump {
+                    ((addr & 0x0F00) >> 8) as u8
+                } else {
+                    0
+                };
+                self.pc = addr + u16::from(self.read_reg(reg)?);
+            }
             Instruction::Call(addr) => {
-                self.push_stack(self.pc + OPCODE_SIZE)?;
+                self.push_stack(addr)?;
                 self.pc = addr;
             }
             Instruction::SetVConst(x, val) => {
@@ -198,18 +347,27 @@ impl Chip8 {
                 let reg_x = self.read_reg(x)?;
                 let reg_y = self.read_reg(y)?;
                 self.write_reg(x, reg_x | reg_y)?;
+                if self.quirks.logic {
+                    self.write_reg(FLAG_REG, 0)?;
+                }
                 self.pc += OPCODE_SIZE;
             }
             Instruction::And(x, y) => {
                 let reg_x = self.read_reg(x)?;
                 let reg_y = self.read_reg(y)?;
                 self.write_reg(x, reg_x & reg_y)?;
+                if self.quirks.logic {
+                    self.write_reg(FLAG_REG, 0)?;
+                }
                 self.pc += OPCODE_SIZE;
             }
             Instruction::Xor(x, y) => {
                 let reg_x = self.read_reg(x)?;
                 let reg_y = self.read_reg(y)?;
                 self.write_reg(x, reg_x ^ reg_y)?;
+                if self.quirks.logic {
+                    self.write_reg(FLAG_REG, 0)?;
+                }
                 self.pc += OPCODE_SIZE;
             }
             Instruction::Add(x, y) => {
@@ -227,10 +385,10 @@ impl Chip8 {
                 self.write_reg(x, reg_x.wrapping_sub(reg_y))?;
                 self.pc += OPCODE_SIZE;
             }
-            Instruction::ShiftRight(x) => {
-                let reg_x = self.read_reg(x)?;
-                self.write_reg(FLAG_REG, reg_x & 0x1)?;
-                self.write_reg(x, reg_x >> 1)?;
+            Instruction::ShiftRight(x, y) => {
+                let reg = self.read_reg(if self.quirks.shift { x } else { y })?;
+                self.write_reg(FLAG_REG, reg & 0x1)?;
+                self.write_reg(x, reg >> 1)?;
                 self.pc += OPCODE_SIZE;
             }
             Instruction::SubN(x, y) => {
@@ -240,10 +398,10 @@ impl Chip8 {
                 self.write_reg(x, reg_y.wrapping_sub(reg_x))?;
                 self.pc += OPCODE_SIZE;
             }
-            Instruction::ShiftLeft(x) => {
-                let reg_x = self.read_reg(x)?;
-                self.write_reg(FLAG_REG, (reg_x & 0b10000000) >> 7)?;
-                self.write_reg(x, reg_x << 1)?;
+            Instruction::ShiftLeft(x, y) => {
+                let reg = self.read_reg(if self.quirks.shift { x } else { y })?;
+                self.write_reg(FLAG_REG, (reg & 0b10000000) >> 7)?;
+                self.write_reg(x, reg << 1)?;
                 self.pc += OPCODE_SIZE;
             }
             Instruction::SkipNotEqual(x, y) => {
@@ -260,41 +418,29 @@ impl Chip8 {
                 self.pc += OPCODE_SIZE;
             }
             Instruction::Rand(x, val) => {
-                self.write_reg(x, OsRng.gen::<u8>() & val)?;
+                let rand = self.rng.gen_u8();
+                self.write_reg(x, rand & val)?;
                 self.pc += OPCODE_SIZE;
             }
             Instruction::Draw(x, y, n) => {
-                let reg_x = self.read_reg(x)?;
-                let reg_y = self.read_reg(y)?;
-                self.write_reg(FLAG_REG, 0)?;
-
-                for y in 0..n {
-                    let pix_row = self.memory[self.i as usize + y as usize];
-                    for x in 0..8 {
-                        let pix = pix_row & (0x01 << (7 - x)) != 0;
-                        let gfx_index =
-                            (reg_x as usize + x as usize + ((y + reg_y) as usize * 64)) % (32 * 64);
-                        if self.gfx[gfx_index] && pix {
-                            self.write_reg(FLAG_REG, 1)?;
-                        }
-                        self.gfx[gfx_index] ^= pix;
-                    }
+                // With the vblank quirk, only one sprite can be drawn per timer tick
+                if !self.quirks.vblank || self.vblank {
+                    self.draw(x, y, n)?;
+                    self.vblank = false;
+                    self.pc += OPCODE_SIZE;
                 }
-
-                self.draw_flag = true;
-                self.pc += OPCODE_SIZE;
             }
             Instruction::SkipPressed(x) => {
-                let reg_x = self.read_reg(x)?;
-                self.pc += if self.keys[reg_x as usize] {
+                let pressed = self.read_key(x)?;
+                self.pc += if pressed {
                     2 * OPCODE_SIZE
                 } else {
                     OPCODE_SIZE
                 }
             }
             Instruction::SkipNotPressed(x) => {
-                let reg_x = self.read_reg(x)?;
-                self.pc += if self.keys[reg_x as usize] {
+                let pressed = self.read_key(x)?;
+                self.pc += if pressed {
                     OPCODE_SIZE
                 } else {
                     2 * OPCODE_SIZE
@@ -317,11 +463,13 @@ impl Chip8 {
             }
             Instruction::SetSound(x) => {
                 let reg_x = self.read_reg(x)?;
+                let was_on = self.is_sound_on();
                 self.sound_timer = reg_x;
+                self.note_sound_change(was_on);
                 self.pc += OPCODE_SIZE;
             }
             Instruction::AddI(reg) => {
-                self.i += u16::from(self.read_reg(reg)?);
+                self.i = self.i.wrapping_add(u16::from(self.read_reg(reg)?));
                 self.pc += OPCODE_SIZE;
             }
             Instruction::LoadFont(reg) => {
@@ -330,81 +478,367 @@ impl Chip8 {
             }
             Instruction::StoreBcd(x) => {
                 let reg_x = self.read_reg(x)?;
-                self.memory[self.i as usize] = reg_x / 100;
-                self.memory[self.i as usize + 1] = (reg_x / 10) % 10;
-                self.memory[self.i as usize + 2] = (reg_x % 100) % 10;
+                let addr = self.mem_range(3)?;
+                self.write_mem(addr, reg_x / 100);
+                self.write_mem(addr + 1, (reg_x / 10) % 10);
+                self.write_mem(addr + 2, (reg_x % 100) % 10);
                 self.pc += OPCODE_SIZE;
             }
             Instruction::StoreV(x) => {
+                let addr = self.mem_range(usize::from(x) + 1)?;
                 for i in 0..x + 1 {
-                    self.memory[self.i as usize + usize::from(i)] = self.read_reg(i)?;
+                    self.write_mem(addr + usize::from(i), self.read_reg(i)?);
                 }
+                self.increment_i_after_bulk(x);
                 self.pc += OPCODE_SIZE;
             }
             Instruction::LoadV(x) => {
+                let addr = self.mem_range(usize::from(x) + 1)?;
                 for i in 0..x + 1 {
-                    self.write_reg(i, self.memory[self.i as usize + usize::from(i)])?;
+                    self.write_reg(i, self.memory[addr + usize::from(i)])?;
                 }
+                self.increment_i_after_bulk(x);
                 self.pc += OPCODE_SIZE;
             }
             Instruction::Unknown(_) => {
-                return Err(Chip8Error::UnknownInstruction(op));
+                return Err(Chip8ErrorKind::UnknownInstruction(op).into());
             }
         }
 
         self.key_pressed = None;
 
-        Ok(op)
+        Ok(())
     }
 
     pub fn update_timers(&mut self) {
+        self.vblank = true;
+
         if self.delay_timer != 0 {
             self.delay_timer -= 1;
         }
 
         if self.sound_timer != 0 {
             self.sound_timer -= 1;
+            self.note_sound_change(true);
         }
     }
 
-    pub fn set_key(&mut self, key: usize, data: bool) {
-        self.keys[key] = data;
+    /// Press or release a key, from 0 to 15
+    pub fn set_key(&mut self, key: usize, data: bool) -> Chip8Result<()> {
+        *self
+            .keys
+            .get_mut(key)
+            .ok_or(Chip8ErrorKind::InvalidKey(key))? = data;
         if data {
             self.key_pressed = Some(key as u8);
         }
+        Ok(())
     }
 
-    #[inline]
-    fn push_stack(&mut self, data: u16) -> Chip8Result<()> {
-        self.stack[self.sp as usize] = data;
-        self.sp += 1;
+    /// Get the registers V0 to VF
+    pub fn registers(&self) -> &[u8; NUM_REGISTERS] {
+        &self.v
+    }
 
-        if self.sp == STACK_SIZE as u8 {
-            return Err(Chip8Error::StackOverflow);
+    /// Get the value of a register
+    pub fn reg(&self, reg: u8) -> Chip8Result<u8> {
+        self.v
+            .get(usize::from(reg))
+            .copied()
+            .ok_or_else(|| Chip8ErrorKind::InvalidReg(reg).into())
+    }
+
+    /// Set the value of a register
+    pub fn set_reg(&mut self, reg: u8, value: u8) -> Chip8Result<()> {
+        self.write_reg(reg, value)
+    }
+
+    /// Get the program counter
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Set the program counter. It has to leave room for a whole instruction, so the last byte of memory is out.
+    pub fn set_pc(&mut self, pc: u16) -> Chip8Result<()> {
+        if usize::from(pc) + 1 >= MEMORY_SIZE {
+            return Err(Chip8ErrorKind::ProgramCounterOutOfBounds(pc).into());
         }
 
+        self.pc = pc;
         Ok(())
     }
 
-    #[inline]
-    fn pop_stack(&mut self) -> Chip8Result<u16> {
-        if self.sp == 0 {
-            return Err(Chip8Error::StackUnderflow);
+    /// Get the I register
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// Set the I register
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    /// Get the memory
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    /// Read a byte of memory
+    pub fn peek(&self, addr: u16) -> Chip8Result<u8> {
+        self.memory
+            .get(usize::from(addr))
+            .copied()
+            .ok_or_else(|| Chip8ErrorKind::InvalidAddress(addr).into())
+    }
+
+    /// Write a byte of memory
+    pub fn poke(&mut self, addr: u16, value: u8) -> Chip8Result<()> {
+        *self
+            .memory
+            .get_mut(usize::from(addr))
+            .ok_or(Chip8ErrorKind::InvalidAddress(addr))? = value;
+        self.memory_generation = next_memory_generation();
+        Ok(())
+    }
+
+    /// Get a number that changes whenever memory is written by anything other than the program,
+    /// like [`Chip8::load`] or [`Chip8::poke`]. No two emulators share a generation unless one is a copy of the other.
+    /// Engines that cache decoded code use it to know when to flush.
+    pub fn memory_generation(&self) -> u64 {
+        self.memory_generation
+    }
+
+    /// Get the calls that haven't returned yet, oldest first
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.stack
+    }
+
+    /// Get the stack pointer, which is the number of calls that haven't returned
+    pub fn sp(&self) -> u8 {
+        self.stack.len() as u8
+    }
+
+    /// Get what happens when calls nest deeper than [`Quirks::stack_depth`]
+    pub fn stack_overflow_mode(&self) -> StackOverflowMode {
+        self.stack_overflow_mode
+    }
+
+    /// Set what happens when calls nest deeper than [`Quirks::stack_depth`]
+    pub fn set_stack_overflow_mode(&mut self, mode: StackOverflowMode) {
+        self.stack_overflow_mode = mode;
+    }
+
+    /// Check whether a call went past the stack depth since the last call, in [`StackOverflowMode::Warn`]
+    pub(crate) fn take_stack_overflowed(&mut self) -> bool {
+        std::mem::replace(&mut self.stack_overflowed, false)
+    }
+
+    /// Get the delay timer
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    /// Set the delay timer
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    /// Get the sound timer
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Set the sound timer
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// Get the state of the keypad
+    pub fn keys(&self) -> &[bool; NUM_KEYS] {
+        &self.keys
+    }
+
+    /// Seed the random number generator used by `CXNN`, making runs reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = SavableRng::seed_from_u64(seed);
+    }
+
+    /// Attach or detach a tracer, returning the previous one
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Get the attached tracer
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Get the attached tracer mutably
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Check whether the display changed since the last call
+    pub fn take_draw_flag(&mut self) -> bool {
+        std::mem::replace(&mut self.draw_flag, false)
+    }
+
+    /// Check whether the buzzer is sounding
+    pub fn is_sound_on(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Check whether the buzzer started and whether it stopped since the last call
+    pub(crate) fn take_sound_changes(&mut self) -> (bool, bool) {
+        (
+            std::mem::replace(&mut self.sound_started, false),
+            std::mem::replace(&mut self.sound_stopped, false),
+        )
+    }
+
+    /// Get the quirks in use
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Set the quirks to use
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    fn draw(&mut self, x: u8, y: u8, n: u8) -> Chip8Result<()> {
+        let reg_x = usize::from(self.read_reg(x)?) % GFX_WIDTH;
+        let reg_y = usize::from(self.read_reg(y)?) % GFX_HEIGHT;
+        let addr = self.mem_range(usize::from(n))?;
+        self.write_reg(FLAG_REG, 0)?;
+
+        for y in 0..usize::from(n) {
+            let mut gfx_y = reg_y + y;
+            if gfx_y >= GFX_HEIGHT {
+                if !self.quirks.wrap {
+                    break;
+                }
+                gfx_y %= GFX_HEIGHT;
+            }
+
+            let pix_row = self.memory[addr + y];
+            for x in 0..8 {
+                let mut gfx_x = reg_x + x;
+                if gfx_x >= GFX_WIDTH {
+                    if !self.quirks.wrap {
+                        break;
+                    }
+                    gfx_x %= GFX_WIDTH;
+                }
+
+                let pix = pix_row & (0x01 << (7 - x)) != 0;
+                let gfx_index = gfx_x + gfx_y * GFX_WIDTH;
+                if self.gfx[gfx_index] && pix {
+                    self.write_reg(FLAG_REG, 1)?;
+                }
+                self.gfx[gfx_index] ^= pix;
+            }
+        }
+
+        self.draw_flag = true;
+
+        Ok(())
+    }
+
+    fn trace_state(&self) -> TraceState {
+        TraceState {
+            v: self.v,
+            i: self.i,
+            sp: self.sp(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
         }
+    }
 
-        self.sp -= 1;
-        let ret = self.stack[self.sp as usize];
-        self.stack[self.sp as usize] = 0;
+    #[inline]
+    fn note_sound_change(&mut self, was_on: bool) {
+        let is_on = self.is_sound_on();
+        self.sound_started |= !was_on && is_on;
+        self.sound_stopped |= was_on && !is_on;
+    }
 
-        Ok(ret)
+    /// Check that `len` bytes from I are all in memory, getting the address of the first
+    #[inline]
+    fn mem_range(&self, len: usize) -> Chip8Result<usize> {
+        let addr = usize::from(self.i);
+        if addr + len > MEMORY_SIZE {
+            // The first address past the end of memory
+            let outside = addr.max(MEMORY_SIZE) as u16;
+            return Err(Chip8ErrorKind::InvalidAddress(outside).into());
+        }
+        Ok(addr)
     }
 
+    /// Get whether the key in register `x` is held down
     #[inline]
-    fn read_reg(&mut self, reg: u8) -> Chip8Result<u8> {
-        self.v
-            .get(usize::from(reg))
+    fn read_key(&self, x: u8) -> Chip8Result<bool> {
+        let key = usize::from(self.read_reg(x)?);
+        self.keys
+            .get(key)
             .copied()
-            .ok_or(Chip8Error::InvalidReg(reg))
+            .ok_or_else(|| Chip8ErrorKind::InvalidKey(key).into())
+    }
+
+    /// Write a byte of memory from the program. The address must have been checked with `mem_range`.
+    #[inline]
+    fn write_mem(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        if self.tracer.is_some() {
+            self.trace_writes.push(MemoryWrite {
+                addr: addr as u16,
+                value,
+            });
+        }
+    }
+
+    #[inline]
+    fn increment_i_after_bulk(&mut self, x: u8) {
+        if !self.quirks.memory_leave_i_unchanged {
+            self.i = self.i.wrapping_add(u16::from(x));
+            if !self.quirks.memory_increment_by_x {
+                self.i = self.i.wrapping_add(1);
+            }
+        }
+    }
+
+    /// Push a call from the current pc, checking the depth first so a failed call changes nothing
+    #[inline]
+    fn push_stack(&mut self, target: u16) -> Chip8Result<()> {
+        let depth = self.stack.len();
+        if depth >= MAX_STACK_DEPTH {
+            return Err(Chip8ErrorKind::StackOverflow.into());
+        }
+        if depth >= usize::from(self.quirks.stack_depth) {
+            match self.stack_overflow_mode {
+                StackOverflowMode::Error => return Err(Chip8ErrorKind::StackOverflow.into()),
+                StackOverflowMode::Warn => self.stack_overflowed = true,
+            }
+        }
+
+        self.stack.push(CallFrame {
+            call_site: self.pc,
+            target,
+        });
+        Ok(())
+    }
+
+    /// Pop a call, returning its return address
+    #[inline]
+    fn pop_stack(&mut self) -> Chip8Result<u16> {
+        self.stack
+            .pop()
+            .map(|frame| frame.return_address())
+            .ok_or_else(|| Chip8ErrorKind::StackUnderflow.into())
+    }
+
+    #[inline]
+    fn read_reg(&self, reg: u8) -> Chip8Result<u8> {
+        self.reg(reg)
     }
 
     #[inline]
@@ -412,11 +846,40 @@ impl Chip8 {
         *self
             .v
             .get_mut(usize::from(reg))
-            .ok_or(Chip8Error::InvalidReg(reg))? = value;
+            .ok_or(Chip8ErrorKind::InvalidReg(reg))? = value;
         Ok(())
     }
 }
 
+impl Clone for Chip8 {
+    /// Copy the emulator state. The tracer is not copied, so the copy is never traced.
+    fn clone(&self) -> Self {
+        Chip8 {
+            memory: self.memory,
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            stack: self.stack.clone(),
+            stack_overflow_mode: self.stack_overflow_mode,
+            stack_overflowed: self.stack_overflowed,
+            gfx: self.gfx,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            draw_flag: self.draw_flag,
+            sound_started: self.sound_started,
+            sound_stopped: self.sound_stopped,
+            keys: self.keys,
+            key_pressed: self.key_pressed,
+            quirks: self.quirks,
+            vblank: self.vblank,
+            rng: self.rng.clone(),
+            tracer: None,
+            trace_writes: Vec::new(),
+            memory_generation: self.memory_generation,
+        }
+    }
+}
+
 impl Default for Chip8 {
     fn default() -> Self {
         Self::new()
@@ -426,11 +889,12 @@ impl Default for Chip8 {
 impl fmt::Display for Chip8 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Chip8")?;
-        writeln!(f, "PC: {}", self.pc)?;
-        writeln!(f, "Stack: {:?}", self.stack)?;
-        writeln!(f, "SP: {}", self.sp)?;
-        writeln!(f, "V: {:?}", self.v)?;
-        writeln!(f, "I: {:?}", self.i)?;
+        writeln!(f, "PC: {:#05X}", self.pc)?;
+        let returns: Vec<u16> = self.stack.iter().map(CallFrame::return_address).collect();
+        writeln!(f, "Stack: {:03X?}", returns)?;
+        writeln!(f, "SP: {}", self.sp())?;
+        writeln!(f, "V: {:02X?}", self.v)?;
+        writeln!(f, "I: {:#05X}", self.i)?;
         writeln!(f, "Delay timer: {}", self.delay_timer)?;
         write!(f, "Sound timer: {}", self.sound_timer)
     }
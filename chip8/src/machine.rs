@@ -0,0 +1,359 @@
+//! Frame scheduling.
+//!
+//! A [`Machine`] owns a [`Chip8`] and decides when to execute instructions and when to tick the timers.
+//! Instructions run at a configurable rate while the timers always tick at [`TIMER_HZ`],
+//! independent of how often the host calls in.
+
+#[cfg(feature = "capture")]
+use crate::capture::Recorder;
+use crate::{
+    database::DEFAULT_TICKRATE,
+    deflicker::{
+        Deflicker,
+        DeflickerMode,
+    },
+    engine::{
+        Engine,
+        EngineKind,
+    },
+    state::{
+        Reader,
+        StateError,
+        Writer,
+    },
+    Chip8,
+    Chip8Result,
+    GFX_SIZE,
+};
+use std::{
+    convert::TryFrom,
+    time::Duration,
+};
+
+/// The rate the delay and sound timers count down at
+pub const TIMER_HZ: u32 = 60;
+
+/// The default number of instructions executed per second
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = DEFAULT_TICKRATE * TIMER_HZ;
+
+/// The most host time one call to [`Machine::run_for`] catches up on, so a host that stalls or sleeps
+/// doesn't get a burst of instructions afterwards
+pub const MAX_RUN_FOR: Duration = Duration::from_millis(250);
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+const MACHINE_MAGIC: &[u8; 4] = b"C8MS";
+
+/// What happened during a call to [`Machine::run_frame`] or [`Machine::run_for`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEvents {
+    /// The number of instructions executed
+    pub instructions: u64,
+
+    /// The number of times the timers ticked
+    pub timer_ticks: u64,
+
+    /// Whether the display changed
+    pub drew: bool,
+
+    /// Whether the sound turned on at some point
+    pub sound_started: bool,
+
+    /// Whether the sound turned off at some point
+    pub sound_stopped: bool,
+
+    /// Whether the sound is on at the end of the run
+    pub sound_on: bool,
+
+    /// Whether a call nested deeper than the stack depth, with [`StackOverflowMode::Warn`](crate::StackOverflowMode::Warn)
+    pub stack_overflowed: bool,
+}
+
+/// A chip8 with a clock
+pub struct Machine {
+    chip8: Chip8,
+    engine: Box<dyn Engine + Send>,
+    engine_kind: EngineKind,
+    instructions_per_second: u32,
+
+    // Time is measured in units of 1 / (instructions_per_second * TIMER_HZ) seconds,
+    // so an instruction takes TIMER_HZ units and a timer period takes instructions_per_second units.
+    /// Units of time that have passed
+    clock: u64,
+    /// The time the next instruction is due
+    next_instruction: u64,
+    /// The time the next timer tick is due
+    next_timer: u64,
+    /// Nanoseconds times units per second that did not add up to a whole unit yet
+    remainder: u128,
+
+    deflicker: Deflicker,
+    /// The end of the last frame given to the deflicker
+    presented: Option<u64>,
+    #[cfg(feature = "capture")]
+    recorder: Option<Recorder>,
+}
+
+impl Machine {
+    /// Wrap a chip8
+    pub fn new(chip8: Chip8) -> Self {
+        Machine {
+            chip8,
+            engine: EngineKind::default().create(),
+            engine_kind: EngineKind::default(),
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            clock: 0,
+            next_instruction: 0,
+            next_timer: 0,
+            remainder: 0,
+            deflicker: Deflicker::default(),
+            presented: None,
+            #[cfg(feature = "capture")]
+            recorder: None,
+        }
+    }
+
+    /// Get the chip8
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    /// Get the chip8 mutably
+    pub fn chip8_mut(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+
+    /// Unwrap the chip8
+    pub fn into_inner(self) -> Chip8 {
+        self.chip8
+    }
+
+    /// Get the kind of engine that executes instructions
+    pub fn engine_kind(&self) -> EngineKind {
+        self.engine_kind
+    }
+
+    /// Switch to a different engine
+    pub fn set_engine(&mut self, kind: EngineKind) {
+        self.engine = kind.create();
+        self.engine_kind = kind;
+    }
+
+    /// Get the number of instructions executed per second
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    /// Set the number of instructions executed per second. 0 is treated as 1.
+    ///
+    /// This resets the clock, so any partially elapsed instruction or timer period is dropped.
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second.max(1);
+        self.reset_clock();
+    }
+
+    /// Set the number of instructions executed per frame, like the tick rate from the rom database.
+    /// Rates too fast to count in instructions per second run as fast as can be counted.
+    pub fn set_tickrate(&mut self, tickrate: u32) {
+        let instructions_per_second = tickrate.saturating_mul(TIMER_HZ);
+        self.set_instructions_per_second(instructions_per_second);
+    }
+
+    /// Reset the clock without touching the chip8
+    pub fn reset_clock(&mut self) {
+        self.clock = 0;
+        self.next_instruction = 0;
+        self.next_timer = 0;
+        self.remainder = 0;
+        self.presented = None;
+        self.deflicker.reset();
+    }
+
+    /// Get how frames are blended into the [`presentation`](Self::presentation)
+    pub fn deflicker_mode(&self) -> DeflickerMode {
+        self.deflicker.mode()
+    }
+
+    /// Set how frames are blended into the [`presentation`](Self::presentation), starting over from a blank screen
+    pub fn set_deflicker_mode(&mut self, mode: DeflickerMode) {
+        self.deflicker.set_mode(mode);
+        self.presented = None;
+    }
+
+    /// Get the screen blended over the last frames, as brightnesses from 0 to 255.
+    ///
+    /// It changes once every instruction of a frame ran, so it's the same however often the host calls in.
+    pub fn presentation(&self) -> &[u8; GFX_SIZE] {
+        self.deflicker.presentation()
+    }
+
+    /// Attach or detach a recorder, returning the previous one.
+    ///
+    /// The recorder gets the [`presentation`](Self::presentation) of every frame as it finishes,
+    /// so recordings run on the emulated clock and have every frame however often the host calls in.
+    #[cfg(feature = "capture")]
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) -> Option<Recorder> {
+        std::mem::replace(&mut self.recorder, recorder)
+    }
+
+    /// Get the attached recorder
+    #[cfg(feature = "capture")]
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    /// Get the attached recorder mutably
+    #[cfg(feature = "capture")]
+    pub fn recorder_mut(&mut self) -> Option<&mut Recorder> {
+        self.recorder.as_mut()
+    }
+
+    /// Run for exactly one timer period
+    pub fn run_frame(&mut self) -> Chip8Result<FrameEvents> {
+        self.clock += u64::from(self.instructions_per_second);
+        self.catch_up()
+    }
+
+    /// Run for some amount of host time, up to [`MAX_RUN_FOR`]
+    pub fn run_for(&mut self, duration: Duration) -> Chip8Result<FrameEvents> {
+        let units_per_second = u128::from(self.instructions_per_second) * u128::from(TIMER_HZ);
+        let total = duration
+            .min(MAX_RUN_FOR)
+            .as_nanos()
+            .saturating_mul(units_per_second)
+            .saturating_add(self.remainder);
+        let units = u64::try_from(total / NANOS_PER_SEC).unwrap_or(u64::MAX);
+        self.clock = self.clock.saturating_add(units);
+        self.remainder = total % NANOS_PER_SEC;
+
+        self.catch_up()
+    }
+
+    /// Execute exactly one instruction, ticking the timers first if they are due before it
+    pub fn step(&mut self) -> Chip8Result<FrameEvents> {
+        // Catching up leaves nothing due, so the next instruction is the first thing after the current time
+        self.clock = self.next_instruction + 1;
+        self.catch_up()
+    }
+
+    /// Save the state of the chip8 and the clock
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::new(MACHINE_MAGIC);
+        self.chip8.write_state(&mut writer);
+        writer.u32(self.instructions_per_second);
+        writer.u64(self.clock);
+        writer.u64(self.next_instruction);
+        writer.u64(self.next_timer);
+        writer.u128(self.remainder);
+        writer.finish()
+    }
+
+    /// Restore a state made by [`Machine::save_state`]. Nothing changes if the state is invalid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader::new(data, MACHINE_MAGIC)?;
+        let chip8 = Chip8::read_state(&mut reader)?;
+        let instructions_per_second = reader.u32()?;
+        if instructions_per_second == 0 {
+            return Err(StateError::InvalidValue("instructions_per_second"));
+        }
+        let clock = reader.u64()?;
+        let next_instruction = reader.u64()?;
+        let next_timer = reader.u64()?;
+        let remainder = reader.u128()?;
+        if remainder >= NANOS_PER_SEC {
+            return Err(StateError::InvalidValue("remainder"));
+        }
+        reader.finish()?;
+
+        self.chip8.restore(chip8);
+        self.instructions_per_second = instructions_per_second;
+        self.clock = clock;
+        self.next_instruction = next_instruction;
+        self.next_timer = next_timer;
+        self.remainder = remainder;
+        self.presented = None;
+        self.deflicker.reset();
+        Ok(())
+    }
+
+    /// Execute everything that is due before the current time
+    fn catch_up(&mut self) -> Chip8Result<FrameEvents> {
+        let mut events = FrameEvents::default();
+        self.chip8.take_sound_changes();
+        self.chip8.take_stack_overflowed();
+
+        while self.next_timer < self.clock || self.next_instruction < self.clock {
+            // Timers go first when both are due at the same time
+            if self.next_timer <= self.next_instruction {
+                self.present();
+                self.chip8.update_timers();
+                self.next_timer += u64::from(self.instructions_per_second);
+                events.timer_ticks += 1;
+            } else {
+                // Run every instruction that is due before the next timer tick in one go
+                let until = self.clock.min(self.next_timer);
+                let hz = u64::from(TIMER_HZ);
+                let count = (until - self.next_instruction).div_ceil(hz);
+
+                // Instructions that ran before an error still took their time
+                let (executed, result) = match self.engine.run(&mut self.chip8, count) {
+                    Ok(executed) => (executed, Ok(())),
+                    Err(e) => (e.executed, Err(e.error)),
+                };
+                self.next_instruction += executed * hz;
+                events.instructions += executed;
+                result?;
+            }
+        }
+
+        self.present();
+        events.drew = self.chip8.take_draw_flag();
+        (events.sound_started, events.sound_stopped) = self.chip8.take_sound_changes();
+        events.sound_on = self.chip8.is_sound_on();
+        events.stack_overflowed = self.chip8.take_stack_overflowed();
+
+        Ok(events)
+    }
+
+    /// Give the deflicker and the recorder the frame that ends at the next timer tick, once all of its instructions ran.
+    /// The tick at the start of the clock ends no frame.
+    fn present(&mut self) {
+        if self.next_timer > 0
+            && self.next_instruction >= self.next_timer
+            && self.presented != Some(self.next_timer)
+        {
+            self.deflicker.push(&self.chip8.gfx);
+            self.presented = Some(self.next_timer);
+            #[cfg(feature = "capture")]
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.push(self.deflicker.presentation(), self.chip8.is_sound_on());
+            }
+        }
+    }
+}
+
+impl Clone for Machine {
+    /// Copy the machine. The engine's cache is shared with the copy, but the tracer and recorder aren't copied.
+    fn clone(&self) -> Self {
+        Machine {
+            chip8: self.chip8.clone(),
+            engine: self.engine.boxed_clone(),
+            engine_kind: self.engine_kind,
+            instructions_per_second: self.instructions_per_second,
+            clock: self.clock,
+            next_instruction: self.next_instruction,
+            next_timer: self.next_timer,
+            remainder: self.remainder,
+            deflicker: self.deflicker.clone(),
+            presented: self.presented,
+            #[cfg(feature = "capture")]
+            recorder: None,
+        }
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new(Chip8::default())
+    }
+}
@@ -0,0 +1,107 @@
+/// Behavior differences between chip8 implementations.
+///
+/// The field names follow the community chip-8-database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VX in place instead of shifting VY into VX
+    pub shift: bool,
+
+    /// `FX55`/`FX65` increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+
+    /// `FX55`/`FX65` leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+
+    /// Sprites wrap around the edges of the screen instead of being clipped
+    pub wrap: bool,
+
+    /// `BNNN` jumps to NNN + VX instead of NNN + V0
+    pub jump: bool,
+
+    /// `DXYN` waits for the next timer tick before drawing
+    pub vblank: bool,
+
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0
+    pub logic: bool,
+
+    /// How deep calls can nest
+    pub stack_depth: u8,
+}
+
+impl Quirks {
+    /// The COSMAC VIP interpreter
+    pub const CHIP8: Self = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: false,
+        jump: false,
+        vblank: true,
+        logic: true,
+        stack_depth: 12,
+    };
+
+    /// Modern interpreters that follow the VIP for the most part
+    pub const MODERN_CHIP8: Self = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: false,
+        jump: false,
+        vblank: false,
+        logic: false,
+        stack_depth: 16,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SCHIP: Self = Quirks {
+        shift: true,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: true,
+        wrap: false,
+        jump: true,
+        vblank: false,
+        logic: false,
+        stack_depth: 16,
+    };
+
+    /// XO-CHIP
+    pub const XOCHIP: Self = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: true,
+        jump: false,
+        vblank: false,
+        logic: false,
+        stack_depth: 16,
+    };
+}
+
+impl Quirks {
+    /// Get the quirks of a platform, by its id in the community chip-8-database
+    pub fn for_platform(id: &str) -> Option<Self> {
+        match id {
+            "originalChip8" => Some(Quirks::CHIP8),
+            "modernChip8" => Some(Quirks::MODERN_CHIP8),
+            "superchip" => Some(Quirks::SCHIP),
+            "xochip" => Some(Quirks::XOCHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: false,
+            vblank: false,
+            logic: false,
+            stack_depth: 16,
+        }
+    }
+}
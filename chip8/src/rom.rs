@@ -0,0 +1,408 @@
+//! Loading roms from the containers they're shared in.
+//!
+//! [`Rom::parse`] tells the formats apart by their first bytes:
+//!
+//! - Octo cartridges are GIFs (`GIF8`) with the program hidden in their pixels. Reading them needs the `gif` feature.
+//!   Read in order, frame by frame, the low two bits of each pixel's palette index make up a byte stream,
+//!   four pixels to a byte with the most significant bits first.
+//!   The stream is a big-endian `u32` length followed by that many bytes of JSON, `{ "options": {..}, "program": ".." }`.
+//!   The program is Octo source and is built with the [`assembler`](crate::assembler), so it has to stick to the subset it supports:
+//!   labels, `:const`, calls, the instructions in Octo syntax, `if`, `loop ... again` with `while`, and bytes of data.
+//!   Macros, `:alias`, `:org`, `:calc`, `:next`, `:unpack` and the other directives aren't supported,
+//!   and cartridges that use them fail with [`RomError::UnsupportedSource`].
+//! - c8b bundles start with `CBF`, then a version byte and the big-endian `u16` offset of the properties.
+//!   From byte 6 up to the properties is a table of programs, 5 bytes each:
+//!   a platform id from [`BUNDLE_PLATFORMS`], then the big-endian `u16` offset and length of the bytecode.
+//!   Properties run to the end of the file as a tag byte, a length byte and the value.
+//!   Tag 0 is the tick rate as a big-endian number and tag 1 is the name in UTF-8. Others are skipped.
+//! - Anything else is a raw program.
+//!
+//! Options stored in the container come first, then what the [`RomDatabase`] knows about the program,
+//! then the defaults of its platform.
+
+use crate::{
+    assembler::{
+        assemble,
+        AssembleError,
+    },
+    database::{
+        RomDatabase,
+        RomInfo,
+        DEFAULT_TICKRATE,
+    },
+    source_map::SourceMap,
+    Chip8,
+    Chip8Result,
+    Quirks,
+    MEMORY_SIZE,
+    MEMORY_START,
+};
+use serde::Deserialize;
+use std::{
+    fmt,
+    path::Path,
+};
+
+/// A platform programs in a c8b bundle can be for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundlePlatform {
+    pub id: u8,
+    pub name: &'static str,
+    /// The platform's id in the community chip-8-database
+    pub database_id: &'static str,
+    pub load_address: u16,
+}
+
+/// The platforms that can be run from a bundle, best first
+pub const BUNDLE_PLATFORMS: &[BundlePlatform] = &[
+    BundlePlatform {
+        id: 0x00,
+        name: "CHIP-8",
+        database_id: "originalChip8",
+        load_address: 0x200,
+    },
+    BundlePlatform {
+        id: 0x01,
+        name: "CHIP-8 on the ETI-660",
+        database_id: "originalChip8",
+        load_address: 0x600,
+    },
+    BundlePlatform {
+        id: 0x0D,
+        name: "SUPER-CHIP 1.1",
+        database_id: "superchip",
+        load_address: 0x200,
+    },
+    BundlePlatform {
+        id: 0x11,
+        name: "XO-CHIP",
+        database_id: "xochip",
+        load_address: 0x200,
+    },
+];
+
+/// The container a rom came in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    Raw,
+    OctoCartridge,
+    Bundle,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(std::io::Error),
+    #[cfg(feature = "gif")]
+    Gif(gif::DecodingError),
+    /// The data in a cartridge is cut off or isn't what Octo writes
+    InvalidCartridge(String),
+    /// The program in a cartridge uses Octo the assembler doesn't support, or doesn't assemble at all
+    UnsupportedSource(AssembleError),
+    InvalidBundle(&'static str),
+    /// None of the programs in a bundle are for a platform in [`BUNDLE_PLATFORMS`], by their ids
+    UnsupportedPlatforms(Vec<u8>),
+    /// The program doesn't fit in memory above its load address
+    TooLarge {
+        size: usize,
+        load_address: u16,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(e) => e.fmt(f),
+            #[cfg(feature = "gif")]
+            RomError::Gif(e) => write!(f, "invalid cartridge gif: {}", e),
+            RomError::InvalidCartridge(reason) => write!(f, "invalid cartridge: {}", reason),
+            RomError::UnsupportedSource(e) => write!(f, "unsupported cartridge source: {}", e),
+            RomError::InvalidBundle(reason) => write!(f, "invalid bundle: {}", reason),
+            RomError::UnsupportedPlatforms(ids) => {
+                write!(f, "the bundle has no programs for a supported platform (")?;
+                for (i, id) in ids.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:#04X}", id)?;
+                }
+                write!(f, ")")
+            }
+            RomError::TooLarge { size, load_address } => write!(
+                f,
+                "a {} byte program doesn't fit in memory at {:#05X}",
+                size, load_address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<std::io::Error> for RomError {
+    fn from(e: std::io::Error) -> Self {
+        RomError::Io(e)
+    }
+}
+
+#[cfg(feature = "gif")]
+impl From<gif::DecodingError> for RomError {
+    fn from(e: gif::DecodingError) -> Self {
+        RomError::Gif(e)
+    }
+}
+
+impl From<AssembleError> for RomError {
+    fn from(e: AssembleError) -> Self {
+        RomError::UnsupportedSource(e)
+    }
+}
+
+/// A program and how to run it
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub format: RomFormat,
+    pub data: Vec<u8>,
+    pub load_address: u16,
+    /// The id of the platform in the community chip-8-database, if it's known
+    pub platform: Option<String>,
+    pub quirks: Quirks,
+    pub tickrate: u32,
+    pub title: Option<String>,
+    /// What the database knows about the program
+    pub info: Option<RomInfo>,
+    /// The source map of a program assembled from a cartridge
+    pub source_map: Option<SourceMap>,
+}
+
+impl Rom {
+    /// Read a rom from a file
+    pub fn open<P: AsRef<Path>>(path: P, database: &RomDatabase) -> Result<Self, RomError> {
+        Self::parse(&std::fs::read(path)?, database)
+    }
+
+    /// Read a rom in any of the supported formats
+    pub fn parse(data: &[u8], database: &RomDatabase) -> Result<Self, RomError> {
+        let rom = if data.starts_with(b"GIF8") {
+            Self::parse_cartridge(data, database)?
+        } else if data.starts_with(b"CBF") {
+            Self::parse_bundle(data, database)?
+        } else {
+            Self::raw(data.to_vec(), database)
+        };
+
+        let space = MEMORY_SIZE.saturating_sub(usize::from(rom.load_address));
+        if rom.data.len() > space {
+            return Err(RomError::TooLarge {
+                size: rom.data.len(),
+                load_address: rom.load_address,
+            });
+        }
+
+        Ok(rom)
+    }
+
+    /// Make a rom from a raw program, using the database for its options
+    pub fn raw(data: Vec<u8>, database: &RomDatabase) -> Self {
+        let info = database.lookup(&data);
+        Rom {
+            format: RomFormat::Raw,
+            load_address: info
+                .as_ref()
+                .and_then(|info| info.start_address)
+                .unwrap_or(MEMORY_START as u16),
+            platform: info.as_ref().and_then(|info| info.platform.clone()),
+            quirks: info.as_ref().map(|info| info.quirks).unwrap_or_default(),
+            tickrate: info
+                .as_ref()
+                .map(|info| info.tickrate)
+                .unwrap_or(DEFAULT_TICKRATE),
+            title: info.as_ref().map(|info| info.title.clone()),
+            info,
+            data,
+            source_map: None,
+        }
+    }
+
+    fn parse_cartridge(data: &[u8], database: &RomDatabase) -> Result<Self, RomError> {
+        let payload = cartridge_payload(data)?;
+        let cartridge: Cartridge = serde_json::from_slice(&payload)
+            .map_err(|e| RomError::InvalidCartridge(e.to_string()))?;
+        let assembly = assemble(&cartridge.program, "cartridge.8o")?;
+
+        let info = database.lookup(&assembly.rom);
+        let options = cartridge.options;
+        Ok(Rom {
+            format: RomFormat::OctoCartridge,
+            load_address: MEMORY_START as u16,
+            platform: info.as_ref().and_then(|info| info.platform.clone()),
+            quirks: options.quirks(),
+            tickrate: options
+                .tickrate
+                .or_else(|| info.as_ref().map(|info| info.tickrate))
+                .unwrap_or(DEFAULT_TICKRATE),
+            title: info.as_ref().map(|info| info.title.clone()),
+            info,
+            data: assembly.rom,
+            source_map: Some(assembly.source_map),
+        })
+    }
+
+    fn parse_bundle(data: &[u8], database: &RomDatabase) -> Result<Self, RomError> {
+        if data.len() < 6 {
+            return Err(RomError::InvalidBundle("the header is cut off"));
+        }
+        let properties = usize::from(u16::from_be_bytes([data[4], data[5]]));
+        if properties < 6 || properties > data.len() || (properties - 6) % 5 != 0 {
+            return Err(RomError::InvalidBundle("the program table is malformed"));
+        }
+
+        let mut programs = Vec::new();
+        for entry in data[6..properties].chunks(5) {
+            let offset = usize::from(u16::from_be_bytes([entry[1], entry[2]]));
+            let len = usize::from(u16::from_be_bytes([entry[3], entry[4]]));
+            let bytecode = data
+                .get(offset..offset + len)
+                .ok_or(RomError::InvalidBundle("a program is outside of the file"))?;
+            programs.push((entry[0], bytecode));
+        }
+
+        let (platform, bytecode) = BUNDLE_PLATFORMS
+            .iter()
+            .find_map(|platform| {
+                programs
+                    .iter()
+                    .find(|(id, _)| *id == platform.id)
+                    .map(|(_, bytecode)| (platform, *bytecode))
+            })
+            .ok_or_else(|| {
+                RomError::UnsupportedPlatforms(programs.iter().map(|p| p.0).collect())
+            })?;
+
+        let mut tickrate = None;
+        let mut name = None;
+        let mut rest = &data[properties..];
+        while !rest.is_empty() {
+            if rest.len() < 2 || rest.len() < 2 + usize::from(rest[1]) {
+                return Err(RomError::InvalidBundle("a property is cut off"));
+            }
+            let value = &rest[2..2 + usize::from(rest[1])];
+            match rest[0] {
+                0x00 => {
+                    if value.is_empty() || value.len() > 4 {
+                        return Err(RomError::InvalidBundle("the tick rate is malformed"));
+                    }
+                    tickrate = Some(value.iter().fold(0, |n, &b| n << 8 | u32::from(b)));
+                }
+                0x01 => name = Some(String::from_utf8_lossy(value).into_owned()),
+                _ => {}
+            }
+            rest = &rest[2 + value.len()..];
+        }
+
+        let info = database.lookup(bytecode);
+        Ok(Rom {
+            format: RomFormat::Bundle,
+            load_address: platform.load_address,
+            platform: Some(String::from(platform.database_id)),
+            quirks: Quirks::for_platform(platform.database_id).unwrap_or_default(),
+            tickrate: tickrate
+                .or_else(|| info.as_ref().map(|info| info.tickrate))
+                .unwrap_or(DEFAULT_TICKRATE),
+            title: name.or_else(|| info.as_ref().map(|info| info.title.clone())),
+            info,
+            data: bytecode.to_vec(),
+            source_map: None,
+        })
+    }
+
+    /// Load the program into an emulator and use its quirks
+    pub fn load_into(&self, chip8: &mut Chip8) -> Chip8Result<()> {
+        chip8.load_at(&self.data, self.load_address)?;
+        chip8.set_quirks(self.quirks);
+        Ok(())
+    }
+}
+
+/// The JSON stored in an Octo cartridge
+#[derive(Debug, Deserialize)]
+struct Cartridge {
+    #[serde(default)]
+    options: CartridgeOptions,
+    program: String,
+}
+
+/// The options Octo saves with a program. Unset quirks are off, like in Octo.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CartridgeOptions {
+    tickrate: Option<u32>,
+    #[serde(default)]
+    shift_quirks: bool,
+    #[serde(default)]
+    load_store_quirks: bool,
+    #[serde(default)]
+    clip_quirks: bool,
+    #[serde(default)]
+    jump_quirks: bool,
+    #[serde(default)]
+    v_blank_quirks: bool,
+    #[serde(default)]
+    logic_quirks: bool,
+}
+
+impl CartridgeOptions {
+    fn quirks(&self) -> Quirks {
+        Quirks {
+            shift: self.shift_quirks,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: self.load_store_quirks,
+            wrap: !self.clip_quirks,
+            jump: self.jump_quirks,
+            vblank: self.v_blank_quirks,
+            logic: self.logic_quirks,
+            stack_depth: Quirks::MODERN_CHIP8.stack_depth,
+        }
+    }
+}
+
+/// Get the bytes hidden in the pixels of a cartridge, without the length
+#[cfg(feature = "gif")]
+fn cartridge_payload(data: &[u8]) -> Result<Vec<u8>, RomError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(data)?;
+
+    let mut bytes = Vec::new();
+    let mut byte = 0;
+    let mut pixels = 0;
+    while let Some(frame) = decoder.read_next_frame()? {
+        for &pixel in frame.buffer.iter() {
+            byte = byte << 2 | (pixel & 0b11);
+            pixels += 1;
+            if pixels == 4 {
+                bytes.push(byte);
+                byte = 0;
+                pixels = 0;
+            }
+        }
+    }
+
+    if bytes.len() < 4 {
+        return Err(RomError::InvalidCartridge(String::from(
+            "there is no payload",
+        )));
+    }
+    let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let payload = 4usize
+        .checked_add(len)
+        .and_then(|end| bytes.get(4..end))
+        .ok_or_else(|| RomError::InvalidCartridge(String::from("the payload is cut off")))?;
+    Ok(payload.to_vec())
+}
+
+#[cfg(not(feature = "gif"))]
+fn cartridge_payload(_data: &[u8]) -> Result<Vec<u8>, RomError> {
+    Err(RomError::InvalidCartridge(String::from(
+        "reading cartridges needs the gif feature",
+    )))
+}
@@ -0,0 +1,320 @@
+//! Source maps from assembled roms back to their source.
+//!
+//! A [`SourceMap`] records the file and line each address was assembled from and the labels the source defines,
+//! so tools can show `game.8o:42 (main_loop+6)` instead of `0x2A6`.
+//!
+//! # Format
+//! Source maps are stored as JSON:
+//!
+//! ```json
+//! {
+//!   "files": [ "game.8o" ],
+//!   "lines": [ { "address": 512, "file": 0, "line": 4 } ],
+//!   "labels": [ { "name": "main", "address": 512 } ],
+//!   "end": 644
+//! }
+//! ```
+//!
+//! Each line entry is the address the code or data of a line starts at, and covers every address up to the next entry.
+//! `end` is the address after the last byte, and `labels` and `end` are optional.
+//! Relative file paths are relative to the directory of the source map.
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// An error loading a [`SourceMap`]
+#[derive(Debug)]
+pub enum SourceMapError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// A line refers to a file that isn't listed
+    InvalidFile {
+        line: u32,
+        file: usize,
+    },
+}
+
+impl fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceMapError::Io(e) => e.fmt(f),
+            SourceMapError::Json(e) => e.fmt(f),
+            SourceMapError::InvalidFile { line, file } => {
+                write!(f, "line {} is in file {}, which isn't listed", line, file)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceMapError {}
+
+impl From<std::io::Error> for SourceMapError {
+    fn from(e: std::io::Error) -> Self {
+        SourceMapError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SourceMapError {
+    fn from(e: serde_json::Error) -> Self {
+        SourceMapError::Json(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawSourceMap {
+    files: Vec<PathBuf>,
+    lines: Vec<RawLine>,
+    #[serde(default)]
+    labels: Vec<RawLabel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawLine {
+    address: u16,
+    file: usize,
+    line: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawLabel {
+    name: String,
+    address: u16,
+}
+
+/// A line of a source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Line {
+    /// The index of the file in [`SourceMap::files`]
+    pub file: usize,
+    pub line: u32,
+}
+
+/// Where an address came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    pub address: u16,
+    /// The file and line, as written in the source map
+    pub line: Option<(&'a Path, u32)>,
+    /// The closest label at or before the address, and the distance from it
+    pub symbol: Option<(&'a str, u16)>,
+}
+
+impl fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some((file, line)) => write!(f, "{}:{}", file.display(), line)?,
+            None => write!(f, "{:#05X}", self.address)?,
+        }
+        match self.symbol {
+            Some((name, 0)) => write!(f, " ({})", name),
+            Some((name, offset)) => write!(f, " ({}+{})", name, offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Maps addresses to source lines and labels
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<PathBuf>,
+    /// The directory relative file paths are relative to
+    base: PathBuf,
+    lines: BTreeMap<u16, Line>,
+    /// The first address of every line
+    line_addresses: BTreeMap<Line, u16>,
+    labels: Vec<(String, u16)>,
+    /// The first label defined at each address, as an index into `labels`
+    symbols: BTreeMap<u16, usize>,
+    end: Option<u16>,
+}
+
+impl SourceMap {
+    /// Make an empty source map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a source map. Relative file paths are kept relative to the current directory.
+    pub fn from_json(data: &[u8]) -> Result<Self, SourceMapError> {
+        let raw: RawSourceMap = serde_json::from_slice(data)?;
+
+        let mut map = SourceMap::new();
+        for file in raw.files {
+            map.add_file(file);
+        }
+        for line in raw.lines {
+            if line.file >= map.files.len() {
+                return Err(SourceMapError::InvalidFile {
+                    line: line.line,
+                    file: line.file,
+                });
+            }
+            map.add_line(line.address, line.file, line.line);
+        }
+        for label in raw.labels {
+            map.add_label(label.name, label.address);
+        }
+        map.end = raw.end;
+        Ok(map)
+    }
+
+    /// Load a source map from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SourceMapError> {
+        let path = path.as_ref();
+        let mut map = Self::from_json(&std::fs::read(path)?)?;
+        map.base = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        Ok(map)
+    }
+
+    /// Write the source map as JSON
+    pub fn to_json(&self) -> String {
+        let raw = RawSourceMap {
+            files: self.files.clone(),
+            lines: self
+                .lines
+                .iter()
+                .map(|(&address, line)| RawLine {
+                    address,
+                    file: line.file,
+                    line: line.line,
+                })
+                .collect(),
+            labels: self
+                .labels
+                .iter()
+                .map(|(name, address)| RawLabel {
+                    name: name.clone(),
+                    address: *address,
+                })
+                .collect(),
+            end: self.end,
+        };
+        serde_json::to_string_pretty(&raw).expect("source maps are always valid JSON")
+    }
+
+    /// Add a source file, returning its index
+    pub fn add_file<P: Into<PathBuf>>(&mut self, path: P) -> usize {
+        self.files.push(path.into());
+        self.files.len() - 1
+    }
+
+    /// Record that a line starts at an address
+    pub fn add_line(&mut self, address: u16, file: usize, line: u32) {
+        let line = Line { file, line };
+        self.lines.insert(address, line);
+        let first = self.line_addresses.entry(line).or_insert(address);
+        *first = address.min(*first);
+    }
+
+    /// Record a label
+    pub fn add_label<S: Into<String>>(&mut self, name: S, address: u16) {
+        self.labels.push((name.into(), address));
+        self.symbols.entry(address).or_insert(self.labels.len() - 1);
+    }
+
+    /// Set the address after the last mapped byte
+    pub fn set_end(&mut self, end: Option<u16>) {
+        self.end = end;
+    }
+
+    /// Get the source files, as written in the source map
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Get the path of a source file, relative to the current directory
+    pub fn file_path(&self, file: usize) -> PathBuf {
+        self.base.join(&self.files[file])
+    }
+
+    /// Find a source file by path
+    pub fn find_file<P: AsRef<Path>>(&self, path: P) -> Option<usize> {
+        let path = path.as_ref();
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        (0..self.files.len()).find(|&file| {
+            let file = self.file_path(file);
+            file.canonicalize().unwrap_or(file) == path
+        })
+    }
+
+    /// Check whether an address is in the mapped range
+    fn contains(&self, address: u16) -> bool {
+        self.end.is_none_or(|end| address < end)
+    }
+
+    /// Get the line an address is part of
+    pub fn line(&self, address: u16) -> Option<Line> {
+        if !self.contains(address) {
+            return None;
+        }
+        self.lines
+            .range(..=address)
+            .next_back()
+            .map(|(_, &line)| line)
+    }
+
+    /// Find the first mapped line at or after a line, and the address it starts at
+    pub fn resolve_line(&self, file: usize, line: u32) -> Option<(Line, u16)> {
+        self.line_addresses
+            .range(Line { file, line }..)
+            .next()
+            .filter(|(found, _)| found.file == file)
+            .map(|(&found, &address)| (found, address))
+    }
+
+    /// Get the address of a label
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|&(_, address)| address)
+    }
+
+    /// Get the closest label at or before an address, and the distance from it
+    pub fn symbol(&self, address: u16) -> Option<(&str, u16)> {
+        if !self.contains(address) {
+            return None;
+        }
+        self.symbols
+            .range(..=address)
+            .next_back()
+            .map(|(&start, &index)| (self.labels[index].0.as_str(), address - start))
+    }
+
+    /// Get where an address came from, if the source map knows anything about it
+    pub fn location(&self, address: u16) -> Option<SourceLocation<'_>> {
+        let line = self
+            .line(address)
+            .map(|line| (self.files[line.file].as_path(), line.line));
+        let symbol = self.symbol(address);
+        if line.is_none() && symbol.is_none() {
+            return None;
+        }
+
+        Some(SourceLocation {
+            address,
+            line,
+            symbol,
+        })
+    }
+
+    /// Describe an address, like `game.8o:42 (main_loop+6)`, or `0x2A6` if it isn't mapped
+    pub fn describe(&self, address: u16) -> String {
+        match self.location(address) {
+            Some(location) => location.to_string(),
+            None => format!("{:#05X}", address),
+        }
+    }
+}
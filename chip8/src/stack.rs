@@ -0,0 +1,34 @@
+//! The call stack.
+//!
+//! How deep calls can nest is a quirk, [`Quirks::stack_depth`](crate::Quirks::stack_depth), since the COSMAC VIP
+//! only had room for 12 return addresses while later interpreters have 16 or more.
+
+use crate::OPCODE_SIZE;
+
+/// A call that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallFrame {
+    /// The address of the `2NNN` instruction
+    pub call_site: u16,
+    /// The address that was called
+    pub target: u16,
+}
+
+impl CallFrame {
+    /// Get the address execution continues at after the call returns
+    pub fn return_address(&self) -> u16 {
+        self.call_site + OPCODE_SIZE
+    }
+}
+
+/// What happens when calls nest deeper than the stack depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StackOverflowMode {
+    /// The call fails with [`Chip8ErrorKind::StackOverflow`](crate::Chip8ErrorKind::StackOverflow)
+    #[default]
+    Error,
+    /// The call goes through and [`FrameEvents::stack_overflowed`](crate::FrameEvents::stack_overflowed) is set,
+    /// which helps to find runaway recursion during development.
+    /// Calls still fail past [`MAX_STACK_DEPTH`](crate::MAX_STACK_DEPTH).
+    Warn,
+}
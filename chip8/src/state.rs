@@ -0,0 +1,366 @@
+//! Save states.
+//!
+//! [`Chip8::save_state`] and [`Machine::save_state`](crate::Machine::save_state) write the complete
+//! emulator state to a small binary blob that can be restored later, for hosts that need to store states
+//! as bytes instead of keeping clones around.
+//! Quirks and the random number generator are part of the state, but an attached tracer is not.
+//!
+//! All numbers are little endian. A state starts with a 4 byte magic and a format version.
+
+use crate::{
+    CallFrame,
+    Chip8,
+    Quirks,
+    StackOverflowMode,
+    MAX_STACK_DEPTH,
+    MEMORY_SIZE,
+    NUM_KEYS,
+    NUM_REGISTERS,
+};
+use rand::{
+    Rng,
+    SeedableRng,
+};
+use rand_chacha::ChaCha20Rng;
+use std::fmt;
+
+/// The current format version
+pub const STATE_VERSION: u8 = 2;
+
+const CHIP8_MAGIC: &[u8; 4] = b"C8ST";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data is not a state of the expected kind
+    InvalidMagic,
+    /// The state was made by an incompatible version
+    UnsupportedVersion(u8),
+    /// The data ended early or has trailing bytes
+    InvalidLength(usize),
+    /// A field holds a value that can't occur
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::InvalidLength(len) => write!(f, "invalid save state length {}", len),
+            StateError::InvalidValue(field) => write!(f, "invalid value for '{}'", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A random number generator that remembers enough to be saved
+#[derive(Clone)]
+pub(crate) struct SavableRng {
+    rng: ChaCha20Rng,
+    seed: [u8; 32],
+    /// The number of 32 bit words drawn so far
+    words: u128,
+}
+
+impl SavableRng {
+    pub(crate) fn from_entropy() -> Self {
+        Self::from_seed(rand::random())
+    }
+
+    /// Seed the same way `SeedableRng::seed_from_u64` does, so sequences match `StdRng`
+    pub(crate) fn seed_from_u64(seed: u64) -> Self {
+        struct Seed([u8; 32]);
+
+        impl SeedableRng for Seed {
+            type Seed = [u8; 32];
+
+            fn from_seed(seed: [u8; 32]) -> Self {
+                Seed(seed)
+            }
+        }
+
+        Self::from_seed(Seed::seed_from_u64(seed).0)
+    }
+
+    fn from_seed(seed: [u8; 32]) -> Self {
+        SavableRng {
+            rng: ChaCha20Rng::from_seed(seed),
+            seed,
+            words: 0,
+        }
+    }
+
+    pub(crate) fn gen_u8(&mut self) -> u8 {
+        // A u8 takes one word
+        self.words += 1;
+        self.rng.gen()
+    }
+
+    fn write_state(&self, writer: &mut Writer) {
+        writer.bytes(&self.seed);
+        writer.u128(self.words);
+    }
+
+    fn read_state(reader: &mut Reader) -> Result<Self, StateError> {
+        let mut rng = Self::from_seed(reader.array()?);
+        rng.words = reader.u128()?;
+        rng.rng.set_word_pos(rng.words);
+        Ok(rng)
+    }
+}
+
+pub(crate) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub(crate) fn new(magic: &[u8; 4]) -> Self {
+        let mut writer = Writer(Vec::new());
+        writer.bytes(magic);
+        writer.u8(STATE_VERSION);
+        writer
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(u8::from(value));
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u128(&mut self, value: u128) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8], magic: &[u8; 4]) -> Result<Self, StateError> {
+        let mut reader = Reader {
+            data,
+            len: data.len(),
+        };
+        if reader.bytes(4).ok() != Some(&magic[..]) {
+            return Err(StateError::InvalidMagic);
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::InvalidLength(self.len));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue(field)),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn u128(&mut self) -> Result<u128, StateError> {
+        self.array().map(u128::from_le_bytes)
+    }
+
+    /// Make sure all of the data was read
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        if !self.data.is_empty() {
+            return Err(StateError::InvalidLength(self.len));
+        }
+        Ok(())
+    }
+}
+
+impl Chip8 {
+    /// Save the emulator state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::new(CHIP8_MAGIC);
+        self.write_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Restore a state made by [`Chip8::save_state`]. Nothing changes if the state is invalid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader::new(data, CHIP8_MAGIC)?;
+        let state = Self::read_state(&mut reader)?;
+        reader.finish()?;
+        self.restore(state);
+        Ok(())
+    }
+
+    pub(crate) fn write_state(&self, writer: &mut Writer) {
+        writer.bytes(&self.memory);
+        writer.bytes(&self.v);
+        writer.u16(self.i);
+        writer.u16(self.pc);
+        // Every slot is written so states always have the same size
+        for slot in 0..MAX_STACK_DEPTH {
+            let frame = self.stack.get(slot).copied().unwrap_or(CallFrame {
+                call_site: 0,
+                target: 0,
+            });
+            writer.u16(frame.call_site);
+            writer.u16(frame.target);
+        }
+        writer.u8(self.sp());
+        writer.bool(self.stack_overflow_mode == StackOverflowMode::Warn);
+        writer.bool(self.stack_overflowed);
+        for &pixel in self.gfx.iter() {
+            writer.bool(pixel);
+        }
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bool(self.draw_flag);
+        writer.bool(self.sound_started);
+        writer.bool(self.sound_stopped);
+        for &key in self.keys.iter() {
+            writer.bool(key);
+        }
+        match self.key_pressed {
+            Some(key) => {
+                writer.bool(true);
+                writer.u8(key);
+            }
+            None => {
+                writer.bool(false);
+                writer.u8(0);
+            }
+        }
+
+        let quirks = self.quirks;
+        writer.bool(quirks.shift);
+        writer.bool(quirks.memory_increment_by_x);
+        writer.bool(quirks.memory_leave_i_unchanged);
+        writer.bool(quirks.wrap);
+        writer.bool(quirks.jump);
+        writer.bool(quirks.vblank);
+        writer.bool(quirks.logic);
+        writer.u8(quirks.stack_depth);
+        writer.bool(self.vblank);
+
+        self.rng.write_state(writer);
+    }
+
+    /// Read a state into a new chip8
+    pub(crate) fn read_state(reader: &mut Reader) -> Result<Self, StateError> {
+        let mut chip8 = Chip8::new();
+        chip8.memory.copy_from_slice(reader.bytes(MEMORY_SIZE)?);
+        chip8.v.copy_from_slice(reader.bytes(NUM_REGISTERS)?);
+        chip8.i = reader.u16()?;
+        chip8.pc = reader.u16()?;
+        let mut frames = Vec::with_capacity(MAX_STACK_DEPTH);
+        for _ in 0..MAX_STACK_DEPTH {
+            frames.push(CallFrame {
+                call_site: reader.u16()?,
+                target: reader.u16()?,
+            });
+        }
+        frames.truncate(usize::from(reader.u8()?));
+        chip8.stack = frames;
+        chip8.stack_overflow_mode = if reader.bool("stack_overflow_mode")? {
+            StackOverflowMode::Warn
+        } else {
+            StackOverflowMode::Error
+        };
+        chip8.stack_overflowed = reader.bool("stack_overflowed")?;
+        for pixel in chip8.gfx.iter_mut() {
+            *pixel = reader.bool("gfx")?;
+        }
+        chip8.delay_timer = reader.u8()?;
+        chip8.sound_timer = reader.u8()?;
+        chip8.draw_flag = reader.bool("draw_flag")?;
+        chip8.sound_started = reader.bool("sound_started")?;
+        chip8.sound_stopped = reader.bool("sound_stopped")?;
+        for key in chip8.keys.iter_mut() {
+            *key = reader.bool("keys")?;
+        }
+        let key_pressed = reader.bool("key_pressed")?;
+        let key = reader.u8()?;
+        if usize::from(key) >= NUM_KEYS {
+            return Err(StateError::InvalidValue("key_pressed"));
+        }
+        chip8.key_pressed = if key_pressed { Some(key) } else { None };
+
+        chip8.quirks = Quirks {
+            shift: reader.bool("quirks")?,
+            memory_increment_by_x: reader.bool("quirks")?,
+            memory_leave_i_unchanged: reader.bool("quirks")?,
+            wrap: reader.bool("quirks")?,
+            jump: reader.bool("quirks")?,
+            vblank: reader.bool("quirks")?,
+            logic: reader.bool("quirks")?,
+            stack_depth: reader.u8()?,
+        };
+        chip8.vblank = reader.bool("vblank")?;
+
+        chip8.rng = SavableRng::read_state(reader)?;
+
+        Ok(chip8)
+    }
+
+    /// Take over the state of another chip8, keeping the tracer
+    pub(crate) fn restore(&mut self, state: Chip8) {
+        let tracer = self.tracer.take();
+        *self = state;
+        self.tracer = tracer;
+        // The state was made by a different emulator, or memory may have changed since it was made
+        self.memory_generation = crate::next_memory_generation();
+    }
+}
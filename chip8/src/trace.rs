@@ -0,0 +1,699 @@
+//! Execution tracing.
+//!
+//! A [`Tracer`] attached with [`Chip8::set_tracer`] records every executed instruction.
+//! Records can be kept in a compact in-memory [`TraceBuffer`] or streamed as JSON lines or text.
+//!
+//! # Text format
+//! One line per executed instruction, showing the state *after* it ran:
+//!
+//! ```text
+//! 0200: 6A02 SetVConst(10, 2)         V0:00 V1:00 ... VF:00 I:0000 SP:00 DT:00 ST:00 W:0300=01
+//! ```
+//!
+//! The line starts with the address and opcode in hex.
+//! The decoded instruction follows and is informational only.
+//! The rest of the line is whitespace separated `KEY:VALUE` fields with hex values:
+//! `V0` through `VF`, `I`, `SP`, `DT` and `ST`,
+//! plus one `W:ADDR=VALUE` field per memory write made by the instruction.
+//! With a [`SourceMap`], lines end with `; ` and the source location of the instruction.
+//!
+//! [`Chip8::set_tracer`]: crate::Chip8::set_tracer
+
+use crate::{
+    source_map::SourceMap,
+    Instruction,
+    NUM_REGISTERS,
+};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    ops::Range,
+    sync::Arc,
+};
+
+/// A memory write made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// The traced part of the machine state
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TraceState {
+    pub v: [u8; NUM_REGISTERS],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// One executed instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// The number of instructions executed before this one since the tracer was attached
+    pub step: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+
+    /// The state before the instruction ran
+    pub before: TraceState,
+
+    /// The state after the instruction ran
+    pub after: TraceState,
+
+    pub writes: Vec<MemoryWrite>,
+}
+
+impl TraceRecord {
+    /// Iterate over the registers the instruction changed, as `(register, old, new)`
+    pub fn register_changes(&self) -> impl Iterator<Item = (u8, u8, u8)> + '_ {
+        self.before
+            .v
+            .iter()
+            .zip(self.after.v.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(reg, (&old, &new))| (reg as u8, old, new))
+    }
+
+    /// Get the old and new value of I, if the instruction changed it
+    pub fn i_change(&self) -> Option<(u16, u16)> {
+        if self.before.i != self.after.i {
+            Some((self.before.i, self.after.i))
+        } else {
+            None
+        }
+    }
+
+    /// Write this record as a line of JSON
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_json_with_source(writer, None)
+    }
+
+    /// Write this record as a line of JSON, with the source location of the instruction
+    fn write_json_with_source<W: Write>(
+        &self,
+        mut writer: W,
+        source: Option<&str>,
+    ) -> io::Result<()> {
+        let record = JsonRecord {
+            step: self.step,
+            pc: self.pc,
+            opcode: self.opcode,
+            instruction: self.instruction.to_string(),
+            state: self.after,
+            register_changes: self
+                .register_changes()
+                .map(|(reg, old, new)| JsonRegisterChange { reg, old, new })
+                .collect(),
+            i_change: self.i_change(),
+            writes: &self.writes,
+            source,
+        };
+        serde_json::to_writer(&mut writer, &record)?;
+        writeln!(writer)
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = self.instruction.to_string();
+        write!(
+            f,
+            "{:04X}: {:04X} {:<24}",
+            self.pc, self.opcode, instruction
+        )?;
+        for (reg, value) in self.after.v.iter().enumerate() {
+            write!(f, " V{:X}:{:02X}", reg, value)?;
+        }
+        write!(
+            f,
+            " I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X}",
+            self.after.i, self.after.sp, self.after.delay_timer, self.after.sound_timer
+        )?;
+        for write in self.writes.iter() {
+            write!(f, " W:{:04X}={:02X}", write.addr, write.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A line of a text trace, which may come from another emulator.
+///
+/// Only the address and opcode are required. Missing fields are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextTraceLine {
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [Option<u8>; NUM_REGISTERS],
+    pub i: Option<u16>,
+    pub sp: Option<u8>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+    pub writes: Vec<MemoryWrite>,
+}
+
+impl From<&TraceRecord> for TextTraceLine {
+    fn from(record: &TraceRecord) -> Self {
+        let mut v = [None; NUM_REGISTERS];
+        for (dest, &value) in v.iter_mut().zip(record.after.v.iter()) {
+            *dest = Some(value);
+        }
+
+        TextTraceLine {
+            pc: record.pc,
+            opcode: record.opcode,
+            v,
+            i: Some(record.after.i),
+            sp: Some(record.after.sp),
+            delay_timer: Some(record.after.delay_timer),
+            sound_timer: Some(record.after.sound_timer),
+            writes: record.writes.clone(),
+        }
+    }
+}
+
+/// An error parsing a [`TextTraceLine`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseTraceError {
+    MissingAddress,
+    MissingOpcode,
+    InvalidHex(String),
+    InvalidWrite(String),
+}
+
+impl fmt::Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseTraceError::MissingAddress => write!(f, "missing address"),
+            ParseTraceError::MissingOpcode => write!(f, "missing opcode"),
+            ParseTraceError::InvalidHex(value) => write!(f, "invalid hex value '{}'", value),
+            ParseTraceError::InvalidWrite(value) => write!(f, "invalid memory write '{}'", value),
+        }
+    }
+}
+
+impl std::error::Error for ParseTraceError {}
+
+fn parse_hex<T>(
+    value: &str,
+    parse: fn(&str, u32) -> Result<T, std::num::ParseIntError>,
+) -> Result<T, ParseTraceError> {
+    parse(value, 16).map_err(|_| ParseTraceError::InvalidHex(value.to_string()))
+}
+
+impl std::str::FromStr for TextTraceLine {
+    type Err = ParseTraceError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut tokens = line.split_whitespace();
+        let pc = tokens.next().ok_or(ParseTraceError::MissingAddress)?;
+        let pc = parse_hex(pc.trim_end_matches(':'), u16::from_str_radix)?;
+        let opcode = tokens.next().ok_or(ParseTraceError::MissingOpcode)?;
+        let opcode = parse_hex(opcode, u16::from_str_radix)?;
+
+        let mut parsed = TextTraceLine {
+            pc,
+            opcode,
+            v: [None; NUM_REGISTERS],
+            i: None,
+            sp: None,
+            delay_timer: None,
+            sound_timer: None,
+            writes: Vec::new(),
+        };
+
+        // Anything that isn't a known field, like the decoded instruction, is skipped
+        for (key, value) in tokens.filter_map(|token| token.split_once(':')) {
+            match key {
+                "I" => parsed.i = Some(parse_hex(value, u16::from_str_radix)?),
+                "SP" => parsed.sp = Some(parse_hex(value, u8::from_str_radix)?),
+                "DT" => parsed.delay_timer = Some(parse_hex(value, u8::from_str_radix)?),
+                "ST" => parsed.sound_timer = Some(parse_hex(value, u8::from_str_radix)?),
+                "W" => {
+                    let (addr, value) = value
+                        .split_once('=')
+                        .ok_or_else(|| ParseTraceError::InvalidWrite(value.to_string()))?;
+                    parsed.writes.push(MemoryWrite {
+                        addr: parse_hex(addr, u16::from_str_radix)?,
+                        value: parse_hex(value, u8::from_str_radix)?,
+                    });
+                }
+                _ => {
+                    let reg = key
+                        .strip_prefix('V')
+                        .filter(|reg| reg.len() == 1)
+                        .and_then(|reg| usize::from_str_radix(reg, 16).ok());
+                    if let Some(reg) = reg {
+                        parsed.v[reg] = Some(parse_hex(value, u8::from_str_radix)?);
+                    }
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    step: u64,
+    pc: u16,
+    opcode: u16,
+    instruction: String,
+    state: TraceState,
+    register_changes: Vec<JsonRegisterChange>,
+    i_change: Option<(u16, u16)>,
+    writes: &'a [MemoryWrite],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonRegisterChange {
+    reg: u8,
+    old: u8,
+    new: u8,
+}
+
+const DELTA_I: u8 = 1 << 0;
+const DELTA_SP: u8 = 1 << 1;
+const DELTA_DT: u8 = 1 << 2;
+const DELTA_ST: u8 = 1 << 3;
+
+/// A bounded in-memory trace.
+///
+/// Records are delta encoded, usually taking around a dozen bytes each.
+/// Once full, the oldest records are dropped.
+#[derive(Debug, Clone)]
+pub struct TraceBuffer {
+    capacity: usize,
+    len: usize,
+    bytes: VecDeque<u8>,
+
+    /// The step of the record before the oldest one
+    base_step: u64,
+    /// The state after the record before the oldest one
+    base: TraceState,
+    /// The step of the newest record
+    last_step: u64,
+    /// The state after the newest record
+    last: TraceState,
+}
+
+impl TraceBuffer {
+    /// Make a buffer that holds up to `capacity` records
+    pub fn new(capacity: usize) -> Self {
+        TraceBuffer {
+            capacity,
+            len: 0,
+            bytes: VecDeque::new(),
+            base_step: 0,
+            base: TraceState::default(),
+            last_step: 0,
+            last: TraceState::default(),
+        }
+    }
+
+    /// The number of records held
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The maximum number of records held
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of bytes used by the records
+    pub fn encoded_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Drop all records
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.bytes.clear();
+    }
+
+    /// Add a record, dropping the oldest one if full
+    pub fn push(&mut self, record: &TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.len == 0 {
+            self.base_step = record.step.wrapping_sub(1);
+            self.base = record.before;
+            self.last_step = self.base_step;
+            self.last = record.before;
+        }
+
+        let mut encoded = Vec::with_capacity(32);
+        encoded.push(0);
+        let mut step_delta = record.step.wrapping_sub(self.last_step);
+        while step_delta >= 0x80 {
+            encoded.push((step_delta as u8) | 0x80);
+            step_delta >>= 7;
+        }
+        encoded.push(step_delta as u8);
+        encoded.extend_from_slice(&record.pc.to_le_bytes());
+        encoded.extend_from_slice(&record.opcode.to_le_bytes());
+        encode_delta(&mut encoded, &self.last, &record.before);
+        encode_delta(&mut encoded, &record.before, &record.after);
+        encoded.push(record.writes.len() as u8);
+        for write in record.writes.iter() {
+            encoded.extend_from_slice(&write.addr.to_le_bytes());
+            encoded.push(write.value);
+        }
+        encoded[0] = encoded.len() as u8;
+
+        self.bytes.extend(encoded);
+        self.last_step = record.step;
+        self.last = record.after;
+        self.len += 1;
+
+        if self.len > self.capacity {
+            let (record, len) = decode(&self.bytes, 0, self.base_step, &self.base);
+            self.bytes.drain(..len);
+            self.base_step = record.step;
+            self.base = record.after;
+            self.len -= 1;
+        }
+    }
+
+    /// Iterate over the records, oldest first
+    pub fn records(&self) -> impl Iterator<Item = TraceRecord> + '_ {
+        let mut offset = 0;
+        let mut state = self.base;
+        let mut step = self.base_step;
+        (0..self.len).map(move |_| {
+            let (record, len) = decode(&self.bytes, offset, step, &state);
+            offset += len;
+            state = record.after;
+            step = record.step;
+            record
+        })
+    }
+
+    /// Write all records as text
+    pub fn write_text<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for record in self.records() {
+            writeln!(writer, "{}", record)?;
+        }
+        Ok(())
+    }
+
+    /// Write all records as JSON lines
+    pub fn write_json_lines<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for record in self.records() {
+            record.write_json(&mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_delta(buffer: &mut Vec<u8>, from: &TraceState, to: &TraceState) {
+    let mut mask = 0u16;
+    for (reg, (old, new)) in from.v.iter().zip(to.v.iter()).enumerate() {
+        if old != new {
+            mask |= 1 << reg;
+        }
+    }
+    buffer.extend_from_slice(&mask.to_le_bytes());
+    buffer.extend(
+        to.v.iter()
+            .enumerate()
+            .filter(|(reg, _)| mask & (1 << reg) != 0)
+            .map(|(_, &value)| value),
+    );
+
+    let mut flags = 0;
+    if from.i != to.i {
+        flags |= DELTA_I;
+    }
+    if from.sp != to.sp {
+        flags |= DELTA_SP;
+    }
+    if from.delay_timer != to.delay_timer {
+        flags |= DELTA_DT;
+    }
+    if from.sound_timer != to.sound_timer {
+        flags |= DELTA_ST;
+    }
+    buffer.push(flags);
+    if flags & DELTA_I != 0 {
+        buffer.extend_from_slice(&to.i.to_le_bytes());
+    }
+    if flags & DELTA_SP != 0 {
+        buffer.push(to.sp);
+    }
+    if flags & DELTA_DT != 0 {
+        buffer.push(to.delay_timer);
+    }
+    if flags & DELTA_ST != 0 {
+        buffer.push(to.sound_timer);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a VecDeque<u8>,
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> u8 {
+        let value = self.bytes[self.offset];
+        self.offset += 1;
+        value
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes([self.u8(), self.u8()])
+    }
+
+    fn varint(&mut self) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8();
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    fn delta(&mut self, from: &TraceState) -> TraceState {
+        let mut state = *from;
+        let mask = self.u16();
+        for reg in 0..NUM_REGISTERS {
+            if mask & (1 << reg) != 0 {
+                state.v[reg] = self.u8();
+            }
+        }
+
+        let flags = self.u8();
+        if flags & DELTA_I != 0 {
+            state.i = self.u16();
+        }
+        if flags & DELTA_SP != 0 {
+            state.sp = self.u8();
+        }
+        if flags & DELTA_DT != 0 {
+            state.delay_timer = self.u8();
+        }
+        if flags & DELTA_ST != 0 {
+            state.sound_timer = self.u8();
+        }
+        state
+    }
+}
+
+/// Decode the record at `offset`, given the step and state after the record before it
+fn decode(
+    bytes: &VecDeque<u8>,
+    offset: usize,
+    previous_step: u64,
+    previous_state: &TraceState,
+) -> (TraceRecord, usize) {
+    let mut reader = Reader { bytes, offset };
+    let len = usize::from(reader.u8());
+    let step = previous_step.wrapping_add(reader.varint());
+    let pc = reader.u16();
+    let opcode = reader.u16();
+    let before = reader.delta(previous_state);
+    let after = reader.delta(&before);
+    let writes = (0..reader.u8())
+        .map(|_| MemoryWrite {
+            addr: reader.u16(),
+            value: reader.u8(),
+        })
+        .collect();
+
+    let record = TraceRecord {
+        step,
+        pc,
+        opcode,
+        instruction: Instruction::from(opcode),
+        before,
+        after,
+        writes,
+    };
+    (record, len)
+}
+
+/// Where a [`Tracer`] puts its records
+pub enum TraceOutput {
+    Buffer(TraceBuffer),
+    JsonLines(Box<dyn Write + Send>),
+    Text(Box<dyn Write + Send>),
+}
+
+impl fmt::Debug for TraceOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceOutput::Buffer(buffer) => f.debug_tuple("Buffer").field(buffer).finish(),
+            TraceOutput::JsonLines(_) => f.write_str("JsonLines"),
+            TraceOutput::Text(_) => f.write_str("Text"),
+        }
+    }
+}
+
+/// Records executed instructions
+#[derive(Debug)]
+pub struct Tracer {
+    output: TraceOutput,
+    filter: Option<Range<u16>>,
+    source_map: Option<Arc<SourceMap>>,
+    step: u64,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Make a tracer with the given output
+    pub fn new(output: TraceOutput) -> Self {
+        Tracer {
+            output,
+            filter: None,
+            source_map: None,
+            step: 0,
+            error: None,
+        }
+    }
+
+    /// Keep up to `capacity` records in memory
+    pub fn buffer(capacity: usize) -> Self {
+        Self::new(TraceOutput::Buffer(TraceBuffer::new(capacity)))
+    }
+
+    /// Stream records as JSON lines
+    pub fn json_lines<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::new(TraceOutput::JsonLines(Box::new(writer)))
+    }
+
+    /// Stream records as text
+    pub fn text<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::new(TraceOutput::Text(Box::new(writer)))
+    }
+
+    /// Only record instructions with an address in `range`
+    pub fn with_filter(mut self, range: Range<u16>) -> Self {
+        self.filter = Some(range);
+        self
+    }
+
+    /// Add source locations to streamed records
+    pub fn with_source_map(mut self, source_map: Arc<SourceMap>) -> Self {
+        self.source_map = Some(source_map);
+        self
+    }
+
+    /// Get the source map
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map.as_deref()
+    }
+
+    /// Get the output
+    pub fn output(&self) -> &TraceOutput {
+        &self.output
+    }
+
+    /// Get the in-memory trace, if this tracer keeps one
+    pub fn trace_buffer(&self) -> Option<&TraceBuffer> {
+        match &self.output {
+            TraceOutput::Buffer(buffer) => Some(buffer),
+            _ => None,
+        }
+    }
+
+    /// The number of instructions executed since the tracer was attached, whether recorded or not
+    pub fn steps(&self) -> u64 {
+        self.step
+    }
+
+    /// Take the first error that happened while writing records.
+    ///
+    /// Nothing more is written after an error.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Flush the output
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            TraceOutput::Buffer(_) => Ok(()),
+            TraceOutput::JsonLines(writer) | TraceOutput::Text(writer) => writer.flush(),
+        }
+    }
+
+    /// Claim a step number, returning it if the instruction at `pc` should be recorded
+    pub(crate) fn begin_step(&mut self, pc: u16) -> Option<u64> {
+        let step = self.step;
+        self.step += 1;
+
+        let wanted = self.filter.as_ref().is_none_or(|range| range.contains(&pc));
+        if wanted && self.error.is_none() {
+            Some(step)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn record(&mut self, record: TraceRecord) {
+        let source = match (&self.output, self.source_map.as_ref()) {
+            (TraceOutput::Buffer(_), _) | (_, None) => None,
+            (_, Some(map)) => map.location(record.pc).map(|location| location.to_string()),
+        };
+
+        let result = match &mut self.output {
+            TraceOutput::Buffer(buffer) => {
+                buffer.push(&record);
+                Ok(())
+            }
+            TraceOutput::JsonLines(writer) => {
+                record.write_json_with_source(writer, source.as_deref())
+            }
+            TraceOutput::Text(writer) => match source {
+                Some(source) => writeln!(writer, "{} ; {}", record, source),
+                None => writeln!(writer, "{}", record),
+            },
+        };
+
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use chip8::{
+    CallFrame,
+    Chip8ErrorKind,
+    MEMORY_SIZE,
+};
+
+#[test]
+fn registers() {
+    let mut chip8 = common::chip8(&[]);
+    chip8.set_reg(0x3, 0x42).unwrap();
+    chip8.set_reg(0xF, 1).unwrap();
+    assert_eq!(chip8.reg(0x3).unwrap(), 0x42);
+    assert_eq!(chip8.registers()[0x3], 0x42);
+    assert_eq!(chip8.registers()[0xF], 1);
+    assert_eq!(
+        chip8.reg(16).unwrap_err().kind(),
+        &Chip8ErrorKind::InvalidReg(16)
+    );
+
+    chip8.set_i(0x123);
+    assert_eq!(chip8.i(), 0x123);
+}
+
+#[test]
+fn pc_leaves_room_for_an_instruction() {
+    let mut chip8 = common::chip8(&[]);
+    assert_eq!(chip8.pc(), 0x200);
+    chip8.set_pc(0xFFE).unwrap();
+    assert_eq!(chip8.pc(), 0xFFE);
+
+    for &pc in &[0xFFF, 0x1000, 0xFFFF] {
+        let error = chip8.set_pc(pc).unwrap_err();
+        assert_eq!(error.kind(), &Chip8ErrorKind::ProgramCounterOutOfBounds(pc));
+        assert!(error.is_recoverable());
+        assert_eq!(chip8.pc(), 0xFFE);
+    }
+}
+
+#[test]
+fn memory() {
+    let mut chip8 = common::chip8(&[0x12, 0x34]);
+    assert_eq!(chip8.memory().len(), MEMORY_SIZE);
+    // The font starts at 0 and the rom at 0x200
+    assert_eq!(chip8.peek(0).unwrap(), 0xF0);
+    assert_eq!(chip8.peek(0x201).unwrap(), 0x34);
+
+    let generation = chip8.memory_generation();
+    chip8.poke(0xFFF, 0xAB).unwrap();
+    assert_eq!(chip8.memory()[0xFFF], 0xAB);
+    assert_eq!(chip8.peek(0xFFF).unwrap(), 0xAB);
+    assert_ne!(chip8.memory_generation(), generation);
+
+    assert_eq!(
+        chip8.peek(0x1000).unwrap_err().kind(),
+        &Chip8ErrorKind::InvalidAddress(0x1000)
+    );
+}
+
+#[test]
+fn stack_timers_and_keys() {
+    // call 0x204, then v0 := 9 and the buzzer for v0 frames
+    let mut chip8 = common::chip8(&[0x22, 0x04, 0x00, 0x00, 0x60, 0x09, 0xF0, 0x18]);
+    for _ in 0..3 {
+        chip8.cycle().unwrap();
+    }
+    assert_eq!(
+        chip8.call_stack(),
+        [CallFrame {
+            call_site: 0x200,
+            target: 0x204
+        }]
+    );
+    assert_eq!(chip8.sp(), 1);
+    assert_eq!(chip8.sound_timer(), 9);
+    assert!(chip8.is_sound_on());
+
+    chip8.set_sound_timer(0);
+    assert!(!chip8.is_sound_on());
+    chip8.set_delay_timer(30);
+    assert_eq!(chip8.delay_timer(), 30);
+    chip8.update_timers();
+    assert_eq!(chip8.delay_timer(), 29);
+
+    chip8.set_key(0xA, true).unwrap();
+    assert!(chip8.keys()[0xA]);
+    assert_eq!(chip8.keys().iter().filter(|&&key| key).count(), 1);
+}
+
+#[test]
+fn display_is_hex() {
+    // v0 := 0xAB, i := 0x2F0, call 0x208, set the delay timer to v0
+    let mut chip8 = common::chip8(&[0x60, 0xAB, 0xA2, 0xF0, 0x22, 0x08, 0x00, 0x00, 0xF0, 0x15]);
+    for _ in 0..4 {
+        chip8.cycle().unwrap();
+    }
+    assert_eq!(
+        chip8.to_string(),
+        "Chip8\n\
+         PC: 0x20A\n\
+         Stack: [206]\n\
+         SP: 1\n\
+         V: [AB, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00]\n\
+         I: 0x2F0\n\
+         Delay timer: 171\n\
+         Sound timer: 0"
+    );
+}
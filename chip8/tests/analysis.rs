@@ -0,0 +1,255 @@
+mod common;
+
+use chip8::{
+    analysis::{
+        Analysis,
+        ByteKind,
+        Edge,
+        EdgeKind,
+        SelfModifyingWrite,
+    },
+    Instruction,
+};
+use std::collections::BTreeSet;
+
+fn edge(target: u16, kind: EdgeKind) -> Edge {
+    Edge { target, kind }
+}
+
+#[test]
+fn ibm_is_one_block_and_a_loop() {
+    let analysis = Analysis::new(&common::rom("roms/ibm.c8"));
+    assert_eq!(analysis.load_address(), 0x200);
+
+    let blocks: Vec<_> = analysis
+        .blocks()
+        .map(|block| (block.start, block.end))
+        .collect();
+    assert_eq!(blocks, [(0x200, 0x228), (0x228, 0x22A)]);
+
+    // The jump to itself starts a new block, so the first one falls into it
+    let main = analysis.block(0x200).unwrap();
+    assert_eq!(main.instructions.len(), 20);
+    assert_eq!(main.instructions[0], (0x200, Instruction::ClearDisplay));
+    assert_eq!(main.successors, [edge(0x228, EdgeKind::Fallthrough)]);
+    let end = analysis.block(0x228).unwrap();
+    assert_eq!(end.instructions, [(0x228, Instruction::Jump(0x228))]);
+    assert_eq!(end.successors, [edge(0x228, EdgeKind::Jump)]);
+
+    assert_eq!(analysis.block_containing(0x210).unwrap().start, 0x200);
+    assert!(analysis.block(0x210).is_none());
+    assert!(analysis.block_containing(0x22A).is_none());
+    assert!(analysis.is_instruction(0x226));
+    assert!(!analysis.is_instruction(0x227));
+    assert!(!analysis.is_instruction(0x22A));
+
+    assert!(analysis.subroutines().is_empty());
+    assert!(analysis.self_modifying_writes().is_empty());
+    assert!(analysis.external_targets().is_empty());
+    assert!(analysis.invalid_instructions().is_empty());
+    assert_eq!(analysis.label(0x200).as_deref(), Some("main"));
+    assert_eq!(analysis.label(0x228).as_deref(), Some("loc_228"));
+    assert_eq!(analysis.label(0x22A), None);
+}
+
+#[test]
+fn skips_branch_over_one_instruction() {
+    let analysis = Analysis::new(&common::rom("pong.c8"));
+
+    // vf != 0 then jump back, otherwise carry on past the jump
+    let block = analysis.block(0x21A).unwrap();
+    assert_eq!(
+        block.successors,
+        [
+            edge(0x21E, EdgeKind::Fallthrough),
+            edge(0x220, EdgeKind::Skip)
+        ]
+    );
+    assert_eq!(
+        analysis.block(0x21E).unwrap().successors,
+        [edge(0x21A, EdgeKind::Jump)]
+    );
+
+    // Every skip target is the start of a block two instructions on
+    for block in analysis.blocks() {
+        for skip in block
+            .successors
+            .iter()
+            .filter(|edge| edge.kind == EdgeKind::Skip)
+        {
+            assert_eq!(skip.target, block.end + 2);
+            assert!(analysis.block(skip.target).is_some());
+            assert!(block
+                .successors
+                .contains(&edge(block.end, EdgeKind::Fallthrough)));
+        }
+    }
+}
+
+#[test]
+fn calls_find_subroutines() {
+    let analysis = Analysis::new(&common::rom("pong.c8"));
+    assert_eq!(
+        analysis.subroutines().iter().copied().collect::<Vec<_>>(),
+        [0x2D4]
+    );
+    assert_eq!(
+        analysis.block(0x200).unwrap().successors,
+        [
+            edge(0x212, EdgeKind::Fallthrough),
+            edge(0x2D4, EdgeKind::Call)
+        ]
+    );
+
+    // The score drawing is one block that returns
+    let score = analysis.block(0x2D4).unwrap();
+    assert_eq!(score.end, 0x2EA);
+    assert_eq!(
+        score.instructions.last(),
+        Some(&(0x2E8, Instruction::Return))
+    );
+    assert!(score.successors.is_empty());
+    assert_eq!(analysis.subroutine_blocks(0x2D4), BTreeSet::from([0x2D4]));
+    assert_eq!(analysis.label(0x2D4).as_deref(), Some("sub_2D4"));
+
+    // The main loop doesn't follow calls into the subroutine
+    let main = analysis.subroutine_blocks(0x200);
+    assert!(main.contains(&0x2A2));
+    assert!(!main.contains(&0x2D4));
+
+    let tictac = Analysis::new(&common::rom("TICTAC.c8"));
+    assert_eq!(
+        tictac.subroutines().iter().copied().collect::<Vec<_>>(),
+        [0x27C, 0x2C8, 0x344, 0x34A, 0x366, 0x388, 0x394]
+    );
+}
+
+#[test]
+fn sprites_and_data_are_not_code() {
+    let ibm = Analysis::new(&common::rom("roms/ibm.c8"));
+    assert_eq!(ibm.byte_kind(0x200), ByteKind::Code);
+    assert_eq!(ibm.byte_kind(0x229), ByteKind::Code);
+    // The logo is drawn from 0x22A on
+    for addr in 0x22A..0x284 {
+        assert_eq!(ibm.byte_kind(addr), ByteKind::Sprite, "{:03X}", addr);
+    }
+    // Outside of the rom
+    assert_eq!(ibm.byte_kind(0x1FF), ByteKind::Unknown);
+    assert_eq!(ibm.byte_kind(0x284), ByteKind::Unknown);
+
+    let pong = Analysis::new(&common::rom("pong.c8"));
+    assert_eq!(pong.byte_kind(0x2E8), ByteKind::Code);
+    // The paddle and the ball
+    for addr in 0x2EA..0x2F1 {
+        assert_eq!(pong.byte_kind(addr), ByteKind::Sprite, "{:03X}", addr);
+    }
+    assert_eq!(pong.byte_kind(0x2F1), ByteKind::Unknown);
+    // The score goes through BCD
+    for addr in 0x2F2..0x2F5 {
+        assert_eq!(pong.byte_kind(addr), ByteKind::Data, "{:03X}", addr);
+    }
+    assert_eq!(pong.byte_kind(0x2F5), ByteKind::Unknown);
+
+    let listing = pong.disassemble();
+    assert!(listing.starts_with("main:\n    200: 6A02  "), "{}", listing);
+    assert!(listing.contains("sub_2D4:\n    2D4: A2F2  "), "{}", listing);
+    assert!(listing.contains("    2EA: 80                      ; sprite #.......\n"));
+    assert!(listing.contains("    2F2: 00 00 00                ; data\n"));
+}
+
+#[test]
+fn stores_into_code_are_self_modifying() {
+    let rom = [
+        0xA2, 0x08, // i := 0x208
+        0x60, 0x00, // v0 := 0
+        0xF0, 0x55, // save v0, over the clear below
+        0xA2, 0x0E, // i := 0x20E
+        0x00, 0xE0, // clear
+        0xF1, 0x33, // bcd v1, into the data after the loop
+        0x12, 0x0C, // loop
+        0x00, 0x00, 0x00,
+    ];
+    let analysis = Analysis::new(&rom);
+    assert_eq!(
+        analysis.self_modifying_writes(),
+        [SelfModifyingWrite {
+            pc: 0x204,
+            start: 0x208,
+            end: 0x209,
+        }]
+    );
+    assert_eq!(analysis.byte_kind(0x208), ByteKind::Code);
+    assert_eq!(analysis.byte_kind(0x20E), ByteKind::Data);
+
+    // A write to the second byte of an instruction counts too
+    let rom = [0xA2, 0x05, 0xF0, 0x55, 0x12, 0x04];
+    assert_eq!(
+        Analysis::new(&rom).self_modifying_writes(),
+        [SelfModifyingWrite {
+            pc: 0x202,
+            start: 0x205,
+            end: 0x206,
+        }]
+    );
+
+    for name in &["pong.c8", "TICTAC.c8", "roms/ibm.c8"] {
+        assert!(Analysis::new(&common::rom(name))
+            .self_modifying_writes()
+            .is_empty());
+    }
+}
+
+#[test]
+fn dot_clusters_subroutines() {
+    let analysis = Analysis::new(&common::rom("pong.c8"));
+    let dot = analysis.to_dot();
+    assert!(dot.starts_with("digraph rom {\n"), "{}", dot);
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("    subgraph cluster_0 {\n        label=\"main\";\n"));
+    assert!(dot.contains("    subgraph cluster_1 {\n        label=\"sub_2D4\";\n"));
+    assert!(dot.contains("        n2D4 [label=\"sub_2D4\\l2D4: "));
+
+    // Every block is a node once and every edge is drawn with its style
+    for block in analysis.blocks() {
+        let node = format!("n{:03X} [label=", block.start);
+        assert_eq!(dot.matches(&node).count(), 1, "{}", node);
+    }
+    assert!(dot.contains("    n200 -> n2D4 [style=dashed];\n"));
+    assert!(dot.contains("    n200 -> n212;\n"));
+    assert!(dot.contains("    n21A -> n220 [color=red];\n"));
+    assert!(dot.contains("    n21E -> n21A;\n"));
+    let edges = analysis
+        .blocks()
+        .map(|block| block.successors.len())
+        .sum::<usize>();
+    assert_eq!(dot.matches(" -> ").count(), edges);
+}
+
+#[test]
+fn jump_tables_follow_v0() {
+    // v0 := 2, jump0 0x208, then the table of two jumps
+    let rom = [
+        0x60, 0x02, 0xB2, 0x08, 0x00, 0x00, 0x00, 0x00, 0x12, 0x08, 0x12, 0x0A,
+    ];
+    let analysis = Analysis::new(&rom);
+    assert_eq!(
+        analysis.block(0x200).unwrap().successors,
+        [edge(0x20A, EdgeKind::Table)]
+    );
+    assert!(analysis.block(0x208).is_none());
+    assert!(analysis
+        .to_dot()
+        .contains("    n200 -> n20A [color=blue];\n"));
+
+    // With a random v0 every jump in the table is a target
+    let mut random = rom;
+    random[..2].copy_from_slice(&[0xC0, 0xFF]);
+    assert_eq!(
+        Analysis::new(&random).block(0x200).unwrap().successors,
+        [edge(0x208, EdgeKind::Table), edge(0x20A, EdgeKind::Table)]
+    );
+
+    // Jumps past the rom are external
+    let analysis = Analysis::new(&[0x13, 0x00]);
+    assert_eq!(analysis.external_targets(), &BTreeSet::from([0x300]));
+}
@@ -0,0 +1,110 @@
+use chip8::{
+    archive::{
+        ArchiveError,
+        RomArchive,
+        MAX_ENTRY_SIZE,
+    },
+    database::RomDatabase,
+    rom::Rom,
+};
+use std::{
+    io::{
+        Cursor,
+        Write,
+    },
+    path::Path,
+};
+use zip::write::{
+    FileOptions,
+    ZipWriter,
+};
+
+fn repo() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/.."))
+}
+
+#[test]
+fn games_zip() {
+    let mut archive = RomArchive::open(repo().join("GAMES.zip")).unwrap();
+    assert_eq!(archive.roms().len(), 43);
+    assert_eq!(archive.roms()[0].path, "GAMES/15PUZZLE.ch8");
+    // `.c8k` keymaps and `GAMES.TXT` aren't roms
+    assert!(archive.roms().iter().all(|rom| rom.path.ends_with(".ch8")));
+
+    let hidden = archive.find("hidden").unwrap();
+    assert_eq!(archive.find("GAMES/HIDDEN.ch8").unwrap(), hidden);
+    assert_eq!(archive.roms()[hidden].file_name(), "HIDDEN.ch8");
+    assert!(archive.notes(hidden).unwrap().unwrap().contains("HIDDEN!"));
+    assert!(!archive.roms()[0].has_notes());
+    assert_eq!(archive.notes(0).unwrap(), None);
+
+    let ibm = archive.find("ibm").unwrap();
+    assert_eq!(archive.roms()[ibm].path, "GAMES/TEST/IBM.ch8");
+    let data = archive.read(ibm).unwrap();
+    assert_eq!(data.len(), 132);
+    let rom = Rom::parse(&data, &RomDatabase::bundled()).unwrap();
+    assert_eq!(rom.data, data);
+}
+
+#[test]
+fn picking_by_index() {
+    let archive = RomArchive::open(repo().join("GAMES.zip")).unwrap();
+    assert_eq!(archive.find("1").unwrap(), 1);
+    assert_eq!(archive.roms()[1].file_name(), "AIRPLANE.ch8");
+    match archive.find("43") {
+        Err(e @ ArchiveError::NotFound(_)) => {
+            assert_eq!(e.to_string(), "there is no rom '43' in the archive")
+        }
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+    assert!(archive.find("nothing").is_err());
+}
+
+#[test]
+fn notes_ignore_case() {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+    zip.start_file("BC_test.ch8", options).unwrap();
+    zip.write_all(&std::fs::read(repo().join("BC_test.ch8")).unwrap())
+        .unwrap();
+    zip.start_file("BC_TEST.txt", options).unwrap();
+    zip.write_all(b"BC_Chip8Test notes").unwrap();
+    zip.start_file("other.c8", options).unwrap();
+    zip.write_all(&[0x12, 0x00]).unwrap();
+    let data = zip.finish().unwrap().into_inner();
+
+    let mut archive = RomArchive::new(Cursor::new(data)).unwrap();
+    assert_eq!(archive.roms().len(), 2);
+    assert_eq!(
+        archive.notes(0).unwrap().as_deref(),
+        Some("BC_Chip8Test notes")
+    );
+    assert_eq!(archive.notes(1).unwrap(), None);
+    assert_eq!(archive.read(1).unwrap(), [0x12, 0x00]);
+}
+
+#[test]
+fn huge_entries_are_not_read() {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+    zip.start_file("huge.ch8", options).unwrap();
+    zip.write_all(&vec![0; MAX_ENTRY_SIZE as usize + 1])
+        .unwrap();
+    zip.start_file("huge.txt", options).unwrap();
+    zip.write_all(&vec![b'a'; MAX_ENTRY_SIZE as usize + 1])
+        .unwrap();
+    zip.start_file("big.ch8", options).unwrap();
+    zip.write_all(&vec![0; MAX_ENTRY_SIZE as usize]).unwrap();
+    let data = zip.finish().unwrap().into_inner();
+
+    let mut archive = RomArchive::new(Cursor::new(data)).unwrap();
+    match archive.read(0) {
+        Err(e @ ArchiveError::TooLarge(_)) => assert_eq!(
+            e.to_string(),
+            "'huge.ch8' is bigger than the 131072 bytes a rom can be"
+        ),
+        other => panic!("{:?}", other.map(|data| data.len())),
+    }
+    assert!(matches!(archive.notes(0), Err(ArchiveError::TooLarge(_))));
+    assert_eq!(archive.read(1).unwrap().len(), MAX_ENTRY_SIZE as usize);
+}
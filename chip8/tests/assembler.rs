@@ -0,0 +1,172 @@
+mod common;
+
+use chip8::{
+    assembler::{
+        assemble,
+        AssembleErrorKind,
+    },
+    source_map::SourceMap,
+    trace::Tracer,
+    Machine,
+};
+use std::{
+    io::Write,
+    path::Path,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+fn roms() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../roms"))
+}
+
+/// Assemble a program and return its opcodes
+fn opcodes(source: &str) -> Vec<u16> {
+    let rom = assemble(source, "test.8o").unwrap().rom;
+    rom.chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+        .collect()
+}
+
+#[test]
+fn ibm_logo_matches_the_rom() {
+    let source = std::fs::read_to_string(roms().join("ibm.8o")).unwrap();
+    let assembly = assemble(&source, "ibm.8o").unwrap();
+    assert_eq!(assembly.rom, std::fs::read(roms().join("ibm.c8")).unwrap());
+
+    let map = std::fs::read_to_string(roms().join("ibm.map.json")).unwrap();
+    assert_eq!(assembly.source_map.to_json(), map.trim_end());
+}
+
+#[test]
+fn instructions() {
+    let source = "
+        :const speed 3
+        : main
+            clear
+            v0 := speed
+            v1 += v0
+            v2 -= 1
+            v3 =- v4
+            v5 >>= v5
+            i := data
+            i += v1
+            i := hex v2
+            delay := v0
+            v6 := delay
+            v7 := random 0x0F
+            sprite v0 v1 5
+            bcd v2
+            save v3
+            load v3
+            :call sub
+            sub
+            jump main
+        : sub
+            if v0 == 3 then return
+            if v1 != v2 begin
+                v0 := key
+            else
+                v0 := 0
+            end
+            loop
+                v0 += 1
+                while v0 != 10
+                if v1 key then return
+            again
+            ;
+        : data
+            0b11110000 0xFF
+    ";
+    assert_eq!(
+        opcodes(source),
+        vec![
+            0x00E0, 0x6003, 0x8104, 0x72FF, 0x8347, 0x8556, 0xA242, 0xF11E, 0xF229, 0xF015, 0xF607,
+            0xC70F, 0xD015, 0xF233, 0xF355, 0xF365, 0x2226, 0x2226, 0x1200, // sub:
+            0x4003, 0x00EE, 0x9120, 0x1232, 0xF00A, 0x1234, 0x6000, // loop:
+            0x7001, 0x400A, 0x1240, 0xE1A1, 0x00EE, 0x1234, 0x00EE, // data:
+            0xF0FF,
+        ]
+    );
+}
+
+#[test]
+fn errors_have_line_numbers() {
+    let error = assemble("clear\n\nv0 := 256\n", "test.8o").unwrap_err();
+    assert_eq!(error.line, 3);
+    assert_eq!(
+        error.kind,
+        AssembleErrorKind::OutOfRange(String::from("256"))
+    );
+    assert_eq!(error.to_string(), "line 3: '256' is out of range");
+
+    let error = assemble(": main\n  jump nowhere\n", "test.8o").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert_eq!(
+        error.kind,
+        AssembleErrorKind::UnknownName(String::from("nowhere"))
+    );
+
+    let error = assemble("loop\n  v0 += 1\n", "test.8o").unwrap_err();
+    assert_eq!(error.line, 1);
+    assert_eq!(error.kind, AssembleErrorKind::Unclosed("loop"));
+}
+
+#[test]
+fn source_locations() {
+    let source = ": main\n  clear\n: main_loop\n  v0 += 1\n  v1 += 1\n  jump main_loop\n";
+    let map = assemble(source, "game.8o").unwrap().source_map;
+
+    assert_eq!(map.describe(0x200), "game.8o:2 (main)");
+    assert_eq!(map.describe(0x204), "game.8o:5 (main_loop+2)");
+    assert_eq!(map.describe(0x206), "game.8o:6 (main_loop+4)");
+    // Past the end of the program
+    assert_eq!(map.describe(0x208), "0x208");
+    assert_eq!(map.label("main_loop"), Some(0x202));
+
+    let map = SourceMap::from_json(map.to_json().as_bytes()).unwrap();
+    assert_eq!(map.describe(0x206), "game.8o:6 (main_loop+4)");
+    assert!(SourceMap::from_json(
+        br#"{ "files": [], "lines": [ { "address": 512, "file": 0, "line": 1 } ] }"#
+    )
+    .is_err());
+}
+
+/// A writer that can be read after the tracer owns it
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn traces_show_source_locations() {
+    let source = ": main\n  v0 := 1\n: done\n  jump done\n";
+    let assembly = assemble(source, "game.8o").unwrap();
+
+    let buffer = SharedBuffer::default();
+    let mut chip8 = common::chip8(&assembly.rom);
+    chip8.set_tracer(Some(
+        Tracer::text(buffer.clone()).with_source_map(Arc::new(assembly.source_map)),
+    ));
+    let mut machine = Machine::new(chip8);
+    machine.step().unwrap();
+    machine.step().unwrap();
+    machine.chip8_mut().tracer_mut().unwrap().flush().unwrap();
+
+    let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = trace.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with(" ; game.8o:2 (main)"), "{}", lines[0]);
+    assert!(lines[1].ends_with(" ; game.8o:4 (done)"), "{}", lines[1]);
+}
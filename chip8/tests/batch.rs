@@ -0,0 +1,63 @@
+mod common;
+
+use chip8::{
+    batch::{
+        VecChip8,
+        OBSERVATION_LEN,
+    },
+    GFX_WIDTH,
+};
+
+fn rom() -> Vec<u8> {
+    common::rom("roms/tetris.c8")
+}
+
+/// Unpack an observation into pixels, the way the display stores them
+fn unpack(observation: &[u64]) -> Vec<bool> {
+    observation
+        .iter()
+        .flat_map(|row| (0..GFX_WIDTH).map(move |x| row & (1 << (63 - x)) != 0))
+        .collect()
+}
+
+#[test]
+fn observations_match_displays() {
+    let mut batch = VecChip8::new(&rom(), 4).unwrap();
+    batch.seed_all(7);
+    for frame in 0..120u16 {
+        let actions: Vec<u16> = (0..4).map(|i| (frame * 31 + i * 7) & 0x7F).collect();
+        batch.step(&actions);
+    }
+
+    assert_eq!(batch.observations().len(), 4 * OBSERVATION_LEN);
+    for i in 0..batch.len() {
+        assert!(batch.error(i).is_none());
+        assert!(unpack(batch.observation(i)) == batch.machine(i).chip8().gfx[..]);
+    }
+}
+
+#[test]
+fn restoring_a_snapshot_replays_the_same_frames() {
+    let mut batch = VecChip8::new(&rom(), 2).unwrap();
+    batch.seed_all(1);
+    for _ in 0..60 {
+        batch.step(&[0x10, 0x10]);
+    }
+
+    let snapshot = batch.snapshot(0);
+    let run = |batch: &mut VecChip8| {
+        let mut frames = Vec::new();
+        for frame in 0..120u16 {
+            batch.step(&[1 << (frame % 16), 0]);
+            frames.push(batch.observation(0).to_vec());
+        }
+        frames
+    };
+
+    let first = run(&mut batch);
+    batch.restore(0, &snapshot);
+    assert_eq!(run(&mut batch), first);
+
+    batch.reset(1);
+    assert!(batch.observation(1).iter().all(|&row| row == 0));
+}
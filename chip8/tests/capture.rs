@@ -0,0 +1,251 @@
+mod common;
+
+use chip8::{
+    capture::{
+        save_png,
+        scale_screen,
+        write_png,
+        CaptureError,
+        Colors,
+        Recorder,
+        RecordingFormat,
+        WavWriter,
+    },
+    Machine,
+    GFX_SIZE,
+};
+use std::{
+    fs::File,
+    io::Write,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+const COLORS: Colors = Colors {
+    background: 0x102030,
+    foreground: 0xF0E0D0,
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+/// A machine running a program, two instructions a frame
+fn machine(program: &[u8]) -> Machine {
+    let mut machine = common::machine(program);
+    machine.set_tickrate(2);
+    machine
+}
+
+/// Draws the top left pixel on one frame and erases it on the next
+const BLINKER: &[u8] = &[0xA0, 0x00, 0xD1, 0x15, 0x12, 0x02];
+
+/// Draws a digit, then loops forever
+const STILL: &[u8] = &[0xA0, 0x00, 0xD1, 0x15, 0x12, 0x04];
+
+/// Sounds the buzzer for 5 frames, then loops forever
+const BEEP: &[u8] = &[0x60, 0x05, 0xF0, 0x18, 0x12, 0x04];
+
+fn png_reader(path: &Path) -> png::Reader<File> {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    decoder.read_info().unwrap()
+}
+
+#[test]
+fn screenshots_are_scaled_and_indexed() {
+    let mut screen = [0; GFX_SIZE];
+    screen[0] = 255;
+    screen[65] = 128;
+
+    let mut png = Vec::new();
+    write_png(&mut png, &screen, 3, COLORS).unwrap();
+    let path = temp_path("screenshot.png");
+    save_png(&path, &screen, 3, COLORS).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), png);
+
+    let mut reader = png_reader(&path);
+    let info = reader.info();
+    assert_eq!((info.width, info.height), (192, 96));
+    assert_eq!(info.color_type, png::ColorType::Indexed);
+    let palette = info.palette.as_ref().unwrap();
+    assert_eq!(palette[..3], [0x10, 0x20, 0x30]);
+    assert_eq!(palette[255 * 3..], [0xF0, 0xE0, 0xD0]);
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(pixels, scale_screen(&screen, 3));
+    assert_eq!(pixels[..4], [255, 255, 255, 0]);
+    assert_eq!(pixels[192 * 2..192 * 2 + 4], [255, 255, 255, 0]);
+    assert_eq!(pixels[192 * 3 + 3], 128);
+}
+
+#[test]
+fn gifs_keep_60hz_timing() {
+    let path = temp_path("blinker.gif");
+    let mut machine = machine(BLINKER);
+    machine.set_recorder(Some(Recorder::create(&path, 2, COLORS).unwrap()));
+    // However the host slices time, every frame is recorded
+    for &nanos in &[10_000_000, 3_000_000, 40_000_000, 30_333_334, 16_666_666] {
+        machine.run_for(Duration::from_nanos(nanos)).unwrap();
+    }
+    let recorder = machine.set_recorder(None).unwrap();
+    assert_eq!(recorder.frames(), 6);
+    recorder.finish().unwrap();
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (128, 64));
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        frames.push((frame.delay, frame.buffer[0]));
+    }
+    assert_eq!(
+        frames,
+        [(2, 255), (1, 0), (2, 255), (2, 0), (1, 255), (2, 0)]
+    );
+}
+
+#[test]
+fn apngs_merge_repeated_frames() {
+    let path = temp_path("still.png");
+    let mut machine = machine(STILL);
+    let recorder = Recorder::create(&path, 1, COLORS).unwrap();
+    machine.set_recorder(Some(recorder));
+    for _ in 0..100 {
+        machine.run_frame().unwrap();
+    }
+    machine.set_recorder(None).unwrap().finish().unwrap();
+
+    let mut reader = png_reader(&path);
+    assert_eq!(reader.info().animation_control.unwrap().num_frames, 1);
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    let control = reader.info().frame_control.unwrap();
+    assert_eq!((control.delay_num, control.delay_den), (100, 60));
+    assert_eq!(pixels[..5], [255, 255, 255, 255, 0]);
+}
+
+#[test]
+fn y4m_has_every_frame() {
+    let path = temp_path("still.y4m");
+    let mut machine = machine(STILL);
+    let recorder = Recorder::create(&path, 2, COLORS).unwrap();
+    machine.set_recorder(Some(recorder));
+    for _ in 0..3 {
+        machine.run_frame().unwrap();
+    }
+    machine.set_recorder(None).unwrap().finish().unwrap();
+
+    let data = std::fs::read(&path).unwrap();
+    let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+    assert_eq!(data[..header.len()], header[..]);
+    let frames: Vec<_> = data[header.len()..].chunks(6 + 128 * 64 * 3).collect();
+    assert_eq!(frames.len(), 3);
+    for frame in frames {
+        assert_eq!(frame[..6], b"FRAME\n"[..]);
+        let y = &frame[6..6 + 128 * 64];
+        // The foreground is brighter than the background, in the pixels of the digit's top row
+        assert!(y[0] > y[128 * 64 - 1]);
+        assert_eq!(y[0], y[7]);
+    }
+}
+
+#[test]
+fn wavs_follow_the_sound_timer() {
+    let path = temp_path("beep.wav");
+    let mut machine = machine(BEEP);
+    let recorder = Recorder::audio_only(WavWriter::create(&path, 44100).unwrap());
+    machine.set_recorder(Some(recorder));
+    // Slicing time unevenly still gets exactly a sixth of a second of audio
+    for &nanos in &[50_000_000, 7_000_000, 109_666_667] {
+        machine.run_for(Duration::from_nanos(nanos)).unwrap();
+    }
+    let recorder = machine.set_recorder(None).unwrap();
+    assert_eq!(recorder.frames(), 10);
+    recorder.finish().unwrap();
+
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!(data[..4], b"RIFF"[..]);
+    assert_eq!(data[4..8], (36u32 + 14700).to_le_bytes());
+    assert_eq!(data[8..16], b"WAVEfmt "[..]);
+    assert_eq!(data[24..28], 44100u32.to_le_bytes());
+    assert_eq!(data[36..40], b"data"[..]);
+    assert_eq!(data[40..44], 14700u32.to_le_bytes());
+
+    let samples: Vec<i16> = data[44..]
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect();
+    assert_eq!(samples.len(), 7350);
+    // 5 frames of 735 samples sound and the rest are silent
+    assert!(samples[..735 * 5].iter().all(|&sample| sample != 0));
+    assert!(samples[735 * 5..].iter().all(|&sample| sample == 0));
+}
+
+/// Fails every write after the first `left` bytes
+struct FailingWriter {
+    left: usize,
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.left == 0 {
+            return Err(std::io::Error::other("disk full"));
+        }
+        let len = data.len().min(self.left);
+        self.left -= len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn video_errors_still_finish_the_wav() {
+    let path = temp_path("failed_video.wav");
+    // The header fits but the first frame doesn't
+    let recorder = Recorder::new(FailingWriter { left: 100 }, RecordingFormat::Y4m, 1, COLORS)
+        .unwrap()
+        .with_audio(WavWriter::create(&path, 44100).unwrap());
+    let mut machine = machine(BEEP);
+    machine.set_recorder(Some(recorder));
+    machine.run_for(Duration::from_millis(100)).unwrap();
+
+    let recorder = machine.set_recorder(None).unwrap();
+    assert_eq!(recorder.frames(), 6);
+    let error = recorder.finish().unwrap_err();
+    assert!(matches!(error, CaptureError::Io(_)), "{:?}", error);
+
+    // The WAV has every frame and its header has their size
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!(data[40..44], (735u32 * 6 * 2).to_le_bytes());
+    assert_eq!(data.len(), 44 + 735 * 6 * 2);
+}
+
+#[test]
+fn formats_come_from_extensions() {
+    assert_eq!(
+        RecordingFormat::from_path(Path::new("run.GIF")),
+        Some(RecordingFormat::Gif)
+    );
+    assert_eq!(
+        RecordingFormat::from_path(Path::new("run.apng")),
+        Some(RecordingFormat::Apng)
+    );
+    assert_eq!("y4m".parse(), Ok(RecordingFormat::Y4m));
+    assert!(matches!(
+        Recorder::create(temp_path("run.mp4"), 1, COLORS),
+        Err(CaptureError::UnknownFormat(_))
+    ));
+    assert!(matches!(
+        Recorder::new(Vec::new(), RecordingFormat::Gif, 2000, COLORS),
+        Err(CaptureError::ScaleTooLarge(2000))
+    ));
+}
@@ -0,0 +1,26 @@
+//! Helpers shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use chip8::{
+    Chip8,
+    Machine,
+};
+use std::path::Path;
+
+/// Read a rom from the root of the repository, like `"pong.c8"` or `"roms/ibm.c8"`
+pub fn rom(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(name)).unwrap()
+}
+
+/// A chip8 with a program loaded
+pub fn chip8(program: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.init();
+    chip8.load(program).unwrap();
+    chip8
+}
+
+/// A machine running a program at the default speed
+pub fn machine(program: &[u8]) -> Machine {
+    Machine::new(chip8(program))
+}
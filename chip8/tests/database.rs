@@ -0,0 +1,160 @@
+mod common;
+
+use chip8::{
+    database::{
+        sha1_hex,
+        DatabaseError,
+        RomDatabase,
+        DEFAULT_TICKRATE,
+    },
+    Quirks,
+};
+
+const PROGRAMS: &str = r##"[
+    {
+        "title": "Quirky",
+        "authors": ["Someone"],
+        "roms": {
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d": {
+                "file": "quirky.ch8",
+                "platforms": ["originalChip8", "modernChip8"],
+                "quirkyPlatforms": {
+                    "originalChip8": { "shift": true, "stackDepth": 2 }
+                },
+                "keys": { "up": 5 },
+                "colors": { "pixels": ["#000000", "#ffffff"] }
+            }
+        }
+    },
+    {
+        "title": "Fast",
+        "roms": {
+            "7c4a8d09ca3762af61e59520943dc26494f8941b": {
+                "platforms": ["superchip"],
+                "tickrate": 30,
+                "startAddress": 768
+            },
+            "0000000000000000000000000000000000000000": {}
+        }
+    }
+]"##;
+
+const HASHES: &str = r#"{
+    "AAF4C61DDCC5E8A2DABEDE0F3B482CD9AEA9434D": 0,
+    "7c4a8d09ca3762af61e59520943dc26494f8941b": 1,
+    "0000000000000000000000000000000000000000": 1,
+    "1111111111111111111111111111111111111111": 1
+}"#;
+
+const PLATFORMS: &str = r#"[
+    {
+        "id": "originalChip8",
+        "defaultTickrate": 15,
+        "quirks": { "vblank": false }
+    },
+    { "id": "superchip" }
+]"#;
+
+fn database() -> RomDatabase {
+    RomDatabase::from_json(PROGRAMS, HASHES, PLATFORMS).unwrap()
+}
+
+#[test]
+fn lookups_match_hashes_in_any_case() {
+    let database = database();
+    assert_eq!(database.len(), 4);
+
+    // The SHA-1 of "hello"
+    assert_eq!(
+        sha1_hex(b"hello"),
+        "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+    );
+    let info = database.lookup(b"hello").unwrap();
+    assert_eq!(info.title, "Quirky");
+    assert_eq!(info.sha1, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d");
+    assert_eq!(info.authors, ["Someone"]);
+    assert_eq!(info.file.as_deref(), Some("quirky.ch8"));
+    assert_eq!(info.keys["up"], 5);
+    assert_eq!(info.colors.unwrap().pixels, ["#000000", "#ffffff"]);
+    assert_eq!(
+        database
+            .lookup_hash("AAF4C61DDCC5E8A2DABEDE0F3B482CD9AEA9434D")
+            .unwrap()
+            .title,
+        "Quirky"
+    );
+
+    assert_eq!(database.lookup(b"goodbye"), None);
+    // Hashes without a rom in their program are unknown
+    assert_eq!(
+        database.lookup_hash("1111111111111111111111111111111111111111"),
+        None
+    );
+}
+
+#[test]
+fn quirks_come_from_the_first_platform_with_overrides() {
+    let info = database().lookup(b"hello").unwrap();
+    assert_eq!(info.platform.as_deref(), Some("originalChip8"));
+    assert_eq!(
+        info.quirks,
+        Quirks {
+            // From the rom
+            shift: true,
+            stack_depth: 2,
+            // From platforms.json
+            vblank: false,
+            ..Quirks::CHIP8
+        }
+    );
+    assert_eq!(info.tickrate, 15);
+    assert_eq!(info.start_address, None);
+}
+
+#[test]
+fn roms_override_the_tickrate() {
+    let database = database();
+    let info = database.lookup(b"123456").unwrap();
+    assert_eq!(info.title, "Fast");
+    assert_eq!(info.quirks, Quirks::SCHIP);
+    assert_eq!(info.tickrate, 30);
+    assert_eq!(info.start_address, Some(0x300));
+
+    // No platform gets the defaults
+    let info = database
+        .lookup_hash("0000000000000000000000000000000000000000")
+        .unwrap();
+    assert_eq!(info.platform, None);
+    assert_eq!(info.quirks, Quirks::default());
+    assert_eq!(info.tickrate, DEFAULT_TICKRATE);
+}
+
+#[test]
+fn invalid_databases_are_rejected() {
+    let hashes = r#"{ "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d": 2 }"#;
+    match RomDatabase::from_json(PROGRAMS, hashes, PLATFORMS) {
+        Err(DatabaseError::InvalidProgramIndex(hash, 2)) => {
+            assert_eq!(hash, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")
+        }
+        _ => panic!("expected an invalid program index"),
+    }
+    assert!(matches!(
+        RomDatabase::from_json("{", HASHES, PLATFORMS),
+        Err(DatabaseError::Json(_))
+    ));
+}
+
+#[test]
+fn the_bundled_database_knows_the_bundled_roms() {
+    let pong = common::rom("pong.c8");
+    let info = RomDatabase::bundled().lookup(&pong).unwrap();
+    assert_eq!(info.title, "Pong");
+    assert_eq!(info.platform.as_deref(), Some("originalChip8"));
+    assert_eq!(info.keys["player2Up"], 12);
+
+    // Space Invaders was written for the CHIP-48 and shifts in place
+    let invaders = common::rom("invaders.c8");
+    let info = RomDatabase::bundled().lookup(&invaders).unwrap();
+    assert_eq!(info.title, "Space Invaders");
+    assert!(info.quirks.shift);
+}
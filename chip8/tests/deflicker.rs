@@ -0,0 +1,99 @@
+mod common;
+
+use chip8::{
+    deflicker::{
+        Deflicker,
+        DeflickerMode,
+    },
+    Machine,
+    GFX_SIZE,
+};
+use std::time::Duration;
+
+/// A machine that draws the top left pixel on one frame and erases it on the next
+fn blinker(mode: DeflickerMode) -> Machine {
+    // i := hex v0, then draw and jump back to drawing, two instructions a frame
+    let mut machine = common::machine(&[0xA0, 0x00, 0xD1, 0x15, 0x12, 0x02]);
+    machine.set_tickrate(2);
+    machine.set_deflicker_mode(mode);
+    machine
+}
+
+/// Run frames, getting the raw pixel and how it's presented after each
+fn frames(machine: &mut Machine, count: usize) -> Vec<(bool, u8)> {
+    (0..count)
+        .map(|_| {
+            machine.run_frame().unwrap();
+            (machine.chip8().gfx[0], machine.presentation()[0])
+        })
+        .collect()
+}
+
+#[test]
+fn off_shows_each_frame() {
+    let mut machine = blinker(DeflickerMode::Off);
+    assert_eq!(
+        frames(&mut machine, 4),
+        [(true, 255), (false, 0), (true, 255), (false, 0)]
+    );
+}
+
+#[test]
+fn or_keeps_pixels_lit() {
+    let mut machine = blinker(DeflickerMode::Or(2));
+    assert_eq!(
+        frames(&mut machine, 4),
+        [(true, 255), (false, 255), (true, 255), (false, 255)]
+    );
+
+    let mut deflicker = Deflicker::new(DeflickerMode::Or(3));
+    let mut lit = [false; GFX_SIZE];
+    lit[5] = true;
+    deflicker.push(&lit);
+    let shown: Vec<u8> = (0..4)
+        .map(|_| {
+            deflicker.push(&[false; GFX_SIZE]);
+            deflicker.presentation()[5]
+        })
+        .collect();
+    assert_eq!(shown, [255, 255, 0, 0]);
+}
+
+#[test]
+fn decay_fades() {
+    let mut machine = blinker(DeflickerMode::Decay(0.5));
+    assert_eq!(
+        frames(&mut machine, 4),
+        [(true, 255), (false, 128), (true, 255), (false, 128)]
+    );
+
+    // Resetting the clock starts from a blank screen
+    machine.reset_clock();
+    assert_eq!(machine.presentation()[0], 0);
+    assert_eq!(machine.deflicker_mode(), DeflickerMode::Decay(0.5));
+}
+
+#[test]
+fn presentation_doesnt_depend_on_the_host() {
+    let mut by_frame = blinker(DeflickerMode::Decay(0.25));
+    frames(&mut by_frame, 5);
+
+    let mut by_time = blinker(DeflickerMode::Decay(0.25));
+    for &nanos in &[10_000_000, 3_000_000, 40_000_000, 30_333_334] {
+        by_time.run_for(Duration::from_nanos(nanos)).unwrap();
+    }
+    assert_eq!(by_time.chip8().gfx[..], by_frame.chip8().gfx[..]);
+    assert_eq!(by_time.presentation()[..], by_frame.presentation()[..]);
+    assert_eq!(by_time.presentation()[0], 255);
+}
+
+#[test]
+fn modes_parse() {
+    assert_eq!("off".parse(), Ok(DeflickerMode::Off));
+    assert_eq!("or:3".parse(), Ok(DeflickerMode::Or(3)));
+    assert_eq!("decay:0.75".parse(), Ok(DeflickerMode::Decay(0.75)));
+    assert!("or:0".parse::<DeflickerMode>().is_err());
+    assert!("decay:2".parse::<DeflickerMode>().is_err());
+    assert!("blend".parse::<DeflickerMode>().is_err());
+    assert_eq!(DeflickerMode::Or(3).to_string(), "or:3");
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use chip8::{
+    database::RomDatabase,
+    engine::EngineKind,
+    trace::Tracer,
+    Chip8,
+    Machine,
+};
+use std::path::Path;
+
+const FRAMES: usize = 1200;
+
+fn machine(rom: &[u8], engine: EngineKind) -> Machine {
+    let mut chip8 = common::chip8(rom);
+    chip8.seed_rng(0xC8);
+
+    let info = RomDatabase::bundled().lookup(rom);
+    if let Some(info) = info.as_ref() {
+        chip8.set_quirks(info.quirks);
+    }
+
+    let mut machine = Machine::new(chip8);
+    machine.set_tickrate(info.map_or(15, |info| info.tickrate));
+    machine.set_engine(engine);
+    machine
+}
+
+fn assert_same_state(name: &str, frame: usize, a: &Chip8, b: &Chip8) {
+    let context = format!("{} diverged at frame {}", name, frame);
+    assert_eq!(a.pc(), b.pc(), "{}: pc", context);
+    assert_eq!(a.i(), b.i(), "{}: i", context);
+    assert_eq!(a.registers(), b.registers(), "{}: registers", context);
+    assert_eq!(a.call_stack(), b.call_stack(), "{}: stack", context);
+    assert_eq!(a.delay_timer(), b.delay_timer(), "{}: delay timer", context);
+    assert_eq!(a.sound_timer(), b.sound_timer(), "{}: sound timer", context);
+    assert!(a.memory()[..] == b.memory()[..], "{}: memory", context);
+    assert!(a.gfx[..] == b.gfx[..], "{}: display", context);
+}
+
+/// Run a rom on the interpreter and on `engine` with the same inputs, comparing after every frame
+fn differential(name: &str, rom: &[u8], engine: EngineKind) {
+    let mut reference = machine(rom, EngineKind::Interpreter);
+    let mut candidate = machine(rom, engine);
+
+    // A cheap deterministic key pattern, so games leave their title screens
+    let mut input: u32 = 0x1234_5678;
+    for frame in 0..FRAMES {
+        if frame % 4 == 0 {
+            input = input.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = ((input >> 16) % 16) as usize;
+            let pressed = (input >> 8) & 1 == 1;
+            reference.chip8_mut().set_key(key, pressed).unwrap();
+            candidate.chip8_mut().set_key(key, pressed).unwrap();
+        }
+
+        let expected = reference.run_frame();
+        let actual = candidate.run_frame();
+        match (expected, actual) {
+            (Ok(expected), Ok(actual)) => assert_eq!(expected, actual, "{}: frame events", name),
+            (Err(expected), Err(actual)) => {
+                assert_eq!(
+                    format!("{:?}", expected),
+                    format!("{:?}", actual),
+                    "{}: errors",
+                    name
+                );
+                assert_same_state(name, frame, reference.chip8(), candidate.chip8());
+                return;
+            }
+            (expected, actual) => panic!(
+                "{}: frame {} returned {:?} vs {:?}",
+                name, frame, expected, actual
+            ),
+        }
+
+        assert_same_state(name, frame, reference.chip8(), candidate.chip8());
+    }
+}
+
+fn roms() -> Vec<(String, Vec<u8>)> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let mut roms = Vec::new();
+    for dir in [root.clone(), root.join("roms")] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let is_rom = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| matches!(ext, "c8" | "ch8"));
+            if is_rom {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                roms.push((name, std::fs::read(&path).unwrap()));
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+/// A rom that rewrites code later in its own block and code in a cached subroutine
+const SELF_MODIFYING: &[u8] = &[
+    0x65, 0x01, // 200: V5 = 1
+    0xA2, 0x0A, // 202: I = 0x20A
+    0x60, 0x75, // 204: V0 = 0x75
+    0xF0, 0x55, // 206: store V0 at 0x20A, turning V5 = 5 into V5 += 5
+    0x22, 0x16, // 208: call 0x216
+    0x65, 0x05, // 20A: V5 = 5
+    0xA2, 0x16, // 20C: I = 0x216
+    0x60, 0x74, // 20E: V0 = 0x74
+    0xF0, 0x55, // 210: store V0 at 0x216, turning V3 += 1 into V4 += 1
+    0x22, 0x16, // 212: call 0x216
+    0x12, 0x14, // 214: loop forever
+    0x73, 0x01, // 216: V3 += 1
+    0x00, 0xEE, // 218: return
+];
+
+const CACHING_ENGINES: &[EngineKind] = &[EngineKind::BlockCache, EngineKind::Threaded];
+
+#[test]
+fn engines_match_interpreter_on_roms() {
+    let roms = roms();
+    assert!(!roms.is_empty());
+    for &engine in CACHING_ENGINES {
+        for (name, rom) in roms.iter() {
+            differential(&format!("{} on {:?}", name, engine), rom, engine);
+        }
+    }
+}
+
+#[test]
+fn engines_see_self_modifying_code() {
+    for &engine in CACHING_ENGINES {
+        differential("self modifying", SELF_MODIFYING, engine);
+
+        let mut machine = machine(SELF_MODIFYING, engine);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.chip8().reg(5).unwrap(), 6);
+        assert_eq!(machine.chip8().reg(3).unwrap(), 1);
+        assert_eq!(machine.chip8().reg(4).unwrap(), 1);
+    }
+}
+
+#[test]
+fn engines_flush_after_poke() {
+    for &engine in CACHING_ENGINES {
+        flush_after_poke(engine);
+    }
+}
+
+fn flush_after_poke(engine: EngineKind) {
+    let mut machine = machine(SELF_MODIFYING, engine);
+    machine.run_frame().unwrap();
+
+    // Turn the infinite loop into V6 = 0x42 followed by another loop
+    let chip8 = machine.chip8_mut();
+    chip8.poke(0x214, 0x66).unwrap();
+    chip8.poke(0x215, 0x42).unwrap();
+    chip8.poke(0x216, 0x12).unwrap();
+    chip8.poke(0x217, 0x16).unwrap();
+    chip8.set_pc(0x214).unwrap();
+    machine.run_frame().unwrap();
+    assert_eq!(machine.chip8().reg(6).unwrap(), 0x42);
+}
+
+#[test]
+fn engines_trace_like_interpreter() {
+    let rom = common::rom("roms/tetris.c8");
+    let trace = |engine| {
+        let mut machine = machine(&rom, engine);
+        machine
+            .chip8_mut()
+            .set_tracer(Some(Tracer::buffer(usize::MAX)));
+        for _ in 0..100 {
+            machine.run_frame().unwrap();
+        }
+
+        let mut text = Vec::new();
+        let tracer = machine.chip8().tracer().unwrap();
+        tracer
+            .trace_buffer()
+            .unwrap()
+            .write_text(&mut text)
+            .unwrap();
+        text
+    };
+
+    let expected = trace(EngineKind::Interpreter);
+    assert!(!expected.is_empty());
+    for &engine in CACHING_ENGINES {
+        assert!(trace(engine) == expected, "{:?} traced differently", engine);
+    }
+}
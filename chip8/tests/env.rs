@@ -0,0 +1,144 @@
+mod common;
+
+use chip8::env::{
+    Env,
+    EnvConfig,
+    GameSpec,
+    Location,
+};
+
+/// Play a bundled game until its episode ends, returning the nonzero rewards
+fn play(file: &str, mut policy: impl FnMut(&Env) -> usize) -> (Env, Vec<f64>) {
+    let rom = common::rom(file);
+    let spec = GameSpec::find_bundled(&rom).unwrap();
+    let mut env = Env::new(&rom, spec, EnvConfig::default()).unwrap();
+
+    let mut rewards = Vec::new();
+    loop {
+        let action = policy(&env);
+        let (_, reward, done) = env.step(action);
+        if reward != 0.0 {
+            rewards.push(reward);
+        }
+        if done {
+            break;
+        }
+    }
+
+    assert!(!env.is_truncated());
+    assert!(env.error().is_none());
+    (env, rewards)
+}
+
+#[test]
+fn bundled_specs_match_repo_roms() {
+    for (file, name) in [
+        ("pong.c8", "pong"),
+        ("breakout.c8", "breakout"),
+        ("roms/tetris.c8", "tetris"),
+        ("invaders.c8", "invaders"),
+    ] {
+        let spec = GameSpec::find_bundled(&common::rom(file)).unwrap();
+        assert_eq!(spec.name, name);
+    }
+}
+
+#[test]
+fn locations_parse() {
+    assert_eq!("VE".parse(), Ok(Location::Register(0xE)));
+    assert_eq!("PC".parse(), Ok(Location::Pc));
+    assert_eq!("[0x2F0]".parse(), Ok(Location::Memory(0x2F0)));
+    assert_eq!("bcd[2052]".parse(), Ok(Location::Bcd(0x804)));
+    assert!("V10".parse::<Location>().is_err());
+    assert!("[0x10000]".parse::<Location>().is_err());
+}
+
+#[test]
+fn breakout_episodes_end_and_pay_out() {
+    let rom = common::rom("breakout.c8");
+    let spec = GameSpec::find_bundled(&rom).unwrap();
+    let mut env = Env::new(&rom, spec, EnvConfig::default()).unwrap();
+
+    // Alternating left and right serves every ball and breaks some bricks
+    let mut total = 0.0;
+    let mut steps = 0;
+    loop {
+        let (observation, reward, done) = env.step(1 + steps % 2);
+        assert_eq!(observation.len(), 32);
+        total += reward;
+        steps += 1;
+        if done {
+            break;
+        }
+    }
+
+    assert!(!env.is_truncated());
+    assert!(env.error().is_none());
+    assert!(total > 0.0);
+
+    env.reset();
+    assert_eq!(env.frame(), 0);
+}
+
+#[test]
+fn pong_ends_when_a_player_reaches_nine() {
+    // With the left paddle held at the top, the right player misses the first serve and then
+    // wins every point
+    let (env, rewards) = play("pong.c8", |_| 1);
+    assert_eq!(rewards[0], 1.0);
+    assert_eq!(rewards[1..], [-1.0; 9]);
+    assert_eq!(env.machine().chip8().registers()[0xE], 19);
+}
+
+#[test]
+fn tetris_pays_for_lines_and_ends_when_the_well_fills() {
+    const NOTHING: usize = 0;
+    const LEFT: usize = 1;
+    const RIGHT: usize = 2;
+    const ROTATE: usize = 3;
+    const DROP: usize = 4;
+
+    // The rotation and column of the first pieces: a T, an I and an O along the bottom row,
+    // an S out of the way and a Z into the last gap
+    let plan = [(0, 27), (1, 30), (0, 35), (0, 27), (0, 33)];
+    let mut pieces = 0;
+    let mut last_y = 0;
+    let (env, rewards) = play("roms/tetris.c8", |env| {
+        // The piece is at (V0, V1) with rotation V3, and spawns at y = 3
+        let regs = env.machine().chip8().registers();
+        let (x, y, rotation) = (regs[0], regs[1], regs[3]);
+        if y == 3 && last_y != 3 {
+            pieces += 1;
+        }
+        last_y = y;
+
+        if pieces == 0 {
+            return NOTHING;
+        }
+        match plan.get(pieces - 1) {
+            Some(&(target, _)) if rotation != target => ROTATE,
+            Some(&(_, column)) if x > column => LEFT,
+            Some(&(_, column)) if x < column => RIGHT,
+            // Everything after the plan piles up under the spawn point
+            _ => DROP,
+        }
+    });
+
+    assert_eq!(rewards, [1.0]);
+    assert_eq!(pieces, 9);
+    let regs = env.machine().chip8().registers();
+    assert_eq!((regs[0xA], regs[0xC]), (1, 5));
+}
+
+#[test]
+fn invaders_pays_for_each_invader_and_ends_when_they_land() {
+    // Left alone, the invaders walk down to the ground
+    let (env, rewards) = play("invaders.c8", |_| 0);
+    assert!(rewards.is_empty());
+    assert_eq!(env.machine().chip8().registers()[0xC], 24);
+
+    // Firing from where the player starts hits two of the four
+    let (env, rewards) = play("invaders.c8", |_| 3);
+    assert_eq!(rewards, [1.0, 1.0]);
+    assert_eq!(env.machine().chip8().registers()[0xE].count_ones(), 2);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use chip8::{
+    engine::EngineKind,
+    CallFrame,
+    Chip8,
+    Chip8ErrorKind,
+    Instruction,
+    Machine,
+};
+
+const ENGINES: &[EngineKind] = &[
+    EngineKind::Interpreter,
+    EngineKind::BlockCache,
+    EngineKind::Threaded,
+];
+
+fn machine(rom: &[u8], engine: EngineKind) -> Machine {
+    let mut machine = common::machine(rom);
+    machine.set_engine(engine);
+    machine
+}
+
+#[test]
+fn unknown_instructions_have_context() {
+    // v0 := 5, call 0x206, then an unknown instruction in the subroutine
+    let rom = [0x60, 0x05, 0x22, 0x06, 0x00, 0x00, 0xFF, 0xFF];
+    for &engine in ENGINES {
+        let error = machine(&rom, engine).run_frame().unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &Chip8ErrorKind::UnknownInstruction(Instruction::Unknown(0xFFFF)),
+            "{:?}",
+            engine
+        );
+        assert!(error.is_fatal());
+        assert!(!error.is_recoverable());
+
+        let context = error.context().unwrap();
+        assert_eq!(context.pc, 0x206);
+        assert_eq!(context.opcode, Some(0xFFFF));
+        assert_eq!(context.registers[0], 5);
+        assert_eq!(
+            context.call_stack,
+            [CallFrame {
+                call_site: 0x202,
+                target: 0x206
+            }]
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "unknown instruction FFFF at 0x206 (FFFF)"
+        );
+        assert_eq!(
+            format!("{:#}", error),
+            "unknown instruction FFFF at 0x206 (FFFF)\n\
+             V: [05, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00]\n\
+             I: 0x000\n\
+             Stack: [204]"
+        );
+    }
+}
+
+#[test]
+fn running_off_the_end_of_memory_has_no_opcode() {
+    // Jump to the last byte of memory
+    let rom = [0x1F, 0xFF];
+    for &engine in ENGINES {
+        let error = machine(&rom, engine).run_frame().unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &Chip8ErrorKind::ProgramCounterOutOfBounds(0xFFF)
+        );
+        assert_eq!(error.pc(), Some(0xFFF));
+        assert_eq!(error.context().unwrap().opcode, None);
+        assert_eq!(
+            error.to_string(),
+            "the program counter 0xFFF is outside of memory at 0xFFF"
+        );
+    }
+}
+
+#[test]
+fn memory_past_the_end_is_invalid() {
+    // i := 0xFFF, then a store, a draw and a load that run past the end of memory
+    let roms: [(&[u8], &str); 3] = [
+        (
+            &[0xAF, 0xFF, 0xF0, 0x33],
+            "address 0x1000 is outside of memory at 0x202 (F033)",
+        ),
+        (
+            &[0xAF, 0xFF, 0xD0, 0x05],
+            "address 0x1000 is outside of memory at 0x202 (D005)",
+        ),
+        (
+            &[0xAF, 0xFF, 0xFF, 0x65],
+            "address 0x1000 is outside of memory at 0x202 (FF65)",
+        ),
+    ];
+    for &engine in ENGINES {
+        for &(rom, message) in &roms {
+            let mut machine = machine(rom, engine);
+            let error = machine.run_frame().unwrap_err();
+            assert_eq!(
+                error.kind(),
+                &Chip8ErrorKind::InvalidAddress(0x1000),
+                "{:?}",
+                engine
+            );
+            assert_eq!(error.to_string(), message);
+            assert_eq!(error.context().unwrap().i, 0xFFF);
+            // Nothing was written before the error
+            assert_eq!(machine.chip8().peek(0xFFF).unwrap(), 0);
+            assert_eq!(machine.chip8().registers(), &[0; 16]);
+        }
+    }
+}
+
+#[test]
+fn failed_draws_leave_vf_alone() {
+    // vf := 1, i := 0xFFF, then a draw that runs past the end of memory
+    let rom = [0x6F, 0x01, 0xAF, 0xFF, 0xD0, 0x05];
+    for &engine in ENGINES {
+        let mut machine = machine(&rom, engine);
+        let error = machine.run_frame().unwrap_err();
+        assert_eq!(error.kind(), &Chip8ErrorKind::InvalidAddress(0x1000));
+        assert_eq!(machine.chip8().reg(0xF).unwrap(), 1, "{:?}", engine);
+    }
+}
+
+#[test]
+fn adding_to_i_wraps() {
+    // i := 0xFFF, v0 := 0xFF, then add v0 to i forever
+    let rom = [0xAF, 0xFF, 0x60, 0xFF, 0xF0, 0x1E, 0x12, 0x04];
+    for &engine in ENGINES {
+        let mut machine = machine(&rom, engine);
+        for _ in 0..100 {
+            machine.run_frame().unwrap();
+        }
+        // 349 additions of 0xFF, wrapping once
+        assert_eq!(
+            u32::from(machine.chip8().i()),
+            (0xFFF + 349 * 0xFF) % 0x10000,
+            "{:?}",
+            engine
+        );
+    }
+}
+
+#[test]
+fn keys_past_f_are_invalid() {
+    // v0 := 0x10, then skip if key v0 is pressed
+    let rom = [0x60, 0x10, 0xE0, 0x9E];
+    for &engine in ENGINES {
+        let error = machine(&rom, engine).run_frame().unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &Chip8ErrorKind::InvalidKey(16),
+            "{:?}",
+            engine
+        );
+        assert_eq!(error.pc(), Some(0x202));
+        assert_eq!(error.to_string(), "there is no key 0x10 at 0x202 (E09E)");
+    }
+}
+
+#[test]
+fn returning_from_main_underflows() {
+    let error = machine(&[0x00, 0xEE], EngineKind::Interpreter)
+        .step()
+        .unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::StackUnderflow);
+    assert_eq!(
+        error.to_string(),
+        "returned with an empty call stack at 0x200 (00EE)"
+    );
+}
+
+#[test]
+fn api_errors_are_recoverable() {
+    let mut chip8 = Chip8::new();
+    chip8.init();
+
+    let error = chip8.poke(0x1000, 1).unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::InvalidAddress(0x1000));
+    assert!(error.is_recoverable());
+    assert_eq!(error.context(), None);
+    assert_eq!(error.to_string(), "address 0x1000 is outside of memory");
+
+    let error = chip8.set_key(16, true).unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::InvalidKey(16));
+    assert!(error.is_recoverable());
+    assert_eq!(chip8.keys(), &[false; 16]);
+
+    let error = chip8.set_reg(16, 0).unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::InvalidReg(16));
+    assert!(error.is_recoverable());
+
+    let error = chip8.load(&[0; 8192]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "a 8192 byte program doesn't fit in memory"
+    );
+
+    let error: Box<dyn std::error::Error> = Box::new(error);
+    assert!(error.to_string().contains("8192"));
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use chip8::{
+    Instruction,
+    GFX_HEIGHT,
+    GFX_SIZE,
+    GFX_WIDTH,
+};
+
+#[test]
+fn the_screen_is_64_by_32() {
+    assert_eq!((GFX_WIDTH, GFX_HEIGHT, GFX_SIZE), (64, 32, 2048));
+}
+
+#[test]
+fn jump_offset_and_shifts_decode() {
+    assert!(matches!(
+        Instruction::from(0xB30C),
+        Instruction::JumpOffset(0x30C)
+    ));
+    assert!(matches!(
+        Instruction::from(0x8126),
+        Instruction::ShiftRight(1, 2)
+    ));
+    assert!(matches!(
+        Instruction::from(0x832E),
+        Instruction::ShiftLeft(3, 2)
+    ));
+}
+
+#[test]
+fn jump_offset_adds_v0() {
+    let rom = [
+        0x60, 0x04, // v0 := 4
+        0xB2, 0x06, // jump0 0x206
+        0x00, 0x00, //
+        0x12, 0x06, // loop without drawing
+        0x12, 0x08, //
+        0xD0, 0x15, // draw the font's 0 at (4, 0)
+        0x12, 0x0C, // loop
+    ];
+    let mut chip8 = common::chip8(&rom);
+    for _ in 0..8 {
+        chip8.cycle().unwrap();
+    }
+
+    let lit: Vec<_> = (0..GFX_WIDTH).filter(|&x| chip8.gfx[x]).collect();
+    assert_eq!(lit, [4, 5, 6, 7]);
+}
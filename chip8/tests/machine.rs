@@ -0,0 +1,104 @@
+mod common;
+
+use chip8::{
+    engine::EngineKind,
+    machine::MAX_RUN_FOR,
+    Chip8ErrorKind,
+    Machine,
+};
+use std::time::Duration;
+
+fn machine() -> Machine {
+    let mut chip8 = common::chip8(&common::rom("breakout.c8"));
+    chip8.seed_rng(1);
+    let mut machine = Machine::new(chip8);
+    machine.set_tickrate(10);
+    machine
+}
+
+#[test]
+fn stepping_matches_running_frames() {
+    let mut frames = machine();
+    let mut steps = machine();
+    for frame in 0..300 {
+        frames.chip8_mut().set_key(4, frame % 50 < 25).unwrap();
+        steps.chip8_mut().set_key(4, frame % 50 < 25).unwrap();
+
+        let events = frames.run_frame().unwrap();
+        assert_eq!(events.instructions, 10);
+        let mut ticks = 0;
+        for _ in 0..events.instructions {
+            ticks += steps.step().unwrap().timer_ticks;
+        }
+        assert_eq!(ticks, events.timer_ticks);
+        assert_eq!(steps.chip8().save_state(), frames.chip8().save_state());
+    }
+
+    // Stepping and running frames mix
+    steps.step().unwrap();
+    steps.run_frame().unwrap();
+    assert_eq!(steps.run_frame().unwrap().instructions, 10);
+}
+
+/// Sets v0 and v1, hits an unknown instruction, then loops at 0x206
+const FAULTY: &[u8] = &[0x60, 0x01, 0x61, 0x02, 0xFF, 0xFF, 0x12, 0x06];
+
+#[test]
+fn instructions_before_an_error_keep_their_time() {
+    for &kind in &[
+        EngineKind::Interpreter,
+        EngineKind::BlockCache,
+        EngineKind::Threaded,
+    ] {
+        let chip8 = common::chip8(FAULTY);
+        let error = kind.create().run(&mut chip8.clone(), 7).unwrap_err();
+        assert_eq!(error.executed, 2, "{:?}", kind);
+        assert_eq!(error.error.pc(), Some(0x204));
+
+        let mut machine = Machine::new(chip8);
+        machine.set_engine(kind);
+        machine.set_tickrate(7);
+        let error = machine.run_frame().unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            Chip8ErrorKind::UnknownInstruction(_)
+        ));
+        assert_eq!(machine.chip8().registers()[..2], [1, 2]);
+
+        // Skipping the bad instruction runs the rest of the first frame and then the second, not two whole frames
+        machine.chip8_mut().set_pc(0x206).unwrap();
+        let events = machine.run_frame().unwrap();
+        assert_eq!(events.instructions, 5 + 7, "{:?}", kind);
+        assert_eq!(events.timer_ticks, 1);
+    }
+}
+
+#[test]
+fn speeds_are_clamped() {
+    let mut machine = machine();
+    machine.set_tickrate(0);
+    assert_eq!(machine.instructions_per_second(), 1);
+    machine.set_instructions_per_second(0);
+    assert_eq!(machine.instructions_per_second(), 1);
+    // One instruction a second is one every 60 frames
+    let instructions: u64 = (0..120)
+        .map(|_| machine.run_frame().unwrap().instructions)
+        .sum();
+    assert_eq!(instructions, 2);
+
+    machine.set_tickrate(u32::MAX);
+    assert_eq!(machine.instructions_per_second(), u32::MAX);
+    machine.set_tickrate(1000);
+    assert_eq!(machine.instructions_per_second(), 60_000);
+}
+
+#[test]
+fn long_runs_are_cut_short() {
+    let mut machine = machine();
+    // 250 ms at 600 instructions a second
+    assert_eq!(MAX_RUN_FOR, Duration::from_millis(250));
+    let events = machine.run_for(Duration::MAX).unwrap();
+    assert_eq!((events.instructions, events.timer_ticks), (150, 15));
+    let events = machine.run_for(Duration::from_secs(60)).unwrap();
+    assert_eq!((events.instructions, events.timer_ticks), (150, 15));
+}
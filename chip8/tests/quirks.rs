@@ -0,0 +1,174 @@
+mod common;
+
+use chip8::{
+    Chip8,
+    Quirks,
+    GFX_SIZE,
+    GFX_WIDTH,
+};
+
+/// A chip8 running a program with some quirks
+fn chip8(rom: &[u8], quirks: Quirks) -> Chip8 {
+    let mut chip8 = common::chip8(rom);
+    chip8.set_quirks(quirks);
+    chip8
+}
+
+/// Run a program with a timer tick before every instruction, so vblank never holds it up
+fn run(rom: &[u8], quirks: Quirks, instructions: usize) -> Chip8 {
+    let mut chip8 = chip8(rom, quirks);
+    for _ in 0..instructions {
+        chip8.update_timers();
+        chip8.cycle().unwrap();
+    }
+    chip8
+}
+
+/// Append code that saves every register to 0x300, draws the ones in `regs` a row each in the
+/// top left corner and then loops. It draws at `vE`, `vD`, so the program must leave `vE` at 0
+fn reveal(mut rom: Vec<u8>, regs: &[u8]) -> Vec<u8> {
+    rom.extend_from_slice(&[0xA3, 0x00, 0xFF, 0x55]);
+    for (row, &reg) in regs.iter().enumerate() {
+        rom.extend_from_slice(&[0xA3, reg, 0x6D, row as u8, 0xDE, 0xD1]);
+    }
+    let end = 0x200 + rom.len() as u16;
+    rom.extend_from_slice(&(0x1000 | end).to_be_bytes());
+    rom
+}
+
+/// The byte drawn on a row by `reveal`
+fn row(chip8: &Chip8, y: usize) -> u8 {
+    (0..8).fold(0, |byte, x| byte << 1 | chip8.gfx[y * GFX_WIDTH + x] as u8)
+}
+
+/// The lit pixels, as `(x, y)`
+fn lit(chip8: &Chip8) -> Vec<(usize, usize)> {
+    (0..GFX_SIZE)
+        .filter(|&i| chip8.gfx[i])
+        .map(|i| (i % GFX_WIDTH, i / GFX_WIDTH))
+        .collect()
+}
+
+#[test]
+fn jump_offset_uses_v0_or_vx() {
+    // v0 := 4, v2 := 8, jump0 0x20C, then v5 := 1 at 0x210 or v5 := 2 at 0x214
+    let rom = reveal(
+        vec![
+            0x60, 0x04, 0x62, 0x08, 0xB2, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x65, 0x01, 0x12, 0x16, 0x65, 0x02,
+        ],
+        &[5],
+    );
+    assert_eq!(row(&run(&rom, Quirks::CHIP8, 16), 0), 1);
+    assert_eq!(row(&run(&rom, Quirks::SCHIP, 16), 0), 2);
+}
+
+#[test]
+fn shifts_use_vy_or_vx() {
+    // v1 := 0x81, v2 := 0x03, v1 >>= v2
+    let right = reveal(vec![0x61, 0x81, 0x62, 0x03, 0x81, 0x26], &[1, 0xF]);
+    // v3 := 0x81, v2 := 0x03, v3 <<= v2
+    let left = reveal(vec![0x63, 0x81, 0x62, 0x03, 0x83, 0x2E], &[3, 0xF]);
+
+    let vip = run(&right, Quirks::CHIP8, 16);
+    assert_eq!((row(&vip, 0), row(&vip, 1)), (0x01, 1));
+    let vip = run(&left, Quirks::CHIP8, 16);
+    assert_eq!((row(&vip, 0), row(&vip, 1)), (0x06, 0));
+
+    let schip = run(&right, Quirks::SCHIP, 16);
+    assert_eq!((row(&schip, 0), row(&schip, 1)), (0x40, 1));
+    let schip = run(&left, Quirks::SCHIP, 16);
+    assert_eq!((row(&schip, 0), row(&schip, 1)), (0x02, 1));
+}
+
+#[test]
+fn sprites_clip_or_wrap() {
+    // i := 0x20A, v0 := 62, v1 := 31, draw 2 rows, then the sprite: a row of 3 pixels twice
+    let rom = [
+        0xA2, 0x0A, 0x60, 0x3E, 0x61, 0x1F, 0xD0, 0x12, 0x00, 0x00, 0xE0, 0xE0,
+    ];
+    let clipped = run(&rom, Quirks::MODERN_CHIP8, 4);
+    assert_eq!(lit(&clipped), [(62, 31), (63, 31)]);
+
+    let wrapped = run(&rom, Quirks::XOCHIP, 4);
+    assert_eq!(
+        lit(&wrapped),
+        [(0, 0), (62, 0), (63, 0), (0, 31), (62, 31), (63, 31)]
+    );
+
+    // Positions past the edge wrap either way: v0 := 66 starts at column 2
+    let mut rom = rom;
+    rom[3] = 0x42;
+    let chip8 = run(&rom, Quirks::MODERN_CHIP8, 4);
+    assert_eq!(lit(&chip8), [(2, 31), (3, 31), (4, 31)]);
+
+    // Drawing the same sprite again erases it and sets VF, which is then drawn at the top
+    let rom = reveal(
+        vec![
+            0xA2, 0x0C, 0x60, 0x42, 0x61, 0x1F, 0xD0, 0x12, 0xD0, 0x12, 0x12, 0x0E, 0xE0, 0xE0,
+        ],
+        &[0xF],
+    );
+    let chip8 = run(&rom, Quirks::MODERN_CHIP8, 16);
+    assert_eq!(lit(&chip8), [(7, 0)]);
+}
+
+#[test]
+fn vblank_waits_for_the_timers() {
+    // draw the font's 0 twice
+    let rom = [0xD0, 0x01, 0xD0, 0x01];
+    let mut vip = chip8(&rom, Quirks::CHIP8);
+    vip.cycle().unwrap();
+    vip.cycle().unwrap();
+    assert!(lit(&vip).is_empty());
+    vip.update_timers();
+    vip.cycle().unwrap();
+    vip.cycle().unwrap();
+    assert_eq!(lit(&vip).len(), 4);
+    vip.update_timers();
+    vip.cycle().unwrap();
+    assert!(lit(&vip).is_empty());
+
+    let mut modern = chip8(&rom, Quirks::MODERN_CHIP8);
+    modern.cycle().unwrap();
+    assert_eq!(lit(&modern).len(), 4);
+    modern.cycle().unwrap();
+    assert!(lit(&modern).is_empty());
+}
+
+#[test]
+fn logic_resets_vf() {
+    // vf := 5, v0 |= v1
+    let rom = reveal(vec![0x6F, 0x05, 0x80, 0x11], &[0xF]);
+    assert_eq!(row(&run(&rom, Quirks::CHIP8, 16), 0), 0);
+    assert_eq!(row(&run(&rom, Quirks::MODERN_CHIP8, 16), 0), 5);
+}
+
+#[test]
+fn bulk_memory_moves_i() {
+    // i := 0x300, save v0 - v2, load v0 - v2, then mark where i ended up and draw 0x300 - 0x307
+    let rom = [
+        0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65, 0x60, 0xFF, 0xF0, 0x55, 0xA3, 0x00, 0xDE, 0xD8,
+    ];
+    let cases = [
+        (Quirks::CHIP8, 6),
+        (Quirks::SCHIP, 0),
+        (
+            Quirks {
+                memory_increment_by_x: true,
+                ..Quirks::CHIP8
+            },
+            4,
+        ),
+    ];
+    for &(quirks, i) in &cases {
+        let chip8 = run(&rom, quirks, 7);
+        let rows: Vec<_> = (0..8).map(|y| row(&chip8, y)).collect();
+        assert_eq!(
+            rows.iter().position(|&row| row == 0xFF),
+            Some(i),
+            "{:?}",
+            quirks
+        );
+    }
+}
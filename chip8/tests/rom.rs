@@ -0,0 +1,245 @@
+use chip8::{
+    database::{
+        sha1_hex,
+        RomDatabase,
+        DEFAULT_TICKRATE,
+    },
+    rom::{
+        Rom,
+        RomError,
+        RomFormat,
+    },
+    Chip8,
+    Chip8ErrorKind,
+    Quirks,
+};
+
+/// Hide a payload in the pixels of a gif the way Octo does
+fn cartridge(json: &str) -> Vec<u8> {
+    let mut stream = (json.len() as u32).to_be_bytes().to_vec();
+    stream.extend_from_slice(json.as_bytes());
+    hide(&stream)
+}
+
+/// Hide a byte stream in the pixels of a gif, split over two frames
+fn hide(stream: &[u8]) -> Vec<u8> {
+    let mut pixels: Vec<u8> = stream
+        .iter()
+        .flat_map(|&b| vec![b >> 6, b >> 4, b >> 2, b].into_iter())
+        // Octo keeps the label in the upper bits
+        .map(|pixel| pixel & 0b11 | 0b100)
+        .collect();
+    let width = 16;
+    let height = pixels.len().div_ceil(2 * width);
+    pixels.resize(width * height * 2, 0);
+
+    let palette: Vec<u8> = (0..8u8).flat_map(|i| vec![i * 32; 3]).collect();
+    let mut gif = Vec::new();
+    {
+        let mut encoder =
+            gif::Encoder::new(&mut gif, width as u16, height as u16, &palette).unwrap();
+        for frame in pixels.chunks(width * height) {
+            let frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, frame, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+    }
+    gif
+}
+
+/// Build a c8b bundle from its programs and properties
+fn bundle(programs: &[(u8, &[u8])], properties: &[(u8, &[u8])]) -> Vec<u8> {
+    let table_end = 6 + 5 * programs.len();
+    let properties_len: usize = properties.iter().map(|(_, value)| 2 + value.len()).sum();
+
+    let mut data = b"CBF\x00".to_vec();
+    data.extend_from_slice(&(table_end as u16).to_be_bytes());
+    let mut offset = table_end + properties_len;
+    for (platform, bytecode) in programs {
+        data.push(*platform);
+        data.extend_from_slice(&(offset as u16).to_be_bytes());
+        data.extend_from_slice(&(bytecode.len() as u16).to_be_bytes());
+        offset += bytecode.len();
+    }
+    for (tag, value) in properties {
+        data.push(*tag);
+        data.push(value.len() as u8);
+        data.extend_from_slice(value);
+    }
+    for (_, bytecode) in programs {
+        data.extend_from_slice(bytecode);
+    }
+    data
+}
+
+#[test]
+fn raw_roms_use_the_database() {
+    let database = RomDatabase::bundled();
+    let ibm = include_bytes!("../../roms/ibm.c8");
+    let rom = Rom::parse(ibm, &database).unwrap();
+    let info = database.lookup(ibm).unwrap();
+    assert_eq!(rom.format, RomFormat::Raw);
+    assert_eq!(rom.load_address, 0x200);
+    assert_eq!(rom.quirks, info.quirks);
+    assert_eq!(rom.tickrate, info.tickrate);
+    assert_eq!(rom.title, Some(info.title));
+
+    let rom = Rom::parse(&[0x12, 0x00], &database).unwrap();
+    assert_eq!(rom.quirks, Quirks::default());
+    assert_eq!(rom.tickrate, DEFAULT_TICKRATE);
+    assert!(rom.info.is_none());
+}
+
+#[test]
+fn database_start_addresses_are_used() {
+    let program = [0x16, 0x00];
+    let programs = format!(
+        r#"[{{ "title": "Loop", "roms": {{ "{}": {{ "platforms": ["originalChip8"], "startAddress": 1536 }} }} }}]"#,
+        sha1_hex(&program)
+    );
+    let hashes = format!(r#"{{ "{}": 0 }}"#, sha1_hex(&program));
+    let database = RomDatabase::from_json(&programs, &hashes, "[]").unwrap();
+
+    let rom = Rom::parse(&program, &database).unwrap();
+    assert_eq!(rom.load_address, 0x600);
+    assert_eq!(rom.quirks, Quirks::CHIP8);
+
+    let mut chip8 = Chip8::new();
+    chip8.init();
+    rom.load_into(&mut chip8).unwrap();
+    assert_eq!(chip8.pc(), 0x600);
+    assert_eq!(chip8.peek(0x601).unwrap(), 0x00);
+    assert_eq!(chip8.quirks(), Quirks::CHIP8);
+}
+
+#[test]
+fn oversized_roms_are_rejected() {
+    let mut chip8 = Chip8::new();
+    chip8.init();
+
+    // Fits exactly
+    chip8.load(&[0x12; 4096 - 0x200]).unwrap();
+    let error = chip8.load(&[0x12; 4096 - 0x200 + 1]).unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::InvalidProgramSize(3585));
+    assert!(error.is_recoverable());
+
+    let error = chip8.load_at(&[0x12; 4096 - 0x600 + 1], 0x600).unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::InvalidProgramSize(2561));
+    let error = chip8.load_at(&[], 0x1000).unwrap_err();
+    assert_eq!(error.kind(), &Chip8ErrorKind::InvalidAddress(0x1000));
+
+    match Rom::parse(&[0x12; 4000], &RomDatabase::bundled()) {
+        Err(
+            e @ RomError::TooLarge {
+                size: 4000,
+                load_address: 0x200,
+            },
+        ) => assert_eq!(
+            e.to_string(),
+            "a 4000 byte program doesn't fit in memory at 0x200"
+        ),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn octo_cartridges() {
+    let json = r##"{
+        "options": { "tickrate": 20, "shiftQuirks": true, "clipQuirks": true, "fillColor": "#FFCC00" },
+        "program": ": main\n  v0 := 1\n  jump main\n"
+    }"##;
+    let rom = Rom::parse(&cartridge(json), &RomDatabase::bundled()).unwrap();
+    assert_eq!(rom.format, RomFormat::OctoCartridge);
+    assert_eq!(rom.data, [0x60, 0x01, 0x12, 0x00]);
+    assert_eq!(rom.tickrate, 20);
+    assert!(rom.quirks.shift);
+    assert!(!rom.quirks.wrap);
+    assert!(!rom.quirks.vblank);
+    assert_eq!(
+        rom.source_map.unwrap().describe(0x202),
+        "cartridge.8o:3 (main+2)"
+    );
+
+    let rom = Rom::parse(
+        &cartridge(r#"{ "program": "clear" }"#),
+        &RomDatabase::bundled(),
+    )
+    .unwrap();
+    assert_eq!(rom.data, [0x00, 0xE0]);
+    assert_eq!(rom.tickrate, DEFAULT_TICKRATE);
+    assert!(rom.quirks.wrap);
+
+    let error = Rom::parse(
+        &cartridge(r#"{ "program": "v0 := 256" }"#),
+        &RomDatabase::bundled(),
+    )
+    .unwrap_err();
+    assert!(
+        matches!(error, RomError::UnsupportedSource(_)),
+        "{:?}",
+        error
+    );
+    assert!(error
+        .to_string()
+        .starts_with("unsupported cartridge source: line 1: "));
+    let error = Rom::parse(
+        &cartridge(r#"{ "program": ":macro twice { clear clear }" }"#),
+        &RomDatabase::bundled(),
+    )
+    .unwrap_err();
+    assert!(
+        matches!(error, RomError::UnsupportedSource(_)),
+        "{:?}",
+        error
+    );
+    let error = Rom::parse(&cartridge("not json"), &RomDatabase::bundled()).unwrap_err();
+    assert!(
+        matches!(error, RomError::InvalidCartridge(_)),
+        "{:?}",
+        error
+    );
+
+    // Lengths past the end of the stream, up to the largest there is
+    for &len in &[1000, u32::MAX] {
+        let mut stream = len.to_be_bytes().to_vec();
+        stream.extend_from_slice(b"{}");
+        let error = Rom::parse(&hide(&stream), &RomDatabase::bundled()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid cartridge: the payload is cut off"
+        );
+    }
+}
+
+#[test]
+fn bundles_pick_the_best_platform() {
+    let database = RomDatabase::bundled();
+    let data = bundle(
+        &[(0x11, &[0xF0, 0x00]), (0x01, &[0x16, 0x00])],
+        &[(0x00, &[0x01, 0x00]), (0x01, b"Loop"), (0x7F, &[1, 2, 3])],
+    );
+    let rom = Rom::parse(&data, &database).unwrap();
+    assert_eq!(rom.format, RomFormat::Bundle);
+    assert_eq!(rom.data, [0x16, 0x00]);
+    assert_eq!(rom.load_address, 0x600);
+    assert_eq!(rom.platform.as_deref(), Some("originalChip8"));
+    assert_eq!(rom.quirks, Quirks::CHIP8);
+    assert_eq!(rom.tickrate, 256);
+    assert_eq!(rom.title.as_deref(), Some("Loop"));
+
+    let rom = Rom::parse(&bundle(&[(0x11, &[0x12, 0x00])], &[]), &database).unwrap();
+    assert_eq!(rom.quirks, Quirks::XOCHIP);
+    assert_eq!(rom.load_address, 0x200);
+    assert_eq!(rom.title, None);
+
+    let error = Rom::parse(&bundle(&[(0x42, &[0x12, 0x00])], &[]), &database).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the bundle has no programs for a supported platform (0x42)"
+    );
+    let mut truncated = bundle(&[(0x00, &[0x12, 0x00])], &[]);
+    truncated.pop();
+    assert!(matches!(
+        Rom::parse(&truncated, &database),
+        Err(RomError::InvalidBundle(_))
+    ));
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use chip8::{
+    CallFrame,
+    Chip8ErrorKind,
+    Machine,
+    Quirks,
+    StackOverflowMode,
+    MAX_STACK_DEPTH,
+};
+
+/// A machine running a subroutine that calls itself forever
+fn recursion(quirks: Quirks) -> Machine {
+    let mut chip8 = common::chip8(&[0x00, 0xE0, 0x22, 0x04, 0x22, 0x04]);
+    chip8.set_quirks(quirks);
+    let mut machine = Machine::new(chip8);
+    machine.step().unwrap();
+    machine
+}
+
+/// Step until the program fails, returning the number of calls that went through
+fn calls_until_overflow(machine: &mut Machine) -> usize {
+    for calls in 0.. {
+        if let Err(e) = machine.step() {
+            assert_eq!(e.kind(), &Chip8ErrorKind::StackOverflow);
+            return calls;
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn overflow_happens_past_the_depth() {
+    let mut machine = recursion(Quirks::default());
+    assert_eq!(calls_until_overflow(&mut machine), 16);
+
+    // The failed call changed nothing
+    let chip8 = machine.chip8();
+    assert_eq!(chip8.pc(), 0x204);
+    assert_eq!(chip8.sp(), 16);
+    assert_eq!(
+        chip8.call_stack()[0],
+        CallFrame {
+            call_site: 0x202,
+            target: 0x204
+        }
+    );
+    assert!(chip8.call_stack()[1..]
+        .iter()
+        .all(|frame| frame.call_site == 0x204 && frame.return_address() == 0x206));
+    assert_eq!(calls_until_overflow(&mut machine), 0);
+}
+
+#[test]
+fn the_vip_has_a_shallower_stack() {
+    assert_eq!(Quirks::CHIP8.stack_depth, 12);
+    assert_eq!(Quirks::for_platform("originalChip8"), Some(Quirks::CHIP8));
+    assert_eq!(calls_until_overflow(&mut recursion(Quirks::CHIP8)), 12);
+
+    let quirks = Quirks {
+        stack_depth: 2,
+        ..Quirks::default()
+    };
+    assert_eq!(calls_until_overflow(&mut recursion(quirks)), 2);
+}
+
+#[test]
+fn warn_mode_lets_calls_through() {
+    let mut machine = recursion(Quirks::default());
+    machine
+        .chip8_mut()
+        .set_stack_overflow_mode(StackOverflowMode::Warn);
+
+    for _ in 0..16 {
+        assert!(!machine.step().unwrap().stack_overflowed);
+    }
+    assert!(machine.step().unwrap().stack_overflowed);
+    assert_eq!(machine.chip8().sp(), 17);
+
+    // Even warnings stop where SP runs out
+    assert_eq!(calls_until_overflow(&mut machine), MAX_STACK_DEPTH - 17);
+    assert_eq!(usize::from(machine.chip8().sp()), MAX_STACK_DEPTH);
+}
+
+#[test]
+fn returns_pop_frames() {
+    // call 0x206, jump to self, return
+    let mut machine = common::machine(&[0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x00, 0xEE]);
+
+    machine.step().unwrap();
+    assert_eq!(
+        machine.chip8().call_stack(),
+        [CallFrame {
+            call_site: 0x200,
+            target: 0x206
+        }]
+    );
+    machine.step().unwrap();
+    assert_eq!(machine.chip8().pc(), 0x202);
+    assert!(machine.chip8().call_stack().is_empty());
+}
+
+#[test]
+fn save_states_keep_the_stack() {
+    let mut machine = recursion(Quirks::CHIP8);
+    let empty = machine.save_state();
+    for _ in 0..5 {
+        machine.step().unwrap();
+    }
+    machine
+        .chip8_mut()
+        .set_stack_overflow_mode(StackOverflowMode::Warn);
+    let state = machine.save_state();
+    assert_eq!(state.len(), empty.len());
+
+    let mut restored = Machine::default();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.chip8().call_stack(), machine.chip8().call_stack());
+    assert_eq!(restored.chip8().quirks(), Quirks::CHIP8);
+    assert_eq!(
+        restored.chip8().stack_overflow_mode(),
+        StackOverflowMode::Warn
+    );
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use chip8::{
+    state::StateError,
+    Chip8,
+    Machine,
+};
+
+fn machine() -> Machine {
+    let mut chip8 = common::chip8(&common::rom("pong.c8"));
+    chip8.seed_rng(3);
+    Machine::new(chip8)
+}
+
+/// Run with some input and collect the displays
+fn play(machine: &mut Machine) -> Vec<Vec<bool>> {
+    (0..300)
+        .map(|frame| {
+            machine.chip8_mut().set_key(1, frame % 40 < 20).unwrap();
+            machine.run_frame().unwrap();
+            machine.chip8().gfx.to_vec()
+        })
+        .collect()
+}
+
+#[test]
+fn loading_a_state_replays_the_same_frames() {
+    let mut original = machine();
+    for _ in 0..90 {
+        original.run_frame().unwrap();
+    }
+    let state = original.save_state();
+
+    // A fresh machine with a different seed must end up in the same place
+    let mut restored = machine();
+    restored.chip8_mut().seed_rng(4);
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+
+    assert!(play(&mut original) == play(&mut restored));
+}
+
+#[test]
+fn chip8_states_round_trip() {
+    let mut machine = machine();
+    for _ in 0..30 {
+        machine.run_frame().unwrap();
+    }
+    let state = machine.chip8().save_state();
+
+    let mut chip8 = Chip8::new();
+    chip8.load_state(&state).unwrap();
+    assert_eq!(chip8.save_state(), state);
+    assert_eq!(chip8.pc(), machine.chip8().pc());
+    assert_eq!(chip8.quirks(), machine.chip8().quirks());
+}
+
+#[test]
+fn invalid_states_are_rejected() {
+    let mut machine = machine();
+    let state = machine.save_state();
+    let before = machine.save_state();
+
+    assert_eq!(
+        machine.load_state(&state[..state.len() - 1]),
+        Err(StateError::InvalidLength(state.len() - 1))
+    );
+    assert_eq!(
+        machine.load_state(&machine.chip8().save_state()),
+        Err(StateError::InvalidMagic)
+    );
+
+    // Less than a whole nanosecond unit can be left over
+    let mut remainder = state.clone();
+    let len = remainder.len();
+    remainder[len - 16..].copy_from_slice(&1_000_000_000u128.to_le_bytes());
+    assert_eq!(
+        machine.load_state(&remainder),
+        Err(StateError::InvalidValue("remainder"))
+    );
+
+    let mut future = state.clone();
+    future[4] = 200;
+    assert_eq!(
+        machine.load_state(&future),
+        Err(StateError::UnsupportedVersion(200))
+    );
+
+    assert_eq!(machine.save_state(), before);
+}
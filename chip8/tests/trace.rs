@@ -0,0 +1,269 @@
+mod common;
+
+use chip8::{
+    trace::{
+        MemoryWrite,
+        TextTraceLine,
+        TraceBuffer,
+        TraceRecord,
+        TraceState,
+        Tracer,
+    },
+    Instruction,
+};
+use serde_json::Value;
+use std::{
+    io::Write,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// i := 0x300, va := 0xFB, then store va as BCD and increment it forever
+const COUNTER: &[u8] = &[0xA3, 0x00, 0x6A, 0xFB, 0xFA, 0x33, 0x7A, 0x01, 0x12, 0x04];
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<String> {
+        let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(String::from).collect()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run the counter for some instructions with a tracer, returning the tracer
+fn trace(tracer: Tracer, instructions: usize) -> Tracer {
+    let mut chip8 = common::chip8(COUNTER);
+    chip8.set_tracer(Some(tracer));
+    for _ in 0..instructions {
+        chip8.cycle().unwrap();
+    }
+    let mut tracer = chip8.set_tracer(None).unwrap();
+    tracer.flush().unwrap();
+    assert!(tracer.take_error().is_none());
+    tracer
+}
+
+fn records(tracer: &Tracer) -> Vec<TraceRecord> {
+    tracer.trace_buffer().unwrap().records().collect()
+}
+
+#[test]
+fn buffers_keep_the_newest_records() {
+    let all = records(&trace(Tracer::buffer(1000), 300));
+    assert_eq!(all.len(), 300);
+    for (step, record) in all.iter().enumerate() {
+        assert_eq!(record.step, step as u64);
+        assert_eq!(record.instruction, Instruction::from(record.opcode));
+    }
+    for pair in all.windows(2) {
+        assert_eq!(pair[0].after, pair[1].before);
+    }
+
+    // The first store is of 251
+    assert_eq!(all[2].pc, 0x204);
+    assert_eq!(
+        all[2].writes,
+        [
+            MemoryWrite {
+                addr: 0x300,
+                value: 2
+            },
+            MemoryWrite {
+                addr: 0x301,
+                value: 5
+            },
+            MemoryWrite {
+                addr: 0x302,
+                value: 1
+            },
+        ]
+    );
+    assert_eq!(
+        all[3].register_changes().collect::<Vec<_>>(),
+        [(0xA, 0xFB, 0xFC)]
+    );
+    assert_eq!(all[0].i_change(), Some((0, 0x300)));
+
+    let tracer = trace(Tracer::buffer(50), 300);
+    let buffer = tracer.trace_buffer().unwrap();
+    assert_eq!((buffer.len(), buffer.capacity()), (50, 50));
+    assert!(buffer.encoded_len() < 50 * 32);
+    assert_eq!(records(&tracer), all[250..]);
+    assert_eq!(tracer.steps(), 300);
+}
+
+#[test]
+fn buffers_round_trip_any_record() {
+    let state = |v0, i| TraceState {
+        v: [v0; 16],
+        i,
+        sp: 2,
+        delay_timer: 60,
+        sound_timer: 1,
+    };
+    let records = [
+        TraceRecord {
+            step: 5,
+            pc: 0x200,
+            opcode: 0xF055,
+            instruction: Instruction::from(0xF055),
+            before: state(1, 0x400),
+            after: state(2, 0x401),
+            writes: vec![MemoryWrite {
+                addr: 0x400,
+                value: 2,
+            }],
+        },
+        // A gap in steps too big for one byte
+        TraceRecord {
+            step: 100_000,
+            pc: 0xFFE,
+            opcode: 0x00E0,
+            instruction: Instruction::ClearDisplay,
+            before: state(3, 0xFFFF),
+            after: state(3, 0xFFFF),
+            writes: Vec::new(),
+        },
+        TraceRecord {
+            step: 100_001,
+            pc: 0x300,
+            opcode: 0x1300,
+            instruction: Instruction::Jump(0x300),
+            before: state(3, 0xFFFF),
+            after: TraceState::default(),
+            writes: Vec::new(),
+        },
+    ];
+
+    let mut buffer = TraceBuffer::new(2);
+    for record in records.iter() {
+        buffer.push(record);
+    }
+    assert_eq!(buffer.records().collect::<Vec<_>>(), records[1..]);
+
+    buffer.clear();
+    assert!(buffer.is_empty());
+    buffer.push(&records[0]);
+    assert_eq!(buffer.records().collect::<Vec<_>>(), records[..1]);
+
+    let mut empty = TraceBuffer::new(0);
+    empty.push(&records[0]);
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn filters_skip_instructions_outside_the_range() {
+    let tracer = trace(Tracer::buffer(1000).with_filter(0x204..0x206), 30);
+    let records = records(&tracer);
+    assert_eq!(records.len(), 10);
+    assert!(records.iter().all(|record| record.pc == 0x204));
+    // Steps still count every instruction
+    assert_eq!(records[1].step, 5);
+    assert_eq!(tracer.steps(), 30);
+}
+
+#[test]
+fn text_parses_back() {
+    let expected = records(&trace(Tracer::buffer(1000), 100));
+    let text = SharedBuffer::default();
+    trace(Tracer::text(text.clone()), 100);
+
+    let lines = text.lines();
+    assert_eq!(lines.len(), 100);
+    for (line, record) in lines.iter().zip(expected.iter()) {
+        let parsed: TextTraceLine = line.parse().unwrap();
+        assert_eq!(parsed, TextTraceLine::from(record));
+    }
+    assert!(lines[2].starts_with("0204: FA33 "), "{}", lines[2]);
+    assert!(
+        lines[2].ends_with(" W:0300=02 W:0301=05 W:0302=01"),
+        "{}",
+        lines[2]
+    );
+
+    // The same goes for a whole buffer
+    let mut written = Vec::new();
+    trace(Tracer::buffer(1000), 100)
+        .trace_buffer()
+        .unwrap()
+        .write_text(&mut written)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(written)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>(),
+        lines
+    );
+}
+
+#[test]
+fn json_parses_back() {
+    let expected = records(&trace(Tracer::buffer(1000), 100));
+    let json = SharedBuffer::default();
+    trace(Tracer::json_lines(json.clone()), 100);
+
+    let lines = json.lines();
+    assert_eq!(lines.len(), 100);
+    for (line, record) in lines.iter().zip(expected.iter()) {
+        let value: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(value["step"], record.step);
+        assert_eq!(value["pc"], record.pc);
+        assert_eq!(value["opcode"], record.opcode);
+        assert_eq!(value["instruction"], record.instruction.to_string());
+        assert_eq!(value["state"]["i"], record.after.i);
+        let v: Vec<u8> = serde_json::from_value(value["state"]["v"].clone()).unwrap();
+        assert_eq!(v, record.after.v);
+
+        let changes: Vec<(u8, u8, u8)> = value["register_changes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|change| {
+                serde_json::from_value::<(u8, u8, u8)>(serde_json::json!([
+                    change["reg"],
+                    change["old"],
+                    change["new"]
+                ]))
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(changes, record.register_changes().collect::<Vec<_>>());
+        let i_change: Option<(u16, u16)> =
+            serde_json::from_value(value["i_change"].clone()).unwrap();
+        assert_eq!(i_change, record.i_change());
+        assert_eq!(
+            value["writes"].as_array().unwrap().len(),
+            record.writes.len()
+        );
+        assert!(value.get("source").is_none());
+    }
+
+    let mut written = Vec::new();
+    trace(Tracer::buffer(1000), 100)
+        .trace_buffer()
+        .unwrap()
+        .write_json_lines(&mut written)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(written)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>(),
+        lines
+    );
+}